@@ -1,19 +1,44 @@
-use std::io::{Read, Seek};
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, Write},
+};
 
 use crate::{
     bitvec::BitVec64,
+    cil::{CallSite, Label, MethodToken},
+    coded_index::CodedIndex,
+    compressed::{decode_unsigned, encode_unsigned},
     field::{Field, FieldIndex},
-    method::{MemberRef, MemberRefParent, MethodDef, MethodIndex},
+    method::{MemberRef, MemberRefParent, MethodDef, MethodIndex, StandAloneSigIndex},
     param::{Param, ParamIndex},
-    pe_file::{PEFile, PEFileReadError, RVA},
+    pe_file::{DataDirectory, HeaderPolicy, PEFile, PEFileReadError, RVA},
     resolution_scope::ResolutionScope,
-    table::TypeRef,
+    table::{
+        AssemblyOS, AssemblyProcessor, AssemblyRefOS, AssemblyRefProcessor, ClassLayout, Constant,
+        CustomAttribute, DeclSecurity, EncLog, EncMap, Event, EventMap, EventPtr, ExportedType,
+        FieldLayout, FieldMarshal, FieldPtr, FieldRVA, File, FileIndex, GenericParam,
+        GenericParamConstraint, GenericParamIndex, ImplMap, InterfaceImpl, ManifestResource,
+        MethodImpl, MethodPtr, MethodSemantics, MethodSpec, ModuleRef, NestedClass, ParamPtr,
+        Property, PropertyMap, PropertyPtr, TypeRef, TypeSpec,
+    },
     type_def::{TypeDef, TypeDefOrRef},
 };
 #[derive(Copy, Clone, Debug)]
-pub(crate) struct AssemblyRefIndex(pub u32);
-#[derive(Clone, Copy)]
-struct HeapSizes {
+pub struct AssemblyRefIndex(pub u32);
+impl AssemblyRefIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x23).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x23);
+    }
+}
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HeapSizes {
     bitvec: BitVec64,
 }
 impl From<u8> for HeapSizes {
@@ -23,69 +48,93 @@ impl From<u8> for HeapSizes {
         }
     }
 }
+impl From<HeapSizes> for u8 {
+    fn from(value: HeapSizes) -> Self {
+        u64::from(value.bitvec) as u8
+    }
+}
+/// Writes an index into a heap or table, which is 4 bytes wide if `wide` is set, and 2 bytes wide otherwise.
+pub(crate) fn write_index(out: &mut Vec<u8>, index: u32, wide: bool) {
+    if wide {
+        out.extend(index.to_le_bytes());
+    } else {
+        out.extend((index as u16).to_le_bytes());
+    }
+}
 impl HeapSizes {
-    pub fn read_string_index(&self, slice: &[u8]) -> StringIndex {
-        if self.bitvec.bit(0) {
-            StringIndex(u32_from_slice_at(slice, 0))
-        } else {
-            StringIndex(u16_from_slice_at(slice, 0) as u32)
-        }
+    /// Picks the index sizes for heaps with the given sizes in bytes.
+    pub(crate) fn new(strings: usize, guid: usize, blob: usize) -> Self {
+        let wide = |size: usize| (size >= 1 << 16) as u8;
+        (wide(strings) | wide(guid) << 1 | wide(blob) << 2).into()
     }
-    pub fn string_index_size(&self) -> usize {
-        if self.bitvec.bit(0) {
-            4
-        } else {
-            2
-        }
+    pub fn write_string_index(&self, out: &mut Vec<u8>, index: StringIndex) {
+        write_index(out, index.0, self.bitvec.bit(0));
     }
-    pub fn read_guid_index(&self, slice: &[u8]) -> GUIDIndex {
-        if self.bitvec.bit(1) {
-            GUIDIndex(u32_from_slice_at(slice, 0))
-        } else {
-            GUIDIndex(u16_from_slice_at(slice, 0) as u32)
-        }
+    pub fn write_guid_index(&self, out: &mut Vec<u8>, index: GUIDIndex) {
+        write_index(out, index.0, self.bitvec.bit(1));
     }
-    pub fn guid_index_size(&self) -> usize {
-        if self.bitvec.bit(1) {
-            4
-        } else {
-            2
-        }
+    pub fn write_blob_index(&self, out: &mut Vec<u8>, index: BlobIndex) {
+        write_index(out, index.0, self.bitvec.bit(2));
     }
-    pub fn blob_index_size(&self) -> usize {
-        if self.bitvec.bit(2) {
-            4
+    fn read_index(&self, slice: &mut &[u8], bit: u8) -> Result<u32, DecodeErrorKind> {
+        if self.bitvec.bit(bit) {
+            take_u32(slice)
         } else {
-            2
+            take_u16(slice).map(u32::from)
         }
     }
-    pub fn read_blob_index(&self, slice: &[u8]) -> BlobIndex {
-        if self.bitvec.bit(2) {
-            BlobIndex(u32_from_slice_at(slice, 0))
-        } else {
-            BlobIndex(u16_from_slice_at(slice, 0) as u32)
-        }
+    pub fn read_string_index(&self, slice: &mut &[u8]) -> Result<StringIndex, DecodeErrorKind> {
+        self.read_index(slice, 0).map(StringIndex)
+    }
+    pub fn read_guid_index(&self, slice: &mut &[u8]) -> Result<GUIDIndex, DecodeErrorKind> {
+        self.read_index(slice, 1).map(GUIDIndex)
+    }
+    pub fn read_blob_index(&self, slice: &mut &[u8]) -> Result<BlobIndex, DecodeErrorKind> {
+        self.read_index(slice, 2).map(BlobIndex)
     }
 }
 #[derive(Debug)]
-enum MetadataStream {
-    LogicalMetadataTable(Box<[u32]>, BitVec64, Box<[Table]>),
+pub(crate) enum MetadataStream {
+    /// The `#~` stream, or the uncompressed `#-` stream of edit-and-continue images if `compressed` is not set.
+    LogicalMetadataTable {
+        compressed: bool,
+        heap_sizes: HeapSizes,
+        /// The extra 4 bytes following the row counts, present if bit 0x40 of the heap sizes is set.
+        extra_data: Option<u32>,
+        sorted: u64,
+        rows: Box<[u32]>,
+        tables: BitVec64,
+        encoded_tables: Box<[Table]>,
+    },
     Strings(Box<[u8]>),
     US(Box<[u8]>),
     Blob(Box<[u8]>),
     GUID(Box<[u128]>),
+    /// A stream this crate doesn't know about, kept as is.
+    Unknown {
+        name: Box<str>,
+        data: Box<[u8]>,
+    },
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) struct StringIndex(pub u32);
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) struct GUIDIndex(pub u32);
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct BlobIndex(pub u32);
+/// Offset of a string in the #US heap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UserStringIndex(pub u32);
 #[derive(Clone, Debug)]
 pub(crate) enum Table {
     Module {
+        /// Number of edit-and-continue generations applied to the module, 0 for a freshly compiled one.
+        generation: u16,
         name: StringIndex,
         mvid: GUIDIndex,
+        /// Edit-and-continue ids of the current and the previous generation.
+        enc_id: GUIDIndex,
+        enc_base_id: GUIDIndex,
     },
     TypeRefTable(Box<[TypeRef]>),
     TypeDefTable(Box<[TypeDef]>),
@@ -106,30 +155,141 @@ pub(crate) enum Table {
         culture: StringIndex,
     },
     AssemblyRefs(Box<[AssemblyRef]>),
+    FieldPtr(Box<[FieldPtr]>),
+    MethodPtr(Box<[MethodPtr]>),
+    ParamPtr(Box<[ParamPtr]>),
+    InterfaceImpl(Box<[InterfaceImpl]>),
+    Constant(Box<[Constant]>),
+    CustomAttribute(Box<[CustomAttribute]>),
+    FieldMarshal(Box<[FieldMarshal]>),
+    DeclSecurity(Box<[DeclSecurity]>),
+    ClassLayout(Box<[ClassLayout]>),
+    FieldLayout(Box<[FieldLayout]>),
+    EventMap(Box<[EventMap]>),
+    EventPtr(Box<[EventPtr]>),
+    Event(Box<[Event]>),
+    PropertyMap(Box<[PropertyMap]>),
+    PropertyPtr(Box<[PropertyPtr]>),
+    Property(Box<[Property]>),
+    MethodSemantics(Box<[MethodSemantics]>),
+    MethodImpl(Box<[MethodImpl]>),
+    ModuleRef(Box<[ModuleRef]>),
+    TypeSpec(Box<[TypeSpec]>),
+    ImplMap(Box<[ImplMap]>),
+    FieldRVA(Box<[FieldRVA]>),
+    EncLog(Box<[EncLog]>),
+    EncMap(Box<[EncMap]>),
+    AssemblyProcessor(Box<[AssemblyProcessor]>),
+    AssemblyOS(Box<[AssemblyOS]>),
+    AssemblyRefProcessor(Box<[AssemblyRefProcessor]>),
+    AssemblyRefOS(Box<[AssemblyRefOS]>),
+    File(Box<[File]>),
+    ExportedType(Box<[ExportedType]>),
+    ManifestResource(Box<[ManifestResource]>),
+    NestedClass(Box<[NestedClass]>),
+    GenericParam(Box<[GenericParam]>),
+    MethodSpec(Box<[MethodSpec]>),
+    GenericParamConstraint(Box<[GenericParamConstraint]>),
 }
 #[derive(Clone, Debug)]
 pub(crate) struct AssemblyRef {
-    hash_alg_id: u32,
     major: u16,
     minor: u16,
     build_number: u16,
     revision_number: u16,
     flags: u32,
-    public_key: BlobIndex,
+    public_key_or_token: BlobIndex,
     name: StringIndex,
     culture: StringIndex,
+    hash_value: BlobIndex,
 }
 
 impl AssemblyRef {
+    pub(crate) fn new(
+        (major, minor, build_number, revision_number): (u16, u16, u16, u16),
+        flags: u32,
+        public_key_or_token: BlobIndex,
+        name: StringIndex,
+        culture: StringIndex,
+        hash_value: BlobIndex,
+    ) -> Self {
+        Self {
+            major,
+            minor,
+            build_number,
+            revision_number,
+            flags,
+            public_key_or_token,
+            name,
+            culture,
+            hash_value,
+        }
+    }
     pub(crate) fn name(&self) -> StringIndex {
         self.name
     }
+    pub(crate) fn culture(&self) -> StringIndex {
+        self.culture
+    }
+    pub(crate) fn flags(&self) -> u32 {
+        self.flags
+    }
+    pub(crate) fn public_key_or_token(&self) -> BlobIndex {
+        self.public_key_or_token
+    }
+    pub(crate) fn hash_value(&self) -> BlobIndex {
+        self.hash_value
+    }
+    pub(crate) fn version(&self) -> (u16, u16, u16, u16) {
+        (
+            self.major,
+            self.minor,
+            self.build_number,
+            self.revision_number,
+        )
+    }
 }
 pub(crate) fn table_rows(tables_rows: &[u32], tables: BitVec64, table: u8) -> Option<u32> {
-    match tables.into_iter().position(|v| v == table) {
-        Some(type_def_row) => Some(tables_rows[type_def_row as usize]),
-        None => None,
+    tables
+        .into_iter()
+        .position(|v| v == table)
+        .map(|type_def_row| tables_rows[type_def_row])
+}
+/// Reads an index into `table`, which takes 4 bytes once the table has 2^16 rows or more.
+pub(crate) fn read_table_index(
+    table_slice: &mut &[u8],
+    tables_rows: &[u32],
+    tables: BitVec64,
+    table: u8,
+) -> Result<u32, DecodeErrorKind> {
+    if table_rows(tables_rows, tables, table).unwrap_or_default() >= 1 << 16 {
+        take_u32(table_slice)
+    } else {
+        take_u16(table_slice).map(u32::from)
+    }
+}
+pub(crate) fn write_table_index(
+    out: &mut Vec<u8>,
+    index: u32,
+    tables_rows: &[u32],
+    tables: BitVec64,
+    table: u8,
+) {
+    let wide = table_rows(tables_rows, tables, table).unwrap_or_default() >= 1 << 16;
+    write_index(out, index, wide);
+}
+/// Decodes `rows` rows of a table. On failure, returns the 1-based index of the row which could not be decoded.
+fn decode_rows<T>(
+    rows: u32,
+    table_slice: &mut &[u8],
+    mut decode_row: impl FnMut(&mut &[u8]) -> Result<T, DecodeErrorKind>,
+) -> Result<Vec<T>, (u32, DecodeErrorKind)> {
+    // The row count comes from the file, so it can't be trusted to size the allocation.
+    let mut decoded = Vec::with_capacity((rows as usize).min(table_slice.len()));
+    for row in 0..rows {
+        decoded.push(decode_row(table_slice).map_err(|kind| (row + 1, kind))?);
     }
+    Ok(decoded)
 }
 impl Table {
     fn from(
@@ -139,213 +299,142 @@ impl Table {
         sizes: HeapSizes,
         tables_rows: &[u32],
         tables: BitVec64,
-    ) -> Table {
+    ) -> Result<Table, (u32, DecodeErrorKind)> {
         match table {
             0x00 => {
-                assert_eq!(rows, 1, "There can be one and only one module table!");
-                let generation = u16_from_slice_at(table_slice, 0);
-                assert_eq!(generation, 0);
-                *table_slice = &table_slice[2..];
-                let name = sizes.read_string_index(table_slice);
-                *table_slice = &table_slice[sizes.string_index_size()..];
-                let mvid = sizes.read_guid_index(table_slice);
-                *table_slice = &table_slice[sizes.guid_index_size()..];
-                let enc_id = sizes.read_guid_index(table_slice);
-                *table_slice = &table_slice[sizes.guid_index_size()..];
-                assert_eq!(enc_id, GUIDIndex(0));
-                let enc_base_id = sizes.read_guid_index(table_slice);
-                *table_slice = &table_slice[sizes.guid_index_size()..];
-                assert_eq!(enc_base_id, GUIDIndex(0));
-                Self::Module { name, mvid }
+                if rows != 1 {
+                    return Err((
+                        0,
+                        DecodeErrorKind::InvalidValue {
+                            field: "Module row count",
+                            value: rows as u64,
+                        },
+                    ));
+                }
+                let mut modules = decode_rows(rows, table_slice, |table_slice| {
+                    Ok(Self::Module {
+                        generation: take_u16(table_slice)?,
+                        name: sizes.read_string_index(table_slice)?,
+                        mvid: sizes.read_guid_index(table_slice)?,
+                        enc_id: sizes.read_guid_index(table_slice)?,
+                        enc_base_id: sizes.read_guid_index(table_slice)?,
+                    })
+                })?;
+                Ok(modules.remove(0))
             }
             0x1 => {
-                let mut type_refs = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let scope = ResolutionScope::decode(table_slice, tables_rows, tables);
-                    let name = sizes.read_string_index(table_slice);
-                    assert_ne!(name.0,0);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    let namespace = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    println!("scope:{scope:?} name:{name:?} namespace:{namespace:?}");
-                    type_refs.push(TypeRef::new(scope, name, namespace));
-                }
-                Table::TypeRefTable(type_refs.into())
+                let type_refs = decode_rows(rows, table_slice, |table_slice| {
+                    let scope = ResolutionScope::decode(table_slice, tables_rows, tables)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    if name.0 == 0 {
+                        return Err(DecodeErrorKind::InvalidValue {
+                            field: "TypeName",
+                            value: 0,
+                        });
+                    }
+                    let namespace = sizes.read_string_index(table_slice)?;
+                    Ok(TypeRef::new(scope, name, namespace))
+                })?;
+                Ok(Table::TypeRefTable(type_refs.into()))
             }
             0x2 => {
-                let mut flags = Vec::with_capacity(rows as usize);
-                let mut type_names = Vec::with_capacity(rows as usize);
-                let mut type_namespaces = Vec::with_capacity(rows as usize);
-                let mut derived_from = Vec::with_capacity(rows as usize);
-                let mut field_idx = Vec::with_capacity(rows as usize);
-                let mut method_idx = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    flags.push(u32_from_slice_at(table_slice, 0));
-                    *table_slice = &table_slice[4..];
-                    type_names.push(sizes.read_string_index(table_slice));
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    type_namespaces.push(sizes.read_string_index(table_slice));
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    derived_from.push(TypeDefOrRef::decode(table_slice, tables_rows, tables));
-                    field_idx.push(FieldIndex::decode(table_slice, tables_rows, tables));
-                    //let idx = u32_from_slice_at(table_slice, 0);
-                    
-                    let idx = MethodIndex::decode(table_slice, tables_rows, tables);
-
-                    method_idx.push(idx);
-                }
-
-                let type_defs = TypeDef::from_vecs(
-                    &flags,
-                    &type_names,
-                    &type_namespaces,
-                    &derived_from,
-                    &field_idx,
-                    &method_idx,
-                );
-                println!("type_defs:{type_defs:?}");
-                let rva = u32_from_slice_at(table_slice, 0);
-                println!("after typedef:0x{rva:x}");
-                Self::TypeDefTable(type_defs.into())
+                let type_defs = decode_rows(rows, table_slice, |table_slice| {
+                    let flags = take_u32(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    let namespace = sizes.read_string_index(table_slice)?;
+                    let derived_from = TypeDefOrRef::decode(table_slice, tables_rows, tables)?;
+                    let field_index = FieldIndex::decode(table_slice, tables_rows, tables)?;
+                    let method_index = MethodIndex::decode(table_slice, tables_rows, tables)?;
+                    Ok(TypeDef::new(
+                        flags,
+                        name,
+                        namespace,
+                        derived_from,
+                        field_index,
+                        method_index,
+                    ))
+                })?;
+                Ok(Self::TypeDefTable(type_defs.into()))
             }
             0x4 => {
-                let mut fields = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let flags = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let name = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    println!("Field name:{name:?}");
-                    let signature = sizes.read_blob_index(table_slice);
-                    *table_slice = &table_slice[sizes.blob_index_size()..];
-                    fields.push(Field::new(flags, name, signature));
-                }
-                Table::Fields(fields.into())
+                let fields = decode_rows(rows, table_slice, |table_slice| {
+                    let flags = take_u16(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    let signature = sizes.read_blob_index(table_slice)?;
+                    Ok(Field::new(flags, name, signature))
+                })?;
+                Ok(Table::Fields(fields.into()))
             }
             0x6 => {
-                let mut method_defs = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let rva = u32_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[4..];
-                    let impl_flags = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let flags = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let name = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    let signature = sizes.read_blob_index(table_slice);
-                    *table_slice = &table_slice[sizes.blob_index_size()..];
-                    let param_index = ParamIndex::decode(table_slice, tables_rows, tables);
-                    method_defs.push(MethodDef::new(
+                let method_defs = decode_rows(rows, table_slice, |table_slice| {
+                    let rva = take_u32(table_slice)?;
+                    let impl_flags = take_u16(table_slice)?;
+                    let flags = take_u16(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    let signature = sizes.read_blob_index(table_slice)?;
+                    let param_index = ParamIndex::decode(table_slice, tables_rows, tables)?;
+                    Ok(MethodDef::new(
                         rva,
                         impl_flags,
                         flags,
                         name,
                         signature,
                         param_index,
-                    ));
-                }
-                Self::MethodDefTable(method_defs.into())
+                    ))
+                })?;
+                Ok(Self::MethodDefTable(method_defs.into()))
             }
             0x8 => {
-                let mut params = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let flags = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let sequence = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let name = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    params.push(Param::new(flags, sequence, name))
-                }
-                Self::Param(params)
+                let params = decode_rows(rows, table_slice, |table_slice| {
+                    let flags = take_u16(table_slice)?;
+                    let sequence = take_u16(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    Ok(Param::new(flags, sequence, name))
+                })?;
+                Ok(Self::Param(params))
             }
             0xa => {
-                let mut member_refs = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let class = MemberRefParent::decode(table_slice, tables_rows, tables);
-                    let name = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    let signature = sizes.read_blob_index(table_slice);
-                    *table_slice = &table_slice[sizes.blob_index_size()..];
-
-                    member_refs.push(MemberRef::new(class, name, signature))
-                }
-                Self::MemberRef(member_refs.into())
+                let member_refs = decode_rows(rows, table_slice, |table_slice| {
+                    let class = MemberRefParent::decode(table_slice, tables_rows, tables)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    let signature = sizes.read_blob_index(table_slice)?;
+                    Ok(MemberRef::new(class, name, signature))
+                })?;
+                Ok(Self::MemberRef(member_refs.into()))
             }
             0x11 => {
-                let mut sigs = Vec::with_capacity(rows as usize);
-                for _ in 0..rows {
-                    let signature = sizes.read_blob_index(table_slice);
-                    *table_slice = &table_slice[sizes.blob_index_size()..];
-
-                    sigs.push(signature);
-                }
-                Self::StandAloneSig(sigs.into())
+                let sigs = decode_rows(rows, table_slice, |table_slice| {
+                    sizes.read_blob_index(table_slice)
+                })?;
+                Ok(Self::StandAloneSig(sigs.into()))
             }
             0x20 => {
-                let hash_alg_id = u32_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[4..];
-                let major = u16_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[2..];
-                let minor = u16_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[2..];
-                let build_number = u16_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[2..];
-
-                let revision_number = u16_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[2..];
-                let flags = u32_from_slice_at(table_slice, 0);
-                *table_slice = &table_slice[4..];
-                let public_key = sizes.read_blob_index(table_slice);
-                *table_slice = &table_slice[sizes.blob_index_size()..];
-                let name = sizes.read_string_index(table_slice);
-                    println!("asm_name:{name:?} minor:{minor:x} major:{major:x} build_number:{build_number:x} revision_number:{revision_number:x}");
-                *table_slice = &table_slice[sizes.string_index_size()..];
-                assert_ne!(name.0,0);
-                let culture = sizes.read_string_index(table_slice);
-                    *table_slice = &table_slice[sizes.string_index_size()..];
-                    println!("asm_culture:{culture:?}");
-                Self::Assembly {
-                    hash_alg_id,
-                    major,
-                    minor,
-                    build_number,
-                    revision_number,
-                    flags,
-                    public_key,
-                    name,
-                    culture
-                }
-            }
-            
-            0x23 => {
-                let mut refs = Vec::with_capacity(rows as usize);
-                for _ in 0..rows{
-                    let hash_alg_id = u32_from_slice_at(table_slice, 0);
-                    println!("hash_alg_id:{hash_alg_id:x}");
-                    *table_slice = &table_slice[4..];
-                    let major = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let minor = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let build_number = u16_from_slice_at(table_slice, 0);
-                    println!("build_number:{build_number}");
-                    *table_slice = &table_slice[2..];
-                    let revision_number = u16_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[2..];
-                    let flags = u32_from_slice_at(table_slice, 0);
-                    *table_slice = &table_slice[4..];
-                    let public_key = sizes.read_blob_index(table_slice);
-                    *table_slice = &table_slice[sizes.blob_index_size()..];
-                    let name = sizes.read_string_index(table_slice);
-                    //assert_ne!(name.0,0);
-                    println!("asm_ref_name:{name:?}");
-                    //*table_slice = &table_slice[sizes.string_index_size()..];
-                    //let culture = sizes.read_string_index(table_slice);
-                    //*table_slice = &table_slice[sizes.string_index_size()..];
-                    //println!("asm_ref_culture:{culture:?}");
-                    refs.push(AssemblyRef {
+                if rows != 1 {
+                    return Err((
+                        0,
+                        DecodeErrorKind::InvalidValue {
+                            field: "Assembly row count",
+                            value: rows as u64,
+                        },
+                    ));
+                }
+                let mut assemblies = decode_rows(rows, table_slice, |table_slice| {
+                    let hash_alg_id = take_u32(table_slice)?;
+                    let major = take_u16(table_slice)?;
+                    let minor = take_u16(table_slice)?;
+                    let build_number = take_u16(table_slice)?;
+                    let revision_number = take_u16(table_slice)?;
+                    let flags = take_u32(table_slice)?;
+                    let public_key = sizes.read_blob_index(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    if name.0 == 0 {
+                        return Err(DecodeErrorKind::InvalidValue {
+                            field: "Name",
+                            value: 0,
+                        });
+                    }
+                    let culture = sizes.read_string_index(table_slice)?;
+                    Ok(Table::Assembly {
                         hash_alg_id,
                         major,
                         minor,
@@ -354,59 +443,808 @@ impl Table {
                         flags,
                         public_key,
                         name,
-                        culture:StringIndex(0),
-                    });
-                }
-                let refs = [];
-                Self::AssemblyRefs(refs.into())
+                        culture,
+                    })
+                })?;
+                Ok(assemblies.remove(0))
+            }
+            0x23 => {
+                let refs = decode_rows(rows, table_slice, |table_slice| {
+                    let major = take_u16(table_slice)?;
+                    let minor = take_u16(table_slice)?;
+                    let build_number = take_u16(table_slice)?;
+                    let revision_number = take_u16(table_slice)?;
+                    let flags = take_u32(table_slice)?;
+                    let public_key_or_token = sizes.read_blob_index(table_slice)?;
+                    let name = sizes.read_string_index(table_slice)?;
+                    let culture = sizes.read_string_index(table_slice)?;
+                    let hash_value = sizes.read_blob_index(table_slice)?;
+                    Ok(AssemblyRef {
+                        major,
+                        minor,
+                        build_number,
+                        revision_number,
+                        flags,
+                        public_key_or_token,
+                        name,
+                        culture,
+                        hash_value,
+                    })
+                })?;
+                Ok(Self::AssemblyRefs(refs.into()))
+            }
+            0x03 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    FieldPtr::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::FieldPtr(rows.into()))
+            }
+            0x05 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    MethodPtr::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::MethodPtr(rows.into()))
+            }
+            0x07 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ParamPtr::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ParamPtr(rows.into()))
+            }
+            0x09 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    InterfaceImpl::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::InterfaceImpl(rows.into()))
+            }
+            0x0b => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    Constant::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::Constant(rows.into()))
+            }
+            0x0c => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    CustomAttribute::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::CustomAttribute(rows.into()))
+            }
+            0x0d => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    FieldMarshal::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::FieldMarshal(rows.into()))
+            }
+            0x0e => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    DeclSecurity::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::DeclSecurity(rows.into()))
+            }
+            0x0f => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ClassLayout::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ClassLayout(rows.into()))
+            }
+            0x10 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    FieldLayout::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::FieldLayout(rows.into()))
+            }
+            0x12 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    EventMap::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::EventMap(rows.into()))
+            }
+            0x13 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    EventPtr::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::EventPtr(rows.into()))
+            }
+            0x14 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    Event::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::Event(rows.into()))
+            }
+            0x15 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    PropertyMap::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::PropertyMap(rows.into()))
+            }
+            0x16 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    PropertyPtr::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::PropertyPtr(rows.into()))
+            }
+            0x17 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    Property::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::Property(rows.into()))
+            }
+            0x18 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    MethodSemantics::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::MethodSemantics(rows.into()))
+            }
+            0x19 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    MethodImpl::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::MethodImpl(rows.into()))
+            }
+            0x1a => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ModuleRef::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ModuleRef(rows.into()))
+            }
+            0x1b => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    TypeSpec::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::TypeSpec(rows.into()))
+            }
+            0x1c => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ImplMap::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ImplMap(rows.into()))
+            }
+            0x1d => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    FieldRVA::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::FieldRVA(rows.into()))
+            }
+            0x1e => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    EncLog::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::EncLog(rows.into()))
+            }
+            0x1f => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    EncMap::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::EncMap(rows.into()))
+            }
+            0x21 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    AssemblyProcessor::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::AssemblyProcessor(rows.into()))
+            }
+            0x22 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    AssemblyOS::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::AssemblyOS(rows.into()))
+            }
+            0x24 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    AssemblyRefProcessor::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::AssemblyRefProcessor(rows.into()))
             }
-            _ => todo!("Unknown table 0x{table:x}",),
+            0x25 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    AssemblyRefOS::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::AssemblyRefOS(rows.into()))
+            }
+            0x26 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    File::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::File(rows.into()))
+            }
+            0x27 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ExportedType::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ExportedType(rows.into()))
+            }
+            0x28 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    ManifestResource::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::ManifestResource(rows.into()))
+            }
+            0x29 => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    NestedClass::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::NestedClass(rows.into()))
+            }
+            0x2a => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    GenericParam::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::GenericParam(rows.into()))
+            }
+            0x2b => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    MethodSpec::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::MethodSpec(rows.into()))
+            }
+            0x2c => {
+                let rows = decode_rows(rows, table_slice, |table_slice| {
+                    GenericParamConstraint::decode(table_slice, sizes, tables_rows, tables)
+                })?;
+                Ok(Self::GenericParamConstraint(rows.into()))
+            }
+            _ => Err((0, DecodeErrorKind::UnknownTable(table))),
         }
     }
 }
-fn next_table(mut table_list: u64) -> Option<(u64, u64)> {
-    let leading_zeros = table_list.leading_zeros();
-    if leading_zeros == 64 {
-        return None;
+impl Table {
+    /// Number of this table, as used in the `Valid` bit vector of the `#~` stream.
+    fn table(&self) -> u8 {
+        match self {
+            Self::Module { .. } => 0x00,
+            Self::TypeRefTable(_) => 0x01,
+            Self::TypeDefTable(_) => 0x02,
+            Self::Fields(_) => 0x04,
+            Self::MethodDefTable(_) => 0x06,
+            Self::Param(_) => 0x08,
+            Self::MemberRef(_) => 0x0a,
+            Self::StandAloneSig(_) => 0x11,
+            Self::Assembly { .. } => 0x20,
+            Self::AssemblyRefs(_) => 0x23,
+            Self::FieldPtr(_) => 0x03,
+            Self::MethodPtr(_) => 0x05,
+            Self::ParamPtr(_) => 0x07,
+            Self::InterfaceImpl(_) => 0x09,
+            Self::Constant(_) => 0x0b,
+            Self::CustomAttribute(_) => 0x0c,
+            Self::FieldMarshal(_) => 0x0d,
+            Self::DeclSecurity(_) => 0x0e,
+            Self::ClassLayout(_) => 0x0f,
+            Self::FieldLayout(_) => 0x10,
+            Self::EventMap(_) => 0x12,
+            Self::EventPtr(_) => 0x13,
+            Self::Event(_) => 0x14,
+            Self::PropertyMap(_) => 0x15,
+            Self::PropertyPtr(_) => 0x16,
+            Self::Property(_) => 0x17,
+            Self::MethodSemantics(_) => 0x18,
+            Self::MethodImpl(_) => 0x19,
+            Self::ModuleRef(_) => 0x1a,
+            Self::TypeSpec(_) => 0x1b,
+            Self::ImplMap(_) => 0x1c,
+            Self::FieldRVA(_) => 0x1d,
+            Self::EncLog(_) => 0x1e,
+            Self::EncMap(_) => 0x1f,
+            Self::AssemblyProcessor(_) => 0x21,
+            Self::AssemblyOS(_) => 0x22,
+            Self::AssemblyRefProcessor(_) => 0x24,
+            Self::AssemblyRefOS(_) => 0x25,
+            Self::File(_) => 0x26,
+            Self::ExportedType(_) => 0x27,
+            Self::ManifestResource(_) => 0x28,
+            Self::NestedClass(_) => 0x29,
+            Self::GenericParam(_) => 0x2a,
+            Self::MethodSpec(_) => 0x2b,
+            Self::GenericParamConstraint(_) => 0x2c,
+        }
+    }
+    fn rows(&self) -> u32 {
+        let rows = match self {
+            Self::Module { .. } | Self::Assembly { .. } => 1,
+            Self::TypeRefTable(refs) => refs.len(),
+            Self::TypeDefTable(defs) => defs.len(),
+            Self::Fields(fields) => fields.len(),
+            Self::MethodDefTable(defs) => defs.len(),
+            Self::Param(params) => params.len(),
+            Self::MemberRef(refs) => refs.len(),
+            Self::StandAloneSig(sigs) => sigs.len(),
+            Self::AssemblyRefs(refs) => refs.len(),
+            Self::FieldPtr(rows) => rows.len(),
+            Self::MethodPtr(rows) => rows.len(),
+            Self::ParamPtr(rows) => rows.len(),
+            Self::InterfaceImpl(rows) => rows.len(),
+            Self::Constant(rows) => rows.len(),
+            Self::CustomAttribute(rows) => rows.len(),
+            Self::FieldMarshal(rows) => rows.len(),
+            Self::DeclSecurity(rows) => rows.len(),
+            Self::ClassLayout(rows) => rows.len(),
+            Self::FieldLayout(rows) => rows.len(),
+            Self::EventMap(rows) => rows.len(),
+            Self::EventPtr(rows) => rows.len(),
+            Self::Event(rows) => rows.len(),
+            Self::PropertyMap(rows) => rows.len(),
+            Self::PropertyPtr(rows) => rows.len(),
+            Self::Property(rows) => rows.len(),
+            Self::MethodSemantics(rows) => rows.len(),
+            Self::MethodImpl(rows) => rows.len(),
+            Self::ModuleRef(rows) => rows.len(),
+            Self::TypeSpec(rows) => rows.len(),
+            Self::ImplMap(rows) => rows.len(),
+            Self::FieldRVA(rows) => rows.len(),
+            Self::EncLog(rows) => rows.len(),
+            Self::EncMap(rows) => rows.len(),
+            Self::AssemblyProcessor(rows) => rows.len(),
+            Self::AssemblyOS(rows) => rows.len(),
+            Self::AssemblyRefProcessor(rows) => rows.len(),
+            Self::AssemblyRefOS(rows) => rows.len(),
+            Self::File(rows) => rows.len(),
+            Self::ExportedType(rows) => rows.len(),
+            Self::ManifestResource(rows) => rows.len(),
+            Self::NestedClass(rows) => rows.len(),
+            Self::GenericParam(rows) => rows.len(),
+            Self::MethodSpec(rows) => rows.len(),
+            Self::GenericParamConstraint(rows) => rows.len(),
+        };
+        rows as u32
+    }
+    /// Encodes all rows of this table. This is the inverse of [`Table::from`].
+    fn encode(&self, out: &mut Vec<u8>, sizes: HeapSizes, tables_rows: &[u32], tables: BitVec64) {
+        match self {
+            Self::Module {
+                generation,
+                name,
+                mvid,
+                enc_id,
+                enc_base_id,
+            } => {
+                out.extend(generation.to_le_bytes());
+                sizes.write_string_index(out, *name);
+                sizes.write_guid_index(out, *mvid);
+                sizes.write_guid_index(out, *enc_id);
+                sizes.write_guid_index(out, *enc_base_id);
+            }
+            Self::TypeRefTable(refs) => {
+                for type_ref in refs.iter() {
+                    type_ref.scope().encode(out, tables_rows, tables);
+                    sizes.write_string_index(out, type_ref.name());
+                    sizes.write_string_index(out, type_ref.namespace());
+                }
+            }
+            Self::TypeDefTable(defs) => {
+                for type_def in defs.iter() {
+                    out.extend(type_def.flags().to_le_bytes());
+                    sizes.write_string_index(out, type_def.name());
+                    sizes.write_string_index(out, type_def.namespace());
+                    type_def.derived_from().encode(out, tables_rows, tables);
+                    type_def.field_index().encode(out, tables_rows, tables);
+                    type_def.method_index().encode(out, tables_rows, tables);
+                }
+            }
+            Self::Fields(fields) => {
+                for field in fields.iter() {
+                    out.extend(field.flags().to_le_bytes());
+                    sizes.write_string_index(out, field.name());
+                    sizes.write_blob_index(out, field.signature());
+                }
+            }
+            Self::MethodDefTable(defs) => {
+                for method in defs.iter() {
+                    out.extend((method.rva().0 as u32).to_le_bytes());
+                    out.extend(method.impl_flags().to_le_bytes());
+                    out.extend(method.flags().to_le_bytes());
+                    sizes.write_string_index(out, method.name());
+                    sizes.write_blob_index(out, method.signature());
+                    method.param_start().encode(out, tables_rows, tables);
+                }
+            }
+            Self::Param(params) => {
+                for param in params {
+                    out.extend(param.flags().to_le_bytes());
+                    out.extend(param.sequence().to_le_bytes());
+                    sizes.write_string_index(out, param.name());
+                }
+            }
+            Self::MemberRef(refs) => {
+                for member_ref in refs.iter() {
+                    member_ref.class().encode(out, tables_rows, tables);
+                    sizes.write_string_index(out, member_ref.name());
+                    sizes.write_blob_index(out, member_ref.signature());
+                }
+            }
+            Self::StandAloneSig(sigs) => {
+                for sig in sigs.iter() {
+                    sizes.write_blob_index(out, *sig);
+                }
+            }
+            Self::Assembly {
+                hash_alg_id,
+                major,
+                minor,
+                build_number,
+                revision_number,
+                flags,
+                public_key,
+                name,
+                culture,
+            } => {
+                out.extend(hash_alg_id.to_le_bytes());
+                for part in [major, minor, build_number, revision_number] {
+                    out.extend(part.to_le_bytes());
+                }
+                out.extend(flags.to_le_bytes());
+                sizes.write_blob_index(out, *public_key);
+                sizes.write_string_index(out, *name);
+                sizes.write_string_index(out, *culture);
+            }
+            Self::AssemblyRefs(refs) => {
+                for assembly_ref in refs.iter() {
+                    let (major, minor, build_number, revision_number) = assembly_ref.version();
+                    for part in [major, minor, build_number, revision_number] {
+                        out.extend(part.to_le_bytes());
+                    }
+                    out.extend(assembly_ref.flags.to_le_bytes());
+                    sizes.write_blob_index(out, assembly_ref.public_key_or_token);
+                    sizes.write_string_index(out, assembly_ref.name);
+                    sizes.write_string_index(out, assembly_ref.culture);
+                    sizes.write_blob_index(out, assembly_ref.hash_value);
+                }
+            }
+            Self::FieldPtr(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::MethodPtr(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ParamPtr(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::InterfaceImpl(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::Constant(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::CustomAttribute(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::FieldMarshal(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::DeclSecurity(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ClassLayout(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::FieldLayout(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::EventMap(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::EventPtr(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::Event(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::PropertyMap(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::PropertyPtr(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::Property(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::MethodSemantics(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::MethodImpl(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ModuleRef(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::TypeSpec(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ImplMap(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::FieldRVA(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::EncLog(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::EncMap(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::AssemblyProcessor(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::AssemblyOS(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::AssemblyRefProcessor(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::AssemblyRefOS(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::File(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ExportedType(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::ManifestResource(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::NestedClass(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::GenericParam(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::MethodSpec(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+            Self::GenericParamConstraint(rows) => {
+                for row in rows.iter() {
+                    row.encode(out, sizes, tables_rows, tables);
+                }
+            }
+        }
     }
-    let mask = 1_u64 << leading_zeros;
-    table_list &= !mask;
-    Some((table_list, mask))
 }
+/// Rounds the length of `stream` up to a multiple of 4, as required for all metadata streams.
+fn pad_stream(stream: &mut Vec<u8>) {
+    stream.resize((stream.len() + 3) & !3, 0);
+}
+/// Heap sizes bit marking the 4 bytes of extra data after the row counts of a table stream.
+const EXTRA_DATA: u8 = 0x40;
+/// Tables which are sorted when written by this crate, as specified in II.22.
+const SORTED_TABLES: u64 = 0x0000_1600_3301_FA00;
 impl MetadataStream {
-    fn logical_metadata_table(stream: &[u8]) -> Self {
-        let reserved = u32_from_slice_at(stream, 0);
-        assert_eq!(reserved, 0);
-        let major = stream[4];
-        assert_eq!(major, 2);
-        let minor = stream[5];
-        assert_eq!(minor, 0);
-        let heap_sizes = stream[6];
-        let reserved = stream[7];
-        assert_eq!(reserved, 1);
-        let present_tables = u64_from_slice_at(stream, 8);
-        assert!(present_tables < 1 << 44);
-        let sorted_tables = u64_from_slice_at(stream, 16);
+    /// Creates a `#~` stream containing `tables`, which must be ordered by their table number.
+    pub(crate) fn tables(tables: Vec<Table>, heap_sizes: HeapSizes) -> Self {
+        let rows: Vec<u32> = tables.iter().map(Table::rows).collect();
+        let present = tables
+            .iter()
+            .fold(0_u64, |present, table| present | 1 << table.table());
+        Self::LogicalMetadataTable {
+            compressed: true,
+            heap_sizes,
+            extra_data: None,
+            sorted: SORTED_TABLES,
+            rows: rows.into(),
+            tables: present.into(),
+            encoded_tables: tables.into(),
+        }
+    }
+    pub(crate) fn strings(heap: Vec<u8>) -> Self {
+        Self::Strings(heap.into())
+    }
+    pub(crate) fn user_strings(heap: Vec<u8>) -> Self {
+        Self::US(heap.into())
+    }
+    pub(crate) fn blobs(heap: Vec<u8>) -> Self {
+        Self::Blob(heap.into())
+    }
+    pub(crate) fn guids(heap: Vec<u128>) -> Self {
+        Self::GUID(heap.into())
+    }
+    fn name(&self) -> &str {
+        match self {
+            Self::LogicalMetadataTable {
+                compressed: true, ..
+            } => "#~",
+            Self::LogicalMetadataTable {
+                compressed: false, ..
+            } => "#-",
+            Self::Strings(_) => "#Strings",
+            Self::US(_) => "#US",
+            Self::Blob(_) => "#Blob",
+            Self::GUID(_) => "#GUID",
+            Self::Unknown { name, .. } => name,
+        }
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut stream = match self {
+            Self::LogicalMetadataTable {
+                heap_sizes,
+                extra_data,
+                sorted,
+                rows,
+                tables,
+                encoded_tables,
+                ..
+            } => {
+                let mut stream = vec![0, 0, 0, 0, 2, 0, (*heap_sizes).into(), 1];
+                stream.extend(u64::from(*tables).to_le_bytes());
+                stream.extend(sorted.to_le_bytes());
+                for row in rows.iter() {
+                    stream.extend(row.to_le_bytes());
+                }
+                if let Some(extra_data) = extra_data {
+                    stream.extend(extra_data.to_le_bytes());
+                }
+                for table in encoded_tables.iter() {
+                    table.encode(&mut stream, *heap_sizes, rows, *tables);
+                }
+                stream
+            }
+            Self::Strings(heap) | Self::US(heap) | Self::Blob(heap) => heap.to_vec(),
+            Self::GUID(guids) => guids.iter().flat_map(|guid| guid.to_le_bytes()).collect(),
+            Self::Unknown { data, .. } => data.to_vec(),
+        };
+        pad_stream(&mut stream);
+        stream
+    }
+    fn logical_metadata_table(stream: &[u8], compressed: bool) -> Result<Self, AssemblyReadError> {
+        let name = if compressed { "#~" } else { "#-" };
+        let err = |offset, kind| AssemblyReadError::Decode {
+            location: DecodeLocation::Stream(name.into()),
+            offset,
+            kind,
+        };
+        let mut header = stream;
+        let reserved = take_u32(&mut header).map_err(|kind| err(0, kind))?;
+        if reserved != 0 {
+            return Err(err(
+                0,
+                DecodeErrorKind::InvalidValue {
+                    field: "Reserved",
+                    value: reserved as u64,
+                },
+            ));
+        }
+        let [major, minor, heap_sizes, reserved] =
+            take(&mut header).map_err(|kind| err(4, kind))?;
+        if major != 2 || minor != 0 {
+            return Err(err(
+                4,
+                DecodeErrorKind::InvalidValue {
+                    field: "MajorVersion",
+                    value: ((major as u64) << 8) | minor as u64,
+                },
+            ));
+        }
+        if reserved != 1 {
+            return Err(err(
+                7,
+                DecodeErrorKind::InvalidValue {
+                    field: "Reserved",
+                    value: reserved as u64,
+                },
+            ));
+        }
+        let present_tables = take_u64(&mut header).map_err(|kind| err(8, kind))?;
+        if present_tables >= 1 << 45 {
+            return Err(err(
+                8,
+                DecodeErrorKind::InvalidValue {
+                    field: "Valid",
+                    value: present_tables,
+                },
+            ));
+        }
+        let sorted = take_u64(&mut header).map_err(|kind| err(16, kind))?;
 
         let table_count = present_tables.count_ones();
         let mut rows = Vec::with_capacity(table_count as usize);
-        for idx in 0..table_count {
-            rows.push(u32_from_slice_at(stream, (24 + idx * 4) as usize));
+        for _ in 0..table_count {
+            let offset = stream.len() - header.len();
+            rows.push(take_u32(&mut header).map_err(|kind| err(offset, kind))?);
         }
-        let mut table_slice: &[u8] = &stream[(24 + table_count as usize * 4)..];
+        let extra_data = if heap_sizes & EXTRA_DATA != 0 {
+            let offset = stream.len() - header.len();
+            Some(take_u32(&mut header).map_err(|kind| err(offset, kind))?)
+        } else {
+            None
+        };
+        let mut table_slice: &[u8] = header;
         let tables: BitVec64 = present_tables.into();
         let mut encoded_tables = Vec::with_capacity(table_count as usize);
         for (table, curr_rows) in tables.into_iter().zip(rows.iter()) {
-            encoded_tables.push(Table::from(
+            let encoded = Table::from(
                 table,
                 &mut table_slice,
                 *curr_rows,
                 heap_sizes.into(),
                 &rows,
                 tables,
-            ));
+            )
+            .map_err(|(row, kind)| AssemblyReadError::Decode {
+                location: DecodeLocation::Table {
+                    table,
+                    name: table_name(table),
+                    row,
+                },
+                offset: stream.len() - table_slice.len(),
+                kind,
+            })?;
+            encoded_tables.push(encoded);
         }
-        Self::LogicalMetadataTable(rows.into(), tables, encoded_tables.into())
+        Ok(Self::LogicalMetadataTable {
+            compressed,
+            heap_sizes: heap_sizes.into(),
+            extra_data,
+            sorted,
+            rows: rows.into(),
+            tables,
+            encoded_tables: encoded_tables.into(),
+        })
     }
     fn string_stream(stream: &[u8]) -> Self {
         Self::Strings(stream.to_owned().into())
@@ -415,302 +1253,1398 @@ impl MetadataStream {
         Self::US(stream.to_owned().into())
     }
     fn blob_stream(stream: &[u8]) -> Self {
-        println!("blob len:{}", stream.len());
         Self::Blob(stream.to_owned().into())
     }
     fn guid_stream(stream: &[u8]) -> Self {
-        let guid_count = stream.len() / std::mem::size_of::<u128>();
-        let mut guids = Vec::with_capacity(guid_count);
-        for index in 0..guid_count {
-            guids.push(u128_from_slice_at(stream, index * 16));
-        }
-        Self::GUID(guids.to_owned().into())
+        let guids = stream
+            .chunks_exact(std::mem::size_of::<u128>())
+            .map(|guid| u128::from_le_bytes(guid.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        Self::GUID(guids.into())
     }
-    fn from_slice(metadata: &[u8], curr_offset: &mut usize) -> Self {
-        let stream_offset = u32_from_slice_at(metadata, *curr_offset);
-
-        let stream_size = u32_from_slice_at(metadata, *curr_offset + 4);
-        println!("stream_offset:{stream_offset} stream_size:{stream_size}");
-        *curr_offset += 8;
-        let name_start = *curr_offset;
-        while metadata[*curr_offset] != 0 {
-            *curr_offset += 1;
-        }
-        let name_end = *curr_offset;
-        if *curr_offset | 0b11 != 0 {
-            *curr_offset &= !0b11;
-            *curr_offset += 0b100;
-        }
+    /// Decodes the stream whose header starts at `curr_offset`, and advances it to the next header.
+    fn from_slice(metadata: &[u8], curr_offset: &mut usize) -> Result<Self, AssemblyReadError> {
+        let err = |offset, kind| AssemblyReadError::Decode {
+            location: DecodeLocation::MetadataRoot,
+            offset,
+            kind,
+        };
+        let header_offset = *curr_offset;
+        let stream_offset = u32_from_slice_at(metadata, header_offset)
+            .ok_or_else(|| err(header_offset, DecodeErrorKind::UnexpectedEnd))?;
+        let stream_size = u32_from_slice_at(metadata, header_offset + 4)
+            .ok_or_else(|| err(header_offset + 4, DecodeErrorKind::UnexpectedEnd))?;
+        let name_start = header_offset + 8;
+        let name_len = metadata
+            .get(name_start..)
+            .and_then(|name| name.iter().position(|c| *c == 0))
+            .ok_or_else(|| err(name_start, DecodeErrorKind::UnexpectedEnd))?;
+        // The name is null terminated, and padded to the next 4 byte boundary.
+        *curr_offset = name_start + ((name_len + 4) & !0b11);
 
-        let name =
-            std::str::from_utf8(&metadata[name_start..name_end]).expect("Not utf8 stream name!");
-        println!("name:{name} curr_offset:{curr_offset}");
-        let stream =
-            &metadata[(stream_offset as usize)..(stream_offset as usize + stream_size as usize)];
+        let name = std::str::from_utf8(&metadata[name_start..name_start + name_len])
+            .map_err(|_| err(name_start, DecodeErrorKind::InvalidUtf8))?;
+        let stream = (stream_offset as usize)
+            .checked_add(stream_size as usize)
+            .and_then(|end| metadata.get(stream_offset as usize..end))
+            .ok_or_else(|| err(header_offset, DecodeErrorKind::UnexpectedEnd))?;
 
         match name {
-            "#~" => Self::logical_metadata_table(stream),
-            "#Strings" => Self::string_stream(stream),
-            "#US" => Self::us_stream(stream),
-            "#Blob" => Self::blob_stream(stream),
-            "#GUID" => Self::guid_stream(stream),
-            _ => todo!("Unknown stream \"{name}\"."),
+            "#~" => Self::logical_metadata_table(stream, true),
+            "#-" => Self::logical_metadata_table(stream, false),
+            "#Strings" => Ok(Self::string_stream(stream)),
+            "#US" => Ok(Self::us_stream(stream)),
+            "#Blob" => Ok(Self::blob_stream(stream)),
+            "#GUID" => Ok(Self::guid_stream(stream)),
+            _ => Ok(Self::Unknown {
+                name: name.into(),
+                data: stream.into(),
+            }),
         }
     }
 }
 #[derive(Debug)]
-struct RawMetadata {
-    major: u32,
-    minor: u32,
+pub(crate) struct RawMetadata {
+    major: u16,
+    minor: u16,
     version: Box<str>,
+    /// Length of the version field, including its null terminator and padding.
+    version_length: u32,
     streams: Vec<MetadataStream>,
+    /// The bytes the metadata was read from, which are written back as they are. Compilers lay out, order and pad
+    /// streams in ways [`Self::encode`] doesn't reproduce, so this keeps an unmodified assembly intact.
+    source: Option<Box<[u8]>>,
 }
 impl RawMetadata {
-    fn from_slice(metadata: &[u8]) -> Self {
-        let magic = u32_from_slice_at(metadata, 0);
-        assert_eq!(magic, 0x424A5342);
-        let major = u32_from_slice_at(metadata, 4);
-        let minor = u32_from_slice_at(metadata, 6);
-        let reserved = u32_from_slice_at(metadata, 8);
-        assert_eq!(reserved, 0);
-        let length = u32_from_slice_at(metadata, 12);
-        let rounded_length = (length / 4 + (length % 4)) * 4;
-        let version = &metadata[16..(16 + rounded_length as usize)];
-        let null = version.iter().position(|c| *c == 0).unwrap_or(8);
-        let version = std::str::from_utf8(&version[..null]).expect("Not utf8 version name!");
-        println!("version:{version}");
-        let flags = u16_from_slice_at(metadata, 16 + rounded_length as usize);
-        assert_eq!(flags, 0);
-        let stream_count = u16_from_slice_at(metadata, 16 + rounded_length as usize + 2);
-        let mut curr_offset = 16 + rounded_length as usize + 4;
-        println!("curr_offset:{curr_offset}");
-        let mut streams = Vec::with_capacity(curr_offset);
+    pub(crate) fn new(version: &str, streams: Vec<MetadataStream>) -> Self {
+        Self {
+            major: 1,
+            minor: 1,
+            version: version.into(),
+            version_length: (version.len() as u32 + 4) & !3,
+            streams,
+            source: None,
+        }
+    }
+    /// The version of the runtime the metadata targets, such as `v4.0.30319`.
+    pub(crate) fn version(&self) -> &str {
+        &self.version
+    }
+    /// Encodes the metadata root, followed by all of its streams. Metadata which was read from a file is returned
+    /// exactly as it was read.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        if let Some(source) = &self.source {
+            return source.to_vec();
+        }
+        let mut version = self.version.as_bytes().to_vec();
+        version.resize(self.version_length as usize, 0);
+        let streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|stream| (stream.name(), stream.encode()))
+            .collect();
+        let stream_headers_size: usize = streams
+            .iter()
+            .map(|(name, _)| 8 + ((name.len() + 4) & !3))
+            .sum();
+        let mut metadata = Vec::new();
+        metadata.extend(0x424A5342_u32.to_le_bytes());
+        metadata.extend(self.major.to_le_bytes());
+        metadata.extend(self.minor.to_le_bytes());
+        metadata.extend(0_u32.to_le_bytes());
+        metadata.extend((version.len() as u32).to_le_bytes());
+        metadata.extend(version);
+        // Flags
+        metadata.extend(0_u16.to_le_bytes());
+        metadata.extend((streams.len() as u16).to_le_bytes());
+        let mut stream_offset = metadata.len() + stream_headers_size;
+        for (name, stream) in &streams {
+            metadata.extend((stream_offset as u32).to_le_bytes());
+            metadata.extend((stream.len() as u32).to_le_bytes());
+            let mut name = name.as_bytes().to_vec();
+            name.push(0);
+            pad_stream(&mut name);
+            metadata.extend(name);
+            stream_offset += stream.len();
+        }
+        for (_, stream) in streams {
+            metadata.extend(stream);
+        }
+        metadata
+    }
+    fn from_slice(metadata: &[u8]) -> Result<Self, AssemblyReadError> {
+        let err = |offset, kind| AssemblyReadError::Decode {
+            location: DecodeLocation::MetadataRoot,
+            offset,
+            kind,
+        };
+        let field_at = |offset| {
+            u32_from_slice_at(metadata, offset)
+                .ok_or_else(|| err(offset, DecodeErrorKind::UnexpectedEnd))
+        };
+        let magic = field_at(0)?;
+        if magic != 0x424A5342 {
+            return Err(err(
+                0,
+                DecodeErrorKind::InvalidValue {
+                    field: "Signature",
+                    value: magic as u64,
+                },
+            ));
+        }
+        let major =
+            u16_from_slice_at(metadata, 4).ok_or_else(|| err(4, DecodeErrorKind::UnexpectedEnd))?;
+        let minor =
+            u16_from_slice_at(metadata, 6).ok_or_else(|| err(6, DecodeErrorKind::UnexpectedEnd))?;
+        let reserved = field_at(8)?;
+        if reserved != 0 {
+            return Err(err(
+                8,
+                DecodeErrorKind::InvalidValue {
+                    field: "Reserved",
+                    value: reserved as u64,
+                },
+            ));
+        }
+        // The length already includes the null terminator and the padding after it.
+        let version_length = field_at(12)?;
+        let version = metadata
+            .get(16..(16 + version_length as usize))
+            .ok_or_else(|| err(16, DecodeErrorKind::UnexpectedEnd))?;
+        let null = version
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(version.len());
+        let version = std::str::from_utf8(&version[..null])
+            .map_err(|_| err(16, DecodeErrorKind::InvalidUtf8))?;
+        let flags_offset = 16 + version_length as usize;
+        let flags = u16_from_slice_at(metadata, flags_offset)
+            .ok_or_else(|| err(flags_offset, DecodeErrorKind::UnexpectedEnd))?;
+        if flags != 0 {
+            return Err(err(
+                flags_offset,
+                DecodeErrorKind::InvalidValue {
+                    field: "Flags",
+                    value: flags as u64,
+                },
+            ));
+        }
+        let stream_count = u16_from_slice_at(metadata, flags_offset + 2)
+            .ok_or_else(|| err(flags_offset + 2, DecodeErrorKind::UnexpectedEnd))?;
+        let mut curr_offset = flags_offset + 4;
+        let mut streams = Vec::with_capacity(stream_count as usize);
         for _ in 0..stream_count {
-            streams.push(MetadataStream::from_slice(metadata, &mut curr_offset));
+            streams.push(MetadataStream::from_slice(metadata, &mut curr_offset)?);
         }
-        RawMetadata {
+        Ok(RawMetadata {
             major,
             minor,
             version: version.into(),
+            version_length,
             streams,
+            source: Some(metadata.into()),
+        })
+    }
+}
+/// Runtime flags of the CLI header(`COMIMAGE_FLAGS_*`, II.25.3.3.1).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CLIFlags(u32);
+impl CLIFlags {
+    /// The image contains only CIL.
+    pub const IL_ONLY: Self = Self(0x1);
+    /// The image can only be loaded into a 32 bit process.
+    pub const REQUIRED_32BIT: Self = Self(0x2);
+    /// The image has a strong name signature.
+    pub const STRONG_NAME_SIGNED: Self = Self(0x8);
+    /// The entry point is an RVA of native code, rather than a metadata token.
+    pub const NATIVE_ENTRYPOINT: Self = Self(0x10);
+    pub const TRACK_DEBUG_DATA: Self = Self(0x1_0000);
+    /// The image prefers to run in a 32 bit process, but may run in a 64 bit one.
+    pub const PREFERRED_32BIT: Self = Self(0x2_0000);
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+    /// Checks if all flags set in `other` are also set in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+impl std::ops::BitOr for CLIFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+/// What gets called when an image is executed.
+#[derive(Copy, Clone, Debug)]
+pub enum EntryPoint {
+    /// The image can't be executed, which is usual for libraries.
+    None,
+    Method(MethodIndex),
+    /// The entry point is defined in another module of a multi-module assembly.
+    File(FileIndex),
+    /// Native code, for images with [`CLIFlags::NATIVE_ENTRYPOINT`] set.
+    Native(RVA),
+}
+impl EntryPoint {
+    fn decode(value: u32, flags: CLIFlags) -> Result<Self, DecodeErrorKind> {
+        if flags.contains(CLIFlags::NATIVE_ENTRYPOINT) {
+            return Ok(Self::Native(RVA(value as u64)));
+        }
+        let index = value & 0x00FF_FFFF;
+        match value >> 24 {
+            _ if value == 0 => Ok(Self::None),
+            0x06 => Ok(Self::Method(MethodIndex(index))),
+            0x26 => Ok(Self::File(FileIndex(index))),
+            _ => Err(DecodeErrorKind::InvalidValue {
+                field: "EntryPointToken",
+                value: value as u64,
+            }),
+        }
+    }
+    fn encode(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Method(MethodIndex(index)) => 0x0600_0000 | index,
+            Self::File(FileIndex(index)) => 0x2600_0000 | index,
+            Self::Native(rva) => rva.0 as u32,
         }
     }
 }
+/// The CLI header(II.25.3.3), which locates the metadata and the other runtime data of an image.
 #[derive(Debug)]
 pub struct CILHeader {
-    flags: u32,
-    entrypoint: u32,
-    native_resource_rva: u32,
-    native_resource_size: u32,
-    strong_name_rva: u32,
-    strong_name_size: u32,
-    vtable_fixups: u64,
+    /// The size of the header, as stored in its `cb` field.
+    size: u32,
+    runtime_major: u16,
+    runtime_minor: u16,
+    metadata: DataDirectory,
+    flags: CLIFlags,
+    entrypoint: EntryPoint,
+    /// Data of the managed resources embedded in the image.
+    resources: DataDirectory,
+    strong_name_signature: DataDirectory,
+    code_manager_table: DataDirectory,
+    vtable_fixups: DataDirectory,
+    export_address_table_jumps: DataDirectory,
+    managed_native_header: DataDirectory,
     raw_metadata: RawMetadata,
 }
-fn u128_from_slice_at(slice: &[u8], offset: usize) -> u128 {
-    let value: [u8; 16] = slice[offset..(offset + 16)].try_into().unwrap();
-    u128::from_le_bytes(value)
+/// Reads `N` bytes from the start of `slice` and advances it past them. `slice` is left untouched if it is too short,
+/// so that the offset of the failed read can be recovered from its length.
+pub(crate) fn take<const N: usize>(slice: &mut &[u8]) -> Result<[u8; N], DecodeErrorKind> {
+    let bytes: [u8; N] = slice
+        .get(..N)
+        .ok_or(DecodeErrorKind::UnexpectedEnd)?
+        .try_into()
+        .unwrap();
+    *slice = &slice[N..];
+    Ok(bytes)
+}
+pub(crate) fn take_u8(slice: &mut &[u8]) -> Result<u8, DecodeErrorKind> {
+    take::<1>(slice).map(|[byte]| byte)
+}
+pub(crate) fn take_u16(slice: &mut &[u8]) -> Result<u16, DecodeErrorKind> {
+    take(slice).map(u16::from_le_bytes)
+}
+pub(crate) fn take_u32(slice: &mut &[u8]) -> Result<u32, DecodeErrorKind> {
+    take(slice).map(u32::from_le_bytes)
 }
-pub fn u64_from_slice_at(slice: &[u8], offset: usize) -> u64 {
-    let value: [u8; 8] = slice[offset..(offset + 8)].try_into().unwrap();
-    u64::from_le_bytes(value)
+pub(crate) fn take_u64(slice: &mut &[u8]) -> Result<u64, DecodeErrorKind> {
+    take(slice).map(u64::from_le_bytes)
 }
-pub fn u32_from_slice_at(slice: &[u8], offset: usize) -> u32 {
-    let value: [u8; 4] = slice[offset..(offset + 4)].try_into().unwrap();
-    u32::from_le_bytes(value)
+pub fn u32_from_slice_at(slice: &[u8], offset: usize) -> Option<u32> {
+    let value: [u8; 4] = slice.get(offset..(offset + 4))?.try_into().unwrap();
+    Some(u32::from_le_bytes(value))
 }
-pub fn u16_from_slice_at(slice: &[u8], offset: usize) -> u16 {
-    let value: [u8; 2] = slice[offset..(offset + 2)].try_into().unwrap();
-    u16::from_le_bytes(value)
+pub fn u16_from_slice_at(slice: &[u8], offset: usize) -> Option<u16> {
+    let value: [u8; 2] = slice.get(offset..(offset + 2))?.try_into().unwrap();
+    Some(u16::from_le_bytes(value))
 }
+/// Size of the CLI header, as specified in II.25.3.3.
+pub(crate) const CIL_HEADER_SIZE: usize = 72;
 impl CILHeader {
-    fn read_from_pe(pe_file: &PEFile) -> Self {
-        let cli_header_rva: RVA = pe_file.pe_header().nt_header().cil_header();
-        let cil_header_size = pe_file.pe_header().nt_header().cil_header_size();
-        //let cli_header_rva = RVA(8192);
-        let cli_header = pe_file
-            .slice_at_rva(cli_header_rva, cil_header_size as u64)
-            .expect("CIL header has invalid RVA");
-        println!("cli_header:{cli_header:?}");
-        let cb = u32_from_slice_at(cli_header, 0);
-        let major_runtime = u16_from_slice_at(cli_header, 4);
-        let minor_runtime = u16_from_slice_at(cli_header, 6);
-        let metadata_rva = u32_from_slice_at(cli_header, 8);
-        //println!("metadata_rva:{metadata_rva}");
-        let metadata_size = u32_from_slice_at(cli_header, 8 + 4);
-        let flags = u32_from_slice_at(cli_header, 16);
-        let metadata = pe_file
-            .slice_at_rva(RVA(metadata_rva as u64), metadata_size as u64)
-            .expect("Metadata header has invalid RVA");
-        let entrypoint = u32_from_slice_at(cli_header, 20);
-        let native_resource_rva = u32_from_slice_at(cli_header, 24);
-        let native_resource_size = u32_from_slice_at(cli_header, 28);
-        let strong_name_rva = u32_from_slice_at(cli_header, 24);
-        let strong_name_size = u32_from_slice_at(cli_header, 28);
-        let code_manager_table = u64_from_slice_at(cli_header, 40);
-        assert_eq!(code_manager_table, 0);
-        let vtable_fixups = u64_from_slice_at(cli_header, 48);
-        let export_address_table_jumps = u64_from_slice_at(cli_header, 56);
-        assert_eq!(export_address_table_jumps, 0);
-        let managed_native_header = u64_from_slice_at(cli_header, 56);
-        assert_eq!(managed_native_header, 0);
-        let raw_metadata = RawMetadata::from_slice(metadata);
+    pub(crate) fn new(
+        flags: CLIFlags,
+        entrypoint: EntryPoint,
+        resources: DataDirectory,
+        raw_metadata: RawMetadata,
+    ) -> Self {
         Self {
+            size: CIL_HEADER_SIZE as u32,
+            runtime_major: 2,
+            runtime_minor: 5,
+            metadata: DataDirectory::default(),
             flags,
             entrypoint,
-            native_resource_rva,
-            native_resource_size,
-            strong_name_rva,
-            strong_name_size,
-            vtable_fixups,
+            resources,
+            strong_name_signature: DataDirectory::default(),
+            code_manager_table: DataDirectory::default(),
+            vtable_fixups: DataDirectory::default(),
+            export_address_table_jumps: DataDirectory::default(),
+            managed_native_header: DataDirectory::default(),
             raw_metadata,
         }
     }
-
-    pub fn raw_metadata(&self) -> &RawMetadata {
+    pub(crate) fn raw_metadata(&self) -> &RawMetadata {
         &self.raw_metadata
     }
+    /// Version of the runtime the image needs, as a `(major, minor)` pair.
+    pub fn runtime_version(&self) -> (u16, u16) {
+        (self.runtime_major, self.runtime_minor)
+    }
+    pub fn flags(&self) -> CLIFlags {
+        self.flags
+    }
+    pub fn entrypoint(&self) -> EntryPoint {
+        self.entrypoint
+    }
+    pub fn metadata(&self) -> DataDirectory {
+        self.metadata
+    }
+    pub fn resources(&self) -> DataDirectory {
+        self.resources
+    }
+    pub fn strong_name_signature(&self) -> DataDirectory {
+        self.strong_name_signature
+    }
+    /// Reserved, and always empty outside of precompiled images.
+    pub fn code_manager_table(&self) -> DataDirectory {
+        self.code_manager_table
+    }
+    pub fn vtable_fixups(&self) -> DataDirectory {
+        self.vtable_fixups
+    }
+    /// Reserved, and always empty outside of precompiled images.
+    pub fn export_address_table_jumps(&self) -> DataDirectory {
+        self.export_address_table_jumps
+    }
+    /// The ReadyToRun header of images precompiled by crossgen, empty for IL-only images.
+    pub fn managed_native_header(&self) -> DataDirectory {
+        self.managed_native_header
+    }
+    /// Encodes the header itself, with the metadata placed at `metadata`.
+    pub(crate) fn encode(&self, metadata: DataDirectory) -> Vec<u8> {
+        let mut header = Vec::with_capacity(CIL_HEADER_SIZE);
+        header.extend(self.size.to_le_bytes());
+        header.extend(self.runtime_major.to_le_bytes());
+        header.extend(self.runtime_minor.to_le_bytes());
+        for field in [
+            metadata.rva,
+            metadata.size,
+            self.flags.bits(),
+            self.entrypoint.encode(),
+            self.resources.rva,
+            self.resources.size,
+            self.strong_name_signature.rva,
+            self.strong_name_signature.size,
+            self.code_manager_table.rva,
+            self.code_manager_table.size,
+            self.vtable_fixups.rva,
+            self.vtable_fixups.size,
+            self.export_address_table_jumps.rva,
+            self.export_address_table_jumps.size,
+            self.managed_native_header.rva,
+            self.managed_native_header.size,
+        ] {
+            header.extend(field.to_le_bytes());
+        }
+        header
+    }
+    fn read_from_pe(pe_file: &PEFile) -> Result<Self, AssemblyReadError> {
+        let cli_header_rva: RVA = pe_file.pe_header().nt_header().cil_header();
+        let cil_header_size = pe_file.pe_header().nt_header().cil_header_size();
+        let cli_header = pe_file
+            .slice_at_rva(cli_header_rva, cil_header_size as u64)
+            .ok_or(AssemblyReadError::InvalidRVA {
+                location: DecodeLocation::CILHeader,
+                rva: cli_header_rva,
+                size: cil_header_size as u64,
+            })?;
+        Self::decode(&cli_header, |metadata| {
+            let metadata_rva = RVA(metadata.rva as u64);
+            let metadata_bytes = pe_file
+                .slice_at_rva(metadata_rva, metadata.size as u64)
+                .ok_or(AssemblyReadError::InvalidRVA {
+                    location: DecodeLocation::MetadataRoot,
+                    rva: metadata_rva,
+                    size: metadata.size as u64,
+                })?;
+            RawMetadata::from_slice(&metadata_bytes)
+        })
+    }
+    /// Decodes the header itself, reading the metadata it points to with `read_metadata`.
+    fn decode(
+        cli_header: &[u8],
+        read_metadata: impl FnOnce(DataDirectory) -> Result<RawMetadata, AssemblyReadError>,
+    ) -> Result<Self, AssemblyReadError> {
+        if cli_header.len() < CIL_HEADER_SIZE {
+            return Err(AssemblyReadError::Decode {
+                location: DecodeLocation::CILHeader,
+                offset: cli_header.len(),
+                kind: DecodeErrorKind::UnexpectedEnd,
+            });
+        }
+        // The length of the header was checked above, so none of those reads can fail.
+        let field = |offset| u32_from_slice_at(cli_header, offset).unwrap();
+        let directory = |offset| DataDirectory {
+            rva: field(offset),
+            size: field(offset + 4),
+        };
+        let metadata = directory(8);
+        let flags = CLIFlags::from_bits(field(16));
+        let entrypoint =
+            EntryPoint::decode(field(20), flags).map_err(|kind| AssemblyReadError::Decode {
+                location: DecodeLocation::CILHeader,
+                offset: 20,
+                kind,
+            })?;
+        let raw_metadata = read_metadata(metadata)?;
+        Ok(Self {
+            size: field(0),
+            runtime_major: u16_from_slice_at(cli_header, 4).unwrap(),
+            runtime_minor: u16_from_slice_at(cli_header, 6).unwrap(),
+            metadata,
+            flags,
+            entrypoint,
+            resources: directory(24),
+            strong_name_signature: directory(32),
+            code_manager_table: directory(40),
+            vtable_fixups: directory(48),
+            export_address_table_jumps: directory(56),
+            managed_native_header: directory(64),
+            raw_metadata,
+        })
+    }
 }
 pub struct EncodedAssembly {
     pe_file: PEFile,
     header: CILHeader,
+    layout: MetadataLayout,
 }
-pub fn get_blob(blob_heap: &[u8], pos: BlobIndex) -> &[u8] {
-    let ptr: &[u8] = &blob_heap[pos.0 as usize..];
-    if ptr[0] & 0x80 == 0 {
-        let size = (ptr[0] & 0x7f) as usize;
-        &ptr[1..(1 + size)]
-    } else if ptr[0] & 0x40 == 0 {
-        let size = ((ptr[0] & 0x3f) as usize) << 8 + ptr[1] as usize;
-        &ptr[2..(2 + size)]
-    } else {
-        let size = ((ptr[0] & 0x1f) as usize)
-            << 24 + ((ptr[1] as usize) << 16) + ((ptr[2] as usize) << 8) + ptr[3] as usize;
-        &ptr[4..(4 + size)]
-    }
-}
-pub fn decode_blob_compressed_value(blob_heap: &mut &[u8]) -> u32 {
-    if blob_heap[0] & 0x80 == 0 {
-        let value = (blob_heap[0] & 0x7f) as u32;
-        *blob_heap = &blob_heap[1..];
-        value
-    } else if blob_heap[0] & 0x40 == 0 {
-        let value = ((blob_heap[0] & 0x3f) as u32) << 8 + blob_heap[1] as u32;
-        *blob_heap = &blob_heap[2..];
-        value
-    } else {
-        let value = ((blob_heap[0] & 0x1f) as u32)
-            << 24
-                + ((blob_heap[1] as u32) << 16)
-                + ((blob_heap[2] as u32) << 8)
-                + blob_heap[3] as u32;
-        *blob_heap = &blob_heap[4..];
-        value
+/// Positions of the heaps and tables within the metadata, found once when an assembly is loaded. If a heap appears
+/// more than once, the first one is used.
+#[derive(Debug)]
+struct MetadataLayout {
+    strings: Option<usize>,
+    user_strings: Option<usize>,
+    blobs: Option<usize>,
+    guids: Option<usize>,
+    tables: usize,
+    /// Position of each present table within the table stream, by table number.
+    table_positions: [Option<usize>; 64],
+}
+impl MetadataLayout {
+    fn new(metadata: &RawMetadata) -> Result<Self, AssemblyReadError> {
+        let (mut strings, mut user_strings, mut blobs, mut guids, mut tables) =
+            (None, None, None, None, None);
+        for (position, stream) in metadata.streams.iter().enumerate() {
+            let slot = match stream {
+                MetadataStream::Strings(_) => &mut strings,
+                MetadataStream::US(_) => &mut user_strings,
+                MetadataStream::Blob(_) => &mut blobs,
+                MetadataStream::GUID(_) => &mut guids,
+                MetadataStream::LogicalMetadataTable { .. } => &mut tables,
+                MetadataStream::Unknown { .. } => continue,
+            };
+            slot.get_or_insert(position);
+        }
+        let tables = tables.ok_or_else(|| AssemblyReadError::missing_stream("#~"))?;
+        let MetadataStream::LogicalMetadataTable { encoded_tables, .. } = &metadata.streams[tables]
+        else {
+            unreachable!("`tables` is only set for table streams.")
+        };
+        let mut table_positions = [None; 64];
+        for (position, table) in encoded_tables.iter().enumerate() {
+            table_positions[table.table() as usize] = Some(position);
+        }
+        Ok(Self {
+            strings,
+            user_strings,
+            blobs,
+            guids,
+            tables,
+            table_positions,
+        })
+    }
+}
+pub fn get_blob(blob_heap: &[u8], pos: BlobIndex) -> Result<&[u8], DecodeErrorKind> {
+    let mut ptr: &[u8] = blob_heap
+        .get(pos.0 as usize..)
+        .ok_or(DecodeErrorKind::IndexOutOfRange(pos.0))?;
+    let size = decode_unsigned(&mut ptr)? as usize;
+    ptr.get(..size).ok_or(DecodeErrorKind::UnexpectedEnd)
+}
+/// Decodes the string at `index` of the `#US` heap. Returns it along with the offset of the entry following it.
+fn decode_user_string(
+    heap: &[u8],
+    index: UserStringIndex,
+) -> Result<(String, usize), DecodeErrorKind> {
+    let mut ptr: &[u8] = heap
+        .get(index.0 as usize..)
+        .ok_or(DecodeErrorKind::IndexOutOfRange(index.0))?;
+    let size = decode_unsigned(&mut ptr)? as usize;
+    let next = heap.len() - ptr.len() + size;
+    let bytes = ptr.get(..size).ok_or(DecodeErrorKind::UnexpectedEnd)?;
+    // The UTF-16 code units are followed by a single byte, flagging strings with special characters.
+    let units: Vec<u16> = bytes[..size & !1]
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    let string = String::from_utf16(&units).map_err(|_| DecodeErrorKind::InvalidUtf16)?;
+    Ok((string, next))
+}
+/// Appends `value` to `out` as an entry of the `#US` heap.
+pub(crate) fn encode_user_string(value: &str, out: &mut Vec<u8>) {
+    let units: Vec<u16> = value.encode_utf16().collect();
+    encode_unsigned(units.len() as u32 * 2 + 1, out);
+    let mut special = false;
+    for unit in units {
+        out.extend(unit.to_le_bytes());
+        // Characters which need more than ASCII handling when comparing strings, as listed in II.24.2.4.
+        special |= matches!(unit, 0x01..=0x08 | 0x0E..=0x1F | 0x27 | 0x2D | 0x7F | 0x100..);
     }
+    out.push(special as u8);
 }
 impl EncodedAssembly {
     pub fn pe_file(&self) -> &PEFile {
         &self.pe_file
     }
-    pub fn from_file(file: &mut (impl Read + Seek)) -> Result<Self, AssemblyReadError> {
-        let pe_file = PEFile::from_file(file)?;
-        let header = CILHeader::read_from_pe(&pe_file);
-        Ok(Self { pe_file, header })
+    pub fn cli_header(&self) -> &CILHeader {
+        &self.header
     }
-    pub fn str_at(&self, string_index: StringIndex) -> &str {
-        let slice = &self.string_stream()[(string_index.0 as usize)..];
-        let mut null = 0;
-        while slice[null] != 0 {
-            null += 1;
-        }
-        std::str::from_utf8(&slice[..null]).expect("Invalid asm string!")
+    pub fn from_file(
+        file: &mut (impl Read + Seek),
+        policy: HeaderPolicy,
+    ) -> Result<Self, AssemblyReadError> {
+        let pe_file = PEFile::from_file(file, policy)?;
+        let header = CILHeader::read_from_pe(&pe_file)?;
+        let layout = MetadataLayout::new(&header.raw_metadata)?;
+        Ok(Self {
+            pe_file,
+            header,
+            layout,
+        })
     }
-    pub fn string_stream(&self) -> &[u8] {
-        for stream in &self.header.raw_metadata.streams {
-            match stream {
-                MetadataStream::Strings(strings) => return &strings,
-                _ => (),
-            }
+    fn stream(&self, position: Option<usize>) -> Option<&MetadataStream> {
+        Some(&self.header.raw_metadata.streams[position?])
+    }
+    /// The table with the given number, or `None` if the assembly doesn't have it.
+    fn table(&self, table: u8) -> Option<&Table> {
+        let position = self.layout.table_positions[table as usize]?;
+        Some(&self.table_stream()[position])
+    }
+    /// Writes the assembly back as a PE file. The metadata and the CLI header are encoded again and placed where
+    /// they were read from, and everything else is copied over. The metadata keeps the bytes it was read from, and
+    /// the CLI header keeps all of its fields, so an unmodified assembly is reproduced exactly.
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let invalid_data = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let metadata = self.header.raw_metadata.encode();
+        let slot = self.header.metadata;
+        if metadata.len() > slot.size as usize {
+            return Err(invalid_data(
+                "the encoded metadata does not fit its original location",
+            ));
         }
-        panic!("No String stream!")
+        let mut pe_file = self.pe_file.clone();
+        let metadata_directory = DataDirectory {
+            size: metadata.len() as u32,
+            ..slot
+        };
+        let cli_header = self.header.encode(metadata_directory);
+        pe_file
+            .write_at_rva(RVA(slot.rva as u64), &metadata)
+            .and_then(|_| {
+                pe_file.write_at_rva(pe_file.pe_header().nt_header().cil_header(), &cli_header)
+            })
+            .ok_or_else(|| invalid_data("the CLI header or metadata lie outside of the image"))?;
+        pe_file.write_to(out)
     }
-    pub fn guid_stream(&self) -> &[u128] {
-        for stream in &self.header.raw_metadata.streams {
-            match stream {
-                MetadataStream::GUID(guid) => return &guid,
-                _ => (),
-            }
+    pub fn str_at(&self, string_index: StringIndex) -> Result<&str, AssemblyReadError> {
+        let err = |offset, kind| AssemblyReadError::Decode {
+            location: DecodeLocation::Stream("#Strings".into()),
+            offset,
+            kind,
+        };
+        let strings = self.string_stream()?;
+        let start = string_index.0 as usize;
+        let slice = strings
+            .get(start..)
+            .ok_or_else(|| err(start, DecodeErrorKind::IndexOutOfRange(string_index.0)))?;
+        let null = slice
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or_else(|| err(strings.len(), DecodeErrorKind::UnexpectedEnd))?;
+        std::str::from_utf8(&slice[..null])
+            .map_err(|error| err(start + error.valid_up_to(), DecodeErrorKind::InvalidUtf8))
+    }
+    pub fn string_stream(&self) -> Result<&[u8], AssemblyReadError> {
+        match self.stream(self.layout.strings) {
+            Some(MetadataStream::Strings(strings)) => Ok(strings),
+            _ => Err(AssemblyReadError::missing_stream("#Strings")),
         }
-        panic!("No GUID stream!")
     }
-    pub fn blob_stream(&self) -> &[u8] {
-        for stream in &self.header.raw_metadata.streams {
-            match stream {
-                MetadataStream::Blob(blob) => return &blob,
-                _ => (),
-            }
+    pub fn guid_stream(&self) -> Result<&[u128], AssemblyReadError> {
+        match self.stream(self.layout.guids) {
+            Some(MetadataStream::GUID(guids)) => Ok(guids),
+            _ => Err(AssemblyReadError::missing_stream("#GUID")),
         }
-        panic!("No Blob stream!")
     }
-    pub fn blob_at(&self, blob_index: BlobIndex) -> &[u8] {
-        get_blob(self.blob_stream(), blob_index)
+    pub fn blob_stream(&self) -> Result<&[u8], AssemblyReadError> {
+        match self.stream(self.layout.blobs) {
+            Some(MetadataStream::Blob(blob)) => Ok(blob),
+            _ => Err(AssemblyReadError::missing_stream("#Blob")),
+        }
     }
-    pub fn table_stream(&self) -> &[Table] {
-        for stream in &self.header.raw_metadata.streams {
-            match stream {
-                MetadataStream::LogicalMetadataTable(_, _, tables) => return &tables,
-                _ => (),
-            }
+    pub fn user_string_stream(&self) -> Result<&[u8], AssemblyReadError> {
+        match self.stream(self.layout.user_strings) {
+            Some(MetadataStream::US(user_strings)) => Ok(user_strings),
+            _ => Err(AssemblyReadError::missing_stream("#US")),
         }
-        panic!("No LogicalMetadataTable stream!")
     }
-    pub fn tables_rows(&self) -> (&[u32], BitVec64) {
-        for stream in &self.header.raw_metadata.streams {
-            match stream {
-                MetadataStream::LogicalMetadataTable(rows, tables, _) => return (&rows, *tables),
-                _ => (),
+    pub fn blob_at(&self, blob_index: BlobIndex) -> Result<&[u8], AssemblyReadError> {
+        get_blob(self.blob_stream()?, blob_index).map_err(|kind| AssemblyReadError::Decode {
+            location: DecodeLocation::Stream("#Blob".into()),
+            offset: blob_index.0 as usize,
+            kind,
+        })
+    }
+    /// The string literal with the given index, as loaded by `ldstr`.
+    pub fn user_string_at(&self, index: UserStringIndex) -> Result<String, AssemblyReadError> {
+        decode_user_string(self.user_string_stream()?, index)
+            .map(|(string, _)| string)
+            .map_err(|kind| AssemblyReadError::Decode {
+                location: DecodeLocation::Stream("#US".into()),
+                offset: index.0 as usize,
+                kind,
+            })
+    }
+    /// All string literals in the `#US` heap, in the order they are stored. Yields nothing if there is no such heap.
+    pub fn user_strings(
+        &self,
+    ) -> impl Iterator<Item = Result<(UserStringIndex, String), AssemblyReadError>> + '_ {
+        let heap = self.user_string_stream().unwrap_or_default();
+        // The entry at offset 0 is always the empty one.
+        let mut offset = Some(1);
+        std::iter::from_fn(move || loop {
+            let start = offset.filter(|start| *start < heap.len())?;
+            // Zeros are either padding, or empty entries which no token can refer to.
+            if heap[start] == 0 {
+                offset = Some(start + 1);
+                continue;
             }
+            let index = UserStringIndex(start as u32);
+            return match decode_user_string(heap, index) {
+                Ok((string, next)) => {
+                    offset = Some(next);
+                    Some(Ok((index, string)))
+                }
+                Err(kind) => {
+                    offset = None;
+                    Some(Err(AssemblyReadError::Decode {
+                        location: DecodeLocation::Stream("#US".into()),
+                        offset: start,
+                        kind,
+                    }))
+                }
+            };
+        })
+    }
+    pub fn table_stream(&self) -> &[Table] {
+        match &self.header.raw_metadata.streams[self.layout.tables] {
+            MetadataStream::LogicalMetadataTable { encoded_tables, .. } => encoded_tables,
+            _ => unreachable!("The layout only points at table streams."),
         }
-        panic!("No LogicalMetadataTable stream!")
     }
     pub fn methods(&self) -> &[MethodDef] {
-        for table in self.table_stream() {
-            match table {
-                Table::MethodDefTable(defs) => return &defs,
-                _ => (),
-            }
+        match self.table(0x06) {
+            Some(Table::MethodDefTable(defs)) => defs,
+            _ => &[],
         }
-        &[]
     }
-    pub fn field_len(&self) -> usize {
-        for table in self.table_stream() {
-            match table {
-                //Table::Fields(defs)=>return &defs,
-                _ => (),
-            }
+    pub fn type_defs(&self) -> &[TypeDef] {
+        match self.table(0x02) {
+            Some(Table::TypeDefTable(defs)) => defs,
+            _ => &[],
+        }
+    }
+    pub fn type_refs(&self) -> &[TypeRef] {
+        match self.table(0x01) {
+            Some(Table::TypeRefTable(refs)) => refs,
+            _ => &[],
+        }
+    }
+    pub fn fields(&self) -> &[Field] {
+        match self.table(0x04) {
+            Some(Table::Fields(fields)) => fields,
+            _ => &[],
+        }
+    }
+    pub fn params(&self) -> &[Param] {
+        match self.table(0x08) {
+            Some(Table::Param(params)) => params,
+            _ => &[],
+        }
+    }
+    pub fn field_ptrs(&self) -> &[FieldPtr] {
+        match self.table(0x03) {
+            Some(Table::FieldPtr(ptrs)) => ptrs,
+            _ => &[],
+        }
+    }
+    pub fn method_ptrs(&self) -> &[MethodPtr] {
+        match self.table(0x05) {
+            Some(Table::MethodPtr(ptrs)) => ptrs,
+            _ => &[],
+        }
+    }
+    pub fn param_ptrs(&self) -> &[ParamPtr] {
+        match self.table(0x07) {
+            Some(Table::ParamPtr(ptrs)) => ptrs,
+            _ => &[],
+        }
+    }
+    pub fn member_refs(&self) -> &[MemberRef] {
+        match self.table(0x0a) {
+            Some(Table::MemberRef(refs)) => refs,
+            _ => &[],
+        }
+    }
+    pub fn stand_alone_sigs(&self) -> &[BlobIndex] {
+        match self.table(0x11) {
+            Some(Table::StandAloneSig(sigs)) => sigs,
+            _ => &[],
+        }
+    }
+    pub fn type_specs(&self) -> &[TypeSpec] {
+        match self.table(0x1b) {
+            Some(Table::TypeSpec(specs)) => specs,
+            _ => &[],
+        }
+    }
+    pub fn assembly_refs(&self) -> &[AssemblyRef] {
+        match self.table(0x23) {
+            Some(Table::AssemblyRefs(refs)) => refs,
+            _ => &[],
+        }
+    }
+    pub fn manifest_resources(&self) -> &[ManifestResource] {
+        match self.table(0x28) {
+            Some(Table::ManifestResource(resources)) => resources,
+            _ => &[],
+        }
+    }
+    pub fn generic_params(&self) -> &[GenericParam] {
+        match self.table(0x2a) {
+            Some(Table::GenericParam(params)) => params,
+            _ => &[],
+        }
+    }
+    pub fn method_specs(&self) -> &[MethodSpec] {
+        match self.table(0x2b) {
+            Some(Table::MethodSpec(specs)) => specs,
+            _ => &[],
+        }
+    }
+    pub fn generic_param_constraints(&self) -> &[GenericParamConstraint] {
+        match self.table(0x2c) {
+            Some(Table::GenericParamConstraint(constraints)) => constraints,
+            _ => &[],
+        }
+    }
+    /// Data of the managed resource stored `offset` bytes into the CLI Resources directory, without its length prefix.
+    pub fn managed_resource_at(&self, offset: u32) -> Result<Cow<'_, [u8]>, AssemblyReadError> {
+        let directory = self.header.resources;
+        let err = |offset, kind| AssemblyReadError::Decode {
+            location: DecodeLocation::ManagedResources,
+            offset,
+            kind,
+        };
+        let rva = RVA(directory.rva as u64);
+        let resources = self
+            .pe_file
+            .slice_at_rva(rva, directory.size as u64)
+            .ok_or(AssemblyReadError::InvalidRVA {
+                location: DecodeLocation::ManagedResources,
+                rva,
+                size: directory.size as u64,
+            })?;
+        let start = offset as usize;
+        let length = u32_from_slice_at(&resources, start)
+            .ok_or_else(|| err(start, DecodeErrorKind::UnexpectedEnd))?;
+        let data_start = start + std::mem::size_of::<u32>();
+        let range = data_start..data_start + length as usize;
+        if range.end > resources.len() {
+            return Err(err(resources.len(), DecodeErrorKind::UnexpectedEnd));
+        }
+        Ok(match resources {
+            Cow::Borrowed(resources) => Cow::Borrowed(&resources[range]),
+            Cow::Owned(resources) => Cow::Owned(resources[range].to_vec()),
+        })
+    }
+    pub fn module_name(&self) -> Option<StringIndex> {
+        match self.table(0x00) {
+            Some(Table::Module { name, .. }) => Some(*name),
+            _ => None,
         }
-        0
     }
-    pub fn params_len(&self) -> usize {
-        for table in self.table_stream() {
-            match table {
-                Table::Param(params) => return params.len(),
-                _ => (),
+    pub fn module_mvid(&self) -> Option<u128> {
+        let Some(Table::Module { mvid, .. }) = self.table(0x00) else {
+            return None;
+        };
+        let index = mvid.0.checked_sub(1)?;
+        self.guid_stream().ok()?.get(index as usize).copied()
+    }
+    /// Name and version of the assembly defined by this file, if it has a manifest.
+    pub fn assembly_def(&self) -> Option<(StringIndex, (u16, u16, u16, u16))> {
+        match self.table(0x20) {
+            Some(Table::Assembly {
+                name,
+                major,
+                minor,
+                build_number,
+                revision_number,
+                ..
+            }) => Some((*name, (*major, *minor, *build_number, *revision_number))),
+            _ => None,
+        }
+    }
+}
+/// Part of an assembly which was being decoded when an error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeLocation {
+    CILHeader,
+    /// The metadata root, including the stream headers.
+    MetadataRoot,
+    /// A metadata stream, such as `#~` or `#Strings`.
+    Stream(Box<str>),
+    /// A row of a metadata table. Row 0 means the table as a whole.
+    Table {
+        table: u8,
+        name: &'static str,
+        row: u32,
+    },
+    /// The blob at the given index of the `#Blob` heap.
+    Blob(u32),
+    /// The method body at the given RVA.
+    MethodBody(RVA),
+    /// The Win32 resource directory.
+    Win32Resources,
+    /// The CLI Resources directory, holding embedded managed resources.
+    ManagedResources,
+}
+impl std::fmt::Display for DecodeLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CILHeader => write!(f, "CLI header"),
+            Self::MetadataRoot => write!(f, "metadata root"),
+            Self::Stream(name) => write!(f, "stream \"{name}\""),
+            Self::Table {
+                table,
+                name,
+                row: 0,
+            } => write!(f, "table {name}(0x{table:02x})"),
+            Self::Table { table, name, row } => {
+                write!(f, "row {row} of table {name}(0x{table:02x})")
             }
+            Self::Blob(index) => write!(f, "blob 0x{index:x}"),
+            Self::MethodBody(rva) => write!(f, "method body at RVA 0x{:x}", rva.0),
+            Self::Win32Resources => write!(f, "Win32 resource directory"),
+            Self::ManagedResources => write!(f, "managed resources"),
         }
-        0
     }
-
-    pub fn cil_header(&self) -> &CILHeader {
-        &self.header
+}
+/// The reason why decoding of a part of an assembly failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The data ended before the structure being decoded was complete.
+    UnexpectedEnd,
+    /// A field had a value which is not allowed by ECMA-335.
+    InvalidValue {
+        field: &'static str,
+        value: u64,
+    },
+    InvalidUtf8,
+    InvalidUtf16,
+    /// An index pointed outside of the heap or table it refers to.
+    IndexOutOfRange(u32),
+    /// A coded index had a tag which does not refer to any table.
+    InvalidCodedIndexTag {
+        coded_index: &'static str,
+        tag: u32,
+    },
+    UnknownTable(u8),
+    UnknownStream(Box<str>),
+    MissingStream(&'static str),
+    UnknownOpcode(u16),
+    UnknownElementType(u32),
+    /// Types were nested in each other deeper than this crate decodes.
+    NestedTooDeeply,
+    /// The data is valid, but uses a feature which can't be decoded yet.
+    Unsupported(&'static str),
+}
+impl std::fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Self::InvalidValue { field, value } => write!(f, "invalid {field} 0x{value:x}"),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            Self::InvalidUtf16 => write!(f, "invalid UTF-16"),
+            Self::IndexOutOfRange(index) => write!(f, "index 0x{index:x} is out of range"),
+            Self::InvalidCodedIndexTag { coded_index, tag } => {
+                write!(f, "invalid {coded_index} tag {tag}")
+            }
+            Self::UnknownTable(table) => write!(f, "unknown table 0x{table:02x}"),
+            Self::UnknownStream(name) => write!(f, "unknown stream \"{name}\""),
+            Self::MissingStream(name) => write!(f, "missing stream \"{name}\""),
+            Self::UnknownOpcode(opcode) => write!(f, "unknown opcode 0x{opcode:x}"),
+            Self::UnknownElementType(element) => write!(f, "unknown element type 0x{element:x}"),
+            Self::NestedTooDeeply => write!(f, "types nested too deeply"),
+            Self::Unsupported(feature) => write!(f, "{feature} are not supported"),
+        }
     }
 }
 #[derive(Debug)]
 pub enum AssemblyReadError {
     PEError(PEFileReadError),
+    /// A structure referenced by an RVA does not lie within any section of the image.
+    InvalidRVA {
+        location: DecodeLocation,
+        rva: RVA,
+        size: u64,
+    },
+    /// Decoding failed `offset` bytes from the start of `location`. For table rows, the offset is relative to the
+    /// start of the `#~` stream.
+    Decode {
+        location: DecodeLocation,
+        offset: usize,
+        kind: DecodeErrorKind,
+    },
+}
+impl AssemblyReadError {
+    fn missing_stream(name: &'static str) -> Self {
+        Self::Decode {
+            location: DecodeLocation::MetadataRoot,
+            offset: 0,
+            kind: DecodeErrorKind::MissingStream(name),
+        }
+    }
+}
+impl std::fmt::Display for AssemblyReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PEError(error) => write!(f, "{error}"),
+            Self::InvalidRVA {
+                location,
+                rva,
+                size,
+            } => write!(
+                f,
+                "{location} at RVA 0x{:x} with size 0x{size:x} is outside of the image",
+                rva.0
+            ),
+            Self::Decode {
+                location,
+                offset,
+                kind,
+            } => write!(f, "{kind} in {location} at offset 0x{offset:x}"),
+        }
+    }
 }
+impl std::error::Error for AssemblyReadError {}
 impl From<PEFileReadError> for AssemblyReadError {
     fn from(value: PEFileReadError) -> Self {
         Self::PEError(value)
     }
 }
+/// Why a method body or an assembly could not be encoded.
+#[derive(Debug)]
+pub enum EncodeError {
+    IOError(std::io::Error),
+    /// A branch or exception clause refers to a label which no [`CILOp::Label`](crate::CILOp::Label) defines.
+    UndefinedLabel(Label),
+    /// More than one [`CILOp::Label`](crate::CILOp::Label) defines the same label.
+    DuplicateLabel(Label),
+    /// A protected region or handler of an exception clause ends before it starts.
+    InvalidRegion {
+        start: Label,
+        end: Label,
+    },
+    /// An op calls a method which was not added to the assembly.
+    UnknownMethod(MethodToken),
+    /// A `calli` refers to a signature which was not added to the assembly.
+    UnknownSignature(StandAloneSigIndex),
+    /// The signature of something called is not a valid method signature.
+    InvalidSignature(CallSite, DecodeErrorKind),
+    /// A generic parameter constraint refers to a parameter which was not added to the assembly.
+    UnknownGenericParam(GenericParamIndex),
+}
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IOError(error) => write!(f, "IO error: {error}"),
+            Self::UndefinedLabel(label) => write!(f, "label {} is not defined", label.0),
+            Self::DuplicateLabel(label) => write!(f, "label {} is defined more than once", label.0),
+            Self::InvalidRegion { start, end } => write!(
+                f,
+                "exception clause region ends at label {} before it starts at label {}",
+                end.0, start.0
+            ),
+            Self::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            Self::UnknownSignature(StandAloneSigIndex(index)) => {
+                write!(f, "unknown stand-alone signature {index}")
+            }
+            Self::InvalidSignature(site, kind) => {
+                write!(f, "invalid signature of call site {site:?}: {kind}")
+            }
+            Self::UnknownGenericParam(GenericParamIndex(index)) => {
+                write!(f, "unknown generic parameter {index}")
+            }
+        }
+    }
+}
+impl std::error::Error for EncodeError {}
+impl From<std::io::Error> for EncodeError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IOError(value)
+    }
+}
+/// Name of a metadata table, as used by ECMA-335.
+pub(crate) fn table_name(table: u8) -> &'static str {
+    match table {
+        0x00 => "Module",
+        0x01 => "TypeRef",
+        0x02 => "TypeDef",
+        0x03 => "FieldPtr",
+        0x04 => "Field",
+        0x05 => "MethodPtr",
+        0x06 => "MethodDef",
+        0x07 => "ParamPtr",
+        0x08 => "Param",
+        0x09 => "InterfaceImpl",
+        0x0A => "MemberRef",
+        0x0B => "Constant",
+        0x0C => "CustomAttribute",
+        0x0D => "FieldMarshal",
+        0x0E => "DeclSecurity",
+        0x0F => "ClassLayout",
+        0x10 => "FieldLayout",
+        0x11 => "StandAloneSig",
+        0x12 => "EventMap",
+        0x13 => "EventPtr",
+        0x14 => "Event",
+        0x15 => "PropertyMap",
+        0x16 => "PropertyPtr",
+        0x17 => "Property",
+        0x18 => "MethodSemantics",
+        0x19 => "MethodImpl",
+        0x1A => "ModuleRef",
+        0x1B => "TypeSpec",
+        0x1C => "ImplMap",
+        0x1D => "FieldRVA",
+        0x1E => "EncLog",
+        0x1F => "EncMap",
+        0x20 => "Assembly",
+        0x21 => "AssemblyProcessor",
+        0x22 => "AssemblyOS",
+        0x23 => "AssemblyRef",
+        0x24 => "AssemblyRefProcessor",
+        0x25 => "AssemblyRefOS",
+        0x26 => "File",
+        0x27 => "ExportedType",
+        0x28 => "ManifestResource",
+        0x29 => "NestedClass",
+        0x2A => "GenericParam",
+        0x2B => "MethodSpec",
+        0x2C => "GenericParamConstraint",
+        _ => "Unknown",
+    }
+}
+#[test]
+fn truncated_table_row() {
+    let mut stream = vec![0, 0, 0, 0, 2, 0, 0, 1];
+    // Only the Param table is present, and it has 2 rows.
+    stream.extend(0x100_u64.to_le_bytes());
+    stream.extend(0_u64.to_le_bytes());
+    stream.extend(2_u32.to_le_bytes());
+    stream.extend([0, 0, 1, 0, 1, 0]);
+    stream.extend([0, 0, 2]);
+    let Err(AssemblyReadError::Decode {
+        location,
+        offset,
+        kind,
+    }) = MetadataStream::logical_metadata_table(&stream, true)
+    else {
+        panic!("A truncated table must not decode");
+    };
+    assert_eq!(
+        location,
+        DecodeLocation::Table {
+            table: 0x8,
+            name: "Param",
+            row: 2
+        }
+    );
+    // The flags of the second row are complete, the sequence number is not.
+    assert_eq!(offset, 36);
+    assert_eq!(kind, DecodeErrorKind::UnexpectedEnd);
+}
+#[test]
+fn invalid_metadata_root() {
+    let Err(AssemblyReadError::Decode { location, kind, .. }) =
+        RawMetadata::from_slice(b"BSJA\x01\x00\x01\x00\x00\x00\x00\x00")
+    else {
+        panic!("Metadata with a wrong signature must not decode");
+    };
+    assert_eq!(location, DecodeLocation::MetadataRoot);
+    assert_eq!(
+        kind,
+        DecodeErrorKind::InvalidValue {
+            field: "Signature",
+            value: 0x414A5342
+        }
+    );
+    let Err(AssemblyReadError::Decode { offset, kind, .. }) = RawMetadata::from_slice(b"BSJB")
+    else {
+        panic!("Truncated metadata must not decode");
+    };
+    assert_eq!((offset, kind), (4, DecodeErrorKind::UnexpectedEnd));
+}
+#[test]
+fn truncated_blob() {
+    assert_eq!(
+        get_blob(&[0, 0x81], BlobIndex(1)),
+        Err(DecodeErrorKind::UnexpectedEnd)
+    );
+    assert_eq!(
+        get_blob(&[0, 0x3], BlobIndex(1)),
+        Err(DecodeErrorKind::UnexpectedEnd)
+    );
+    assert_eq!(
+        get_blob(&[0], BlobIndex(2)),
+        Err(DecodeErrorKind::IndexOutOfRange(2))
+    );
+}
+#[test]
+fn nested_class_and_constraint_tables() {
+    let mut stream = vec![0, 0, 0, 0, 2, 0, 0, 1];
+    // NestedClass and GenericParamConstraint, with a single row each.
+    stream.extend((1_u64 << 0x29 | 1 << 0x2C).to_le_bytes());
+    stream.extend(SORTED_TABLES.to_le_bytes());
+    stream.extend(1_u32.to_le_bytes());
+    stream.extend(1_u32.to_le_bytes());
+    stream.extend([2, 0, 1, 0]);
+    stream.extend([1, 0, 0x5, 0]);
+    let decoded = MetadataStream::logical_metadata_table(&stream, true).unwrap();
+    let MetadataStream::LogicalMetadataTable { encoded_tables, .. } = &decoded else {
+        panic!("Expected a table stream");
+    };
+    assert!(matches!(
+        &encoded_tables[..],
+        [Table::NestedClass(_), Table::GenericParamConstraint(_)]
+    ));
+    assert_eq!(decoded.encode(), stream);
+}
+#[test]
+fn metadata_root_streams() {
+    // An uncompressed table stream with extra data, holding just the Module table.
+    let mut tables = vec![0, 0, 0, 0, 2, 0, EXTRA_DATA, 1];
+    tables.extend(1_u64.to_le_bytes());
+    tables.extend(0_u64.to_le_bytes());
+    tables.extend(1_u32.to_le_bytes());
+    tables.extend(0xABCD_u32.to_le_bytes());
+    tables.extend([0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    let tables = MetadataStream::logical_metadata_table(&tables, false).unwrap();
+    let streams = vec![
+        MetadataStream::Unknown {
+            name: "#Pdb".into(),
+            data: [1, 2, 3, 4].into(),
+        },
+        MetadataStream::strings(b"\0Mod".to_vec()),
+        tables,
+        MetadataStream::guids(vec![7]),
+    ];
+    let mut encoded = RawMetadata::new("v4.0.30319", streams).encode();
+    // Version length, padded from 11 to 12 bytes.
+    assert_eq!(encoded[12..16], 12_u32.to_le_bytes());
+    let decoded = RawMetadata::from_slice(&encoded).unwrap();
+    assert_eq!(decoded.version(), "v4.0.30319");
+    let names: Vec<_> = decoded.streams.iter().map(MetadataStream::name).collect();
+    assert_eq!(names, ["#Pdb", "#Strings", "#-", "#GUID"]);
+    assert!(matches!(
+        &decoded.streams[2],
+        MetadataStream::LogicalMetadataTable {
+            compressed: false,
+            extra_data: Some(0xABCD),
+            ..
+        }
+    ));
+    assert_eq!(decoded.encode(), encoded);
+    // Both 4 byte streams swap places in the file, but keep their headers.
+    let pdb_header = 16 + 12 + 4;
+    let strings_header = pdb_header + 8 + 8;
+    let pdb_offset: [u8; 4] = encoded[pdb_header..pdb_header + 4].try_into().unwrap();
+    encoded.copy_within(strings_header..strings_header + 4, pdb_header);
+    encoded[strings_header..strings_header + 4].copy_from_slice(&pdb_offset);
+    // Slack after the last stream, which this crate never writes.
+    encoded.extend([0xCC; 8]);
+    let decoded = RawMetadata::from_slice(&encoded).unwrap();
+    assert!(matches!(
+        &decoded.streams[..2],
+        [MetadataStream::Unknown { data, .. }, MetadataStream::Strings(strings)]
+            if &data[..] == b"\0Mod" && strings[..] == [1, 2, 3, 4]
+    ));
+    // The stream order and the slack are kept, rather than laid out again.
+    assert_eq!(decoded.encode(), encoded);
+}
+#[test]
+fn edit_and_continue_module() {
+    // An uncompressed table stream holding a Module of the third generation, with both EnC ids set.
+    let mut stream = vec![0, 0, 0, 0, 2, 0, 0, 1];
+    stream.extend(1_u64.to_le_bytes());
+    stream.extend(0_u64.to_le_bytes());
+    stream.extend(1_u32.to_le_bytes());
+    stream.extend([3, 0, 1, 0, 1, 0, 2, 0, 3, 0, 0, 0]);
+    let decoded = MetadataStream::logical_metadata_table(&stream, false).unwrap();
+    let MetadataStream::LogicalMetadataTable { encoded_tables, .. } = &decoded else {
+        panic!("Expected a table stream");
+    };
+    assert!(matches!(
+        encoded_tables[..],
+        [Table::Module {
+            generation: 3,
+            enc_id: GUIDIndex(2),
+            enc_base_id: GUIDIndex(3),
+            ..
+        }]
+    ));
+    assert_eq!(decoded.encode(), stream);
+}
+#[test]
+fn metadata_layout() {
+    let tables = vec![
+        Table::Module {
+            generation: 0,
+            name: StringIndex(1),
+            mvid: GUIDIndex(0),
+            enc_id: GUIDIndex(0),
+            enc_base_id: GUIDIndex(0),
+        },
+        Table::AssemblyRefs([].into()),
+    ];
+    let streams = vec![
+        MetadataStream::strings(b"\0Mod\0".to_vec()),
+        MetadataStream::strings(b"\0Other\0".to_vec()),
+        MetadataStream::tables(tables, HeapSizes::new(0, 0, 0)),
+    ];
+    let layout = MetadataLayout::new(&RawMetadata::new("v4.0.30319", streams)).unwrap();
+    assert_eq!(layout.strings, Some(0));
+    assert_eq!(
+        (layout.blobs, layout.user_strings, layout.guids),
+        (None, None, None)
+    );
+    assert_eq!(layout.tables, 2);
+    assert_eq!(layout.table_positions[0x00], Some(0));
+    assert_eq!(layout.table_positions[0x23], Some(1));
+    assert_eq!(layout.table_positions[0x02], None);
+    let streams = vec![MetadataStream::strings(vec![0])];
+    let Err(AssemblyReadError::Decode { kind, .. }) =
+        MetadataLayout::new(&RawMetadata::new("v4.0.30319", streams))
+    else {
+        panic!("Metadata without tables must not load");
+    };
+    assert_eq!(kind, DecodeErrorKind::MissingStream("#~"));
+}
+#[test]
+fn user_string_round_trip() {
+    let mut heap = vec![0];
+    encode_user_string("Hello", &mut heap);
+    let special = heap.len();
+    encode_user_string("it's \u{00e9}\u{1F600}", &mut heap);
+    let empty = heap.len();
+    encode_user_string("", &mut heap);
+    assert_eq!(heap[1..13], *b"\x0bH\0e\0l\0l\0o\0\0");
+    assert_eq!(heap[special - 1], 0);
+    assert_eq!(heap[empty - 1], 1);
+    assert_eq!(heap[empty..], [1, 0]);
+    let (hello, next) = decode_user_string(&heap, UserStringIndex(1)).unwrap();
+    assert_eq!((hello.as_str(), next), ("Hello", special));
+    let (string, next) = decode_user_string(&heap, UserStringIndex(special as u32)).unwrap();
+    assert_eq!((string.as_str(), next), ("it's \u{00e9}\u{1F600}", empty));
+    assert_eq!(
+        decode_user_string(&heap, UserStringIndex(empty as u32)).unwrap(),
+        (String::new(), heap.len())
+    );
+    // A lone surrogate.
+    assert_eq!(
+        decode_user_string(&[0, 3, 0x00, 0xD8, 1], UserStringIndex(1)),
+        Err(DecodeErrorKind::InvalidUtf16)
+    );
+}
+#[test]
+fn cli_header() {
+    let mut bytes = Vec::new();
+    // cb, followed by runtime version 2.5.
+    bytes.extend([72, 0, 0, 0, 2, 0, 5, 0]);
+    for field in [
+        // Metadata, flags, and the second method as the entry point.
+        0x2050,
+        0x100,
+        CLIFlags::IL_ONLY.bits(),
+        0x0600_0002,
+        // Resources, StrongNameSignature and CodeManagerTable.
+        0,
+        0,
+        0,
+        0,
+        0x20,
+        0,
+        // VTableFixups, ExportAddressTableJumps, and a ReadyToRun header as written by crossgen.
+        0,
+        0,
+        0,
+        0,
+        0x3000,
+        0x10,
+    ] {
+        bytes.extend(u32::to_le_bytes(field));
+    }
+    let read_metadata = |metadata: DataDirectory| {
+        assert_eq!((metadata.rva, metadata.size), (0x2050, 0x100));
+        Ok(RawMetadata::new("v4.0.30319", Vec::new()))
+    };
+    let header = CILHeader::decode(&bytes, read_metadata).unwrap();
+    assert_eq!(header.runtime_version(), (2, 5));
+    assert_eq!(header.flags(), CLIFlags::IL_ONLY);
+    assert!(!header
+        .flags()
+        .contains(CLIFlags::IL_ONLY | CLIFlags::REQUIRED_32BIT));
+    assert!(matches!(
+        header.entrypoint(),
+        EntryPoint::Method(MethodIndex(2))
+    ));
+    assert!(header.strong_name_signature().rva == 0 && header.vtable_fixups().rva == 0);
+    // The reserved and ReadyToRun directories are kept rather than rejected.
+    assert_eq!(header.code_manager_table().rva, 0x20);
+    assert_eq!(header.export_address_table_jumps().size, 0);
+    assert_eq!(header.managed_native_header().rva, 0x3000);
+    assert_eq!(header.managed_native_header().size, 0x10);
+    assert_eq!(header.encode(header.metadata()), bytes);
+    let mut strong_name = bytes.clone();
+    strong_name[16] |= 0x8;
+    strong_name[36] = 0x80;
+    let header = CILHeader::decode(&strong_name, read_metadata).unwrap();
+    assert!(header.flags().contains(CLIFlags::STRONG_NAME_SIGNED));
+    assert_eq!(header.strong_name_signature().size, 0x80);
+    assert_eq!(header.resources().size, 0);
+    let Err(AssemblyReadError::Decode {
+        location, offset, ..
+    }) = CILHeader::decode(&bytes[..71], read_metadata)
+    else {
+        panic!("A truncated CLI header must not decode");
+    };
+    assert_eq!((location, offset), (DecodeLocation::CILHeader, 71));
+}
@@ -5,6 +5,11 @@ impl From<u64> for BitVec64 {
         Self(value)
     }
 }
+impl From<BitVec64> for u64 {
+    fn from(value: BitVec64) -> Self {
+        value.0
+    }
+}
 impl BitVec64 {
     pub fn bit(self, bit: u8) -> bool {
         (self.0 >> bit) & 1 != 0
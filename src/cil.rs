@@ -0,0 +1,1583 @@
+use std::collections::HashMap;
+
+use crate::{
+    assembly::{
+        take_u16, take_u32, take_u64, take_u8, DecodeErrorKind, EncodeError, UserStringIndex,
+    },
+    coded_index::CodedIndex,
+    field::FieldIndex,
+    method::{MemberRefIndex, MethodIndex, StandAloneSigIndex},
+    r#type::Type,
+    signature::Signature,
+    table::MethodSpecIndex,
+    type_def::{TypeDefIndex, TypeDefOrRef, TypeRefIndex, TypeSpecIndex},
+};
+/// A CIL instruction, as described in Partition III.
+#[derive(Clone, Debug)]
+pub enum CILOp {
+    /// Marks the position of a label, which branches and exception clauses refer to. Takes up no
+    /// space in the encoded code.
+    Label(Label),
+    Nop,
+    Break,
+    /// `ldarg.0`. The operand-less forms of `ldarg`, `ldloc` and `stloc` have their own variants.
+    LDArg0,
+    LDArg1,
+    LDArg2,
+    LDArg3,
+    LDLoc0,
+    LDLoc1,
+    LDLoc2,
+    LDLoc3,
+    STLoc0,
+    STLoc1,
+    STLoc2,
+    STLoc3,
+    /// `ldarg` and `ldarg.s`. Short forms are decoded into the variant of their long form.
+    LDArg(u16),
+    LDArgA(u16),
+    STArg(u16),
+    LDLoc(u16),
+    LDLocA(u16),
+    STLoc(u16),
+    LDNull,
+    /// Every form of `ldc.i4`, including `ldc.i4.s` and `ldc.i4.0` to `ldc.i4.8`.
+    LDCI4(i32),
+    LDCI8(i64),
+    LDCR4(f32),
+    LDCR8(f64),
+    Dup,
+    Pop,
+    Jmp(MethodToken),
+    Call(MethodToken),
+    CallI(StandAloneSigIndex),
+    Ret,
+    /// Short forms of branches are picked when encoding.
+    Br(Label),
+    BrFalse(Label),
+    BrTrue(Label),
+    BEq(Label),
+    BGe(Label),
+    BGt(Label),
+    BLe(Label),
+    BLt(Label),
+    BNeUn(Label),
+    BGeUn(Label),
+    BGtUn(Label),
+    BLeUn(Label),
+    BLtUn(Label),
+    /// Jumps to the target selected by the value on top of the stack.
+    Switch(Box<[Label]>),
+    LDIndI1,
+    LDIndU1,
+    LDIndI2,
+    LDIndU2,
+    LDIndI4,
+    LDIndU4,
+    LDIndI8,
+    LDIndI,
+    LDIndR4,
+    LDIndR8,
+    LDIndRef,
+    STIndRef,
+    STIndI1,
+    STIndI2,
+    STIndI4,
+    STIndI8,
+    STIndR4,
+    STIndR8,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    DivUn,
+    Rem,
+    RemUn,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    ShrUn,
+    Neg,
+    Not,
+    ConvI1,
+    ConvI2,
+    ConvI4,
+    ConvI8,
+    ConvR4,
+    ConvR8,
+    ConvU4,
+    ConvU8,
+    CallVirt(MethodToken),
+    CpObj(TypeDefOrRef),
+    LDObj(TypeDefOrRef),
+    LDStr(UserStringIndex),
+    NewObj(MethodToken),
+    CastClass(TypeDefOrRef),
+    IsInst(TypeDefOrRef),
+    ConvRUn,
+    Unbox(TypeDefOrRef),
+    Throw,
+    LDFld(FieldToken),
+    LDFldA(FieldToken),
+    STFld(FieldToken),
+    LDSFld(FieldToken),
+    LDSFldA(FieldToken),
+    STSFld(FieldToken),
+    STObj(TypeDefOrRef),
+    ConvOvfI1Un,
+    ConvOvfI2Un,
+    ConvOvfI4Un,
+    ConvOvfI8Un,
+    ConvOvfU1Un,
+    ConvOvfU2Un,
+    ConvOvfU4Un,
+    ConvOvfU8Un,
+    ConvOvfIUn,
+    ConvOvfUUn,
+    Box(TypeDefOrRef),
+    NewArr(TypeDefOrRef),
+    LDLen,
+    LDElemA(TypeDefOrRef),
+    LDElemI1,
+    LDElemU1,
+    LDElemI2,
+    LDElemU2,
+    LDElemI4,
+    LDElemU4,
+    LDElemI8,
+    LDElemI,
+    LDElemR4,
+    LDElemR8,
+    LDElemRef,
+    STElemI,
+    STElemI1,
+    STElemI2,
+    STElemI4,
+    STElemI8,
+    STElemR4,
+    STElemR8,
+    STElemRef,
+    LDElem(TypeDefOrRef),
+    STElem(TypeDefOrRef),
+    UnboxAny(TypeDefOrRef),
+    ConvOvfI1,
+    ConvOvfU1,
+    ConvOvfI2,
+    ConvOvfU2,
+    ConvOvfI4,
+    ConvOvfU4,
+    ConvOvfI8,
+    ConvOvfU8,
+    RefAnyVal(TypeDefOrRef),
+    CkFinite,
+    MkRefAny(TypeDefOrRef),
+    /// Loads the runtime handle of a type, method or field.
+    LDToken(MetadataToken),
+    ConvU2,
+    ConvU1,
+    ConvI,
+    ConvOvfI,
+    ConvOvfU,
+    AddOvf,
+    AddOvfUn,
+    MulOvf,
+    MulOvfUn,
+    SubOvf,
+    SubOvfUn,
+    EndFinally,
+    Leave(Label),
+    STIndI,
+    ConvU,
+    ArgList,
+    CEq,
+    CGt,
+    CGtUn,
+    CLt,
+    CLtUn,
+    LDFtn(MethodToken),
+    LDVirtFtn(MethodToken),
+    LocAlloc,
+    EndFilter,
+    /// `unaligned.` prefix, with the alignment the next access may assume.
+    Unaligned(u8),
+    /// `volatile.` prefix.
+    Volatile,
+    /// `tail.` prefix.
+    Tail,
+    InitObj(TypeDefOrRef),
+    /// `constrained.` prefix of a `callvirt`.
+    Constrained(TypeDefOrRef),
+    CpBlk,
+    InitBlk,
+    /// `no.` prefix, with the checks the runtime may skip.
+    No(u8),
+    Rethrow,
+    SizeOf(TypeDefOrRef),
+    RefAnyType,
+    /// `readonly.` prefix of a `ldelema`.
+    Readonly,
+}
+/// A position in the code of a method, defined by a [`CILOp::Label`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Label(pub u32);
+/// A method an instruction refers to.
+#[derive(Copy, Clone, Debug)]
+pub enum MethodToken {
+    MethodDef(MethodIndex),
+    MemberRef(MemberRefIndex),
+    MethodSpec(MethodSpecIndex),
+}
+impl MethodToken {
+    fn from_token(token: u32) -> Option<Self> {
+        let index = token & 0x00FF_FFFF;
+        match token >> 24 {
+            0x06 => Some(Self::MethodDef(MethodIndex(index))),
+            0x0A => Some(Self::MemberRef(MemberRefIndex(index))),
+            0x2B => Some(Self::MethodSpec(MethodSpecIndex(index))),
+            _ => None,
+        }
+    }
+    fn token(self) -> u32 {
+        match self {
+            Self::MethodDef(MethodIndex(index)) => 0x0600_0000 | index,
+            Self::MemberRef(MemberRefIndex(index)) => 0x0A00_0000 | index,
+            Self::MethodSpec(MethodSpecIndex(index)) => 0x2B00_0000 | index,
+        }
+    }
+}
+/// A field an instruction refers to.
+#[derive(Copy, Clone, Debug)]
+pub enum FieldToken {
+    Field(FieldIndex),
+    MemberRef(MemberRefIndex),
+}
+impl FieldToken {
+    fn from_token(token: u32) -> Option<Self> {
+        let index = token & 0x00FF_FFFF;
+        match token >> 24 {
+            0x04 => Some(Self::Field(FieldIndex(index))),
+            0x0A => Some(Self::MemberRef(MemberRefIndex(index))),
+            _ => None,
+        }
+    }
+    fn token(self) -> u32 {
+        match self {
+            Self::Field(FieldIndex(index)) => 0x0400_0000 | index,
+            Self::MemberRef(MemberRefIndex(index)) => 0x0A00_0000 | index,
+        }
+    }
+}
+/// The operand of `ldtoken`, which may be a type, a method or a field.
+#[derive(Copy, Clone, Debug)]
+pub enum MetadataToken {
+    TypeDef(TypeDefIndex),
+    TypeRef(TypeRefIndex),
+    TypeSpec(TypeSpecIndex),
+    Field(FieldIndex),
+    MethodDef(MethodIndex),
+    MemberRef(MemberRefIndex),
+    MethodSpec(MethodSpecIndex),
+}
+impl MetadataToken {
+    fn from_token(token: u32) -> Option<Self> {
+        let index = token & 0x00FF_FFFF;
+        match token >> 24 {
+            0x02 => Some(Self::TypeDef(TypeDefIndex(index))),
+            0x01 => Some(Self::TypeRef(TypeRefIndex(index))),
+            0x1B => Some(Self::TypeSpec(TypeSpecIndex(index))),
+            0x04 => Some(Self::Field(FieldIndex(index))),
+            0x06 => Some(Self::MethodDef(MethodIndex(index))),
+            0x0A => Some(Self::MemberRef(MemberRefIndex(index))),
+            0x2B => Some(Self::MethodSpec(MethodSpecIndex(index))),
+            _ => None,
+        }
+    }
+    fn token(self) -> u32 {
+        match self {
+            Self::TypeDef(TypeDefIndex(index)) => 0x0200_0000 | index,
+            Self::TypeRef(TypeRefIndex(index)) => 0x0100_0000 | index,
+            Self::TypeSpec(TypeSpecIndex(index)) => 0x1B00_0000 | index,
+            Self::Field(FieldIndex(index)) => 0x0400_0000 | index,
+            Self::MethodDef(MethodIndex(index)) => 0x0600_0000 | index,
+            Self::MemberRef(MemberRefIndex(index)) => 0x0A00_0000 | index,
+            Self::MethodSpec(MethodSpecIndex(index)) => 0x2B00_0000 | index,
+        }
+    }
+}
+impl StandAloneSigIndex {
+    fn from_token(token: u32) -> Option<Self> {
+        (token >> 24 == 0x11).then_some(Self(token & 0x00FF_FFFF))
+    }
+    fn token(self) -> u32 {
+        0x1100_0000 | self.0
+    }
+}
+impl UserStringIndex {
+    fn from_token(token: u32) -> Option<Self> {
+        (token >> 24 == 0x70).then_some(Self(token & 0x00FF_FFFF))
+    }
+    fn token(self) -> u32 {
+        0x7000_0000 | self.0
+    }
+}
+/// Decodes the IL code of a method. Every branch target, and every offset in `region_offsets`,
+/// gets a label, numbered in the order of their offsets. Returns the ops, and the offset of each
+/// label. Errors come with the offset of the instruction or region which caused them.
+pub(crate) fn decode_ops(
+    code: &[u8],
+    region_offsets: &[u32],
+) -> Result<(Vec<CILOp>, Vec<u32>), (usize, DecodeErrorKind)> {
+    let mut slice = code;
+    let mut ops = Vec::new();
+    let mut offsets = Vec::new();
+    while !slice.is_empty() {
+        offsets.push(code.len() - slice.len());
+        ops.push(
+            decode_op(&mut slice, code.len()).map_err(|kind| (code.len() - slice.len(), kind))?,
+        );
+    }
+    // Until they are resolved, labels hold the offset they refer to.
+    let mut label_offsets = Vec::new();
+    for (op, offset) in ops.iter().zip(&offsets) {
+        for Label(target) in op.targets() {
+            if offsets.binary_search(&(*target as usize)).is_err() {
+                return Err((
+                    *offset,
+                    DecodeErrorKind::InvalidValue {
+                        field: "branch target",
+                        value: *target as u64,
+                    },
+                ));
+            }
+            label_offsets.push(*target);
+        }
+    }
+    for offset in region_offsets {
+        // Regions may end at the end of the code.
+        if *offset as usize != code.len() && offsets.binary_search(&(*offset as usize)).is_err() {
+            return Err((
+                *offset as usize,
+                DecodeErrorKind::InvalidValue {
+                    field: "exception clause offset",
+                    value: *offset as u64,
+                },
+            ));
+        }
+        label_offsets.push(*offset);
+    }
+    label_offsets.sort_unstable();
+    label_offsets.dedup();
+    let label_at = |offset: u32| Label(label_offsets.binary_search(&offset).unwrap() as u32);
+    let mut labels = label_offsets.iter().enumerate().peekable();
+    let mut labeled = Vec::with_capacity(ops.len() + label_offsets.len());
+    for (mut op, offset) in ops.into_iter().zip(offsets) {
+        while let Some((label, _)) = labels.next_if(|(_, label)| **label as usize == offset) {
+            labeled.push(CILOp::Label(Label(label as u32)));
+        }
+        for target in op.targets_mut() {
+            *target = label_at(target.0);
+        }
+        labeled.push(op);
+    }
+    labeled.extend(labels.map(|(label, _)| CILOp::Label(Label(label as u32))));
+    Ok((labeled, label_offsets))
+}
+/// Decodes a single instruction. `code_len` is the size of the whole IL code, and the start of
+/// `slice` must be at an offset of `code_len - slice.len()` in it. On error, `slice` is left
+/// pointing at the start of the instruction.
+fn decode_op(slice: &mut &[u8], code_len: usize) -> Result<CILOp, DecodeErrorKind> {
+    let mut op_slice = *slice;
+    let op = decode_op_inner(&mut op_slice, code_len)?;
+    *slice = op_slice;
+    Ok(op)
+}
+fn decode_op_inner(slice: &mut &[u8], code_len: usize) -> Result<CILOp, DecodeErrorKind> {
+    // Branch offsets are relative to the end of the instruction.
+    let branch = |slice: &mut &[u8], size: usize| {
+        let offset = if size == 1 {
+            take_u8(slice)? as i8 as i64
+        } else {
+            take_u32(slice)? as i32 as i64
+        };
+        branch_target(code_len - slice.len(), offset, code_len).map(Label)
+    };
+    let byte = take_u8(slice)?;
+    let op = match byte {
+        0x00 => CILOp::Nop,
+        0x01 => CILOp::Break,
+        0x02 => CILOp::LDArg0,
+        0x03 => CILOp::LDArg1,
+        0x04 => CILOp::LDArg2,
+        0x05 => CILOp::LDArg3,
+        0x06 => CILOp::LDLoc0,
+        0x07 => CILOp::LDLoc1,
+        0x08 => CILOp::LDLoc2,
+        0x09 => CILOp::LDLoc3,
+        0x0A => CILOp::STLoc0,
+        0x0B => CILOp::STLoc1,
+        0x0C => CILOp::STLoc2,
+        0x0D => CILOp::STLoc3,
+        0x0E => CILOp::LDArg(take_u8(slice)?.into()),
+        0x0F => CILOp::LDArgA(take_u8(slice)?.into()),
+        0x10 => CILOp::STArg(take_u8(slice)?.into()),
+        0x11 => CILOp::LDLoc(take_u8(slice)?.into()),
+        0x12 => CILOp::LDLocA(take_u8(slice)?.into()),
+        0x13 => CILOp::STLoc(take_u8(slice)?.into()),
+        0x14 => CILOp::LDNull,
+        0x15 => CILOp::LDCI4(-1),
+        0x16 => CILOp::LDCI4(0),
+        0x17 => CILOp::LDCI4(1),
+        0x18 => CILOp::LDCI4(2),
+        0x19 => CILOp::LDCI4(3),
+        0x1A => CILOp::LDCI4(4),
+        0x1B => CILOp::LDCI4(5),
+        0x1C => CILOp::LDCI4(6),
+        0x1D => CILOp::LDCI4(7),
+        0x1E => CILOp::LDCI4(8),
+        0x1F => CILOp::LDCI4(take_u8(slice)? as i8 as i32),
+        0x20 => CILOp::LDCI4(take_u32(slice)? as i32),
+        0x21 => CILOp::LDCI8(take_u64(slice)? as i64),
+        0x22 => CILOp::LDCR4(f32::from_bits(take_u32(slice)?)),
+        0x23 => CILOp::LDCR8(f64::from_bits(take_u64(slice)?)),
+        0x25 => CILOp::Dup,
+        0x26 => CILOp::Pop,
+        0x27 => CILOp::Jmp(token(slice, MethodToken::from_token)?),
+        0x28 => CILOp::Call(token(slice, MethodToken::from_token)?),
+        0x29 => CILOp::CallI(token(slice, StandAloneSigIndex::from_token)?),
+        0x2A => CILOp::Ret,
+        0x2B => CILOp::Br(branch(slice, 1)?),
+        0x2C => CILOp::BrFalse(branch(slice, 1)?),
+        0x2D => CILOp::BrTrue(branch(slice, 1)?),
+        0x2E => CILOp::BEq(branch(slice, 1)?),
+        0x2F => CILOp::BGe(branch(slice, 1)?),
+        0x30 => CILOp::BGt(branch(slice, 1)?),
+        0x31 => CILOp::BLe(branch(slice, 1)?),
+        0x32 => CILOp::BLt(branch(slice, 1)?),
+        0x33 => CILOp::BNeUn(branch(slice, 1)?),
+        0x34 => CILOp::BGeUn(branch(slice, 1)?),
+        0x35 => CILOp::BGtUn(branch(slice, 1)?),
+        0x36 => CILOp::BLeUn(branch(slice, 1)?),
+        0x37 => CILOp::BLtUn(branch(slice, 1)?),
+        0x38 => CILOp::Br(branch(slice, 4)?),
+        0x39 => CILOp::BrFalse(branch(slice, 4)?),
+        0x3A => CILOp::BrTrue(branch(slice, 4)?),
+        0x3B => CILOp::BEq(branch(slice, 4)?),
+        0x3C => CILOp::BGe(branch(slice, 4)?),
+        0x3D => CILOp::BGt(branch(slice, 4)?),
+        0x3E => CILOp::BLe(branch(slice, 4)?),
+        0x3F => CILOp::BLt(branch(slice, 4)?),
+        0x40 => CILOp::BNeUn(branch(slice, 4)?),
+        0x41 => CILOp::BGeUn(branch(slice, 4)?),
+        0x42 => CILOp::BGtUn(branch(slice, 4)?),
+        0x43 => CILOp::BLeUn(branch(slice, 4)?),
+        0x44 => CILOp::BLtUn(branch(slice, 4)?),
+        0x45 => CILOp::Switch(switch(slice, code_len)?),
+        0x46 => CILOp::LDIndI1,
+        0x47 => CILOp::LDIndU1,
+        0x48 => CILOp::LDIndI2,
+        0x49 => CILOp::LDIndU2,
+        0x4A => CILOp::LDIndI4,
+        0x4B => CILOp::LDIndU4,
+        0x4C => CILOp::LDIndI8,
+        0x4D => CILOp::LDIndI,
+        0x4E => CILOp::LDIndR4,
+        0x4F => CILOp::LDIndR8,
+        0x50 => CILOp::LDIndRef,
+        0x51 => CILOp::STIndRef,
+        0x52 => CILOp::STIndI1,
+        0x53 => CILOp::STIndI2,
+        0x54 => CILOp::STIndI4,
+        0x55 => CILOp::STIndI8,
+        0x56 => CILOp::STIndR4,
+        0x57 => CILOp::STIndR8,
+        0x58 => CILOp::Add,
+        0x59 => CILOp::Sub,
+        0x5A => CILOp::Mul,
+        0x5B => CILOp::Div,
+        0x5C => CILOp::DivUn,
+        0x5D => CILOp::Rem,
+        0x5E => CILOp::RemUn,
+        0x5F => CILOp::And,
+        0x60 => CILOp::Or,
+        0x61 => CILOp::Xor,
+        0x62 => CILOp::Shl,
+        0x63 => CILOp::Shr,
+        0x64 => CILOp::ShrUn,
+        0x65 => CILOp::Neg,
+        0x66 => CILOp::Not,
+        0x67 => CILOp::ConvI1,
+        0x68 => CILOp::ConvI2,
+        0x69 => CILOp::ConvI4,
+        0x6A => CILOp::ConvI8,
+        0x6B => CILOp::ConvR4,
+        0x6C => CILOp::ConvR8,
+        0x6D => CILOp::ConvU4,
+        0x6E => CILOp::ConvU8,
+        0x6F => CILOp::CallVirt(token(slice, MethodToken::from_token)?),
+        0x70 => CILOp::CpObj(token(slice, TypeDefOrRef::from_token)?),
+        0x71 => CILOp::LDObj(token(slice, TypeDefOrRef::from_token)?),
+        0x72 => CILOp::LDStr(token(slice, UserStringIndex::from_token)?),
+        0x73 => CILOp::NewObj(token(slice, MethodToken::from_token)?),
+        0x74 => CILOp::CastClass(token(slice, TypeDefOrRef::from_token)?),
+        0x75 => CILOp::IsInst(token(slice, TypeDefOrRef::from_token)?),
+        0x76 => CILOp::ConvRUn,
+        0x79 => CILOp::Unbox(token(slice, TypeDefOrRef::from_token)?),
+        0x7A => CILOp::Throw,
+        0x7B => CILOp::LDFld(token(slice, FieldToken::from_token)?),
+        0x7C => CILOp::LDFldA(token(slice, FieldToken::from_token)?),
+        0x7D => CILOp::STFld(token(slice, FieldToken::from_token)?),
+        0x7E => CILOp::LDSFld(token(slice, FieldToken::from_token)?),
+        0x7F => CILOp::LDSFldA(token(slice, FieldToken::from_token)?),
+        0x80 => CILOp::STSFld(token(slice, FieldToken::from_token)?),
+        0x81 => CILOp::STObj(token(slice, TypeDefOrRef::from_token)?),
+        0x82 => CILOp::ConvOvfI1Un,
+        0x83 => CILOp::ConvOvfI2Un,
+        0x84 => CILOp::ConvOvfI4Un,
+        0x85 => CILOp::ConvOvfI8Un,
+        0x86 => CILOp::ConvOvfU1Un,
+        0x87 => CILOp::ConvOvfU2Un,
+        0x88 => CILOp::ConvOvfU4Un,
+        0x89 => CILOp::ConvOvfU8Un,
+        0x8A => CILOp::ConvOvfIUn,
+        0x8B => CILOp::ConvOvfUUn,
+        0x8C => CILOp::Box(token(slice, TypeDefOrRef::from_token)?),
+        0x8D => CILOp::NewArr(token(slice, TypeDefOrRef::from_token)?),
+        0x8E => CILOp::LDLen,
+        0x8F => CILOp::LDElemA(token(slice, TypeDefOrRef::from_token)?),
+        0x90 => CILOp::LDElemI1,
+        0x91 => CILOp::LDElemU1,
+        0x92 => CILOp::LDElemI2,
+        0x93 => CILOp::LDElemU2,
+        0x94 => CILOp::LDElemI4,
+        0x95 => CILOp::LDElemU4,
+        0x96 => CILOp::LDElemI8,
+        0x97 => CILOp::LDElemI,
+        0x98 => CILOp::LDElemR4,
+        0x99 => CILOp::LDElemR8,
+        0x9A => CILOp::LDElemRef,
+        0x9B => CILOp::STElemI,
+        0x9C => CILOp::STElemI1,
+        0x9D => CILOp::STElemI2,
+        0x9E => CILOp::STElemI4,
+        0x9F => CILOp::STElemI8,
+        0xA0 => CILOp::STElemR4,
+        0xA1 => CILOp::STElemR8,
+        0xA2 => CILOp::STElemRef,
+        0xA3 => CILOp::LDElem(token(slice, TypeDefOrRef::from_token)?),
+        0xA4 => CILOp::STElem(token(slice, TypeDefOrRef::from_token)?),
+        0xA5 => CILOp::UnboxAny(token(slice, TypeDefOrRef::from_token)?),
+        0xB3 => CILOp::ConvOvfI1,
+        0xB4 => CILOp::ConvOvfU1,
+        0xB5 => CILOp::ConvOvfI2,
+        0xB6 => CILOp::ConvOvfU2,
+        0xB7 => CILOp::ConvOvfI4,
+        0xB8 => CILOp::ConvOvfU4,
+        0xB9 => CILOp::ConvOvfI8,
+        0xBA => CILOp::ConvOvfU8,
+        0xC2 => CILOp::RefAnyVal(token(slice, TypeDefOrRef::from_token)?),
+        0xC3 => CILOp::CkFinite,
+        0xC6 => CILOp::MkRefAny(token(slice, TypeDefOrRef::from_token)?),
+        0xD0 => CILOp::LDToken(token(slice, MetadataToken::from_token)?),
+        0xD1 => CILOp::ConvU2,
+        0xD2 => CILOp::ConvU1,
+        0xD3 => CILOp::ConvI,
+        0xD4 => CILOp::ConvOvfI,
+        0xD5 => CILOp::ConvOvfU,
+        0xD6 => CILOp::AddOvf,
+        0xD7 => CILOp::AddOvfUn,
+        0xD8 => CILOp::MulOvf,
+        0xD9 => CILOp::MulOvfUn,
+        0xDA => CILOp::SubOvf,
+        0xDB => CILOp::SubOvfUn,
+        0xDC => CILOp::EndFinally,
+        0xDD => CILOp::Leave(branch(slice, 4)?),
+        0xDE => CILOp::Leave(branch(slice, 1)?),
+        0xDF => CILOp::STIndI,
+        0xE0 => CILOp::ConvU,
+        0xFE => {
+            let byte = take_u8(slice)?;
+            match byte {
+                0x00 => CILOp::ArgList,
+                0x01 => CILOp::CEq,
+                0x02 => CILOp::CGt,
+                0x03 => CILOp::CGtUn,
+                0x04 => CILOp::CLt,
+                0x05 => CILOp::CLtUn,
+                0x06 => CILOp::LDFtn(token(slice, MethodToken::from_token)?),
+                0x07 => CILOp::LDVirtFtn(token(slice, MethodToken::from_token)?),
+                0x09 => CILOp::LDArg(take_u16(slice)?),
+                0x0A => CILOp::LDArgA(take_u16(slice)?),
+                0x0B => CILOp::STArg(take_u16(slice)?),
+                0x0C => CILOp::LDLoc(take_u16(slice)?),
+                0x0D => CILOp::LDLocA(take_u16(slice)?),
+                0x0E => CILOp::STLoc(take_u16(slice)?),
+                0x0F => CILOp::LocAlloc,
+                0x11 => CILOp::EndFilter,
+                0x12 => CILOp::Unaligned(take_u8(slice)?),
+                0x13 => CILOp::Volatile,
+                0x14 => CILOp::Tail,
+                0x15 => CILOp::InitObj(token(slice, TypeDefOrRef::from_token)?),
+                0x16 => CILOp::Constrained(token(slice, TypeDefOrRef::from_token)?),
+                0x17 => CILOp::CpBlk,
+                0x18 => CILOp::InitBlk,
+                0x19 => CILOp::No(take_u8(slice)?),
+                0x1A => CILOp::Rethrow,
+                0x1C => CILOp::SizeOf(token(slice, TypeDefOrRef::from_token)?),
+                0x1D => CILOp::RefAnyType,
+                0x1E => CILOp::Readonly,
+                _ => return Err(DecodeErrorKind::UnknownOpcode(0xFE00 | byte as u16)),
+            }
+        }
+        _ => return Err(DecodeErrorKind::UnknownOpcode(byte as u16)),
+    };
+    Ok(op)
+}
+fn token<T>(
+    slice: &mut &[u8],
+    from_token: impl FnOnce(u32) -> Option<T>,
+) -> Result<T, DecodeErrorKind> {
+    let token = take_u32(slice)?;
+    from_token(token).ok_or(DecodeErrorKind::InvalidValue {
+        field: "metadata token",
+        value: token as u64,
+    })
+}
+fn branch_target(end: usize, offset: i64, code_len: usize) -> Result<u32, DecodeErrorKind> {
+    let target = end as i64 + offset;
+    if !(0..code_len as i64).contains(&target) {
+        return Err(DecodeErrorKind::InvalidValue {
+            field: "branch target",
+            value: target as u64,
+        });
+    }
+    Ok(target as u32)
+}
+fn switch(slice: &mut &[u8], code_len: usize) -> Result<Box<[Label]>, DecodeErrorKind> {
+    let count = take_u32(slice)?;
+    // Each target takes 4 bytes, which bounds the allocation by the size of the code.
+    let mut offsets = Vec::with_capacity((count as usize).min(slice.len() / 4));
+    for _ in 0..count {
+        offsets.push(take_u32(slice)? as i32 as i64);
+    }
+    let end = code_len - slice.len();
+    offsets
+        .into_iter()
+        .map(|offset| branch_target(end, offset, code_len).map(Label))
+        .collect()
+}
+impl CILOp {
+    /// The targets of a branch, `leave` or `switch`.
+    pub fn targets(&self) -> &[Label] {
+        match self {
+            Self::Switch(targets) => targets,
+            _ => match self.branch() {
+                Some((_, _, target)) => std::slice::from_ref(target),
+                None => &[],
+            },
+        }
+    }
+    fn targets_mut(&mut self) -> &mut [Label] {
+        match self {
+            Self::Switch(targets) => targets,
+            Self::Br(target)
+            | Self::BrFalse(target)
+            | Self::BrTrue(target)
+            | Self::BEq(target)
+            | Self::BGe(target)
+            | Self::BGt(target)
+            | Self::BLe(target)
+            | Self::BLt(target)
+            | Self::BNeUn(target)
+            | Self::BGeUn(target)
+            | Self::BGtUn(target)
+            | Self::BLeUn(target)
+            | Self::BLtUn(target)
+            | Self::Leave(target) => std::slice::from_mut(target),
+            _ => &mut [],
+        }
+    }
+    /// The short and long opcode of an instruction with a single branch target, and that target.
+    fn branch(&self) -> Option<(u8, u8, &Label)> {
+        let (short, target) = match self {
+            Self::Br(target) => (0x2B, target),
+            Self::BrFalse(target) => (0x2C, target),
+            Self::BrTrue(target) => (0x2D, target),
+            Self::BEq(target) => (0x2E, target),
+            Self::BGe(target) => (0x2F, target),
+            Self::BGt(target) => (0x30, target),
+            Self::BLe(target) => (0x31, target),
+            Self::BLt(target) => (0x32, target),
+            Self::BNeUn(target) => (0x33, target),
+            Self::BGeUn(target) => (0x34, target),
+            Self::BGtUn(target) => (0x35, target),
+            Self::BLeUn(target) => (0x36, target),
+            Self::BLtUn(target) => (0x37, target),
+            Self::Leave(target) => return Some((0xDE, 0xDD, target)),
+            _ => return None,
+        };
+        Some((short, short + 0x0D, target))
+    }
+    /// Whether execution never continues with the next instruction.
+    fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            Self::Br(_)
+                | Self::Leave(_)
+                | Self::Ret
+                | Self::Jmp(_)
+                | Self::Throw
+                | Self::Rethrow
+                | Self::EndFinally
+                | Self::EndFilter
+        )
+    }
+    /// The number of values this instruction pops off, and pushes onto the stack. Fails if
+    /// `call_site` can't tell the effect of a call.
+    fn stack_effect(
+        &self,
+        call_site: &impl Fn(CallSite) -> Result<CallStackEffect, EncodeError>,
+    ) -> Result<(u16, u16), EncodeError> {
+        let call = |site| call_site(site).map(|effect| (effect.args, effect.returns as u16));
+        Ok(match self {
+            Self::Call(method) | Self::CallVirt(method) => call(CallSite::Method(*method))?,
+            Self::CallI(signature) => {
+                let (pops, pushes) = call(CallSite::Signature(*signature))?;
+                // The function pointer is popped after the arguments.
+                (pops + 1, pushes)
+            }
+            // The object is created, not popped.
+            Self::NewObj(method) => (call(CallSite::Method(*method))?.0.saturating_sub(1), 1),
+            Self::Label(_)
+            | Self::Nop
+            | Self::Break
+            | Self::Jmp(_)
+            | Self::Ret
+            | Self::Br(_)
+            | Self::EndFinally
+            | Self::Leave(_)
+            | Self::Unaligned(_)
+            | Self::Volatile
+            | Self::Tail
+            | Self::Constrained(_)
+            | Self::No(_)
+            | Self::Readonly
+            | Self::Rethrow => (0, 0),
+            Self::LDArg0
+            | Self::LDArg1
+            | Self::LDArg2
+            | Self::LDArg3
+            | Self::LDLoc0
+            | Self::LDLoc1
+            | Self::LDLoc2
+            | Self::LDLoc3
+            | Self::LDArg(_)
+            | Self::LDArgA(_)
+            | Self::LDLoc(_)
+            | Self::LDLocA(_)
+            | Self::LDNull
+            | Self::LDCI4(_)
+            | Self::LDCI8(_)
+            | Self::LDCR4(_)
+            | Self::LDCR8(_)
+            | Self::LDStr(_)
+            | Self::LDSFld(_)
+            | Self::LDSFldA(_)
+            | Self::LDToken(_)
+            | Self::ArgList
+            | Self::LDFtn(_)
+            | Self::SizeOf(_) => (0, 1),
+            Self::STLoc0
+            | Self::STLoc1
+            | Self::STLoc2
+            | Self::STLoc3
+            | Self::STArg(_)
+            | Self::STLoc(_)
+            | Self::Pop
+            | Self::BrFalse(_)
+            | Self::BrTrue(_)
+            | Self::Switch(_)
+            | Self::Throw
+            | Self::STSFld(_)
+            | Self::EndFilter
+            | Self::InitObj(_) => (1, 0),
+            Self::Dup => (1, 2),
+            Self::BEq(_)
+            | Self::BGe(_)
+            | Self::BGt(_)
+            | Self::BLe(_)
+            | Self::BLt(_)
+            | Self::BNeUn(_)
+            | Self::BGeUn(_)
+            | Self::BGtUn(_)
+            | Self::BLeUn(_)
+            | Self::BLtUn(_)
+            | Self::STIndRef
+            | Self::STIndI1
+            | Self::STIndI2
+            | Self::STIndI4
+            | Self::STIndI8
+            | Self::STIndR4
+            | Self::STIndR8
+            | Self::STIndI
+            | Self::CpObj(_)
+            | Self::STFld(_)
+            | Self::STObj(_) => (2, 0),
+            Self::LDIndI1
+            | Self::LDIndU1
+            | Self::LDIndI2
+            | Self::LDIndU2
+            | Self::LDIndI4
+            | Self::LDIndU4
+            | Self::LDIndI8
+            | Self::LDIndI
+            | Self::LDIndR4
+            | Self::LDIndR8
+            | Self::LDIndRef
+            | Self::Neg
+            | Self::Not
+            | Self::ConvI1
+            | Self::ConvI2
+            | Self::ConvI4
+            | Self::ConvI8
+            | Self::ConvR4
+            | Self::ConvR8
+            | Self::ConvU4
+            | Self::ConvU8
+            | Self::ConvU2
+            | Self::ConvU1
+            | Self::ConvI
+            | Self::ConvU
+            | Self::ConvRUn
+            | Self::ConvOvfI1Un
+            | Self::ConvOvfI2Un
+            | Self::ConvOvfI4Un
+            | Self::ConvOvfI8Un
+            | Self::ConvOvfU1Un
+            | Self::ConvOvfU2Un
+            | Self::ConvOvfU4Un
+            | Self::ConvOvfU8Un
+            | Self::ConvOvfIUn
+            | Self::ConvOvfUUn
+            | Self::ConvOvfI1
+            | Self::ConvOvfU1
+            | Self::ConvOvfI2
+            | Self::ConvOvfU2
+            | Self::ConvOvfI4
+            | Self::ConvOvfU4
+            | Self::ConvOvfI8
+            | Self::ConvOvfU8
+            | Self::ConvOvfI
+            | Self::ConvOvfU
+            | Self::LDObj(_)
+            | Self::CastClass(_)
+            | Self::IsInst(_)
+            | Self::Unbox(_)
+            | Self::UnboxAny(_)
+            | Self::LDFld(_)
+            | Self::LDFldA(_)
+            | Self::Box(_)
+            | Self::NewArr(_)
+            | Self::LDLen
+            | Self::RefAnyVal(_)
+            | Self::CkFinite
+            | Self::MkRefAny(_)
+            | Self::LDVirtFtn(_)
+            | Self::LocAlloc
+            | Self::RefAnyType => (1, 1),
+            Self::Add
+            | Self::Sub
+            | Self::Mul
+            | Self::Div
+            | Self::DivUn
+            | Self::Rem
+            | Self::RemUn
+            | Self::And
+            | Self::Or
+            | Self::Xor
+            | Self::Shl
+            | Self::Shr
+            | Self::ShrUn
+            | Self::AddOvf
+            | Self::AddOvfUn
+            | Self::MulOvf
+            | Self::MulOvfUn
+            | Self::SubOvf
+            | Self::SubOvfUn
+            | Self::CEq
+            | Self::CGt
+            | Self::CGtUn
+            | Self::CLt
+            | Self::CLtUn
+            | Self::LDElemA(_)
+            | Self::LDElemI1
+            | Self::LDElemU1
+            | Self::LDElemI2
+            | Self::LDElemU2
+            | Self::LDElemI4
+            | Self::LDElemU4
+            | Self::LDElemI8
+            | Self::LDElemI
+            | Self::LDElemR4
+            | Self::LDElemR8
+            | Self::LDElemRef
+            | Self::LDElem(_) => (2, 1),
+            Self::STElemI
+            | Self::STElemI1
+            | Self::STElemI2
+            | Self::STElemI4
+            | Self::STElemI8
+            | Self::STElemR4
+            | Self::STElemR8
+            | Self::STElemRef
+            | Self::STElem(_)
+            | Self::CpBlk
+            | Self::InitBlk => (3, 0),
+        })
+    }
+}
+/// Something a call instruction calls.
+#[derive(Copy, Clone, Debug)]
+pub enum CallSite {
+    /// The method called by `call`, `callvirt` or `newobj`.
+    Method(MethodToken),
+    /// The signature of the function pointer called by `calli`.
+    Signature(StandAloneSigIndex),
+}
+/// How a call changes the stack.
+#[derive(Copy, Clone, Debug)]
+pub struct CallStackEffect {
+    /// Number of arguments, including `this`.
+    pub args: u16,
+    pub returns: bool,
+}
+impl CallStackEffect {
+    /// The stack effect of calling a method with `signature`.
+    pub(crate) fn from_signature(signature: &Signature) -> Self {
+        // HASTHIS without EXPLICITTHIS means `this` is not part of the args.
+        let this = signature.has_this() && !signature.explicit_this();
+        let args = signature.args().len() + signature.varargs().len() + this as usize;
+        // Custom modifiers of the return type don't change whether anything is returned.
+        let mut ret = signature.ret();
+        while let Type::Modified { tpe, .. } = ret {
+            ret = tpe;
+        }
+        Self {
+            args: args.try_into().unwrap_or(u16::MAX),
+            returns: !matches!(ret, Type::Void),
+        }
+    }
+}
+/// Computes how deep the stack gets while running `ops`. Execution starts at the first op with an
+/// empty stack, and at each of `handlers` with the given stack depth.
+pub(crate) fn max_stack(
+    ops: &[CILOp],
+    handlers: impl IntoIterator<Item = (Label, u16)>,
+    call_site: impl Fn(CallSite) -> Result<CallStackEffect, EncodeError>,
+) -> Result<u16, EncodeError> {
+    let label_indices = label_indices(ops)?;
+    let mut depths = vec![None; ops.len()];
+    let mut pending = vec![(0_usize, 0_u16)];
+    for (label, depth) in handlers {
+        let index = label_indices
+            .get(&label)
+            .ok_or(EncodeError::UndefinedLabel(label))?;
+        pending.push((*index, depth));
+    }
+    let mut max = 0;
+    while let Some((index, depth)) = pending.pop() {
+        let Some(seen @ None) = depths.get_mut(index) else {
+            continue;
+        };
+        *seen = Some(depth);
+        let op = &ops[index];
+        let (pops, pushes) = op.stack_effect(&call_site)?;
+        let after = depth.saturating_sub(pops).saturating_add(pushes);
+        max = max.max(depth).max(after);
+        // `leave` empties the stack.
+        let target_depth = if matches!(op, CILOp::Leave(_)) {
+            0
+        } else {
+            after
+        };
+        pending.extend(
+            op.targets()
+                .iter()
+                .map(|target| (label_indices[target], target_depth)),
+        );
+        if !op.ends_block() {
+            pending.push((index + 1, after));
+        }
+    }
+    Ok(max)
+}
+/// Maps every label defined in `ops` to the index of its definition, checking that each label is defined exactly once
+/// and that all branch targets are defined.
+fn label_indices(ops: &[CILOp]) -> Result<HashMap<Label, usize>, EncodeError> {
+    let mut indices = HashMap::new();
+    for (index, op) in ops.iter().enumerate() {
+        if let CILOp::Label(label) = op {
+            if indices.insert(*label, index).is_some() {
+                return Err(EncodeError::DuplicateLabel(*label));
+            }
+        }
+    }
+    let mut targets = ops.iter().flat_map(CILOp::targets);
+    if let Some(target) = targets.find(|target| !indices.contains_key(target)) {
+        return Err(EncodeError::UndefinedLabel(*target));
+    }
+    Ok(indices)
+}
+/// Encodes `ops` as IL code, using the shortest form of every instruction. Returns the code, and
+/// the offset of every label.
+pub(crate) fn encode_ops(ops: &[CILOp]) -> Result<(Vec<u8>, HashMap<Label, u32>), EncodeError> {
+    let label_indices = label_indices(ops)?;
+    // Start with every branch in its short form, and widen the ones whose target turns out to be
+    // out of range until the layout stops changing. Widening only ever moves targets further away.
+    let mut long = vec![false; ops.len()];
+    loop {
+        let mut offsets = Vec::with_capacity(ops.len() + 1);
+        let mut scratch = Vec::new();
+        let mut offset = 0;
+        for (op, long) in ops.iter().zip(&long) {
+            offsets.push(offset);
+            scratch.clear();
+            encode_op(op, *long, &|_| 0, &mut scratch);
+            offset += scratch.len() as u32;
+        }
+        offsets.push(offset);
+        // All branch targets were checked to be defined by `label_indices`.
+        let target_offset = |label: &Label| offsets[label_indices[label]];
+        let mut changed = false;
+        for (index, op) in ops.iter().enumerate() {
+            if let (Some((_, _, target)), false) = (op.branch(), long[index]) {
+                let distance = target_offset(target) as i64 - offsets[index + 1] as i64;
+                if i8::try_from(distance).is_err() {
+                    long[index] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            let mut out = Vec::with_capacity(offset as usize);
+            for (op, long) in ops.iter().zip(&long) {
+                encode_op(op, *long, &target_offset, &mut out);
+            }
+            let label_offsets = label_indices
+                .iter()
+                .map(|(label, index)| (*label, offsets[*index]))
+                .collect();
+            return Ok((out, label_offsets));
+        }
+    }
+}
+fn op_with_token(out: &mut Vec<u8>, opcode: &[u8], token: u32) {
+    out.extend(opcode);
+    out.extend(token.to_le_bytes());
+}
+/// Encodes the short forms of `ldarg`, `ldloc` and similar. `macro_opcode` is the opcode of the
+/// form with the index built in, if there is one.
+fn encode_variable(
+    out: &mut Vec<u8>,
+    index: u16,
+    macro_opcode: Option<u8>,
+    short_opcode: u8,
+    long_opcode: u8,
+) {
+    match (macro_opcode, u8::try_from(index)) {
+        (Some(opcode), Ok(index @ 0..=3)) => out.push(opcode + index),
+        (_, Ok(index)) => out.extend([short_opcode, index]),
+        (_, Err(_)) => {
+            out.extend([0xFE, long_opcode]);
+            out.extend(index.to_le_bytes());
+        }
+    }
+}
+/// Appends a single instruction to `out`, which must hold the code before it. `long` selects the
+/// long form of branches, and `target_offset` maps labels to their offset.
+fn encode_op(op: &CILOp, long: bool, target_offset: &dyn Fn(&Label) -> u32, out: &mut Vec<u8>) {
+    if let Some((short_opcode, long_opcode, target)) = op.branch() {
+        // Offsets are relative to the end of the instruction.
+        let target = target_offset(target) as i64;
+        if long {
+            out.push(long_opcode);
+            let end = out.len() as i64 + 4;
+            out.extend(((target - end) as i32).to_le_bytes());
+        } else {
+            out.push(short_opcode);
+            let end = out.len() as i64 + 1;
+            out.push((target - end) as i8 as u8);
+        }
+        return;
+    }
+    match op {
+        CILOp::LDArg(index) => encode_variable(out, *index, Some(0x02), 0x0E, 0x09),
+        CILOp::LDArgA(index) => encode_variable(out, *index, None, 0x0F, 0x0A),
+        CILOp::STArg(index) => encode_variable(out, *index, None, 0x10, 0x0B),
+        CILOp::LDLoc(index) => encode_variable(out, *index, Some(0x06), 0x11, 0x0C),
+        CILOp::LDLocA(index) => encode_variable(out, *index, None, 0x12, 0x0D),
+        CILOp::STLoc(index) => encode_variable(out, *index, Some(0x0A), 0x13, 0x0E),
+        CILOp::LDCI4(value) => match *value {
+            -1..=8 => out.push((0x16 + value) as u8),
+            value if i8::try_from(value).is_ok() => out.extend([0x1F, value as u8]),
+            value => {
+                out.push(0x20);
+                out.extend(value.to_le_bytes());
+            }
+        },
+        CILOp::Switch(targets) => {
+            out.push(0x45);
+            out.extend((targets.len() as u32).to_le_bytes());
+            let end = (out.len() + targets.len() * 4) as i64;
+            for target in targets.iter() {
+                let offset = target_offset(target) as i64 - end;
+                out.extend((offset as i32).to_le_bytes());
+            }
+        }
+        CILOp::Label(_) => (),
+        CILOp::Nop => out.push(0x00),
+        CILOp::Break => out.push(0x01),
+        CILOp::LDArg0 => out.push(0x02),
+        CILOp::LDArg1 => out.push(0x03),
+        CILOp::LDArg2 => out.push(0x04),
+        CILOp::LDArg3 => out.push(0x05),
+        CILOp::LDLoc0 => out.push(0x06),
+        CILOp::LDLoc1 => out.push(0x07),
+        CILOp::LDLoc2 => out.push(0x08),
+        CILOp::LDLoc3 => out.push(0x09),
+        CILOp::STLoc0 => out.push(0x0A),
+        CILOp::STLoc1 => out.push(0x0B),
+        CILOp::STLoc2 => out.push(0x0C),
+        CILOp::STLoc3 => out.push(0x0D),
+        CILOp::LDNull => out.push(0x14),
+        CILOp::LDCI8(value) => {
+            out.push(0x21);
+            out.extend(value.to_le_bytes());
+        }
+        CILOp::LDCR4(value) => {
+            out.push(0x22);
+            out.extend(value.to_bits().to_le_bytes());
+        }
+        CILOp::LDCR8(value) => {
+            out.push(0x23);
+            out.extend(value.to_bits().to_le_bytes());
+        }
+        CILOp::Dup => out.push(0x25),
+        CILOp::Pop => out.push(0x26),
+        CILOp::Jmp(token) => op_with_token(out, &[0x27], token.token()),
+        CILOp::Call(token) => op_with_token(out, &[0x28], token.token()),
+        CILOp::CallI(token) => op_with_token(out, &[0x29], token.token()),
+        CILOp::Ret => out.push(0x2A),
+        CILOp::LDIndI1 => out.push(0x46),
+        CILOp::LDIndU1 => out.push(0x47),
+        CILOp::LDIndI2 => out.push(0x48),
+        CILOp::LDIndU2 => out.push(0x49),
+        CILOp::LDIndI4 => out.push(0x4A),
+        CILOp::LDIndU4 => out.push(0x4B),
+        CILOp::LDIndI8 => out.push(0x4C),
+        CILOp::LDIndI => out.push(0x4D),
+        CILOp::LDIndR4 => out.push(0x4E),
+        CILOp::LDIndR8 => out.push(0x4F),
+        CILOp::LDIndRef => out.push(0x50),
+        CILOp::STIndRef => out.push(0x51),
+        CILOp::STIndI1 => out.push(0x52),
+        CILOp::STIndI2 => out.push(0x53),
+        CILOp::STIndI4 => out.push(0x54),
+        CILOp::STIndI8 => out.push(0x55),
+        CILOp::STIndR4 => out.push(0x56),
+        CILOp::STIndR8 => out.push(0x57),
+        CILOp::Add => out.push(0x58),
+        CILOp::Sub => out.push(0x59),
+        CILOp::Mul => out.push(0x5A),
+        CILOp::Div => out.push(0x5B),
+        CILOp::DivUn => out.push(0x5C),
+        CILOp::Rem => out.push(0x5D),
+        CILOp::RemUn => out.push(0x5E),
+        CILOp::And => out.push(0x5F),
+        CILOp::Or => out.push(0x60),
+        CILOp::Xor => out.push(0x61),
+        CILOp::Shl => out.push(0x62),
+        CILOp::Shr => out.push(0x63),
+        CILOp::ShrUn => out.push(0x64),
+        CILOp::Neg => out.push(0x65),
+        CILOp::Not => out.push(0x66),
+        CILOp::ConvI1 => out.push(0x67),
+        CILOp::ConvI2 => out.push(0x68),
+        CILOp::ConvI4 => out.push(0x69),
+        CILOp::ConvI8 => out.push(0x6A),
+        CILOp::ConvR4 => out.push(0x6B),
+        CILOp::ConvR8 => out.push(0x6C),
+        CILOp::ConvU4 => out.push(0x6D),
+        CILOp::ConvU8 => out.push(0x6E),
+        CILOp::CallVirt(token) => op_with_token(out, &[0x6F], token.token()),
+        CILOp::CpObj(token) => op_with_token(out, &[0x70], token.token()),
+        CILOp::LDObj(token) => op_with_token(out, &[0x71], token.token()),
+        CILOp::LDStr(token) => op_with_token(out, &[0x72], token.token()),
+        CILOp::NewObj(token) => op_with_token(out, &[0x73], token.token()),
+        CILOp::CastClass(token) => op_with_token(out, &[0x74], token.token()),
+        CILOp::IsInst(token) => op_with_token(out, &[0x75], token.token()),
+        CILOp::ConvRUn => out.push(0x76),
+        CILOp::Unbox(token) => op_with_token(out, &[0x79], token.token()),
+        CILOp::Throw => out.push(0x7A),
+        CILOp::LDFld(token) => op_with_token(out, &[0x7B], token.token()),
+        CILOp::LDFldA(token) => op_with_token(out, &[0x7C], token.token()),
+        CILOp::STFld(token) => op_with_token(out, &[0x7D], token.token()),
+        CILOp::LDSFld(token) => op_with_token(out, &[0x7E], token.token()),
+        CILOp::LDSFldA(token) => op_with_token(out, &[0x7F], token.token()),
+        CILOp::STSFld(token) => op_with_token(out, &[0x80], token.token()),
+        CILOp::STObj(token) => op_with_token(out, &[0x81], token.token()),
+        CILOp::ConvOvfI1Un => out.push(0x82),
+        CILOp::ConvOvfI2Un => out.push(0x83),
+        CILOp::ConvOvfI4Un => out.push(0x84),
+        CILOp::ConvOvfI8Un => out.push(0x85),
+        CILOp::ConvOvfU1Un => out.push(0x86),
+        CILOp::ConvOvfU2Un => out.push(0x87),
+        CILOp::ConvOvfU4Un => out.push(0x88),
+        CILOp::ConvOvfU8Un => out.push(0x89),
+        CILOp::ConvOvfIUn => out.push(0x8A),
+        CILOp::ConvOvfUUn => out.push(0x8B),
+        CILOp::Box(token) => op_with_token(out, &[0x8C], token.token()),
+        CILOp::NewArr(token) => op_with_token(out, &[0x8D], token.token()),
+        CILOp::LDLen => out.push(0x8E),
+        CILOp::LDElemA(token) => op_with_token(out, &[0x8F], token.token()),
+        CILOp::LDElemI1 => out.push(0x90),
+        CILOp::LDElemU1 => out.push(0x91),
+        CILOp::LDElemI2 => out.push(0x92),
+        CILOp::LDElemU2 => out.push(0x93),
+        CILOp::LDElemI4 => out.push(0x94),
+        CILOp::LDElemU4 => out.push(0x95),
+        CILOp::LDElemI8 => out.push(0x96),
+        CILOp::LDElemI => out.push(0x97),
+        CILOp::LDElemR4 => out.push(0x98),
+        CILOp::LDElemR8 => out.push(0x99),
+        CILOp::LDElemRef => out.push(0x9A),
+        CILOp::STElemI => out.push(0x9B),
+        CILOp::STElemI1 => out.push(0x9C),
+        CILOp::STElemI2 => out.push(0x9D),
+        CILOp::STElemI4 => out.push(0x9E),
+        CILOp::STElemI8 => out.push(0x9F),
+        CILOp::STElemR4 => out.push(0xA0),
+        CILOp::STElemR8 => out.push(0xA1),
+        CILOp::STElemRef => out.push(0xA2),
+        CILOp::LDElem(token) => op_with_token(out, &[0xA3], token.token()),
+        CILOp::STElem(token) => op_with_token(out, &[0xA4], token.token()),
+        CILOp::UnboxAny(token) => op_with_token(out, &[0xA5], token.token()),
+        CILOp::ConvOvfI1 => out.push(0xB3),
+        CILOp::ConvOvfU1 => out.push(0xB4),
+        CILOp::ConvOvfI2 => out.push(0xB5),
+        CILOp::ConvOvfU2 => out.push(0xB6),
+        CILOp::ConvOvfI4 => out.push(0xB7),
+        CILOp::ConvOvfU4 => out.push(0xB8),
+        CILOp::ConvOvfI8 => out.push(0xB9),
+        CILOp::ConvOvfU8 => out.push(0xBA),
+        CILOp::RefAnyVal(token) => op_with_token(out, &[0xC2], token.token()),
+        CILOp::CkFinite => out.push(0xC3),
+        CILOp::MkRefAny(token) => op_with_token(out, &[0xC6], token.token()),
+        CILOp::LDToken(token) => op_with_token(out, &[0xD0], token.token()),
+        CILOp::ConvU2 => out.push(0xD1),
+        CILOp::ConvU1 => out.push(0xD2),
+        CILOp::ConvI => out.push(0xD3),
+        CILOp::ConvOvfI => out.push(0xD4),
+        CILOp::ConvOvfU => out.push(0xD5),
+        CILOp::AddOvf => out.push(0xD6),
+        CILOp::AddOvfUn => out.push(0xD7),
+        CILOp::MulOvf => out.push(0xD8),
+        CILOp::MulOvfUn => out.push(0xD9),
+        CILOp::SubOvf => out.push(0xDA),
+        CILOp::SubOvfUn => out.push(0xDB),
+        CILOp::EndFinally => out.push(0xDC),
+        CILOp::STIndI => out.push(0xDF),
+        CILOp::ConvU => out.push(0xE0),
+        CILOp::ArgList => out.extend([0xFE, 0x00]),
+        CILOp::CEq => out.extend([0xFE, 0x01]),
+        CILOp::CGt => out.extend([0xFE, 0x02]),
+        CILOp::CGtUn => out.extend([0xFE, 0x03]),
+        CILOp::CLt => out.extend([0xFE, 0x04]),
+        CILOp::CLtUn => out.extend([0xFE, 0x05]),
+        CILOp::LDFtn(token) => op_with_token(out, &[0xFE, 0x06], token.token()),
+        CILOp::LDVirtFtn(token) => op_with_token(out, &[0xFE, 0x07], token.token()),
+        CILOp::LocAlloc => out.extend([0xFE, 0x0F]),
+        CILOp::EndFilter => out.extend([0xFE, 0x11]),
+        CILOp::Unaligned(value) => out.extend([0xFE, 0x12, *value]),
+        CILOp::Volatile => out.extend([0xFE, 0x13]),
+        CILOp::Tail => out.extend([0xFE, 0x14]),
+        CILOp::InitObj(token) => op_with_token(out, &[0xFE, 0x15], token.token()),
+        CILOp::Constrained(token) => op_with_token(out, &[0xFE, 0x16], token.token()),
+        CILOp::CpBlk => out.extend([0xFE, 0x17]),
+        CILOp::InitBlk => out.extend([0xFE, 0x18]),
+        CILOp::No(value) => out.extend([0xFE, 0x19, *value]),
+        CILOp::Rethrow => out.extend([0xFE, 0x1A]),
+        CILOp::SizeOf(token) => op_with_token(out, &[0xFE, 0x1C], token.token()),
+        CILOp::RefAnyType => out.extend([0xFE, 0x1D]),
+        CILOp::Readonly => out.extend([0xFE, 0x1E]),
+        CILOp::Br(_)
+        | CILOp::BrFalse(_)
+        | CILOp::BrTrue(_)
+        | CILOp::BEq(_)
+        | CILOp::BGe(_)
+        | CILOp::BGt(_)
+        | CILOp::BLe(_)
+        | CILOp::BLt(_)
+        | CILOp::BNeUn(_)
+        | CILOp::BGeUn(_)
+        | CILOp::BGtUn(_)
+        | CILOp::BLeUn(_)
+        | CILOp::BLtUn(_)
+        | CILOp::Leave(_) => unreachable!("Branches are encoded above."),
+    }
+}
+#[cfg(test)]
+fn decode_all(code: &[u8]) -> Result<Vec<CILOp>, DecodeErrorKind> {
+    decode_ops(code, &[])
+        .map(|(ops, _)| ops)
+        .map_err(|(_, kind)| kind)
+}
+#[test]
+fn decode_loop() {
+    // The body of `puts` in test/binop.il.
+    let code = [
+        0x02, 0x46, 0x0A, 0x06, 0x16, 0x2E, 0x0E, 0x06, 0xD3, 0x28, 0x01, 0x00, 0x00, 0x0A, 0x02,
+        0x17, 0x58, 0x10, 0x00, 0x2B, 0xEB, 0x2A,
+    ];
+    let (ops, label_offsets) = decode_ops(&code, &[]).unwrap();
+    assert!(matches!(
+        &ops[..],
+        [
+            CILOp::Label(Label(0)),
+            CILOp::LDArg0,
+            CILOp::LDIndI1,
+            CILOp::STLoc0,
+            CILOp::LDLoc0,
+            CILOp::LDCI4(0),
+            CILOp::BEq(Label(1)),
+            CILOp::LDLoc0,
+            CILOp::ConvI,
+            CILOp::Call(MethodToken::MemberRef(MemberRefIndex(1))),
+            CILOp::LDArg0,
+            CILOp::LDCI4(1),
+            CILOp::Add,
+            CILOp::STArg(0),
+            CILOp::Br(Label(0)),
+            CILOp::Label(Label(1)),
+            CILOp::Ret,
+        ]
+    ));
+    assert_eq!(label_offsets, [0, 21]);
+}
+#[test]
+fn decode_prefixed() {
+    let code = [
+        0xFE, 0x16, 0x05, 0x00, 0x00, 0x1B, 0x6F, 0x02, 0x00, 0x00, 0x06, 0xFE, 0x0C, 0x00, 0x01,
+        0x45, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF3, 0xFF, 0xFF, 0xFF, 0x2A,
+    ];
+    let ops = decode_all(&code).unwrap();
+    let [constrained, CILOp::CallVirt(method), ldloc, CILOp::Label(Label(0)), CILOp::Switch(targets), CILOp::Label(Label(1)), CILOp::Ret] =
+        &ops[..]
+    else {
+        panic!("Unexpected ops {ops:?}");
+    };
+    assert!(matches!(
+        constrained,
+        CILOp::Constrained(TypeDefOrRef::TypeSpec(TypeSpecIndex(5)))
+    ));
+    assert!(matches!(method, MethodToken::MethodDef(MethodIndex(2))));
+    assert!(matches!(ldloc, CILOp::LDLoc(0x100)));
+    assert_eq!(&targets[..], [Label(1), Label(0)]);
+    assert_eq!(
+        decode_all(&[0xFE, 0x08]).unwrap_err(),
+        DecodeErrorKind::UnknownOpcode(0xFE08)
+    );
+    // A branch before the start of the method, and one into the middle of an instruction.
+    assert!(decode_all(&[0x2B, 0xF0]).is_err());
+    assert_eq!(
+        decode_all(&[0x2B, 0x01, 0x1F, 0x00]).unwrap_err(),
+        DecodeErrorKind::InvalidValue {
+            field: "branch target",
+            value: 3
+        }
+    );
+    // ldfld needs a field, not a type.
+    assert!(decode_all(&[0x7B, 0x01, 0x00, 0x00, 0x02]).is_err());
+}
+#[test]
+fn decode_regions() {
+    // nop; leave.s 0; pop; leave.s 0; ret
+    let code = [0x00, 0xDE, 0x03, 0x26, 0xDE, 0x00, 0x2A];
+    let (ops, label_offsets) = decode_ops(&code, &[1, 3, 3, 6, 7]).unwrap();
+    assert_eq!(label_offsets, [1, 3, 6, 7]);
+    assert!(matches!(
+        &ops[..],
+        [
+            CILOp::Nop,
+            CILOp::Label(Label(0)),
+            CILOp::Leave(Label(2)),
+            CILOp::Label(Label(1)),
+            CILOp::Pop,
+            CILOp::Leave(Label(2)),
+            CILOp::Label(Label(2)),
+            CILOp::Ret,
+            CILOp::Label(Label(3)),
+        ]
+    ));
+    assert_eq!(
+        decode_ops(&code, &[2]).unwrap_err(),
+        (
+            2,
+            DecodeErrorKind::InvalidValue {
+                field: "exception clause offset",
+                value: 2
+            }
+        )
+    );
+}
+#[test]
+fn encode_round_trip() {
+    let code = [
+        0x02, 0x46, 0x0A, 0x06, 0x16, 0x2E, 0x0E, 0x06, 0xD3, 0x28, 0x01, 0x00, 0x00, 0x0A, 0x02,
+        0x17, 0x58, 0x10, 0x00, 0x2B, 0xEB, 0x2A,
+    ];
+    let (encoded, label_offsets) = encode_ops(&decode_all(&code).unwrap()).unwrap();
+    assert_eq!(encoded, code);
+    assert_eq!(label_offsets[&Label(0)], 0);
+    assert_eq!(label_offsets[&Label(1)], 21);
+}
+#[test]
+fn encode_picks_short_forms() {
+    let ops = [
+        CILOp::LDArg(0),
+        CILOp::LDArg(4),
+        CILOp::LDArg(0x100),
+        CILOp::STLoc(3),
+        CILOp::LDCI4(-1),
+        CILOp::LDCI4(-2),
+        CILOp::LDCI4(0x1000),
+        CILOp::Ret,
+    ];
+    assert_eq!(
+        encode_ops(&ops).unwrap().0,
+        [
+            0x02, 0x0E, 0x04, 0xFE, 0x09, 0x00, 0x01, 0x0D, 0x15, 0x1F, 0xFE, 0x20, 0x00, 0x10,
+            0x00, 0x00, 0x2A
+        ]
+    );
+}
+#[test]
+fn encode_relaxes_branches() {
+    let ops = |nops_after_ret| {
+        let mut ops = vec![CILOp::BrTrue(Label(0)), CILOp::Br(Label(1))];
+        ops.extend((0..124).map(|_| CILOp::Nop));
+        ops.extend([CILOp::Label(Label(0)), CILOp::Ret]);
+        ops.extend((0..nops_after_ret).map(|_| CILOp::Nop));
+        ops.extend([CILOp::Label(Label(1)), CILOp::Ret]);
+        ops
+    };
+    // In their short forms, the first branch jumps 126 bytes ahead, and the second one 128. The
+    // second one needs a long form, which pushes the target of the first one out of range too.
+    let (code, _) = encode_ops(&ops(3)).unwrap();
+    assert_eq!(
+        &code[..10],
+        [0x3A, 0x81, 0x00, 0x00, 0x00, 0x38, 0x80, 0x00, 0x00, 0x00]
+    );
+    let decoded = decode_all(&code).unwrap();
+    assert!(matches!(
+        decoded[..2],
+        [CILOp::BrTrue(Label(0)), CILOp::Br(Label(1))]
+    ));
+    // Once the second branch is close enough, both are short.
+    assert_eq!(
+        &encode_ops(&ops(2)).unwrap().0[..4],
+        [0x2D, 0x7E, 0x2B, 0x7F]
+    );
+}
+#[test]
+fn compute_max_stack() {
+    let call_site = |_| {
+        Ok(CallStackEffect {
+            args: 3,
+            returns: true,
+        })
+    };
+    let ops = [
+        CILOp::LDArg0,
+        CILOp::Dup,
+        CILOp::Dup,
+        CILOp::Call(MethodToken::MethodDef(MethodIndex(1))),
+        CILOp::BrTrue(Label(0)),
+        CILOp::LDNull,
+        CILOp::Label(Label(0)),
+        CILOp::Ret,
+    ];
+    assert_eq!(max_stack(&ops, [], call_site).unwrap(), 3);
+    // Branching back must not loop forever.
+    let ops = [
+        CILOp::Label(Label(0)),
+        CILOp::LDCI4(1),
+        CILOp::Pop,
+        CILOp::Br(Label(0)),
+    ];
+    assert_eq!(max_stack(&ops, [], call_site).unwrap(), 1);
+    // A catch handler is only reachable through the exception it starts with.
+    let ops = [
+        CILOp::Leave(Label(0)),
+        CILOp::Label(Label(1)),
+        CILOp::Pop,
+        CILOp::Leave(Label(0)),
+        CILOp::Label(Label(0)),
+        CILOp::Ret,
+    ];
+    assert_eq!(max_stack(&ops, [], call_site).unwrap(), 0);
+    assert_eq!(max_stack(&ops, [(Label(1), 1)], call_site).unwrap(), 1);
+}
+#[test]
+fn call_stack_effect() {
+    let effect = |mut signature: &[u8]| {
+        CallStackEffect::from_signature(&Signature::decode(&mut signature).unwrap())
+    };
+    // instance void (int32)
+    let instance = effect(&[0x20, 0x01, 0x01, 0x08]);
+    assert_eq!((instance.args, instance.returns), (2, false));
+    // explicit this passes `this` as its first parameter: instance explicit int32 (object)
+    let explicit = effect(&[0x60, 0x01, 0x08, 0x1C]);
+    assert_eq!((explicit.args, explicit.returns), (1, true));
+    // A vararg call site, with a modreq on the void return type: vararg modreq(TypeRef 1) void (int32, ..., string)
+    let vararg = effect(&[0x05, 0x02, 0x1F, 0x05, 0x01, 0x08, 0x41, 0x0E]);
+    assert_eq!((vararg.args, vararg.returns), (2, false));
+}
+#[test]
+fn undefined_and_duplicate_labels() {
+    let undefined = [CILOp::Br(Label(3)), CILOp::Label(Label(0)), CILOp::Ret];
+    assert!(matches!(
+        encode_ops(&undefined),
+        Err(EncodeError::UndefinedLabel(Label(3)))
+    ));
+    let switch = [
+        CILOp::Switch([Label(0), Label(1)].into()),
+        CILOp::Label(Label(0)),
+        CILOp::Ret,
+    ];
+    assert!(matches!(
+        encode_ops(&switch),
+        Err(EncodeError::UndefinedLabel(Label(1)))
+    ));
+    let duplicate = [
+        CILOp::Label(Label(0)),
+        CILOp::Nop,
+        CILOp::Label(Label(0)),
+        CILOp::Ret,
+    ];
+    assert!(matches!(
+        encode_ops(&duplicate),
+        Err(EncodeError::DuplicateLabel(Label(0)))
+    ));
+    let call_site = |_| {
+        Ok(CallStackEffect {
+            args: 0,
+            returns: false,
+        })
+    };
+    assert!(matches!(
+        max_stack(&[CILOp::Ret], [(Label(2), 1)], call_site),
+        Err(EncodeError::UndefinedLabel(Label(2)))
+    ));
+}
@@ -0,0 +1,473 @@
+use crate::{
+    assembly::{table_rows, take_u16, take_u32, write_index, AssemblyRefIndex, DecodeErrorKind},
+    bitvec::BitVec64,
+    field::FieldIndex,
+    method::{MemberRefIndex, MethodIndex, StandAloneSigIndex},
+    param::ParamIndex,
+    table::{
+        AssemblyIndex, DeclSecurityIndex, EventIndex, ExportedTypeIndex, FileIndex,
+        GenericParamConstraintIndex, GenericParamIndex, InterfaceImplIndex, ManifestResourceIndex,
+        MethodSpecIndex, ModuleIndex, ModuleRefIndex, PropertyIndex,
+    },
+    type_def::{TypeDefIndex, TypeRefIndex, TypeSpecIndex},
+};
+/// A kind of coded index, as described in II.24.2.6.
+pub(crate) struct CodedIndexKind {
+    pub(crate) name: &'static str,
+    pub(crate) tag_bits: u8,
+    /// Table referred to by each tag, `None` for unused tags.
+    pub(crate) tables: &'static [Option<u8>],
+}
+impl CodedIndexKind {
+    /// Coded indices take 4 bytes once any of the tables they can refer to is too big for the
+    /// bits left over by the tag.
+    fn is_wide(&self, tables_rows: &[u32], tables: BitVec64) -> bool {
+        self.tables.iter().flatten().any(|table| {
+            table_rows(tables_rows, tables, *table).unwrap_or_default() >= 1 << (16 - self.tag_bits)
+        })
+    }
+    /// Decodes a coded index into the number of the table it refers to, and a 1-based row index.
+    fn decode(
+        &self,
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<(u8, u32), DecodeErrorKind> {
+        let mut encoded_slice = *table_slice;
+        let encoded = if self.is_wide(tables_rows, tables) {
+            take_u32(&mut encoded_slice)?
+        } else {
+            take_u16(&mut encoded_slice)? as u32
+        };
+        let decoded = self.split(encoded)?;
+        *table_slice = encoded_slice;
+        Ok(decoded)
+    }
+    /// Splits an already read coded index into its table and row index.
+    pub(crate) fn split(&self, encoded: u32) -> Result<(u8, u32), DecodeErrorKind> {
+        let tag = encoded & ((1 << self.tag_bits) - 1);
+        let table = self.tables.get(tag as usize).copied().flatten().ok_or(
+            DecodeErrorKind::InvalidCodedIndexTag {
+                coded_index: self.name,
+                tag,
+            },
+        )?;
+        Ok((table, encoded >> self.tag_bits))
+    }
+    /// Combines a table and row index into a coded index.
+    pub(crate) fn join(&self, (table, index): (u8, u32)) -> u32 {
+        let tag = self
+            .tables
+            .iter()
+            .position(|tag_table| *tag_table == Some(table))
+            .expect("The table of a coded index must be one it can refer to.");
+        index << self.tag_bits | tag as u32
+    }
+    fn encode(&self, row: (u8, u32), out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_index(out, self.join(row), self.is_wide(tables_rows, tables));
+    }
+}
+/// An index into one of several tables, stored with a tag saying which table it refers to.
+pub(crate) trait CodedIndex: Copy {
+    const KIND: CodedIndexKind;
+    /// Creates the index out of a row of one of the tables [`Self::KIND`] can refer to.
+    fn from_row(table: u8, index: u32) -> Self;
+    /// Number of the referenced table, and the 1-based index of the referenced row.
+    fn row(self) -> (u8, u32);
+    fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let (table, index) = Self::KIND.decode(table_slice, tables_rows, tables)?;
+        Ok(Self::from_row(table, index))
+    }
+    fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        Self::KIND.encode(self.row(), out, tables_rows, tables);
+    }
+    /// Converts a metadata token, as used by CIL, into this index. Returns `None` if the token
+    /// refers to a table this index can't refer to.
+    fn from_token(token: u32) -> Option<Self> {
+        let table = (token >> 24) as u8;
+        Self::KIND
+            .tables
+            .contains(&Some(table))
+            .then(|| Self::from_row(table, token & 0x00FF_FFFF))
+    }
+    fn token(self) -> u32 {
+        let (table, index) = self.row();
+        u32::from(table) << 24 | index
+    }
+}
+/// Owner of a constant value.
+#[derive(Copy, Clone, Debug)]
+pub enum HasConstant {
+    Field(FieldIndex),
+    Param(ParamIndex),
+    Property(PropertyIndex),
+}
+impl CodedIndex for HasConstant {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "HasConstant",
+        tag_bits: 2,
+        tables: &[Some(0x04), Some(0x08), Some(0x17)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x04 => Self::Field(FieldIndex(index)),
+            0x08 => Self::Param(ParamIndex(index)),
+            _ => Self::Property(PropertyIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::Field(FieldIndex(index)) => (0x04, index),
+            Self::Param(ParamIndex(index)) => (0x08, index),
+            Self::Property(PropertyIndex(index)) => (0x17, index),
+        }
+    }
+}
+/// Anything a custom attribute can be applied to.
+#[derive(Copy, Clone, Debug)]
+pub enum HasCustomAttribute {
+    MethodDef(MethodIndex),
+    Field(FieldIndex),
+    TypeRef(TypeRefIndex),
+    TypeDef(TypeDefIndex),
+    Param(ParamIndex),
+    InterfaceImpl(InterfaceImplIndex),
+    MemberRef(MemberRefIndex),
+    Module(ModuleIndex),
+    DeclSecurity(DeclSecurityIndex),
+    Property(PropertyIndex),
+    Event(EventIndex),
+    StandAloneSig(StandAloneSigIndex),
+    ModuleRef(ModuleRefIndex),
+    TypeSpec(TypeSpecIndex),
+    Assembly(AssemblyIndex),
+    AssemblyRef(AssemblyRefIndex),
+    File(FileIndex),
+    ExportedType(ExportedTypeIndex),
+    ManifestResource(ManifestResourceIndex),
+    GenericParam(GenericParamIndex),
+    GenericParamConstraint(GenericParamConstraintIndex),
+    MethodSpec(MethodSpecIndex),
+}
+impl CodedIndex for HasCustomAttribute {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "HasCustomAttribute",
+        tag_bits: 5,
+        tables: &[
+            Some(0x06),
+            Some(0x04),
+            Some(0x01),
+            Some(0x02),
+            Some(0x08),
+            Some(0x09),
+            Some(0x0a),
+            Some(0x00),
+            Some(0x0e),
+            Some(0x17),
+            Some(0x14),
+            Some(0x11),
+            Some(0x1a),
+            Some(0x1b),
+            Some(0x20),
+            Some(0x23),
+            Some(0x26),
+            Some(0x27),
+            Some(0x28),
+            Some(0x2a),
+            Some(0x2c),
+            Some(0x2b),
+        ],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x06 => Self::MethodDef(MethodIndex(index)),
+            0x04 => Self::Field(FieldIndex(index)),
+            0x01 => Self::TypeRef(TypeRefIndex(index)),
+            0x02 => Self::TypeDef(TypeDefIndex(index)),
+            0x08 => Self::Param(ParamIndex(index)),
+            0x09 => Self::InterfaceImpl(InterfaceImplIndex(index)),
+            0x0a => Self::MemberRef(MemberRefIndex(index)),
+            0x00 => Self::Module(ModuleIndex(index)),
+            0x0e => Self::DeclSecurity(DeclSecurityIndex(index)),
+            0x17 => Self::Property(PropertyIndex(index)),
+            0x14 => Self::Event(EventIndex(index)),
+            0x11 => Self::StandAloneSig(StandAloneSigIndex(index)),
+            0x1a => Self::ModuleRef(ModuleRefIndex(index)),
+            0x1b => Self::TypeSpec(TypeSpecIndex(index)),
+            0x20 => Self::Assembly(AssemblyIndex(index)),
+            0x23 => Self::AssemblyRef(AssemblyRefIndex(index)),
+            0x26 => Self::File(FileIndex(index)),
+            0x27 => Self::ExportedType(ExportedTypeIndex(index)),
+            0x28 => Self::ManifestResource(ManifestResourceIndex(index)),
+            0x2a => Self::GenericParam(GenericParamIndex(index)),
+            0x2c => Self::GenericParamConstraint(GenericParamConstraintIndex(index)),
+            _ => Self::MethodSpec(MethodSpecIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+            Self::Field(FieldIndex(index)) => (0x04, index),
+            Self::TypeRef(TypeRefIndex(index)) => (0x01, index),
+            Self::TypeDef(TypeDefIndex(index)) => (0x02, index),
+            Self::Param(ParamIndex(index)) => (0x08, index),
+            Self::InterfaceImpl(InterfaceImplIndex(index)) => (0x09, index),
+            Self::MemberRef(MemberRefIndex(index)) => (0x0a, index),
+            Self::Module(ModuleIndex(index)) => (0x00, index),
+            Self::DeclSecurity(DeclSecurityIndex(index)) => (0x0e, index),
+            Self::Property(PropertyIndex(index)) => (0x17, index),
+            Self::Event(EventIndex(index)) => (0x14, index),
+            Self::StandAloneSig(StandAloneSigIndex(index)) => (0x11, index),
+            Self::ModuleRef(ModuleRefIndex(index)) => (0x1a, index),
+            Self::TypeSpec(TypeSpecIndex(index)) => (0x1b, index),
+            Self::Assembly(AssemblyIndex(index)) => (0x20, index),
+            Self::AssemblyRef(AssemblyRefIndex(index)) => (0x23, index),
+            Self::File(FileIndex(index)) => (0x26, index),
+            Self::ExportedType(ExportedTypeIndex(index)) => (0x27, index),
+            Self::ManifestResource(ManifestResourceIndex(index)) => (0x28, index),
+            Self::GenericParam(GenericParamIndex(index)) => (0x2a, index),
+            Self::GenericParamConstraint(GenericParamConstraintIndex(index)) => (0x2c, index),
+            Self::MethodSpec(MethodSpecIndex(index)) => (0x2b, index),
+        }
+    }
+}
+/// A field or parameter with marshalling information.
+#[derive(Copy, Clone, Debug)]
+pub enum HasFieldMarshal {
+    Field(FieldIndex),
+    Param(ParamIndex),
+}
+impl CodedIndex for HasFieldMarshal {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "HasFieldMarshal",
+        tag_bits: 1,
+        tables: &[Some(0x04), Some(0x08)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x04 => Self::Field(FieldIndex(index)),
+            _ => Self::Param(ParamIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::Field(FieldIndex(index)) => (0x04, index),
+            Self::Param(ParamIndex(index)) => (0x08, index),
+        }
+    }
+}
+/// Owner of declarative security attributes.
+#[derive(Copy, Clone, Debug)]
+pub enum HasDeclSecurity {
+    TypeDef(TypeDefIndex),
+    MethodDef(MethodIndex),
+    Assembly(AssemblyIndex),
+}
+impl CodedIndex for HasDeclSecurity {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "HasDeclSecurity",
+        tag_bits: 2,
+        tables: &[Some(0x02), Some(0x06), Some(0x20)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x02 => Self::TypeDef(TypeDefIndex(index)),
+            0x06 => Self::MethodDef(MethodIndex(index)),
+            _ => Self::Assembly(AssemblyIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::TypeDef(TypeDefIndex(index)) => (0x02, index),
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+            Self::Assembly(AssemblyIndex(index)) => (0x20, index),
+        }
+    }
+}
+/// The event or property a method implements an accessor of.
+#[derive(Copy, Clone, Debug)]
+pub enum HasSemantics {
+    Event(EventIndex),
+    Property(PropertyIndex),
+}
+impl CodedIndex for HasSemantics {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "HasSemantics",
+        tag_bits: 1,
+        tables: &[Some(0x14), Some(0x17)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x14 => Self::Event(EventIndex(index)),
+            _ => Self::Property(PropertyIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::Event(EventIndex(index)) => (0x14, index),
+            Self::Property(PropertyIndex(index)) => (0x17, index),
+        }
+    }
+}
+/// A method defined in this module, or a reference to one.
+#[derive(Copy, Clone, Debug)]
+pub enum MethodDefOrRef {
+    MethodDef(MethodIndex),
+    MemberRef(MemberRefIndex),
+}
+impl CodedIndex for MethodDefOrRef {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "MethodDefOrRef",
+        tag_bits: 1,
+        tables: &[Some(0x06), Some(0x0a)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x06 => Self::MethodDef(MethodIndex(index)),
+            _ => Self::MemberRef(MemberRefIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+            Self::MemberRef(MemberRefIndex(index)) => (0x0a, index),
+        }
+    }
+}
+/// A field or method forwarded to unmanaged code by the ImplMap table.
+#[derive(Copy, Clone, Debug)]
+pub enum MemberForwarded {
+    Field(FieldIndex),
+    MethodDef(MethodIndex),
+}
+impl CodedIndex for MemberForwarded {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "MemberForwarded",
+        tag_bits: 1,
+        tables: &[Some(0x04), Some(0x06)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x04 => Self::Field(FieldIndex(index)),
+            _ => Self::MethodDef(MethodIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::Field(FieldIndex(index)) => (0x04, index),
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+        }
+    }
+}
+/// Where an exported type or manifest resource is defined.
+#[derive(Copy, Clone, Debug)]
+pub enum Implementation {
+    File(FileIndex),
+    AssemblyRef(AssemblyRefIndex),
+    ExportedType(ExportedTypeIndex),
+}
+impl CodedIndex for Implementation {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "Implementation",
+        tag_bits: 2,
+        tables: &[Some(0x26), Some(0x23), Some(0x27)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x26 => Self::File(FileIndex(index)),
+            0x23 => Self::AssemblyRef(AssemblyRefIndex(index)),
+            _ => Self::ExportedType(ExportedTypeIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::File(FileIndex(index)) => (0x26, index),
+            Self::AssemblyRef(AssemblyRefIndex(index)) => (0x23, index),
+            Self::ExportedType(ExportedTypeIndex(index)) => (0x27, index),
+        }
+    }
+}
+/// Constructor of a custom attribute.
+#[derive(Copy, Clone, Debug)]
+pub enum CustomAttributeType {
+    MethodDef(MethodIndex),
+    MemberRef(MemberRefIndex),
+}
+impl CodedIndex for CustomAttributeType {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "CustomAttributeType",
+        tag_bits: 3,
+        tables: &[None, None, Some(0x06), Some(0x0a)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x06 => Self::MethodDef(MethodIndex(index)),
+            _ => Self::MemberRef(MemberRefIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+            Self::MemberRef(MemberRefIndex(index)) => (0x0a, index),
+        }
+    }
+}
+/// Owner of a generic parameter.
+#[derive(Copy, Clone, Debug)]
+pub enum TypeOrMethodDef {
+    TypeDef(TypeDefIndex),
+    MethodDef(MethodIndex),
+}
+impl CodedIndex for TypeOrMethodDef {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "TypeOrMethodDef",
+        tag_bits: 1,
+        tables: &[Some(0x02), Some(0x06)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x02 => Self::TypeDef(TypeDefIndex(index)),
+            _ => Self::MethodDef(MethodIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::TypeDef(TypeDefIndex(index)) => (0x02, index),
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+        }
+    }
+}
+#[test]
+fn width_depends_on_every_target_table() {
+    use crate::type_def::TypeDefOrRef;
+    // Only TypeSpec is present. A 2 bit tag leaves room for 2^14 rows in a 2 byte index.
+    let tables: BitVec64 = (1_u64 << 0x1B).into();
+    let mut out = Vec::new();
+    TypeDefOrRef::TypeSpec(TypeSpecIndex(3)).encode(&mut out, &[(1 << 14) - 1], tables);
+    assert_eq!(out, [0x0E, 0x00]);
+    out.clear();
+    TypeDefOrRef::TypeSpec(TypeSpecIndex(3)).encode(&mut out, &[1 << 14], tables);
+    assert_eq!(out, [0x0E, 0x00, 0x00, 0x00]);
+    let decoded = TypeDefOrRef::decode(&mut &out[..], &[1 << 14], tables).unwrap();
+    assert!(matches!(decoded, TypeDefOrRef::TypeSpec(TypeSpecIndex(3))));
+    // HasCustomAttribute uses 5 tag bits, so 2^11 rows already need 4 bytes.
+    let tables: BitVec64 = (1_u64 << 0x02).into();
+    out.clear();
+    HasCustomAttribute::TypeDef(TypeDefIndex(1)).encode(&mut out, &[1 << 11], tables);
+    assert_eq!(out, [0x23, 0x00, 0x00, 0x00]);
+}
+#[test]
+fn unused_tag() {
+    let tables: BitVec64 = 0.into();
+    assert_eq!(
+        HasConstant::decode(&mut &[0x03, 0x00][..], &[], tables).unwrap_err(),
+        DecodeErrorKind::InvalidCodedIndexTag {
+            coded_index: "HasConstant",
+            tag: 3
+        }
+    );
+}
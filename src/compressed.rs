@@ -0,0 +1,171 @@
+//! Compressed integers, as used by blob lengths and signatures(II.23.2).
+use crate::assembly::{take, take_u8, DecodeErrorKind};
+/// The largest value a compressed unsigned integer can hold.
+pub(crate) const MAX_UNSIGNED: u32 = 0x1FFF_FFFF;
+/// Reads a compressed unsigned integer, and returns it with its width in bits.
+fn decode(slice: &mut &[u8]) -> Result<(u32, u32), DecodeErrorKind> {
+    let first = *slice.first().ok_or(DecodeErrorKind::UnexpectedEnd)?;
+    if first & 0x80 == 0 {
+        take_u8(slice)?;
+        Ok((first as u32, 7))
+    } else if first & 0x40 == 0 {
+        let value = u16::from_be_bytes(take(slice)?);
+        Ok(((value & 0x3FFF) as u32, 14))
+    } else if first & 0x20 == 0 {
+        let value = u32::from_be_bytes(take(slice)?);
+        Ok((value & MAX_UNSIGNED, 29))
+    } else {
+        Err(DecodeErrorKind::InvalidValue {
+            field: "compressed integer",
+            value: first as u64,
+        })
+    }
+}
+/// Reads a compressed unsigned integer from the start of `slice`, and advances it past it.
+pub(crate) fn decode_unsigned(slice: &mut &[u8]) -> Result<u32, DecodeErrorKind> {
+    decode(slice).map(|(value, _)| value)
+}
+/// Reads a compressed signed integer from the start of `slice`, and advances it past it.
+pub(crate) fn decode_signed(slice: &mut &[u8]) -> Result<i32, DecodeErrorKind> {
+    let (value, bits) = decode(slice)?;
+    // The sign bit was rotated into the lowest bit.
+    let magnitude = (value >> 1) as i32;
+    if value & 1 == 0 {
+        Ok(magnitude)
+    } else {
+        Ok(magnitude - (1 << (bits - 1)))
+    }
+}
+/// Appends `value` to `out` as a compressed unsigned integer. Panics if it is larger than [`MAX_UNSIGNED`].
+pub(crate) fn encode_unsigned(value: u32, out: &mut Vec<u8>) {
+    if value < 0x80 {
+        out.push(value as u8);
+    } else if value < 0x4000 {
+        out.extend((value as u16 | 0x8000).to_be_bytes());
+    } else {
+        assert!(
+            value <= MAX_UNSIGNED,
+            "0x{value:x} is too large for a compressed integer"
+        );
+        out.extend((value | 0xC000_0000).to_be_bytes());
+    }
+}
+/// Appends `value` to `out` as a compressed signed integer. Panics if it doesn't fit in 29 bits.
+pub(crate) fn encode_signed(value: i32, out: &mut Vec<u8>) {
+    let bits = if (-(1 << 6)..1 << 6).contains(&value) {
+        7
+    } else if (-(1 << 13)..1 << 13).contains(&value) {
+        14
+    } else {
+        assert!(
+            (-(1 << 28)..1 << 28).contains(&value),
+            "{value} is too large for a compressed integer"
+        );
+        29
+    };
+    // Two's complement in `bits` bits, rotated left by one so that the sign ends up in the lowest bit.
+    let mask = (1_u32 << bits) - 1;
+    let value = value as u32 & mask;
+    let rotated = ((value << 1) | (value >> (bits - 1))) & mask;
+    // The width follows from the value, not from the rotated bits, which may be small even for wide values.
+    match bits {
+        7 => out.push(rotated as u8),
+        14 => out.extend((rotated as u16 | 0x8000).to_be_bytes()),
+        _ => out.extend((rotated | 0xC000_0000).to_be_bytes()),
+    }
+}
+/// Values around every width boundary, followed by pseudo-random values of every width.
+#[cfg(test)]
+fn samples(min: i64, max: i64) -> impl Iterator<Item = i64> {
+    let boundaries = [0, 1, 0x3F, 0x40, 0x7F, 0x80, 0x1FFF, 0x2000, 0x3FFF, 0x4000]
+        .into_iter()
+        .chain([0xFFF_FFFF, 0x1000_0000, MAX_UNSIGNED as i64])
+        .flat_map(|value: i64| [value, -value, value - 1, -value - 1]);
+    let mut state = 0x2545_F491_4F6C_DD1D_u64;
+    let random = std::iter::repeat_with(move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let width = state % 30;
+        (state >> 34) as i64 % (1 << width)
+    })
+    .flat_map(|value| [value, -value])
+    .take(10_000);
+    boundaries
+        .chain(random)
+        .filter(move |value| (min..=max).contains(value))
+}
+#[test]
+fn unsigned_round_trip() {
+    for value in samples(0, MAX_UNSIGNED as i64) {
+        let value = value as u32;
+        let mut encoded = Vec::new();
+        encode_unsigned(value, &mut encoded);
+        let expected_len = match value {
+            0..=0x7F => 1,
+            0x80..=0x3FFF => 2,
+            _ => 4,
+        };
+        assert_eq!(encoded.len(), expected_len, "0x{value:x}");
+        encoded.push(0xAB);
+        let mut slice = &encoded[..];
+        assert_eq!(decode_unsigned(&mut slice), Ok(value));
+        assert_eq!(slice, [0xAB]);
+    }
+}
+#[test]
+fn signed_round_trip() {
+    for value in samples(-(1 << 28), (1 << 28) - 1) {
+        let value = value as i32;
+        let mut encoded = Vec::new();
+        encode_signed(value, &mut encoded);
+        let mut slice = &encoded[..];
+        assert_eq!(decode_signed(&mut slice), Ok(value), "{value}");
+        assert!(slice.is_empty());
+    }
+}
+#[test]
+fn spec_examples() {
+    // The examples of II.23.2.
+    let unsigned = [
+        (0x03, &[0x03][..]),
+        (0x7F, &[0x7F]),
+        (0x80, &[0x80, 0x80]),
+        (0x2E57, &[0xAE, 0x57]),
+        (0x3FFF, &[0xBF, 0xFF]),
+        (0x4000, &[0xC0, 0x00, 0x40, 0x00]),
+        (0x1FFF_FFFF, &[0xDF, 0xFF, 0xFF, 0xFF]),
+    ];
+    for (value, encoded) in unsigned {
+        let mut out = Vec::new();
+        encode_unsigned(value, &mut out);
+        assert_eq!(out, encoded);
+    }
+    let signed = [
+        (3, &[0x06][..]),
+        (-3, &[0x7B]),
+        (64, &[0x80, 0x80]),
+        (-64, &[0x01]),
+        (8192, &[0xC0, 0x00, 0x40, 0x00]),
+        (-8192, &[0x80, 0x01]),
+        (268_435_455, &[0xDF, 0xFF, 0xFF, 0xFE]),
+        (-268_435_456, &[0xC0, 0x00, 0x00, 0x01]),
+    ];
+    for (value, encoded) in signed {
+        let mut out = Vec::new();
+        encode_signed(value, &mut out);
+        assert_eq!(out, encoded, "{value}");
+    }
+    assert_eq!(
+        decode_unsigned(&mut &[0xFF][..]),
+        Err(DecodeErrorKind::InvalidValue {
+            field: "compressed integer",
+            value: 0xFF
+        })
+    );
+    assert_eq!(
+        decode_unsigned(&mut &[0xC0, 0x00][..]),
+        Err(DecodeErrorKind::UnexpectedEnd)
+    );
+}
@@ -1,27 +1,23 @@
 use crate::{
-    assembly::{table_rows, u16_from_slice_at, u32_from_slice_at, BlobIndex, StringIndex},
+    assembly::{read_table_index, write_table_index, BlobIndex, DecodeErrorKind, StringIndex},
     bitvec::BitVec64,
 };
 #[derive(Copy, Clone, Debug)]
 pub struct FieldIndex(pub u32);
 impl FieldIndex {
-    pub(crate) fn decode(table_slice: &mut &[u8], tables_rows: &[u32], tables: BitVec64) -> Self {
-        let typedef_num = table_rows(tables_rows, tables, 0x4).unwrap_or_default();
-        let max = typedef_num;
-        let index = if max > 1 << 16 {
-            let index = u32_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[4..];
-            index
-        } else {
-            let index = u16_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[2..];
-            index as u32
-        };
-        FieldIndex(index)
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x04).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x04);
     }
 }
 #[derive(Clone, Debug)]
-pub struct Field {
+pub(crate) struct Field {
     flags: u16,
     name: StringIndex,
     signature: BlobIndex,
@@ -35,6 +31,10 @@ impl Field {
         }
     }
 
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
     pub fn name(&self) -> StringIndex {
         self.name
     }
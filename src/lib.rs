@@ -1,20 +1,62 @@
-use std::{
-    io::Read,
-    path::{Path, PathBuf},
-};
+#![allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
+use std::io::{Read, Write};
+#[cfg(test)]
+use std::path::{Path, PathBuf};
 
-use assembly::BlobIndex;
 pub(crate) mod assembly;
 pub(crate) mod bitvec;
+pub(crate) mod cil;
+pub(crate) mod coded_index;
+pub(crate) mod compressed;
 pub(crate) mod field;
 pub(crate) mod method;
 pub(crate) mod param;
 pub(crate) mod pe_file;
 pub(crate) mod resolution_scope;
+pub(crate) mod signature;
 pub(crate) mod table;
 pub(crate) mod r#type;
 pub(crate) mod type_def;
+pub(crate) mod view;
+pub(crate) mod win32_resources;
+pub(crate) mod writer;
+
+pub use assembly::{
+    AssemblyReadError, AssemblyRefIndex, CILHeader, CLIFlags, DecodeErrorKind, DecodeLocation,
+    EncodeError, EntryPoint, UserStringIndex,
+};
+pub use cil::{CILOp, CallSite, CallStackEffect, FieldToken, Label, MetadataToken, MethodToken};
+pub use coded_index::{Implementation, MethodDefOrRef, TypeOrMethodDef};
+pub use field::FieldIndex;
+pub use method::{
+    ExceptionClause, ExceptionClauseKind, MemberRefIndex, MemberRefParent, Method, MethodBody,
+    MethodIndex, StandAloneSigIndex,
+};
+pub use param::ParamIndex;
+pub use pe_file::{
+    DataDirectory, HeaderPolicy, Machine, NTHeader, PEFileReadError, PEFormat, PEHeader, PESection,
+    RVA,
+};
+pub use r#type::Type;
+pub use resolution_scope::ResolutionScope;
+pub use signature::{
+    CallingConvention, FieldSignature, LocalsSignature, MemberRefSignature, MethodSpecSignature,
+    PropertySignature, Signature, StandAloneSignature,
+};
+pub use table::{
+    ExportedTypeIndex, FileIndex, GenericParamFlags, GenericParamIndex, ManifestResourceIndex,
+    MethodSpecIndex, ModuleIndex, ModuleRefIndex, Variance,
+};
+pub use type_def::{TypeDefIndex, TypeDefOrRef, TypeRefIndex, TypeSpecIndex};
+pub use view::{
+    Assembly, AssemblyReference, FieldDefinition, GenericParamDefinition,
+    ManifestResourceDefinition, MemberReference, MethodDefinition, MethodSpecification,
+    ParamDefinition, TypeDefinition, TypeReference, Version,
+};
+pub use win32_resources::{ResourceId, VersionInfo, Win32Resource};
+pub use writer::AssemblyBuilder;
 
+#[cfg(test)]
 fn build_ilasm(path: impl AsRef<Path>, is_dll: bool) -> PathBuf {
     let path = path.as_ref();
     let asm_type = if is_dll { "-dll" } else { "-exe" };
@@ -40,44 +82,93 @@ fn build_ilasm(path: impl AsRef<Path>, is_dll: bool) -> PathBuf {
     }
     out_path
 }
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
 #[cfg(test)]
-use {assembly::EncodedAssembly, std::fs::File, table::DecodedTable};
+use std::fs::File;
+#[cfg(test)]
+fn read_all(asm: &Assembly) -> Result<(), AssemblyReadError> {
+    for tpe in asm.types() {
+        let _ = (tpe.name()?, tpe.namespace()?);
+        for method in tpe.methods() {
+            let _ = (method.name()?, method.signature()?, method.body()?);
+            for param in method.params() {
+                let _ = param.name()?;
+            }
+        }
+        for field in tpe.fields() {
+            let _ = field.name()?;
+        }
+    }
+    for tref in asm.type_refs() {
+        let _ = (tref.name()?, tref.namespace()?);
+    }
+    for aref in asm.assembly_refs() {
+        let _ = aref.name()?;
+    }
+    for param in asm.generic_params() {
+        let _ = param.name()?;
+    }
+    for spec in asm.type_specs() {
+        let _ = spec?;
+    }
+    for spec in asm.method_specs() {
+        let _ = spec.instantiation()?;
+    }
+    Ok(())
+}
 #[test]
 fn deser_add_i32() {
     build_ilasm("test/add_i32.il", true);
     let mut file = File::open("test/add_i32.dll").unwrap();
-    let asm = EncodedAssembly::from_file(&mut file).unwrap();
-    let tables: Vec<_> = asm
-        .table_stream()
-        .iter()
-        .map(|table| DecodedTable::decode(table, &asm))
-        .collect();
+    let asm = Assembly::from_file(&mut file).unwrap();
+    read_all(&asm).unwrap();
+    assert!(asm
+        .methods()
+        .any(|method| method.name().unwrap() == "add_i32"));
 }
 #[test]
 fn deser_class_i32() {
     build_ilasm("test/class.il", true);
     let mut file = File::open("test/class.dll").unwrap();
-    let asm = EncodedAssembly::from_file(&mut file).unwrap();
-    let tables: Vec<_> = asm
-        .table_stream()
-        .iter()
-        .map(|table| DecodedTable::decode(table, &asm))
-        .collect();
+    let asm = Assembly::from_file(&mut file).unwrap();
+    read_all(&asm).unwrap();
+    let counter = asm
+        .types()
+        .find(|tpe| tpe.name().unwrap() == "Counter")
+        .unwrap();
+    let field = counter.fields().next().unwrap();
+    assert_eq!(field.name().unwrap(), "count");
+    assert!(matches!(field.signature().unwrap().field_type(), Type::I32));
+    let add = counter.methods().next().unwrap();
+    assert!(add.signature().unwrap().has_this());
 }
 #[test]
 fn deser_binop() {
     build_ilasm("test/binop.il", true);
     let mut file = File::open("test/binop.dll").unwrap();
-    let asm = EncodedAssembly::from_file(&mut file).unwrap();
-    let tables: Vec<_> = asm
-        .table_stream()
-        .iter()
-        .map(|table| DecodedTable::decode(table, &asm))
-        .collect();
-    panic!();
+    let asm = Assembly::from_file(&mut file).unwrap();
+    read_all(&asm).unwrap();
+    let puts = asm
+        .methods()
+        .find(|method| method.name().unwrap() == "puts")
+        .unwrap();
+    assert!(matches!(
+        puts.signature().unwrap().args(),
+        [Type::Ptr(pointee)] if matches!(**pointee, Type::U8)
+    ));
+    let body = puts.body().unwrap().unwrap();
+    // ldarg.0 ldind.i1 stloc.0 ldloc.0 ldc.i4.0 beq bb_1 ldloc.0 conv.i call Console::Write(char) ...
+    let ops = body.ops();
+    let position = |matches: fn(&CILOp) -> bool| ops.iter().position(matches).unwrap();
+    let load = position(|op| matches!(op, CILOp::LDIndI1));
+    let branch = position(|op| matches!(op, CILOp::BEq(_)));
+    let convert = position(|op| matches!(op, CILOp::ConvI));
+    let call = position(|op| matches!(op, CILOp::Call(MethodToken::MemberRef(_))));
+    assert!(load < branch && branch < convert && convert < call);
+    let malloc = asm
+        .methods()
+        .find(|method| method.name().unwrap() == "malloc")
+        .unwrap();
+    assert!(matches!(malloc.signature().unwrap().args(), [Type::USize]));
 }
 trait ReadHelper {
     fn read_u8(self) -> std::io::Result<u8>;
@@ -107,3 +198,47 @@ impl<R: Read> ReadHelper for R {
         Ok(u64::from_le_bytes(tmp))
     }
 }
+trait WriteHelper {
+    fn write_u8(self, value: u8) -> std::io::Result<()>;
+    fn write_u16(self, value: u16) -> std::io::Result<()>;
+    fn write_u32(self, value: u32) -> std::io::Result<()>;
+    fn write_u64(self, value: u64) -> std::io::Result<()>;
+}
+impl<W: Write> WriteHelper for W {
+    fn write_u8(mut self, value: u8) -> std::io::Result<()> {
+        self.write_all(&[value])
+    }
+    fn write_u16(mut self, value: u16) -> std::io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+    fn write_u32(mut self, value: u32) -> std::io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+    fn write_u64(mut self, value: u64) -> std::io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+}
+#[test]
+fn not_a_pe_file() {
+    let mut file = std::io::Cursor::new(b"This is not an assembly".to_vec());
+    assert!(matches!(
+        Assembly::from_file(&mut file),
+        Err(AssemblyReadError::PEError(_))
+    ));
+}
+/// Metadata of the Win32 interop attributes, compiled by Roslyn as part of the Win32 metadata project(MIT licensed).
+#[cfg(test)]
+const INTEROP_WINMD: &str = "test/Windows.Win32.Interop.winmd";
+#[test]
+fn round_trip_compiled_assembly() {
+    let bytes = std::fs::read(INTEROP_WINMD).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(&bytes)).unwrap();
+    read_all(&asm).unwrap();
+    assert_eq!(asm.name().unwrap(), Some("Windows.Win32.Interop"));
+    let mut rewritten = Vec::new();
+    asm.write_to(&mut rewritten).unwrap();
+    assert!(
+        rewritten == bytes,
+        "The rewritten assembly differs from the original"
+    );
+}
@@ -1,30 +1,34 @@
+use std::collections::HashMap;
+
 use crate::{
-    assembly::{table_rows, u16_from_slice_at, u32_from_slice_at, BlobIndex, StringIndex},
+    assembly::{
+        read_table_index, take_u16, take_u32, take_u8, write_table_index, AssemblyReadError,
+        BlobIndex, DecodeErrorKind, DecodeLocation, EncodeError, StringIndex,
+    },
     bitvec::BitVec64,
+    cil::{decode_ops, encode_ops, max_stack, CILOp, CallSite, CallStackEffect, Label},
+    coded_index::{CodedIndex, CodedIndexKind},
     param::ParamIndex,
-    pe_file::{PEFile, RVA},
-    type_def::{TypeDefIndex, TypeDefOrRef, TypeRefIndex},
+    pe_file::{align_up, PEFile, RVA},
+    table::ModuleRefIndex,
+    type_def::{TypeDefIndex, TypeDefOrRef, TypeRefIndex, TypeSpecIndex},
 };
 #[derive(Copy, Clone, Debug)]
 pub struct MethodIndex(pub u32);
 impl MethodIndex {
-    pub(crate) fn decode(table_slice: &mut &[u8], tables_rows: &[u32], tables: BitVec64) -> Self {
-        let typedef_num = table_rows(tables_rows, tables, 0x6).unwrap_or_default();
-        let max = typedef_num;
-        let index = if max > 1 << 16 {
-            let index = u32_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[4..];
-            index
-        } else {
-            let index = u16_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[2..];
-            index as u32
-        };
-        MethodIndex(index)
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x06).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x06);
     }
 }
 #[derive(Copy, Clone, Debug)]
-pub struct MethodDef {
+pub(crate) struct MethodDef {
     rva: u32,
     impl_flags: u16,
     flags: u16,
@@ -45,6 +49,12 @@ impl MethodDef {
     pub(crate) fn signature(&self) -> BlobIndex {
         self.signature
     }
+    pub(crate) fn flags(&self) -> u16 {
+        self.flags
+    }
+    pub(crate) fn impl_flags(&self) -> u16 {
+        self.impl_flags
+    }
     pub(crate) fn new(
         rva: u32,
         impl_flags: u16,
@@ -64,110 +74,512 @@ impl MethodDef {
     }
 }
 #[derive(Debug)]
-pub(crate) struct Method {
+pub struct Method {
     ops: Box<[CILOp]>,
+    max_stack: u16,
+    local_var_sig: Option<StandAloneSigIndex>,
+    init_locals: bool,
+    exception_clauses: Box<[ExceptionClause]>,
 }
-pub(crate) fn decode_method(file: &PEFile, rva: RVA) -> Method {
-    let first_byte = file
-        .slice_at_rva(rva, 1)
-        .expect("Can't find method header!")[0];
-    let tag = first_byte & 0b11;
-    match tag {
-        0x2 => {
-            let len = first_byte >> 2;
-            let mut slice = &file.slice_at_rva(rva, len as u64 + 1).unwrap()[1..];
-            let mut ops = Vec::new();
-            while slice.len() > 0 {
-                ops.push(decode_op(&mut slice));
+impl Method {
+    pub fn ops(&self) -> &[CILOp] {
+        &self.ops
+    }
+    pub fn max_stack(&self) -> u16 {
+        self.max_stack
+    }
+    /// The signature describing the local variables of this method, if it has any.
+    pub fn local_var_sig(&self) -> Option<StandAloneSigIndex> {
+        self.local_var_sig
+    }
+    /// Whether the locals are zero-initialized before the method runs.
+    pub fn init_locals(&self) -> bool {
+        self.init_locals
+    }
+    pub fn exception_clauses(&self) -> &[ExceptionClause] {
+        &self.exception_clauses
+    }
+}
+/// A protected region of a method and its handler, as described in II.25.4.6. Regions start at
+/// their start label, and end right before their end label.
+#[derive(Clone, Debug)]
+pub struct ExceptionClause {
+    pub kind: ExceptionClauseKind,
+    pub try_start: Label,
+    pub try_end: Label,
+    pub handler_start: Label,
+    pub handler_end: Label,
+}
+#[derive(Copy, Clone, Debug)]
+pub enum ExceptionClauseKind {
+    /// Handles exceptions of the given type.
+    Catch(TypeDefOrRef),
+    /// Handles exceptions the filter code starting at the given label accepts.
+    Filter(Label),
+    Finally,
+    Fault,
+}
+/// An exception clause as it is stored in a method body, with offsets and lengths in bytes.
+#[derive(Clone, Debug)]
+struct RawExceptionClause {
+    flags: u32,
+    try_offset: u32,
+    try_length: u32,
+    handler_offset: u32,
+    handler_length: u32,
+    class_token_or_filter: u32,
+}
+impl RawExceptionClause {
+    fn from_clause(
+        clause: &ExceptionClause,
+        label_offsets: &HashMap<Label, u32>,
+    ) -> Result<Self, EncodeError> {
+        let offset = |label: Label| {
+            label_offsets
+                .get(&label)
+                .copied()
+                .ok_or(EncodeError::UndefinedLabel(label))
+        };
+        let length = |start: Label, end: Label| {
+            offset(end)?
+                .checked_sub(offset(start)?)
+                .ok_or(EncodeError::InvalidRegion { start, end })
+        };
+        let (flags, class_token_or_filter) = match clause.kind {
+            ExceptionClauseKind::Catch(class) => (0x0, class.token()),
+            ExceptionClauseKind::Filter(filter) => (0x1, offset(filter)?),
+            ExceptionClauseKind::Finally => (0x2, 0),
+            ExceptionClauseKind::Fault => (0x4, 0),
+        };
+        Ok(Self {
+            flags,
+            try_offset: offset(clause.try_start)?,
+            try_length: length(clause.try_start, clause.try_end)?,
+            handler_offset: offset(clause.handler_start)?,
+            handler_length: length(clause.handler_start, clause.handler_end)?,
+            class_token_or_filter,
+        })
+    }
+    /// Offsets of the labels this clause refers to.
+    fn label_offsets(&self) -> impl Iterator<Item = u32> {
+        let filter = (self.flags == 0x1).then_some(self.class_token_or_filter);
+        [
+            self.try_offset,
+            self.try_offset + self.try_length,
+            self.handler_offset,
+            self.handler_offset + self.handler_length,
+        ]
+        .into_iter()
+        .chain(filter)
+    }
+    fn resolve(
+        &self,
+        label_at: impl Fn(u32) -> Result<Label, DecodeErrorKind>,
+    ) -> Result<ExceptionClause, DecodeErrorKind> {
+        let kind = match self.flags {
+            0x0 => ExceptionClauseKind::Catch(
+                TypeDefOrRef::from_token(self.class_token_or_filter).ok_or(
+                    DecodeErrorKind::InvalidValue {
+                        field: "ClassToken",
+                        value: self.class_token_or_filter as u64,
+                    },
+                )?,
+            ),
+            0x1 => ExceptionClauseKind::Filter(label_at(self.class_token_or_filter)?),
+            0x2 => ExceptionClauseKind::Finally,
+            0x4 => ExceptionClauseKind::Fault,
+            _ => {
+                return Err(DecodeErrorKind::InvalidValue {
+                    field: "exception clause flags",
+                    value: self.flags as u64,
+                })
             }
-            Method { ops: ops.into() }
+        };
+        Ok(ExceptionClause {
+            kind,
+            try_start: label_at(self.try_offset)?,
+            try_end: label_at(self.try_offset + self.try_length)?,
+            handler_start: label_at(self.handler_offset)?,
+            handler_end: label_at(self.handler_offset + self.handler_length)?,
+        })
+    }
+    /// Whether this clause fits into the small format.
+    fn is_small(&self) -> bool {
+        self.try_offset <= u16::MAX as u32
+            && self.try_length <= u8::MAX as u32
+            && self.handler_offset <= u16::MAX as u32
+            && self.handler_length <= u8::MAX as u32
+    }
+    fn encode(&self, out: &mut Vec<u8>, fat: bool) {
+        if fat {
+            for value in [
+                self.flags,
+                self.try_offset,
+                self.try_length,
+                self.handler_offset,
+                self.handler_length,
+            ] {
+                out.extend(value.to_le_bytes());
+            }
+        } else {
+            out.extend((self.flags as u16).to_le_bytes());
+            out.extend((self.try_offset as u16).to_le_bytes());
+            out.push(self.try_length as u8);
+            out.extend((self.handler_offset as u16).to_le_bytes());
+            out.push(self.handler_length as u8);
         }
-        0x3 => todo!("Can't decode fat headers"),
-        _ => todo!("Unsuported method header"),
+        out.extend(self.class_token_or_filter.to_le_bytes());
     }
 }
-fn decode_op(slice: &mut &[u8]) -> CILOp {
-    let byte = slice[0];
-    match byte {
-        0x2 => {
-            *slice = &slice[1..];
-            CILOp::LDArg0.into()
+#[derive(Copy, Clone, Debug)]
+pub struct MemberRefIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct StandAloneSigIndex(pub u32);
+/// A method body with already assembled IL, ready to be written to an image.
+#[derive(Debug, Clone)]
+pub struct MethodBody {
+    code: Box<[u8]>,
+    max_stack: u16,
+    local_var_sig: Option<StandAloneSigIndex>,
+    init_locals: bool,
+    exception_clauses: Box<[RawExceptionClause]>,
+}
+impl MethodBody {
+    pub fn new(code: impl Into<Box<[u8]>>, max_stack: u16) -> Self {
+        Self {
+            code: code.into(),
+            max_stack,
+            local_var_sig: None,
+            init_locals: false,
+            exception_clauses: [].into(),
         }
-        0x3 => {
-            *slice = &slice[1..];
-            CILOp::LDArg1.into()
+    }
+    /// Assembles `ops`, using the shortest encoding of every instruction. `call_site` tells how
+    /// calls change the stack, which is needed to compute MaxStack. Fails if `call_site` fails, if a
+    /// label is used but not defined, or defined twice, or if a region of an exception clause ends
+    /// before it starts.
+    pub fn from_ops(
+        ops: &[CILOp],
+        exception_clauses: &[ExceptionClause],
+        call_site: impl Fn(CallSite) -> Result<CallStackEffect, EncodeError>,
+    ) -> Result<Self, EncodeError> {
+        let (code, label_offsets) = encode_ops(ops)?;
+        let raw_clauses = exception_clauses
+            .iter()
+            .map(|clause| RawExceptionClause::from_clause(clause, &label_offsets))
+            .collect::<Result<_, _>>()?;
+        // Catch and filter handlers start with the exception on the stack.
+        let handlers = exception_clauses
+            .iter()
+            .flat_map(|clause| match clause.kind {
+                ExceptionClauseKind::Catch(_) => vec![(clause.handler_start, 1)],
+                ExceptionClauseKind::Filter(filter) => vec![(clause.handler_start, 1), (filter, 1)],
+                ExceptionClauseKind::Finally | ExceptionClauseKind::Fault => {
+                    vec![(clause.handler_start, 0)]
+                }
+            });
+        let mut body = Self::new(code, max_stack(ops, handlers, call_site)?);
+        body.exception_clauses = raw_clauses;
+        Ok(body)
+    }
+    /// Sets the signature describing the local variables of this method.
+    pub fn with_locals(mut self, local_var_sig: StandAloneSigIndex, init_locals: bool) -> Self {
+        self.local_var_sig = Some(local_var_sig);
+        self.init_locals = init_locals;
+        self
+    }
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+    pub fn max_stack(&self) -> u16 {
+        self.max_stack
+    }
+    /// Whether this body fits into a tiny header, as described in II.25.4.2.
+    fn is_tiny(&self) -> bool {
+        self.code.len() < 64
+            && self.max_stack <= 8
+            && self.local_var_sig.is_none()
+            && self.exception_clauses.is_empty()
+    }
+    /// Appends the header and the code of this body to `out`. Fat headers must be 4 byte aligned,
+    /// which is up to the caller.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        if self.is_tiny() {
+            out.push((self.code.len() as u8) << 2 | 0x2);
+        } else {
+            // Fat format, with a header size of 3 dwords.
+            let mut flags: u16 = 0x3003;
+            if self.init_locals {
+                flags |= 0x10;
+            }
+            if !self.exception_clauses.is_empty() {
+                // MoreSects
+                flags |= 0x8;
+            }
+            out.extend(flags.to_le_bytes());
+            out.extend(self.max_stack.to_le_bytes());
+            out.extend((self.code.len() as u32).to_le_bytes());
+            let local_var_sig = self
+                .local_var_sig
+                .map_or(0, |StandAloneSigIndex(index)| 0x1100_0000 | index);
+            out.extend(local_var_sig.to_le_bytes());
         }
-        0x4 => {
-            *slice = &slice[1..];
-            CILOp::LDArg2.into()
+        out.extend(self.code.iter());
+        if self.exception_clauses.is_empty() {
+            return;
         }
-        0x5 => {
-            *slice = &slice[1..];
-            CILOp::LDArg3.into()
+        // The exception handling section starts at the next 4 byte boundary.
+        out.resize(align_up(out.len() as u32, 4) as usize, 0);
+        let small_size = 4 + self.exception_clauses.len() * 12;
+        let fat = small_size > u8::MAX as usize
+            || !self
+                .exception_clauses
+                .iter()
+                .all(RawExceptionClause::is_small);
+        if fat {
+            let size = 4 + self.exception_clauses.len() as u32 * 24;
+            out.push(0x41);
+            out.extend(&size.to_le_bytes()[..3]);
+        } else {
+            out.extend([0x01, small_size as u8, 0, 0]);
         }
-        0x25 => {
-            *slice = &slice[1..];
-            CILOp::Dup
+        for clause in self.exception_clauses.iter() {
+            clause.encode(out, fat);
         }
-        0x2a => {
-            *slice = &slice[1..];
-            CILOp::Ret
+    }
+}
+pub(crate) fn decode_method(file: &PEFile, rva: RVA) -> Result<Method, AssemblyReadError> {
+    let err = |offset, kind| AssemblyReadError::Decode {
+        location: DecodeLocation::MethodBody(rva),
+        offset,
+        kind,
+    };
+    let at = |offset: u64, size: u64| {
+        let rva = RVA(rva.0 + offset);
+        file.slice_at_rva(rva, size)
+            .ok_or(AssemblyReadError::InvalidRVA {
+                location: DecodeLocation::MethodBody(rva),
+                rva,
+                size,
+            })
+    };
+    let first_byte = at(0, 1)?[0];
+    let tag = first_byte & 0b11;
+    let mut raw_clauses = Vec::new();
+    let mut sections_offset = 0;
+    let (code, header_size, mut method) = match tag {
+        0x2 => {
+            let len = first_byte >> 2;
+            let method = Method {
+                ops: [].into(),
+                max_stack: 8,
+                local_var_sig: None,
+                init_locals: false,
+                exception_clauses: [].into(),
+            };
+            (at(1, len as u64)?, 1, method)
         }
-        0x58 => {
-            *slice = &slice[1..];
-            CILOp::Add
+        0x3 => {
+            let header = at(0, 12)?;
+            let mut header = &header[..];
+            let flags_and_size = take_u16(&mut header).map_err(|kind| err(0, kind))?;
+            let flags = flags_and_size & 0x0FFF;
+            let header_size = (flags_and_size >> 12) as usize * 4;
+            if header_size < 12 {
+                return Err(err(
+                    0,
+                    DecodeErrorKind::InvalidValue {
+                        field: "fat method header size",
+                        value: header_size as u64,
+                    },
+                ));
+            }
+            let max_stack = take_u16(&mut header).map_err(|kind| err(2, kind))?;
+            let code_size = take_u32(&mut header).map_err(|kind| err(4, kind))?;
+            let local_var_sig = match take_u32(&mut header).map_err(|kind| err(8, kind))? {
+                0 => None,
+                token if token >> 24 == 0x11 => Some(StandAloneSigIndex(token & 0x00FF_FFFF)),
+                token => {
+                    return Err(err(
+                        8,
+                        DecodeErrorKind::InvalidValue {
+                            field: "LocalVarSigTok",
+                            value: token as u64,
+                        },
+                    ))
+                }
+            };
+            let code = at(header_size as u64, code_size as u64)?;
+            if flags & 0x8 != 0 {
+                // Data sections start at the first 4 byte boundary after the code.
+                let mut offset = (header_size as u64 + code_size as u64 + 3) & !3;
+                sections_offset = offset as usize;
+                loop {
+                    let section_header = at(offset, 4)?;
+                    let kind = section_header[0];
+                    let data_size = if kind & 0x40 != 0 {
+                        u32::from_le_bytes([
+                            section_header[1],
+                            section_header[2],
+                            section_header[3],
+                            0,
+                        ])
+                    } else {
+                        section_header[1] as u32
+                    };
+                    if data_size < 4 {
+                        return Err(err(
+                            offset as usize + 1,
+                            DecodeErrorKind::InvalidValue {
+                                field: "method data section size",
+                                value: data_size as u64,
+                            },
+                        ));
+                    }
+                    if kind & 0x1 == 0 {
+                        return Err(err(
+                            offset as usize,
+                            DecodeErrorKind::InvalidValue {
+                                field: "method data section kind",
+                                value: kind as u64,
+                            },
+                        ));
+                    }
+                    let mut clauses = &at(offset, data_size as u64)?[4..];
+                    let fat = kind & 0x40 != 0;
+                    while !clauses.is_empty() {
+                        let clause_offset = offset as usize + data_size as usize - clauses.len();
+                        raw_clauses.push(
+                            decode_exception_clause(&mut clauses, fat)
+                                .map_err(|kind| err(clause_offset, kind))?,
+                        );
+                    }
+                    if kind & 0x80 == 0 {
+                        break;
+                    }
+                    offset = (offset + data_size as u64 + 3) & !3;
+                }
+            }
+            let method = Method {
+                ops: [].into(),
+                max_stack,
+                local_var_sig,
+                init_locals: flags & 0x10 != 0,
+                exception_clauses: [].into(),
+            };
+            (code, header_size, method)
         }
-        0x5a => {
-            *slice = &slice[1..];
-            CILOp::Mul
+        _ => {
+            return Err(err(
+                0,
+                DecodeErrorKind::InvalidValue {
+                    field: "method header",
+                    value: first_byte as u64,
+                },
+            ))
         }
-        _ => todo!("Unhandled op 0x{byte:x}!"),
-    }
+    };
+    let region_offsets: Vec<_> = raw_clauses
+        .iter()
+        .flat_map(RawExceptionClause::label_offsets)
+        .collect();
+    let (ops, label_offsets) = decode_ops(&code, &region_offsets)
+        .map_err(|(offset, kind)| err(header_size + offset, kind))?;
+    let label_at = |offset: u32| {
+        let index =
+            label_offsets
+                .binary_search(&offset)
+                .map_err(|_| DecodeErrorKind::InvalidValue {
+                    field: "exception clause offset",
+                    value: offset as u64,
+                })?;
+        Ok(Label(index as u32))
+    };
+    method.ops = ops.into();
+    method.exception_clauses = raw_clauses
+        .iter()
+        .map(|clause| clause.resolve(label_at))
+        .collect::<Result<_, _>>()
+        .map_err(|kind| err(sections_offset, kind))?;
+    Ok(method)
 }
-#[derive(Debug)]
-enum CILOp {
-    LDArg0,
-    LDArg1,
-    LDArg2,
-    LDArg3,
-    //LDArg(u16),
-    Add,
-    Mul,
-    Ret,
-    Dup,
+/// Decodes a single small or fat exception handling clause.
+fn decode_exception_clause(
+    section: &mut &[u8],
+    fat: bool,
+) -> Result<RawExceptionClause, DecodeErrorKind> {
+    let (flags, try_offset, try_length, handler_offset, handler_length) = if fat {
+        (
+            take_u32(section)?,
+            take_u32(section)?,
+            take_u32(section)?,
+            take_u32(section)?,
+            take_u32(section)?,
+        )
+    } else {
+        (
+            take_u16(section)? as u32,
+            take_u16(section)? as u32,
+            take_u8(section)? as u32,
+            take_u16(section)? as u32,
+            take_u8(section)? as u32,
+        )
+    };
+    // Regions are only ever turned into labels at their end offsets, which must not overflow.
+    for (offset, length) in [(try_offset, try_length), (handler_offset, handler_length)] {
+        if offset.checked_add(length).is_none() {
+            return Err(DecodeErrorKind::InvalidValue {
+                field: "exception clause length",
+                value: length as u64,
+            });
+        }
+    }
+    Ok(RawExceptionClause {
+        flags,
+        try_offset,
+        try_length,
+        handler_offset,
+        handler_length,
+        class_token_or_filter: take_u32(section)?,
+    })
 }
-#[derive(Clone, Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum MemberRefParent {
     TypeDef(TypeDefIndex),
     TypeRef(TypeRefIndex),
+    /// A global member of another module.
+    ModuleRef(ModuleRefIndex),
+    /// The method a vararg call site refers to.
     MethodDef(MethodIndex),
+    TypeSpec(TypeSpecIndex),
 }
-impl MemberRefParent {
-    pub(crate) fn decode(table_slice: &mut &[u8], tables_rows: &[u32], tables: BitVec64) -> Self {
-        let typedef_num = table_rows(tables_rows, tables, 0x2).unwrap_or_default();
-        let max = typedef_num;
-        let encoded = if max > (1 << 14) {
-            let encoded = u32_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[4..];
-            encoded
-        } else {
-            let encoded = u16_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[2..];
-            encoded as u32
-        };
-        let tag = encoded & 0b11;
-        let index = tag & (!0b11);
-        match tag {
-            0x00 => MemberRefParent::TypeDef(TypeDefIndex(index)),
-            0x01 => MemberRefParent::TypeRef(TypeRefIndex(index)),
-            //0x02 => MemberRefParent::TypeSpec(TypeSpecIndex(index)),
-            0x3 => MemberRefParent::MethodDef(MethodIndex(index)),
-            _ => panic!("Invalid MemberRefParent tag:{tag}"),
+impl CodedIndex for MemberRefParent {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "MemberRefParent",
+        tag_bits: 3,
+        tables: &[Some(0x02), Some(0x01), Some(0x1A), Some(0x06), Some(0x1B)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x02 => Self::TypeDef(TypeDefIndex(index)),
+            0x01 => Self::TypeRef(TypeRefIndex(index)),
+            0x1A => Self::ModuleRef(ModuleRefIndex(index)),
+            0x06 => Self::MethodDef(MethodIndex(index)),
+            _ => Self::TypeSpec(TypeSpecIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::TypeDef(TypeDefIndex(index)) => (0x02, index),
+            Self::TypeRef(TypeRefIndex(index)) => (0x01, index),
+            Self::ModuleRef(ModuleRefIndex(index)) => (0x1A, index),
+            Self::MethodDef(MethodIndex(index)) => (0x06, index),
+            Self::TypeSpec(TypeSpecIndex(index)) => (0x1B, index),
         }
     }
 }
 #[derive(Clone, Debug)]
-pub struct MemberRef {
+pub(crate) struct MemberRef {
     class: MemberRefParent,
     name: StringIndex,
     signature: BlobIndex,
@@ -181,4 +593,107 @@ impl MemberRef {
             signature,
         }
     }
+
+    pub fn class(&self) -> MemberRefParent {
+        self.class
+    }
+
+    pub fn name(&self) -> StringIndex {
+        self.name
+    }
+
+    pub fn signature(&self) -> BlobIndex {
+        self.signature
+    }
+}
+#[test]
+fn exception_clauses() {
+    // A small catch clause for TypeRef 2, protecting 0x0..0x10 and handled at 0x10..0x18.
+    let small = [
+        0x00, 0x00, 0x00, 0x00, 0x10, 0x10, 0x00, 0x08, 0x02, 0x00, 0x00, 0x01,
+    ];
+    let clause = decode_exception_clause(&mut &small[..], false).unwrap();
+    assert_eq!(
+        (
+            clause.try_offset,
+            clause.try_length,
+            clause.handler_offset,
+            clause.handler_length
+        ),
+        (0, 0x10, 0x10, 8)
+    );
+    assert_eq!(
+        clause.label_offsets().collect::<Vec<_>>(),
+        [0, 0x10, 0x10, 0x18]
+    );
+    let resolved = clause.resolve(|offset| Ok(Label(offset))).unwrap();
+    assert!(matches!(
+        resolved.kind,
+        ExceptionClauseKind::Catch(TypeDefOrRef::TypeRef(TypeRefIndex(2)))
+    ));
+    assert_eq!(
+        (resolved.try_end, resolved.handler_end),
+        (Label(0x10), Label(0x18))
+    );
+    let mut encoded = Vec::new();
+    clause.encode(&mut encoded, false);
+    assert_eq!(encoded, small);
+    // A fat finally clause.
+    let mut fat = Vec::new();
+    for value in [2_u32, 0x100, 0x200, 0x300, 0x10, 0] {
+        fat.extend(value.to_le_bytes());
+    }
+    let clause = decode_exception_clause(&mut &fat[..], true).unwrap();
+    assert_eq!((clause.try_length, clause.handler_offset), (0x200, 0x300));
+    let resolved = clause.resolve(|offset| Ok(Label(offset))).unwrap();
+    assert!(matches!(resolved.kind, ExceptionClauseKind::Finally));
+    // Catch clauses must name a TypeDef, TypeRef or TypeSpec.
+    fat[20..].copy_from_slice(&0x0600_0001_u32.to_le_bytes());
+    fat[..4].copy_from_slice(&0_u32.to_le_bytes());
+    let clause = decode_exception_clause(&mut &fat[..], true).unwrap();
+    assert!(clause.resolve(|offset| Ok(Label(offset))).is_err());
+    // A try region reaching past u32::MAX.
+    fat[4..12].copy_from_slice(&[0xF0, 0xFF, 0xFF, 0xFF, 0x20, 0, 0, 0]);
+    assert!(matches!(
+        decode_exception_clause(&mut &fat[..], true),
+        Err(DecodeErrorKind::InvalidValue {
+            field: "exception clause length",
+            value: 0x20
+        })
+    ));
+}
+#[test]
+fn invalid_exception_clauses() {
+    let ops = [
+        CILOp::Label(Label(0)),
+        CILOp::Nop,
+        CILOp::Label(Label(1)),
+        CILOp::Ret,
+    ];
+    let clause = |try_end, handler_start| ExceptionClause {
+        kind: ExceptionClauseKind::Finally,
+        try_start: Label(1),
+        try_end,
+        handler_start,
+        handler_end: Label(1),
+    };
+    let call_site = |_| {
+        Ok(CallStackEffect {
+            args: 0,
+            returns: false,
+        })
+    };
+    assert!(MethodBody::from_ops(&ops, &[clause(Label(1), Label(0))], call_site).is_ok());
+    // The protected region ends before it starts.
+    assert!(matches!(
+        MethodBody::from_ops(&ops, &[clause(Label(0), Label(0))], call_site),
+        Err(EncodeError::InvalidRegion {
+            start: Label(1),
+            end: Label(0)
+        })
+    ));
+    assert!(matches!(
+        MethodBody::from_ops(&ops, &[clause(Label(1), Label(5))], call_site),
+        Err(EncodeError::UndefinedLabel(Label(5)))
+    ));
 }
@@ -1,61 +1,170 @@
-use super::ReadHelper;
-use std::io::{Read, Seek, SeekFrom, Write};
-#[derive(Debug, Clone, Copy)]
+use super::{ReadHelper, WriteHelper};
+use crate::{
+    assembly::{AssemblyReadError, DecodeLocation},
+    win32_resources::{decode_resources, Win32Resource},
+};
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, SeekFrom, Write},
+};
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RVA(pub u64);
-#[derive(Debug, Clone, Copy)]
-pub struct VA(u64);
-#[derive(Debug)]
-struct PESection {
-    section_type: SectionType,
+/// Alignment of sections in memory, used when laying out new images.
+pub(crate) const SECTION_ALIGNMENT: u32 = 0x2000;
+/// Alignment of sections in the file, used when laying out new images.
+pub(crate) const FILE_ALIGNMENT: u32 = 0x200;
+/// Image base of new PE32 images.
+const IMAGE_BASE: u32 = 0x400000;
+/// Rounds `value` up to the next multiple of `alignment`, which must be a power of 2.
+pub(crate) fn align_up(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+/// A section of an image, with its raw data as stored in the file.
+#[derive(Debug, Clone)]
+pub struct PESection {
+    name: [u8; 8],
     virtual_adress: u32,
+    virtual_size: u32,
     characteristics: u32,
+    /// Bytes between the end of the previous section(or the headers) and this one, kept so that the file can be
+    /// written back unchanged.
+    padding_before: Vec<u8>,
+    /// Raw data of the section, including the padding up to its file alignment.
     data: Vec<u8>,
 }
+/// Reads exactly `len` bytes. The length comes from the file, so the buffer grows with the data actually read
+/// instead of being allocated upfront.
+fn read_bytes(file: &mut impl Read, len: u64) -> Result<Vec<u8>, PEFileReadError> {
+    let mut bytes = Vec::new();
+    file.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
 impl PESection {
+    /// Reads the section described by `section_header`, with `position` being the current offset in the file.
     fn from_file(
         file: &mut (impl Read + Seek),
         section_header: &SectionHeader,
-        header_end: u64,
+        position: &mut u64,
     ) -> Result<Self, PEFileReadError> {
-        let section_type = section_header.section_type;
-        let mut data = vec![0; section_header.virtual_size as usize];
-        //println!("section_header.offset_of_raw_data:{}",section_header.offset_of_raw_data);
-        file.seek(SeekFrom::Start(section_header.offset_of_raw_data as u64))?;
-        file.read_exact(&mut data)?;
+        let offset = section_header.offset_of_raw_data as u64;
+        let size = section_header.size_of_raw_data as u64;
+        let mut padding_before = Vec::new();
+        if size != 0 {
+            if offset >= *position {
+                padding_before = read_bytes(file, offset - *position)?;
+            } else {
+                file.seek(SeekFrom::Start(offset))?;
+            }
+            *position = offset + size;
+        }
+        let data = read_bytes(file, size)?;
         Ok(Self {
-            section_type,
+            name: section_header.name,
             virtual_adress: section_header.virtual_adress,
+            virtual_size: section_header.virtual_size,
             characteristics: section_header.characteristics,
+            padding_before,
             data,
         })
     }
-    fn slice_at_rva(&self, rva: RVA, length: u64) -> Option<&[u8]> {
-        println!("Trying to get slice of length {length} starting at {rva:?}. self.virtual_adress is {virtual_adress}. length:{length}",virtual_adress = self.virtual_adress,length = self.data.len());
-        let start = self.virtual_adress as u64;
-        let end = self.virtual_adress as u64 + self.data.len() as u64;
-        if start <= rva.0 && (rva.0 + length) < end {
-            let data_start = rva.0 - self.virtual_adress as u64;
-            let data_end = data_start + length;
-            Some(&self.data[data_start as usize..data_end as usize])
+    /// Name of the section, without the null padding.
+    pub fn name(&self) -> &[u8] {
+        let null = self.name.iter().position(|c| *c == 0).unwrap_or(8);
+        &self.name[..null]
+    }
+    pub fn virtual_address(&self) -> u32 {
+        self.virtual_adress
+    }
+    /// Size of the section once loaded. When it is larger than the raw data, the rest is filled with zeroes.
+    pub fn virtual_size(&self) -> u32 {
+        self.virtual_size
+    }
+    pub fn characteristics(&self) -> u32 {
+        self.characteristics
+    }
+    /// Raw data of the section, including the padding up to the file alignment, which is not loaded if it lies
+    /// past the virtual size.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    /// Size of the section in memory. A virtual size of 0 means the size of the raw data.
+    fn loaded_size(&self) -> u64 {
+        if self.virtual_size == 0 {
+            self.data.len() as u64
         } else {
-            None
+            self.virtual_size as u64
+        }
+    }
+    /// Offset of `rva` in this section, if `length` bytes starting at it are loaded from this section.
+    fn offset_of(&self, rva: RVA, length: u64) -> Option<u64> {
+        let offset = rva.0.checked_sub(self.virtual_adress as u64)?;
+        (offset.checked_add(length)? <= self.loaded_size()).then_some(offset)
+    }
+    fn slice_at_rva(&self, rva: RVA, length: u64) -> Option<Cow<'_, [u8]>> {
+        let start = self.offset_of(rva, length)?;
+        let end = start + length;
+        let raw_end = self.loaded_size().min(self.data.len() as u64);
+        if end <= raw_end {
+            return Some(Cow::Borrowed(&self.data[start as usize..end as usize]));
+        }
+        // The part past the raw data is zero-filled.
+        let mut bytes = vec![0; length as usize];
+        if start < raw_end {
+            bytes[..(raw_end - start) as usize]
+                .copy_from_slice(&self.data[start as usize..raw_end as usize]);
         }
+        Some(Cow::Owned(bytes))
+    }
+    fn slice_at_rva_mut(&mut self, rva: RVA, length: u64) -> Option<&mut [u8]> {
+        let start = self.offset_of(rva, length)?;
+        let end = start + length;
+        self.data.get_mut(start as usize..end as usize)
     }
 }
 #[derive(Debug, Clone, Copy)]
-enum SectionType {
+pub(crate) enum SectionType {
     Text,
+    Rsrc,
     Reloc,
 }
-#[derive(Debug)]
+impl SectionType {
+    fn name(self) -> [u8; 8] {
+        match self {
+            Self::Text => *b".text\0\0\0",
+            Self::Rsrc => *b".rsrc\0\0\0",
+            Self::Reloc => *b".reloc\0\0",
+        }
+    }
+    /// Characteristics of newly created sections of this type.
+    fn characteristics(self) -> u32 {
+        match self {
+            // CNT_CODE | MEM_EXECUTE | MEM_READ
+            Self::Text => 0x6000_0020,
+            // CNT_INITIALIZED_DATA | MEM_READ
+            Self::Rsrc => 0x4000_0040,
+            // CNT_INITIALIZED_DATA | MEM_DISCARDABLE | MEM_READ
+            Self::Reloc => 0x4200_0040,
+        }
+    }
+}
+#[derive(Debug, Clone)]
 struct SectionHeader {
-    section_type: SectionType,
+    name: [u8; 8],
     virtual_size: u32,
     virtual_adress: u32,
     size_of_raw_data: u32,
     offset_of_raw_data: u32,
+    /// Relocations and line numbers are only used by object files, but are kept to write the header back.
+    relocations: u32,
+    line_numbers: u32,
+    relocation_count: u16,
+    line_number_count: u16,
     characteristics: u32,
 }
+#[derive(Debug, Clone, Copy)]
 enum Subsystem {
     //None,
     CUI,
@@ -66,18 +175,108 @@ impl TryFrom<u16> for Subsystem {
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
             //0=>Ok(Subsystem::None),
-            2 => Ok(Subsystem::CUI),
-            3 => Ok(Subsystem::GUI),
+            2 => Ok(Subsystem::GUI),
+            3 => Ok(Subsystem::CUI),
             _ => Err(PEFileReadError::WrongSubsystem(value)),
         }
     }
 }
-#[derive(Debug)]
+impl From<Subsystem> for u16 {
+    fn from(value: Subsystem) -> Self {
+        match value {
+            Subsystem::GUI => 2,
+            Subsystem::CUI => 3,
+        }
+    }
+}
+/// A `(RVA, size)` pair from the data directory of the optional header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DataDirectory {
+    pub rva: u32,
+    pub size: u32,
+}
+impl DataDirectory {
+    fn from_file(file: &mut impl Read) -> Result<Self, PEFileReadError> {
+        let rva = file.read_u32()?;
+        let size = file.read_u32()?;
+        Ok(Self { rva, size })
+    }
+    fn is_empty(&self) -> bool {
+        self.rva == 0 && self.size == 0
+    }
+}
+/// Format of the optional header, which decides the width of the addresses and sizes in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PEFormat {
+    PE32,
+    /// The 64 bit format, used by images targeting x64 or ARM64.
+    PE32Plus,
+}
+impl PEFormat {
+    fn from_magic(magic: u16) -> Result<Self, PEFileReadError> {
+        match magic {
+            0x10B => Ok(Self::PE32),
+            0x20B => Ok(Self::PE32Plus),
+            _ => Err(PEFileReadError::WrongMagic),
+        }
+    }
+    fn magic(self) -> u16 {
+        match self {
+            Self::PE32 => 0x10B,
+            Self::PE32Plus => 0x20B,
+        }
+    }
+    fn optional_header_size(self) -> u16 {
+        match self {
+            Self::PE32 => 224,
+            Self::PE32Plus => 240,
+        }
+    }
+    /// Stack reserve, stack commit, heap reserve and heap commit sizes the compilers emit.
+    fn stack_and_heap_sizes(self) -> [u64; 4] {
+        match self {
+            Self::PE32 => [0x100000, 0x1000, 0x100000, 0x1000],
+            Self::PE32Plus => [0x400000, 0x4000, 0x100000, 0x2000],
+        }
+    }
+}
+/// The processor a new image targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    I386,
+    AMD64,
+    ARM64,
+}
+impl Machine {
+    fn value(self) -> u16 {
+        match self {
+            Self::I386 => 0x14C,
+            Self::AMD64 => 0x8664,
+            Self::ARM64 => 0xAA64,
+        }
+    }
+    pub fn format(self) -> PEFormat {
+        match self {
+            Self::I386 => PEFormat::PE32,
+            Self::AMD64 | Self::ARM64 => PEFormat::PE32Plus,
+        }
+    }
+}
+pub(crate) const IMPORT_TABLE_DIRECTORY: usize = 1;
+pub(crate) const RESOURCE_DIRECTORY: usize = 2;
+pub(crate) const BASE_RELOCATION_DIRECTORY: usize = 5;
+pub(crate) const IAT_DIRECTORY: usize = 12;
+pub(crate) const CLI_HEADER_DIRECTORY: usize = 14;
+#[derive(Debug, Clone)]
 pub struct PEFile {
+    /// Bytes between the end of the DOS stub and the PE signature.
+    dos_stub_tail: Vec<u8>,
     header: PEHeader,
     sections: Vec<PESection>,
+    /// Bytes after the last section.
+    trailer: Vec<u8>,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct PEFileHeader {
     machine: u16,
     section_count: u16,
@@ -87,21 +286,28 @@ struct PEFileHeader {
     optional_header_size: u16,
     characteristics: u16,
 }
-#[derive(Debug)]
+/// The COFF file header and the optional header of an image. Values are kept as read, and only checked when
+/// reading with [`HeaderPolicy::Strict`].
+#[derive(Debug, Clone)]
 pub struct PEHeader {
     file_header: PEFileHeader,
+    format: PEFormat,
+    linker_major: u8,
+    linker_minor: u8,
     code_size: u32,
     init_data_size: u32,
     uninit_data_size: u32,
     entrypoint_rva: u32,
     code_rva: u32,
+    /// BaseOfData, which PE32+ headers don't have.
     data_rva: u32,
     nt_header: NTHeader,
     sections: Vec<SectionHeader>,
 }
-#[derive(Debug)]
+/// The Windows specific fields of the optional header, and its data directory.
+#[derive(Debug, Clone)]
 pub struct NTHeader {
-    image_base: u32,
+    image_base: u64,
     section_algiement: u32,
     file_aligement: u32,
     os_major: u16,
@@ -110,145 +316,177 @@ pub struct NTHeader {
     user_minor: u16,
     subsys_major: u16,
     subsys_minor: u16,
-    cil_header: u32,
-    cil_header_size: u32,
+    win32_version: u32,
+    image_size: u32,
+    header_size: u32,
+    checksum: u32,
+    subsystem: u16,
+    dll_flags: u16,
+    stack_reserve_size: u64,
+    stack_commit_size: u64,
+    heap_reserve_size: u64,
+    heap_commit_size: u64,
+    loader_flags: u32,
+    data_directories: [DataDirectory; 16],
 }
-impl TryFrom<&str> for SectionType {
-    type Error = PEFileReadError;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            ".text" => Ok(Self::Text),
-            ".reloc" => Ok(Self::Reloc),
-            _ => Err(PEFileReadError::UnknownSectionName(value.into())),
-        }
-    }
+/// How strictly the headers of an image are checked when it is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    /// Accepts any value which doesn't change the layout of the headers.
+    #[default]
+    Lenient,
+    /// Only accepts the values the compilers emit for IL only images, without Win32 resources, signatures
+    /// or native code.
+    Strict,
 }
 impl SectionHeader {
     fn from_file(file: &mut impl Read) -> Result<Self, PEFileReadError> {
         let mut name = [0; 8];
         file.read_exact(&mut name)?;
-        let null = name.iter().position(|c| *c == 0).unwrap_or(8);
-        let name = std::str::from_utf8(&name[..null]).expect("Not utf8 section name!");
-        let section_type = SectionType::try_from(name)?;
-        let virtual_size = file.read_u32()?;
-        println!("virtual_size:{virtual_size}");
-        let virtual_adress = file.read_u32()?;
-        println!("virtual_adress:{virtual_adress}");
-        let size_of_raw_data = file.read_u32()?;
-        println!("size_of_raw_data:{size_of_raw_data}");
-        let offset_of_raw_data = file.read_u32()?;
-        println!("offset_of_raw_data:{offset_of_raw_data}");
-        let reallcations = file.read_u32()?;
-        assert_eq!(reallcations, 0);
-        let line_numbers = file.read_u32()?;
-        assert_eq!(line_numbers, 0);
-        let reallcation_count = file.read_u16()?;
-        assert_eq!(reallcation_count, 0);
-        let line_number_count = file.read_u16()?;
-        assert_eq!(line_number_count, 0);
-        let characteristics = file.read_u32()?;
         Ok(Self {
-            section_type,
-            virtual_size,
-            virtual_adress,
-            size_of_raw_data,
-            offset_of_raw_data,
-            characteristics,
+            name,
+            virtual_size: file.read_u32()?,
+            virtual_adress: file.read_u32()?,
+            size_of_raw_data: file.read_u32()?,
+            offset_of_raw_data: file.read_u32()?,
+            relocations: file.read_u32()?,
+            line_numbers: file.read_u32()?,
+            relocation_count: file.read_u16()?,
+            line_number_count: file.read_u16()?,
+            characteristics: file.read_u32()?,
         })
     }
+    /// Checks done by [`HeaderPolicy::Strict`].
+    fn validate(&self) -> Result<(), PEFileReadError> {
+        if self.relocations != 0 || self.relocation_count != 0 {
+            return Err(PEFileReadError::SectionRelocationsPresent);
+        }
+        if self.line_numbers != 0 || self.line_number_count != 0 {
+            return Err(PEFileReadError::SectionLineNumbersPresent);
+        }
+        Ok(())
+    }
+    fn serialize(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_all(&self.name)?;
+        out.write_u32(self.virtual_size)?;
+        out.write_u32(self.virtual_adress)?;
+        out.write_u32(self.size_of_raw_data)?;
+        out.write_u32(self.offset_of_raw_data)?;
+        out.write_u32(self.relocations)?;
+        out.write_u32(self.line_numbers)?;
+        out.write_u16(self.relocation_count)?;
+        out.write_u16(self.line_number_count)?;
+        out.write_u32(self.characteristics)
+    }
 }
 impl NTHeader {
     pub fn cil_header(&self) -> RVA {
-        RVA(self.cil_header as u64)
+        RVA(self.data_directories[CLI_HEADER_DIRECTORY].rva as u64)
     }
     pub fn cil_header_size(&self) -> u32 {
-        self.cil_header_size
+        self.data_directories[CLI_HEADER_DIRECTORY].size
     }
-    fn from_file(file: &mut impl Read) -> Result<Self, PEFileReadError> {
-        let image_base = file.read_u32()?;
+    pub fn image_base(&self) -> u64 {
+        self.image_base
+    }
+    pub fn section_alignment(&self) -> u32 {
+        self.section_algiement
+    }
+    pub fn file_alignment(&self) -> u32 {
+        self.file_aligement
+    }
+    pub fn os_version(&self) -> (u16, u16) {
+        (self.os_major, self.os_minor)
+    }
+    pub fn image_version(&self) -> (u16, u16) {
+        (self.user_major, self.user_minor)
+    }
+    pub fn subsystem_version(&self) -> (u16, u16) {
+        (self.subsys_major, self.subsys_minor)
+    }
+    /// The reserved Win32VersionValue field, which should be 0.
+    pub fn win32_version(&self) -> u32 {
+        self.win32_version
+    }
+    pub fn image_size(&self) -> u32 {
+        self.image_size
+    }
+    pub fn header_size(&self) -> u32 {
+        self.header_size
+    }
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+    /// The subsystem, such as 2 for GUI and 3 for console applications.
+    pub fn subsystem(&self) -> u16 {
+        self.subsystem
+    }
+    pub fn dll_flags(&self) -> u16 {
+        self.dll_flags
+    }
+    pub fn stack_reserve_size(&self) -> u64 {
+        self.stack_reserve_size
+    }
+    pub fn stack_commit_size(&self) -> u64 {
+        self.stack_commit_size
+    }
+    pub fn heap_reserve_size(&self) -> u64 {
+        self.heap_reserve_size
+    }
+    pub fn heap_commit_size(&self) -> u64 {
+        self.heap_commit_size
+    }
+    pub fn loader_flags(&self) -> u32 {
+        self.loader_flags
+    }
+    /// All 16 entries of the data directory, indexed as in the PE specification: 0 is the export table, 2
+    /// the resource table, 14 the CLI header, and so on.
+    pub fn data_directories(&self) -> &[DataDirectory; 16] {
+        &self.data_directories
+    }
+    /// Fields whose width depends on the format: 32 bits in PE32 images, 64 in PE32+ ones.
+    fn read_address(file: &mut impl Read, format: PEFormat) -> std::io::Result<u64> {
+        match format {
+            PEFormat::PE32 => file.read_u32().map(u64::from),
+            PEFormat::PE32Plus => file.read_u64(),
+        }
+    }
+    fn write_address(out: &mut impl Write, format: PEFormat, value: u64) -> std::io::Result<()> {
+        match format {
+            PEFormat::PE32 => out.write_u32(value as u32),
+            PEFormat::PE32Plus => out.write_u64(value),
+        }
+    }
+    fn from_file(file: &mut impl Read, format: PEFormat) -> Result<Self, PEFileReadError> {
+        let image_base = Self::read_address(file, format)?;
         let section_algiement = file.read_u32()?;
         let file_aligement = file.read_u32()?;
-        if file_aligement != 512 {
-            return Err(PEFileReadError::WrongFileAligement);
-        }
-        println!("file_aligement:{file_aligement}");
         let os_major = file.read_u16()?;
-
         let os_minor = file.read_u16()?;
-
         let user_major = file.read_u16()?;
         let user_minor = file.read_u16()?;
         let subsys_major = file.read_u16()?;
-        println!("subsys_major:{subsys_major}");
         let subsys_minor = file.read_u16()?;
-        let reserved = file.read_u32()?;
-        if reserved != 0 {
-            return Err(PEFileReadError::WrongMagic);
-        }
-        let _image_size = file.read_u32()?;
-        let _header_size = file.read_u32()?;
+        let win32_version = file.read_u32()?;
+        let image_size = file.read_u32()?;
+        let header_size = file.read_u32()?;
         let checksum = file.read_u32()?;
-        if checksum != 0 {
-            return Err(PEFileReadError::WrongChecksum);
-        }
-        let _subsystem = Subsystem::try_from(file.read_u16()?)?;
-        let _dll_flags = file.read_u16()?;
-        let stack_reserve_size = file.read_u32()?;
-        if stack_reserve_size != 0x100000 {
-            return Err(PEFileReadError::WrongStackReserve);
-        }
-        let stack_commit_size = file.read_u32()?;
-        if stack_commit_size != 0x1000 {
-            return Err(PEFileReadError::WrongStackCommit);
-        }
-        let heap_reserve_size = file.read_u32()?;
-        if heap_reserve_size != 0x100000 {
-            return Err(PEFileReadError::WrongHeapReserve);
-        }
-        let heap_commit_size = file.read_u32()?;
-        if heap_commit_size != 0x1000 {
-            return Err(PEFileReadError::WrongHeapCommit);
-        }
+        let subsystem = file.read_u16()?;
+        let dll_flags = file.read_u16()?;
+        let stack_reserve_size = Self::read_address(file, format)?;
+        let stack_commit_size = Self::read_address(file, format)?;
+        let heap_reserve_size = Self::read_address(file, format)?;
+        let heap_commit_size = Self::read_address(file, format)?;
         let loader_flags = file.read_u32()?;
-        if loader_flags != 0 {
-            return Err(PEFileReadError::WrongLoaderFlags);
-        }
+        // The size of the optional header is fixed, so it must have room for exactly 16 directories.
         let data_dir_count = file.read_u32()?;
         if data_dir_count != 0x10 {
             return Err(PEFileReadError::WrongDirectoryCount);
         }
-        let export_table = file.read_u64()?;
-        if export_table != 0 {
-            return Err(PEFileReadError::ExportTablePresent);
-        }
-        let _import_table = file.read_u64()?;
-        let resource_table = file.read_u64()?;
-        if resource_table != 0 {
-            return Err(PEFileReadError::ResourceTablePresent);
-        }
-        let exception_table = file.read_u64()?;
-        if exception_table != 0 {
-            return Err(PEFileReadError::ExceptionTablePresent);
-        }
-        let certificate_table = file.read_u64()?;
-        if certificate_table != 0 {
-            return Err(PEFileReadError::CertificateTablePresent);
-        }
-        let _base_reallocation_table = file.read_u64()?;
-        let _debug = file.read_u64()?;
-        let _copyright = file.read_u64()?;
-        let _global_ptr = file.read_u64()?;
-        let _tls_table = file.read_u64()?;
-        let _load_config_table = file.read_u64()?;
-        let _bound_import = file.read_u64()?;
-        let _iat: u64 = file.read_u64()?;
-        let delay_import_descr = file.read_u64()?;
-        assert_eq!(delay_import_descr, 0);
-        let cil_header = file.read_u32()?;
-        let cil_header_size = file.read_u32()?;
-        let reserved = file.read_u64()?;
-        assert_eq!(reserved, 0);
+        let mut data_directories = [DataDirectory::default(); 16];
+        for directory in &mut data_directories {
+            *directory = DataDirectory::from_file(file)?;
+        }
         Ok(Self {
             image_base,
             section_algiement,
@@ -259,47 +497,177 @@ impl NTHeader {
             user_minor,
             subsys_major,
             subsys_minor,
-            cil_header,
-            cil_header_size,
+            win32_version,
+            image_size,
+            header_size,
+            checksum,
+            subsystem,
+            dll_flags,
+            stack_reserve_size,
+            stack_commit_size,
+            heap_reserve_size,
+            heap_commit_size,
+            loader_flags,
+            data_directories,
         })
     }
-    fn serialize(&self, out: &mut (impl Write + Seek)) -> std::io::Result<()> {
-        todo!()
+    /// Checks done by [`HeaderPolicy::Strict`].
+    fn validate(&self, format: PEFormat) -> Result<(), PEFileReadError> {
+        if self.file_aligement != FILE_ALIGNMENT {
+            return Err(PEFileReadError::WrongFileAligement);
+        }
+        if self.win32_version != 0 {
+            return Err(PEFileReadError::WrongWin32Version);
+        }
+        if self.checksum != 0 {
+            return Err(PEFileReadError::WrongChecksum);
+        }
+        Subsystem::try_from(self.subsystem)?;
+        let [stack_reserve, stack_commit, heap_reserve, heap_commit] =
+            format.stack_and_heap_sizes();
+        if self.stack_reserve_size != stack_reserve {
+            return Err(PEFileReadError::WrongStackReserve);
+        }
+        if self.stack_commit_size != stack_commit {
+            return Err(PEFileReadError::WrongStackCommit);
+        }
+        if self.heap_reserve_size != heap_reserve {
+            return Err(PEFileReadError::WrongHeapReserve);
+        }
+        if self.heap_commit_size != heap_commit {
+            return Err(PEFileReadError::WrongHeapCommit);
+        }
+        if self.loader_flags != 0 {
+            return Err(PEFileReadError::WrongLoaderFlags);
+        }
+        let must_be_empty = [
+            (0, PEFileReadError::ExportTablePresent),
+            (2, PEFileReadError::ResourceTablePresent),
+            (3, PEFileReadError::ExceptionTablePresent),
+            (4, PEFileReadError::CertificateTablePresent),
+            (13, PEFileReadError::DelayImportTablePresent),
+            (15, PEFileReadError::WrongReservedDirectory),
+        ];
+        for (directory, error) in must_be_empty {
+            if !self.data_directories[directory].is_empty() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+    fn serialize(&self, out: &mut impl Write, format: PEFormat) -> std::io::Result<()> {
+        Self::write_address(out, format, self.image_base)?;
+        out.write_u32(self.section_algiement)?;
+        out.write_u32(self.file_aligement)?;
+        out.write_u16(self.os_major)?;
+        out.write_u16(self.os_minor)?;
+        out.write_u16(self.user_major)?;
+        out.write_u16(self.user_minor)?;
+        out.write_u16(self.subsys_major)?;
+        out.write_u16(self.subsys_minor)?;
+        out.write_u32(self.win32_version)?;
+        out.write_u32(self.image_size)?;
+        out.write_u32(self.header_size)?;
+        out.write_u32(self.checksum)?;
+        out.write_u16(self.subsystem)?;
+        out.write_u16(self.dll_flags)?;
+        for size in [
+            self.stack_reserve_size,
+            self.stack_commit_size,
+            self.heap_reserve_size,
+            self.heap_commit_size,
+        ] {
+            Self::write_address(out, format, size)?;
+        }
+        out.write_u32(self.loader_flags)?;
+        out.write_u32(self.data_directories.len() as u32)?;
+        for directory in &self.data_directories {
+            out.write_u32(directory.rva)?;
+            out.write_u32(directory.size)?;
+        }
+        Ok(())
     }
 }
 impl PEHeader {
     pub fn nt_header(&self) -> &NTHeader {
         &self.nt_header
     }
-    fn from_file(file: &mut (impl Read + Seek)) -> Result<(Self, u64), PEFileReadError> {
+    pub fn format(&self) -> PEFormat {
+        self.format
+    }
+    pub fn machine(&self) -> u16 {
+        self.file_header.machine
+    }
+    pub fn timestamp(&self) -> u32 {
+        self.file_header.timestamp
+    }
+    /// The characteristics of the COFF file header, such as 0x2000 for DLLs.
+    pub fn characteristics(&self) -> u16 {
+        self.file_header.characteristics
+    }
+    pub fn linker_version(&self) -> (u8, u8) {
+        (self.linker_major, self.linker_minor)
+    }
+    pub fn code_size(&self) -> u32 {
+        self.code_size
+    }
+    pub fn initialized_data_size(&self) -> u32 {
+        self.init_data_size
+    }
+    pub fn uninitialized_data_size(&self) -> u32 {
+        self.uninit_data_size
+    }
+    pub fn entrypoint(&self) -> RVA {
+        RVA(self.entrypoint_rva as u64)
+    }
+    pub fn code_base(&self) -> u32 {
+        self.code_rva
+    }
+    /// BaseOfData, or `None` for PE32+ images, which don't have it.
+    pub fn data_base(&self) -> Option<u32> {
+        (self.format == PEFormat::PE32).then_some(self.data_rva)
+    }
+    fn from_file(
+        file: &mut (impl Read + Seek),
+        policy: HeaderPolicy,
+    ) -> Result<(Self, u64), PEFileReadError> {
         let file_header = PEFileHeader::from_file(file)?;
-        let magic = file.read_u16()?;
-        if magic != 0x10B {
-            return Err(PEFileReadError::WrongMagic);
-        }
-        let lmajor = file.read_u8()?;
-        if lmajor != 6 {
-            return Err(PEFileReadError::WrongMagic);
-        }
-        let lminor = file.read_u8()?;
-        if lminor != 0 {
-            return Err(PEFileReadError::WrongMagic);
+        let format = PEFormat::from_magic(file.read_u16()?)?;
+        if file_header.optional_header_size != format.optional_header_size() {
+            return Err(PEFileReadError::UnsupportedOptionalHeaderSize(
+                file_header.optional_header_size,
+            ));
         }
+        let linker_major = file.read_u8()?;
+        let linker_minor = file.read_u8()?;
         let code_size = file.read_u32()?;
         let init_data_size = file.read_u32()?;
         let uninit_data_size = file.read_u32()?;
         let entrypoint_rva = file.read_u32()?;
         let code_rva = file.read_u32()?;
-        let data_rva = file.read_u32()?;
-        let nt_header = NTHeader::from_file(file)?;
+        let data_rva = match format {
+            PEFormat::PE32 => file.read_u32()?,
+            PEFormat::PE32Plus => 0,
+        };
+        let nt_header = NTHeader::from_file(file, format)?;
+        if policy == HeaderPolicy::Strict {
+            nt_header.validate(format)?;
+        }
         let header_end = file.stream_position()?;
         let mut sections = Vec::with_capacity(file_header.section_count as usize);
         for _ in 0..(file_header.section_count) {
-            sections.push(SectionHeader::from_file(file)?);
+            let section = SectionHeader::from_file(file)?;
+            if policy == HeaderPolicy::Strict {
+                section.validate()?;
+            }
+            sections.push(section);
         }
         Ok((
             Self {
                 file_header,
+                format,
+                linker_major,
+                linker_minor,
                 code_size,
                 init_data_size,
                 uninit_data_size,
@@ -312,6 +680,25 @@ impl PEHeader {
             header_end,
         ))
     }
+    fn serialize(&self, out: &mut impl Write) -> std::io::Result<()> {
+        self.file_header.serialize(out)?;
+        out.write_u16(self.format.magic())?;
+        out.write_u8(self.linker_major)?;
+        out.write_u8(self.linker_minor)?;
+        out.write_u32(self.code_size)?;
+        out.write_u32(self.init_data_size)?;
+        out.write_u32(self.uninit_data_size)?;
+        out.write_u32(self.entrypoint_rva)?;
+        out.write_u32(self.code_rva)?;
+        if self.format == PEFormat::PE32 {
+            out.write_u32(self.data_rva)?;
+        }
+        self.nt_header.serialize(out, self.format)?;
+        for section in &self.sections {
+            section.serialize(out)?;
+        }
+        Ok(())
+    }
 }
 impl PEFileHeader {
     fn from_file(file: &mut (impl Read + Seek)) -> Result<Self, PEFileReadError> {
@@ -321,10 +708,6 @@ impl PEFileHeader {
         let symbol_table_offset = file.read_u32()?;
         let symbol_table_size = file.read_u32()?;
         let optional_header_size = file.read_u16()?;
-        if optional_header_size != 224 {
-            todo!("Can't yet handle PE files with headers with size that differes form 224.");
-        }
-        println!("optional_header_size:{optional_header_size}");
         let characteristics = file.read_u16()?;
         Ok(Self {
             machine,
@@ -336,21 +719,50 @@ impl PEFileHeader {
             characteristics,
         })
     }
+    fn serialize(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_u16(self.machine)?;
+        out.write_u16(self.section_count)?;
+        out.write_u32(self.timestamp)?;
+        out.write_u32(self.symbol_table_offset)?;
+        out.write_u32(self.symbol_table_size)?;
+        out.write_u16(self.optional_header_size)?;
+        out.write_u16(self.characteristics)
+    }
 }
 impl PEFile {
-    pub fn slice_at_rva(&self, rva: RVA, length: u64) -> Option<&[u8]> {
-        for section in &self.sections {
-            let slice = section.slice_at_rva(rva, length);
-            if slice.is_some() {
-                return slice;
-            }
+    /// Returns the `length` bytes loaded at `rva`, or `None` if they don't lie within a single section. Bytes
+    /// past the raw data of the section are zeroes.
+    pub fn slice_at_rva(&self, rva: RVA, length: u64) -> Option<Cow<'_, [u8]>> {
+        self.sections
+            .iter()
+            .find_map(|section| section.slice_at_rva(rva, length))
+    }
+    pub fn sections(&self) -> &[PESection] {
+        &self.sections
+    }
+    /// Reads the Win32 resources of the image, such as its version info, manifest or icons.
+    pub fn win32_resources(&self) -> Result<Vec<Win32Resource>, AssemblyReadError> {
+        let directory = self.header.nt_header.data_directories[RESOURCE_DIRECTORY];
+        if directory.is_empty() {
+            return Ok(Vec::new());
         }
-        None
+        let rva = RVA(directory.rva as u64);
+        let bytes =
+            self.slice_at_rva(rva, directory.size as u64)
+                .ok_or(AssemblyReadError::InvalidRVA {
+                    location: DecodeLocation::Win32Resources,
+                    rva,
+                    size: directory.size as u64,
+                })?;
+        decode_resources(&bytes, |rva, size| self.slice_at_rva(rva, size))
     }
     pub fn pe_header(&self) -> &PEHeader {
         &self.header
     }
-    pub fn from_file(file: &mut (impl Read + Seek)) -> Result<Self, PEFileReadError> {
+    pub fn from_file(
+        file: &mut (impl Read + Seek),
+        policy: HeaderPolicy,
+    ) -> Result<Self, PEFileReadError> {
         let mut file_stub = [0; MSDOS_STUB_FIRST.len()];
         // Read first part of the DOS stub
         file.read_exact(&mut file_stub)?;
@@ -364,30 +776,272 @@ impl PEFile {
         if file_stub != MSDOS_STUB_SECOND {
             return Err(PEFileReadError::InavlidDOSStub);
         };
-        println!("lfanew:{lfanew}");
-
-        let mut data_after_stub = vec![0; (lfanew - 128) as usize];
-        file.read_exact(&mut data_after_stub)?;
+        let stub_end = (MSDOS_STUB_FIRST.len() + 4 + MSDOS_STUB_SECOND.len()) as u32;
+        if lfanew < stub_end {
+            return Err(PEFileReadError::InavlidDOSStub);
+        }
+        let dos_stub_tail = read_bytes(file, (lfanew - stub_end) as u64)?;
         let pe = file.read_u32()?;
-        eprintln!("{data_after_stub:?} pe:{pe:8x}");
         if pe != 0x00004550 {
             return Err(PEFileReadError::NotPEFile);
         }
-        let (header, header_end) = PEHeader::from_file(file)?;
+        let (header, header_end) = PEHeader::from_file(file, policy)?;
+        let mut position = header_end + (SECTION_HEADER_SIZE * header.sections.len()) as u64;
         let sections = header
             .sections
             .iter()
-            .map(|section_header| PESection::from_file(file, section_header, header_end))
+            .map(|section_header| PESection::from_file(file, section_header, &mut position))
             .collect::<Result<_, _>>()?;
-        Ok(Self { header, sections })
+        file.seek(SeekFrom::Start(position))?;
+        let mut trailer = Vec::new();
+        file.read_to_end(&mut trailer)?;
+        Ok(Self {
+            dos_stub_tail,
+            header,
+            sections,
+            trailer,
+        })
+    }
+    /// Overwrites the bytes at `rva`, or returns `None` if they don't lie within a single section.
+    pub(crate) fn write_at_rva(&mut self, rva: RVA, bytes: &[u8]) -> Option<()> {
+        self.sections
+            .iter_mut()
+            .find_map(|section| section.slice_at_rva_mut(rva, bytes.len() as u64))?
+            .copy_from_slice(bytes);
+        Some(())
+    }
+    /// Lays out a new image for `machine`. Sections are placed one after another, starting at the first
+    /// page after the headers, so the RVA of each section follows from the sizes of the ones before it.
+    pub(crate) fn new(
+        machine: Machine,
+        sections: Vec<(SectionType, Vec<u8>)>,
+        entrypoint: RVA,
+        data_directories: [DataDirectory; 16],
+        is_dll: bool,
+    ) -> Self {
+        let format = machine.format();
+        let header_size = MSDOS_STUB_FIRST.len()
+            + 4
+            + MSDOS_STUB_SECOND.len()
+            + 4
+            + PE_FILE_HEADER_SIZE
+            + format.optional_header_size() as usize
+            + SECTION_HEADER_SIZE * sections.len();
+        let header_size = align_up(header_size as u32, FILE_ALIGNMENT);
+        let mut virtual_adress = SECTION_ALIGNMENT;
+        let mut offset_of_raw_data = header_size;
+        let mut section_headers = Vec::with_capacity(sections.len());
+        let mut pe_sections = Vec::with_capacity(sections.len());
+        for (section_type, data) in sections {
+            let virtual_size = data.len() as u32;
+            let size_of_raw_data = align_up(virtual_size, FILE_ALIGNMENT);
+            section_headers.push(SectionHeader {
+                name: section_type.name(),
+                virtual_size,
+                virtual_adress,
+                size_of_raw_data,
+                offset_of_raw_data,
+                relocations: 0,
+                line_numbers: 0,
+                relocation_count: 0,
+                line_number_count: 0,
+                characteristics: section_type.characteristics(),
+            });
+            pe_sections.push(PESection {
+                name: section_type.name(),
+                virtual_adress,
+                virtual_size,
+                characteristics: section_type.characteristics(),
+                padding_before: Vec::new(),
+                data,
+            });
+            virtual_adress = align_up(virtual_adress + virtual_size, SECTION_ALIGNMENT);
+            offset_of_raw_data += size_of_raw_data;
+        }
+        let raw_size_of = |characteristics: u32| {
+            section_headers
+                .iter()
+                .filter(|section| section.characteristics & characteristics != 0)
+                .map(|section| section.size_of_raw_data)
+                .sum()
+        };
+        let code_size = raw_size_of(0x20);
+        let init_data_size = raw_size_of(0x40);
+        let code_rva = section_headers
+            .iter()
+            .find(|section| section.characteristics & 0x20 != 0)
+            .map_or(0, |section| section.virtual_adress);
+        let data_rva = section_headers
+            .iter()
+            .find(|section| section.characteristics & 0x20 == 0)
+            .map_or(0, |section| section.virtual_adress);
+        let file_header = PEFileHeader {
+            machine: machine.value(),
+            section_count: section_headers.len() as u16,
+            timestamp: 0,
+            symbol_table_offset: 0,
+            symbol_table_size: 0,
+            optional_header_size: format.optional_header_size(),
+            // EXECUTABLE_IMAGE | LARGE_ADDRESS_AWARE, and DLL if needed.
+            characteristics: 0x0022 | if is_dll { 0x2000 } else { 0 },
+        };
+        let (image_base, dll_flags) = match format {
+            // DYNAMIC_BASE | NX_COMPAT | NO_SEH | TERMINAL_SERVER_AWARE
+            PEFormat::PE32 => (IMAGE_BASE as u64, 0x8540),
+            // The same, and HIGH_ENTROPY_VA
+            PEFormat::PE32Plus if is_dll => (0x1_8000_0000, 0x8560),
+            PEFormat::PE32Plus => (0x1_4000_0000, 0x8560),
+        };
+        let [stack_reserve_size, stack_commit_size, heap_reserve_size, heap_commit_size] =
+            format.stack_and_heap_sizes();
+        let nt_header = NTHeader {
+            image_base,
+            section_algiement: SECTION_ALIGNMENT,
+            file_aligement: FILE_ALIGNMENT,
+            os_major: 4,
+            os_minor: 0,
+            user_major: 0,
+            user_minor: 0,
+            subsys_major: 4,
+            subsys_minor: 0,
+            win32_version: 0,
+            image_size: virtual_adress,
+            header_size,
+            checksum: 0,
+            subsystem: Subsystem::CUI.into(),
+            dll_flags,
+            stack_reserve_size,
+            stack_commit_size,
+            heap_reserve_size,
+            heap_commit_size,
+            loader_flags: 0,
+            data_directories,
+        };
+        let header = PEHeader {
+            file_header,
+            format,
+            linker_major: 6,
+            linker_minor: 0,
+            code_size,
+            init_data_size,
+            uninit_data_size: 0,
+            entrypoint_rva: entrypoint.0 as u32,
+            code_rva,
+            data_rva,
+            nt_header,
+            sections: section_headers,
+        };
+        Self {
+            dos_stub_tail: Vec::new(),
+            header,
+            sections: pe_sections,
+            trailer: Vec::new(),
+        }
+    }
+    /// Writes the image. An image which was read from a file is written back byte for byte, while sections of new
+    /// images are padded with zeroes up to their raw size.
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let stub_end = MSDOS_STUB_FIRST.len() + 4 + MSDOS_STUB_SECOND.len();
+        out.write_all(MSDOS_STUB_FIRST)?;
+        out.write_u32((stub_end + self.dos_stub_tail.len()) as u32)?;
+        out.write_all(MSDOS_STUB_SECOND)?;
+        out.write_all(&self.dos_stub_tail)?;
+        out.write_u32(0x00004550)?;
+        let mut headers = Vec::new();
+        self.header.serialize(&mut headers)?;
+        out.write_all(&headers)?;
+        let mut position = (stub_end + self.dos_stub_tail.len() + 4 + headers.len()) as u64;
+        for (header, section) in self.header.sections.iter().zip(&self.sections) {
+            if header.size_of_raw_data == 0 {
+                continue;
+            }
+            out.write_all(&section.padding_before)?;
+            position += section.padding_before.len() as u64;
+            let padding = (header.offset_of_raw_data as u64).saturating_sub(position);
+            out.write_all(&vec![0; padding as usize])?;
+            position += padding;
+            out.write_all(&section.data)?;
+            let padding = (header.size_of_raw_data as usize).saturating_sub(section.data.len());
+            out.write_all(&vec![0; padding])?;
+            position += padding as u64 + section.data.len() as u64;
+        }
+        out.write_all(&self.trailer)
     }
 }
+const PE_FILE_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+/// Import data for a CLI image: the IAT and lookup table both contain a single entry for
+/// `_CorExeMain` or `_CorDllMain` from `mscoree.dll`, which the x86 entry point stub jumps to.
+pub(crate) struct ImportStub {
+    pub(crate) import_table: DataDirectory,
+    pub(crate) entrypoint: RVA,
+    /// RVA of the absolute address inside the entry point stub, which needs a base relocation.
+    pub(crate) fixup: u32,
+}
+/// Size of the import address table placed by [`ImportStub::write`].
+pub(crate) const IAT_SIZE: u32 = 8;
+impl ImportStub {
+    /// Appends the import table and entry point stub to `text`, which starts at `text_rva`.
+    /// The IAT is expected to be the first [`IAT_SIZE`] bytes of `text`, and is filled in here.
+    pub(crate) fn write(text: &mut Vec<u8>, text_rva: u32, is_dll: bool) -> Self {
+        let rva_of = |text: &Vec<u8>| text_rva + text.len() as u32;
+        text.resize(align_up(text.len() as u32, 4) as usize, 0);
+        let import_table_rva = rva_of(text);
+        let lookup_table_rva = import_table_rva + 40;
+        let hint_name_rva = lookup_table_rva + 8;
+        let entry_name: &[u8] = if is_dll {
+            b"_CorDllMain\0"
+        } else {
+            b"_CorExeMain\0"
+        };
+        let dll_name_rva = hint_name_rva + 2 + entry_name.len() as u32;
+        // Import directory entry, followed by the null terminator entry.
+        text.extend(lookup_table_rva.to_le_bytes());
+        text.extend([0; 8]);
+        text.extend(dll_name_rva.to_le_bytes());
+        text.extend(text_rva.to_le_bytes());
+        text.extend([0; 20]);
+        // Import lookup table
+        text.extend(hint_name_rva.to_le_bytes());
+        text.extend([0; 4]);
+        // Hint/Name table
+        text.extend([0; 2]);
+        text.extend(entry_name);
+        text.extend(b"mscoree.dll\0");
+        let import_table = DataDirectory {
+            rva: import_table_rva,
+            size: rva_of(text) - import_table_rva,
+        };
+        text[..4].copy_from_slice(&hint_name_rva.to_le_bytes());
+        // The address of the jump is aligned to 4.
+        text.resize(align_up(text.len() as u32, 4) as usize + 2, 0);
+        let entrypoint = rva_of(text);
+        text.extend([0xFF, 0x25]);
+        let fixup = rva_of(text);
+        text.extend((IMAGE_BASE + text_rva).to_le_bytes());
+        Self {
+            import_table,
+            entrypoint: RVA(entrypoint as u64),
+            fixup,
+        }
+    }
+}
+/// Creates the contents of a `.reloc` section, with a single HIGHLOW relocation at `fixup`.
+pub(crate) fn base_relocations(fixup: u32) -> Vec<u8> {
+    let mut relocations = Vec::with_capacity(12);
+    relocations.extend((fixup & !0xFFF).to_le_bytes());
+    relocations.extend(12_u32.to_le_bytes());
+    relocations.extend((0x3000 | (fixup & 0xFFF) as u16).to_le_bytes());
+    relocations.extend([0; 2]);
+    relocations
+}
 #[derive(Debug)]
 pub enum PEFileReadError {
     IOError(std::io::Error),
     InavlidDOSStub,
     NotPEFile,
     WrongMagic,
+    WrongWin32Version,
     WrongChecksum,
     WrongSubsystem(u16),
     WrongFileAligement,
@@ -401,8 +1055,25 @@ pub enum PEFileReadError {
     ResourceTablePresent,
     ExceptionTablePresent,
     CertificateTablePresent,
-    UnknownSectionName(Box<str>),
+    DelayImportTablePresent,
+    WrongReservedDirectory,
+    UnsupportedOptionalHeaderSize(u16),
+    SectionRelocationsPresent,
+    SectionLineNumbersPresent,
+}
+impl std::fmt::Display for PEFileReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IOError(error) => write!(f, "IO error: {error}"),
+            Self::WrongSubsystem(subsystem) => write!(f, "unsupported subsystem {subsystem}"),
+            Self::UnsupportedOptionalHeaderSize(size) => {
+                write!(f, "unsupported optional header size {size}")
+            }
+            _ => write!(f, "{self:?}"),
+        }
+    }
 }
+impl std::error::Error for PEFileReadError {}
 impl From<std::io::Error> for PEFileReadError {
     fn from(value: std::io::Error) -> Self {
         Self::IOError(value)
@@ -420,3 +1091,104 @@ const MSDOS_STUB_SECOND: &[u8] = &[
     0x74, 0x20, 0x62, 0x65, 0x20, 0x72, 0x75, 0x6e, 0x20, 0x69, 0x6e, 0x20, 0x44, 0x4f, 0x53, 0x20,
     0x6d, 0x6f, 0x64, 0x65, 0x2e, 0x0d, 0x0d, 0x0a, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
+#[test]
+fn zero_filled_sections() {
+    let section = PESection {
+        name: *b".data\0\0\0",
+        virtual_adress: 0x2000,
+        virtual_size: 0x10,
+        characteristics: 0xC000_0040,
+        padding_before: Vec::new(),
+        data: vec![1, 2, 3, 4],
+    };
+    assert_eq!(section.name(), b".data");
+    assert!(matches!(
+        section.slice_at_rva(RVA(0x2001), 2),
+        Some(Cow::Borrowed([2, 3]))
+    ));
+    assert_eq!(
+        section.slice_at_rva(RVA(0x2002), 4).unwrap()[..],
+        [3, 4, 0, 0]
+    );
+    assert_eq!(section.slice_at_rva(RVA(0x200C), 4).unwrap()[..], [0; 4]);
+    assert!(section.slice_at_rva(RVA(0x200C), 5).is_none());
+    assert!(section.slice_at_rva(RVA(0x1FFF), 1).is_none());
+}
+#[cfg(test)]
+fn small_image(sections: Vec<(SectionType, Vec<u8>)>) -> Vec<u8> {
+    let pe_file = PEFile::new(
+        Machine::I386,
+        sections,
+        RVA(0x2000),
+        [DataDirectory::default(); 16],
+        true,
+    );
+    let mut out = Vec::new();
+    pe_file.write_to(&mut out).unwrap();
+    out
+}
+#[test]
+fn header_policy() {
+    let mut image = small_image(vec![(SectionType::Text, vec![0xCC; 16])]);
+    let lfanew = u32::from_le_bytes(image[0x3C..0x40].try_into().unwrap()) as usize;
+    let optional_header = lfanew + 24;
+    // Linker version 48.0, a checksum, and a resource directory, as Roslyn emits for signed assemblies with
+    // Win32 resources.
+    image[optional_header + 2] = 48;
+    image[optional_header + 64..optional_header + 68].copy_from_slice(&0x1234_u32.to_le_bytes());
+    let resources = optional_header + 96 + 2 * 8;
+    image[resources..resources + 4].copy_from_slice(&0x4000_u32.to_le_bytes());
+    image[resources + 4..resources + 8].copy_from_slice(&0x10_u32.to_le_bytes());
+    let pe_file =
+        PEFile::from_file(&mut std::io::Cursor::new(&image), HeaderPolicy::Lenient).unwrap();
+    let header = pe_file.pe_header();
+    assert_eq!(header.linker_version(), (48, 0));
+    assert_eq!(header.nt_header().checksum(), 0x1234);
+    assert_eq!(
+        header.nt_header().data_directories()[RESOURCE_DIRECTORY],
+        DataDirectory {
+            rva: 0x4000,
+            size: 0x10
+        }
+    );
+    let mut rewritten = Vec::new();
+    pe_file.write_to(&mut rewritten).unwrap();
+    assert_eq!(image, rewritten);
+    let strict = PEFile::from_file(&mut std::io::Cursor::new(&image), HeaderPolicy::Strict);
+    assert!(matches!(strict, Err(PEFileReadError::WrongChecksum)));
+    // Win32VersionValue is reserved, and comes right before SizeOfImage.
+    image[optional_header + 52] = 1;
+    let lenient =
+        PEFile::from_file(&mut std::io::Cursor::new(&image), HeaderPolicy::Lenient).unwrap();
+    assert_eq!(lenient.pe_header().nt_header().win32_version(), 1);
+    image[optional_header + 64..optional_header + 68].fill(0);
+    let strict = PEFile::from_file(&mut std::io::Cursor::new(&image), HeaderPolicy::Strict);
+    assert!(matches!(strict, Err(PEFileReadError::WrongWin32Version)));
+}
+#[test]
+fn read_any_section() {
+    let mut image = small_image(vec![
+        (SectionType::Text, vec![0xCC; 16]),
+        (SectionType::Reloc, vec![0; 12]),
+    ]);
+    let reloc = image
+        .windows(8)
+        .position(|name| name == b".reloc\0\0")
+        .unwrap();
+    image[reloc..reloc + 8].copy_from_slice(b".sdata\0\0");
+    let pe_file =
+        PEFile::from_file(&mut std::io::Cursor::new(&image), HeaderPolicy::Strict).unwrap();
+    let names: Vec<_> = pe_file
+        .sections()
+        .iter()
+        .map(|section| section.name())
+        .collect();
+    assert_eq!(names, [&b".text"[..], b".sdata"]);
+    let sdata = &pe_file.sections()[1];
+    assert_eq!(sdata.characteristics(), 0x4200_0040);
+    assert_eq!(sdata.virtual_size(), 12);
+    assert_eq!(sdata.data().len(), 0x200);
+    let mut rewritten = Vec::new();
+    pe_file.write_to(&mut rewritten).unwrap();
+    assert_eq!(image, rewritten);
+}
@@ -1,39 +1,38 @@
 use crate::{
-    assembly::{u16_from_slice_at, u32_from_slice_at, AssemblyRefIndex},
-    bitvec::BitVec64,
+    assembly::AssemblyRefIndex,
+    coded_index::{CodedIndex, CodedIndexKind},
+    table::{ModuleIndex, ModuleRefIndex},
+    type_def::TypeRefIndex,
 };
 
 #[derive(Copy, Clone, Debug)]
-pub(crate) enum ResolutionScope {
-    Module,
-    //ModuleRef(ModuleRef),
+pub enum ResolutionScope {
+    Module(ModuleIndex),
+    ModuleRef(ModuleRefIndex),
     AssemblyRef(AssemblyRefIndex),
-    //TypeRef(TypeRef),
+    /// The enclosing type of a nested type.
+    TypeRef(TypeRefIndex),
 }
-impl ResolutionScope {
-    pub(crate) fn decode(table_slice: &mut &[u8], tables_rows: &[u32], tables: BitVec64) -> Self {
-        //let typedef_num = table_rows(tables_rows, tables, 0x2).unwrap_or_default();
-        let max = 256; //typedef_num;
-        let encoded = if max > (1 << 14) {
-            let encoded = u32_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[4..];
-            encoded
-        } else {
-            let encoded = u16_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[2..];
-            encoded as u32
-        };
-        let tag = encoded & 0b11;
-        let index = tag & (!0b11);
-        match tag {
-            0x00 => {
-                assert_eq!(index, 0);
-                ResolutionScope::Module
-            }
-            0x01 => todo!("ModuleRef:{index}"),
-            /// ResolutionScope::ModuleRef(ModuleRef(index)),
-            0x02 => ResolutionScope::AssemblyRef(AssemblyRefIndex(index)),
-            _ => panic!("Invalid ResolutionScope tag:{tag}"),
+impl CodedIndex for ResolutionScope {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "ResolutionScope",
+        tag_bits: 2,
+        tables: &[Some(0x00), Some(0x1A), Some(0x23), Some(0x01)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x00 => Self::Module(ModuleIndex(index)),
+            0x1A => Self::ModuleRef(ModuleRefIndex(index)),
+            0x23 => Self::AssemblyRef(AssemblyRefIndex(index)),
+            _ => Self::TypeRef(TypeRefIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::Module(ModuleIndex(index)) => (0x00, index),
+            Self::ModuleRef(ModuleRefIndex(index)) => (0x1A, index),
+            Self::AssemblyRef(AssemblyRefIndex(index)) => (0x23, index),
+            Self::TypeRef(TypeRefIndex(index)) => (0x01, index),
         }
     }
 }
@@ -0,0 +1,560 @@
+use crate::{
+    assembly::{take_u8, DecodeErrorKind},
+    compressed::{decode_unsigned, encode_unsigned},
+    r#type::{decode_type, decode_type_at, Type},
+};
+/// `HASTHIS`: the method has a `this` pointer.
+const HAS_THIS: u8 = 0x20;
+/// `EXPLICITTHIS`: `this` is passed as the first parameter.
+const EXPLICIT_THIS: u8 = 0x40;
+/// `GENERIC`: the method has generic parameters.
+const GENERIC: u8 = 0x10;
+/// Marks the start of the extra arguments passed to a vararg method.
+const SENTINEL: u8 = 0x41;
+// Kinds of the signatures which don't describe methods, stored in the low 4 bits of their first byte.
+const FIELD: u8 = 0x06;
+const LOCAL_SIG: u8 = 0x07;
+const PROPERTY: u8 = 0x08;
+const GENERIC_INST: u8 = 0x0A;
+/// Calling convention of a method signature(II.23.2.1 and II.23.2.3).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CallingConvention {
+    Default,
+    /// The unmanaged calling conventions, only used by `calli` call sites.
+    C,
+    StdCall,
+    ThisCall,
+    FastCall,
+    VarArg,
+    /// An unmanaged calling convention picked by the custom modifiers of the return type.
+    Unmanaged,
+}
+impl CallingConvention {
+    fn from_flags(flags: u8) -> Result<Self, DecodeErrorKind> {
+        match flags & 0x0F {
+            0x0 => Ok(Self::Default),
+            0x1 => Ok(Self::C),
+            0x2 => Ok(Self::StdCall),
+            0x3 => Ok(Self::ThisCall),
+            0x4 => Ok(Self::FastCall),
+            0x5 => Ok(Self::VarArg),
+            0x9 => Ok(Self::Unmanaged),
+            _ => Err(DecodeErrorKind::InvalidValue {
+                field: "calling convention",
+                value: flags as u64,
+            }),
+        }
+    }
+    fn flags(self) -> u8 {
+        match self {
+            Self::Default => 0x0,
+            Self::C => 0x1,
+            Self::StdCall => 0x2,
+            Self::ThisCall => 0x3,
+            Self::FastCall => 0x4,
+            Self::VarArg => 0x5,
+            Self::Unmanaged => 0x9,
+        }
+    }
+}
+/// Reads the first byte of a signature which doesn't describe a method. It must be `kind`, possibly combined with
+/// `allowed_flags`.
+fn take_kind(signature: &mut &[u8], kind: u8, allowed_flags: u8) -> Result<u8, DecodeErrorKind> {
+    let flags = *signature.first().ok_or(DecodeErrorKind::UnexpectedEnd)?;
+    if flags & !allowed_flags != kind {
+        return Err(DecodeErrorKind::InvalidValue {
+            field: "signature kind",
+            value: flags as u64,
+        });
+    }
+    *signature = &signature[1..];
+    Ok(flags)
+}
+/// Reads a count followed by that many types.
+pub(crate) fn decode_types(signature: &mut &[u8]) -> Result<Box<[Type]>, DecodeErrorKind> {
+    decode_types_at(signature, 0)
+}
+/// [`decode_types`] for types nested `depth` levels deep in another type.
+pub(crate) fn decode_types_at(
+    signature: &mut &[u8],
+    depth: u32,
+) -> Result<Box<[Type]>, DecodeErrorKind> {
+    let count = decode_unsigned(signature)?;
+    // Each type takes up at least one byte, which bounds the allocation by the size of the blob.
+    let mut types = Vec::with_capacity((count as usize).min(signature.len()));
+    for _ in 0..count {
+        types.push(decode_type_at(signature, depth)?);
+    }
+    Ok(types.into())
+}
+/// Appends the number of `types`, followed by the types themselves.
+pub(crate) fn encode_types(types: &[Type], out: &mut Vec<u8>) {
+    encode_unsigned(types.len() as u32, out);
+    for tpe in types {
+        tpe.encode(out);
+    }
+}
+/// Signature of a method, a method reference, or a `calli` call site(II.23.2.1 to II.23.2.3).
+#[derive(Debug)]
+pub struct Signature {
+    calling_convention: CallingConvention,
+    has_this: bool,
+    explicit_this: bool,
+    generic_params: u32,
+    ret: Type,
+    args: Box<[Type]>,
+    /// Arguments after the sentinel, passed to the variable part of a vararg method.
+    varargs: Box<[Type]>,
+}
+impl Signature {
+    /// Creates the signature of a non-generic method with no varargs.
+    pub fn new(
+        calling_convention: CallingConvention,
+        has_this: bool,
+        ret: Type,
+        args: impl Into<Box<[Type]>>,
+    ) -> Self {
+        Self {
+            calling_convention,
+            has_this,
+            explicit_this: false,
+            generic_params: 0,
+            ret,
+            args: args.into(),
+            varargs: Box::new([]),
+        }
+    }
+    /// Makes `this` an explicit first argument. Only valid if the method has a `this` pointer.
+    pub fn with_explicit_this(mut self) -> Self {
+        self.explicit_this = true;
+        self
+    }
+    /// Sets the number of generic parameters of the method.
+    pub fn with_generic_params(mut self, count: u32) -> Self {
+        self.generic_params = count;
+        self
+    }
+    /// Sets the extra arguments of a vararg call site or method reference.
+    pub fn with_varargs(mut self, varargs: impl Into<Box<[Type]>>) -> Self {
+        self.varargs = varargs.into();
+        self
+    }
+    pub fn calling_convention(&self) -> CallingConvention {
+        self.calling_convention
+    }
+    /// Whether the method has a `this` pointer. It is only part of [`Self::args`] if [`Self::explicit_this`] is set.
+    pub fn has_this(&self) -> bool {
+        self.has_this
+    }
+    pub fn explicit_this(&self) -> bool {
+        self.explicit_this
+    }
+    /// Number of generic parameters of the method, 0 if it is not generic.
+    pub fn generic_param_count(&self) -> u32 {
+        self.generic_params
+    }
+    pub fn ret(&self) -> &Type {
+        &self.ret
+    }
+    pub fn args(&self) -> &[Type] {
+        &self.args
+    }
+    /// Extra arguments passed to a vararg method, only present in method references and call sites.
+    pub fn varargs(&self) -> &[Type] {
+        &self.varargs
+    }
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        Self::decode_at(signature, 0)
+    }
+    /// Decodes the signature of a function pointer type nested `depth` levels deep in another type.
+    pub(crate) fn decode_at(signature: &mut &[u8], depth: u32) -> Result<Self, DecodeErrorKind> {
+        let flags = take_u8(signature)?;
+        let calling_convention = CallingConvention::from_flags(flags)?;
+        let generic_params = if flags & GENERIC != 0 {
+            decode_unsigned(signature)?
+        } else {
+            0
+        };
+        let param_count = decode_unsigned(signature)?;
+        let ret = decode_type_at(signature, depth)?;
+        let mut args = Vec::with_capacity((param_count as usize).min(signature.len()));
+        let mut varargs = Vec::new();
+        let mut after_sentinel = false;
+        for _ in 0..param_count {
+            if signature.first() == Some(&SENTINEL) {
+                if after_sentinel {
+                    return Err(DecodeErrorKind::InvalidValue {
+                        field: "SENTINEL",
+                        value: SENTINEL as u64,
+                    });
+                }
+                after_sentinel = true;
+                *signature = &signature[1..];
+            }
+            let arg = decode_type_at(signature, depth)?;
+            if after_sentinel {
+                varargs.push(arg);
+            } else {
+                args.push(arg);
+            }
+        }
+        Ok(Self {
+            calling_convention,
+            has_this: flags & HAS_THIS != 0,
+            explicit_this: flags & EXPLICIT_THIS != 0,
+            generic_params,
+            ret,
+            args: args.into(),
+            varargs: varargs.into(),
+        })
+    }
+    /// Appends this signature to `out`, in the format read by [`Self::decode`].
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut flags = self.calling_convention.flags();
+        if self.has_this {
+            flags |= HAS_THIS;
+        }
+        if self.explicit_this {
+            flags |= EXPLICIT_THIS;
+        }
+        if self.generic_params != 0 {
+            flags |= GENERIC;
+        }
+        out.push(flags);
+        if self.generic_params != 0 {
+            encode_unsigned(self.generic_params, out);
+        }
+        encode_unsigned((self.args.len() + self.varargs.len()) as u32, out);
+        self.ret.encode(out);
+        for arg in self.args.iter() {
+            arg.encode(out);
+        }
+        if !self.varargs.is_empty() {
+            out.push(SENTINEL);
+            for arg in self.varargs.iter() {
+                arg.encode(out);
+            }
+        }
+    }
+}
+/// Signature of a field(II.23.2.4).
+#[derive(Debug)]
+pub struct FieldSignature {
+    field_type: Type,
+}
+impl FieldSignature {
+    pub fn new(field_type: Type) -> Self {
+        Self { field_type }
+    }
+    pub fn field_type(&self) -> &Type {
+        &self.field_type
+    }
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        take_kind(signature, FIELD, 0)?;
+        Ok(Self {
+            field_type: decode_type(signature)?,
+        })
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(FIELD);
+        self.field_type.encode(out);
+    }
+}
+/// Signature of a property(II.23.2.5). Indexers have parameters, other properties don't.
+#[derive(Debug)]
+pub struct PropertySignature {
+    has_this: bool,
+    property_type: Type,
+    params: Box<[Type]>,
+}
+impl PropertySignature {
+    pub fn new(has_this: bool, property_type: Type, params: impl Into<Box<[Type]>>) -> Self {
+        Self {
+            has_this,
+            property_type,
+            params: params.into(),
+        }
+    }
+    pub fn has_this(&self) -> bool {
+        self.has_this
+    }
+    pub fn property_type(&self) -> &Type {
+        &self.property_type
+    }
+    pub fn params(&self) -> &[Type] {
+        &self.params
+    }
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        let flags = take_kind(signature, PROPERTY, HAS_THIS)?;
+        let param_count = decode_unsigned(signature)?;
+        let property_type = decode_type(signature)?;
+        let mut params = Vec::with_capacity((param_count as usize).min(signature.len()));
+        for _ in 0..param_count {
+            params.push(decode_type(signature)?);
+        }
+        Ok(Self {
+            has_this: flags & HAS_THIS != 0,
+            property_type,
+            params: params.into(),
+        })
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(if self.has_this {
+            PROPERTY | HAS_THIS
+        } else {
+            PROPERTY
+        });
+        encode_unsigned(self.params.len() as u32, out);
+        self.property_type.encode(out);
+        for param in self.params.iter() {
+            param.encode(out);
+        }
+    }
+}
+/// Types of the local variables of a method(II.23.2.6).
+#[derive(Debug)]
+pub struct LocalsSignature {
+    locals: Box<[Type]>,
+}
+impl LocalsSignature {
+    pub fn new(locals: impl Into<Box<[Type]>>) -> Self {
+        Self {
+            locals: locals.into(),
+        }
+    }
+    pub fn locals(&self) -> &[Type] {
+        &self.locals
+    }
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        take_kind(signature, LOCAL_SIG, 0)?;
+        Ok(Self {
+            locals: decode_types(signature)?,
+        })
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(LOCAL_SIG);
+        encode_types(&self.locals, out);
+    }
+}
+/// The generic arguments of an instantiated generic method(II.23.2.15).
+#[derive(Debug)]
+pub struct MethodSpecSignature {
+    args: Box<[Type]>,
+}
+impl MethodSpecSignature {
+    pub fn new(args: impl Into<Box<[Type]>>) -> Self {
+        Self { args: args.into() }
+    }
+    pub fn args(&self) -> &[Type] {
+        &self.args
+    }
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        take_kind(signature, GENERIC_INST, 0)?;
+        Ok(Self {
+            args: decode_types(signature)?,
+        })
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(GENERIC_INST);
+        encode_types(&self.args, out);
+    }
+}
+/// Signature of a member reference, which refers to either a field or a method.
+#[derive(Debug)]
+pub enum MemberRefSignature {
+    Field(FieldSignature),
+    Method(Signature),
+}
+impl MemberRefSignature {
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        match signature.first() {
+            Some(&FIELD) => FieldSignature::decode(signature).map(Self::Field),
+            _ => Signature::decode(signature).map(Self::Method),
+        }
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Field(signature) => signature.encode(out),
+            Self::Method(signature) => signature.encode(out),
+        }
+    }
+}
+/// A row of the StandAloneSig table, holding either the locals of a method or the signature of a `calli` call site.
+#[derive(Debug)]
+pub enum StandAloneSignature {
+    Locals(LocalsSignature),
+    Method(Signature),
+}
+impl StandAloneSignature {
+    pub fn decode(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        match signature.first() {
+            Some(&LOCAL_SIG) => LocalsSignature::decode(signature).map(Self::Locals),
+            _ => Signature::decode(signature).map(Self::Method),
+        }
+    }
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Locals(signature) => signature.encode(out),
+            Self::Method(signature) => signature.encode(out),
+        }
+    }
+}
+#[test]
+fn method_signatures() {
+    // instance void (int32, string)
+    let signature = Signature::decode(&mut &[0x20, 0x02, 0x01, 0x08, 0x0E][..]).unwrap();
+    assert!(signature.has_this() && !signature.explicit_this());
+    assert_eq!(signature.calling_convention(), CallingConvention::Default);
+    assert!(matches!(signature.ret(), Type::Void));
+    assert!(matches!(signature.args(), [Type::I32, Type::String]));
+    // A generic method with 2 generic parameters, returning int32.
+    let signature = Signature::decode(&mut &[0x10, 0x02, 0x00, 0x08][..]).unwrap();
+    assert_eq!(signature.generic_param_count(), 2);
+    assert!(signature.args().is_empty());
+    // A vararg call, passing a string and a float32 after the fixed int32.
+    let mut blob = &[0x05, 0x03, 0x01, 0x08, 0x41, 0x0E, 0x0C, 0xAA][..];
+    let signature = Signature::decode(&mut blob).unwrap();
+    assert_eq!(signature.calling_convention(), CallingConvention::VarArg);
+    assert!(matches!(signature.args(), [Type::I32]));
+    assert!(matches!(signature.varargs(), [Type::String, Type::F32]));
+    assert_eq!(blob, [0xAA]);
+    assert!(matches!(
+        Signature::decode(&mut &[0x05, 0x02, 0x01, 0x41, 0x08, 0x41, 0x08][..]),
+        Err(DecodeErrorKind::InvalidValue {
+            field: "SENTINEL",
+            ..
+        })
+    ));
+    assert!(matches!(
+        Signature::decode(&mut &[0x06, 0x08][..]),
+        Err(DecodeErrorKind::InvalidValue {
+            field: "calling convention",
+            value: 0x06
+        })
+    ));
+}
+#[test]
+fn other_signatures() {
+    let field = FieldSignature::decode(&mut &[0x06, 0x08][..]).unwrap();
+    assert!(matches!(field.field_type(), Type::I32));
+    // instance string Item[int32]
+    let property = PropertySignature::decode(&mut &[0x28, 0x01, 0x0E, 0x08][..]).unwrap();
+    assert!(property.has_this());
+    assert!(matches!(property.property_type(), Type::String));
+    assert!(matches!(property.params(), [Type::I32]));
+    let locals = LocalsSignature::decode(&mut &[0x07, 0x02, 0x08, 0x0E][..]).unwrap();
+    assert!(matches!(locals.locals(), [Type::I32, Type::String]));
+    let spec = MethodSpecSignature::decode(&mut &[0x0A, 0x01, 0x08][..]).unwrap();
+    assert!(matches!(spec.args(), [Type::I32]));
+    assert!(matches!(
+        MemberRefSignature::decode(&mut &[0x06, 0x0E][..]),
+        Ok(MemberRefSignature::Field(_))
+    ));
+    assert!(matches!(
+        MemberRefSignature::decode(&mut &[0x00, 0x00, 0x01][..]),
+        Ok(MemberRefSignature::Method(_))
+    ));
+    assert!(matches!(
+        StandAloneSignature::decode(&mut &[0x07, 0x01, 0x08][..]),
+        Ok(StandAloneSignature::Locals(_))
+    ));
+    let Ok(StandAloneSignature::Method(call_site)) =
+        StandAloneSignature::decode(&mut &[0x02, 0x00, 0x01][..])
+    else {
+        panic!("Expected a call site signature");
+    };
+    assert_eq!(call_site.calling_convention(), CallingConvention::StdCall);
+    assert!(matches!(
+        FieldSignature::decode(&mut &[0x07, 0x08][..]),
+        Err(DecodeErrorKind::InvalidValue {
+            field: "signature kind",
+            value: 0x07
+        })
+    ));
+    // Only properties may have HASTHIS.
+    assert!(LocalsSignature::decode(&mut &[0x27, 0x00][..]).is_err());
+}
+#[test]
+fn modifiers_and_byrefs() {
+    use crate::{TypeDefOrRef, TypeRefIndex};
+    // string (int32): the return type comes before the params.
+    let signature = Signature::decode(&mut &[0x00, 0x01, 0x0E, 0x08][..]).unwrap();
+    assert!(matches!(signature.ret(), Type::String));
+    assert!(matches!(signature.args(), [Type::I32]));
+    // int32 modopt(TypeRef 1) (int32& modreq(TypeRef 2), typedref)
+    let blob = [0x00, 0x02, 0x20, 0x05, 0x08, 0x1F, 0x09, 0x10, 0x08, 0x16];
+    let signature = Signature::decode(&mut &blob[..]).unwrap();
+    assert!(matches!(
+        signature.ret(),
+        Type::Modified {
+            required: false,
+            modifier: TypeDefOrRef::TypeRef(TypeRefIndex(1)),
+            tpe,
+        } if matches!(**tpe, Type::I32)
+    ));
+    let [Type::Modified {
+        required: true,
+        modifier: TypeDefOrRef::TypeRef(TypeRefIndex(2)),
+        tpe,
+    }, Type::TypedByRef] = signature.args()
+    else {
+        panic!("Unexpected args {:?}", signature.args());
+    };
+    assert!(matches!(&**tpe, Type::Ref(inner) if matches!(**inner, Type::I32)));
+    // ref int32 ()
+    let signature = Signature::decode(&mut &[0x00, 0x00, 0x10, 0x08][..]).unwrap();
+    assert!(matches!(signature.ret(), Type::Ref(_)));
+    // A pinned string local.
+    let locals = LocalsSignature::decode(&mut &[0x07, 0x01, 0x45, 0x0E][..]).unwrap();
+    assert!(matches!(locals.locals(), [Type::Pinned(inner)] if matches!(**inner, Type::String)));
+}
+#[test]
+fn encode_round_trip() {
+    let blobs: [&[u8]; 9] = [
+        // instance void (int32, string)
+        &[0x20, 0x02, 0x01, 0x08, 0x0E],
+        // A generic method returning !!0, taking a class List`1<!!0>
+        &[
+            0x30, 0x01, 0x01, 0x1E, 0x00, 0x15, 0x12, 0x05, 0x01, 0x1E, 0x00,
+        ],
+        // vararg int32 modopt(TypeRef 1) (int32&, ..., string, float32[-2...1, 0...])
+        &[
+            0x05, 0x03, 0x20, 0x05, 0x08, 0x10, 0x08, 0x41, 0x0E, 0x14, 0x0C, 0x03, 0x01, 0x04,
+            0x02, 0x7D, 0x00,
+        ],
+        // explicit this, calling a method int32 *(native int)
+        &[0x60, 0x01, 0x1B, 0x00, 0x01, 0x08, 0x18, 0x1C],
+        &[0x06, 0x1D, 0x11, 0x09],
+        &[0x28, 0x01, 0x0E, 0x08],
+        &[0x08, 0x00, 0x16],
+        &[0x07, 0x02, 0x45, 0x10, 0x08, 0x1F, 0x09, 0x19],
+        &[0x0A, 0x02, 0x1C, 0x13, 0x01],
+    ];
+    for blob in blobs {
+        let mut out = Vec::new();
+        match blob[0] & 0x0F {
+            FIELD => FieldSignature::decode(&mut &blob[..])
+                .unwrap()
+                .encode(&mut out),
+            PROPERTY => PropertySignature::decode(&mut &blob[..])
+                .unwrap()
+                .encode(&mut out),
+            LOCAL_SIG => LocalsSignature::decode(&mut &blob[..])
+                .unwrap()
+                .encode(&mut out),
+            GENERIC_INST => MethodSpecSignature::decode(&mut &blob[..])
+                .unwrap()
+                .encode(&mut out),
+            _ => Signature::decode(&mut &blob[..]).unwrap().encode(&mut out),
+        }
+        assert_eq!(out, blob);
+    }
+    let mut out = Vec::new();
+    Signature::new(CallingConvention::VarArg, true, Type::Void, [Type::I32])
+        .with_varargs([Type::String])
+        .encode(&mut out);
+    assert_eq!(out, [0x25, 0x02, 0x01, 0x08, 0x41, 0x0E]);
+    out.clear();
+    LocalsSignature::new(vec![Type::SZArray(Type::Object.into())]).encode(&mut out);
+    assert_eq!(out, [0x07, 0x01, 0x1D, 0x1C]);
+}
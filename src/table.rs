@@ -1,214 +1,26 @@
-use std::ops::Range;
-
 use crate::{
-    assembly::{decode_blob_compressed_value, EncodedAssembly, StringIndex, Table},
+    assembly::{
+        read_table_index, take_u16, take_u32, take_u8, write_table_index, AssemblyRefIndex,
+        BlobIndex, DecodeErrorKind, HeapSizes, StringIndex,
+    },
+    bitvec::BitVec64,
+    coded_index::{
+        CodedIndex, CustomAttributeType, HasConstant, HasCustomAttribute, HasDeclSecurity,
+        HasFieldMarshal, HasSemantics, Implementation, MemberForwarded, MethodDefOrRef,
+        TypeOrMethodDef,
+    },
     field::FieldIndex,
-    method::{Method, MethodIndex},
+    method::MethodIndex,
     param::ParamIndex,
-    r#type::{decode_type, Type},
     resolution_scope::ResolutionScope,
-    type_def::{TypeDef, TypeDefIndex, TypeDefOrRef},
+    type_def::{TypeDefIndex, TypeDefOrRef},
 };
-pub(crate) enum DecodedTable {
-    Module { name: Box<str>, mvid: u128 },
-    TypeDefTable(Box<[DecodedTypeDef]>),
-    MethodDef(Box<[(Box<str>, Method, Signature, Range<ParamIndex>)]>),
-    Params(Box<[(u16, u16, Box<str>)]>),
-    TypeRefTable(Box<[DecodedTypeRef]>),
-    AssemblyTable{
-        name:Box<str>
-    },
-    AssemblyRefs(Box<[AssemblyDescr]>)
-}
-impl DecodedTable {
-    pub(crate) fn decode(table: &Table, asm: &EncodedAssembly) -> Self {
-        match table {
-            Table::Module { name, mvid } => {
-                let name = asm.str_at(*name).to_owned().into();
-                let mvid = if mvid.0 == 0 {
-                    0
-                } else {
-                    asm.guid_stream()[(mvid.0 - 1) as usize]
-                };
-                Self::Module { name, mvid }
-            }
-            Table::TypeRefTable(refs)=>{
-                let mut decoded_refs = Vec::with_capacity(refs.len());
-                for tref in refs.iter(){
-                    let name: Box<str> = asm.str_at(tref.name()).to_owned().into();
-                    let namespace: Box<str> = asm.str_at(tref.namespace()).to_owned().into();
-                    println!("name:{name:?},namespace:{namespace}");
-            
-                    decoded_refs.push(DecodedTypeRef::new(tref.scope(),name,namespace));
-            } 
-                Self::TypeRefTable(decoded_refs.into())
-                //todo!("{decoded_refs:?}");
-            }
-            Table::TypeDefTable(type_defs) => {
-                let mut res = Vec::with_capacity(type_defs.len());
-                for index in 0..type_defs.len() {
-                    let type_def = type_defs[index];
-                    let flags = type_def.flags();
-                    let name: Box<str> = asm.str_at(type_def.name()).to_owned().into();
-                    let namespace: Box<str> = asm.str_at(type_def.namespace()).to_owned().into();
-                    let derived_from = DotnetTypeRef::new(type_def.derived_from(), asm);
-                    let methods_start = type_def.method_index();
-                    let methods_end = type_defs
-                        .get(index + 1)
-                        .map(|td| td.method_index())
-                        .unwrap_or_else(|| MethodIndex(asm.methods().len() as u32));
-                    let fields_start = type_def.field_index();
-                    let fields_end = type_defs
-                        .get(index + 1)
-                        .map(|td| td.field_index())
-                        .unwrap_or_else(|| FieldIndex(asm.field_len() as u32));
-                    println!(
-                        "type_name:{name},namespace:{namespace},fields_start:{fields_start:?}"
-                    );
-                    res.push(DecodedTypeDef {
-                        flags,
-                        name,
-                        namespace,
-                        derived_from,
-                        fields: (fields_start..fields_end),
-                        methods: (methods_start..methods_end),
-                    })
-                }
-                Self::TypeDefTable(res.into())
-            }
-            Table::MethodDefTable(methods) => {
-                let mut new_methods = Vec::new();
-                for index in 0..methods.len() {
-                    let method = methods[index];
-                    let name: Box<str> = asm.str_at(method.name()).to_owned().into();
-                    println!("method_name:{name}. index:{:?}", method.name());
-                    let signature = method.signature();
-                    let param_start = method.param_start();
-                    let param_end = methods
-                        .get(index + 1)
-                        .map(|method| method.param_start())
-                        .unwrap_or_else(|| ParamIndex(asm.params_len() as u32));
-                    let method = crate::method::decode_method(asm.pe_file(), method.rva());
-                    //let signature = Signature::empty();
-                    let signature = Signature::decode(asm.blob_at(signature), asm);
-
-                    new_methods.push((name, method, signature, param_start..param_end));
-                }
-                Self::MethodDef(new_methods.into())
-            }
-            Table::Param(params) => Self::Params(
-                params
-                    .iter()
-                    .map(|param| {
-                        let flags = param.flags();
-                        let sequence = param.sequence();
-                        let name: Box<str> = asm.str_at(param.name()).to_owned().into();
-                        let param_name =  asm.str_at(param.name());
-                        //println!("field_name:{name}");
-                        (flags, sequence, name)
-                    })
-                    .collect(),
-            ),
-            Table::Fields(fields)=>{
-                for field in fields.iter(){
-                    let name =  asm.str_at(field.name());
-                    println!("field_name:{name}");
-                }
-                todo!();
-            }
-            Table::Assembly { hash_alg_id, major, minor, build_number, revision_number, flags, public_key, name, culture }=>{
-                let name: Box<str> = asm.str_at(*name).to_owned().into();
-                Self::AssemblyTable{name}
-            }
-            Table::AssemblyRefs(refs)=>{
-                for aref in refs.iter(){
-                    let name: Box<str> = asm.str_at(aref.name()).to_owned().into();
-                    println!("assembly_ref_name:{name}");
-                }
-                todo!()
-            }
-            _ => todo!("Table {table:?} is unsuported!"),
-        }
-    }
-}
-struct AssemblyDescr{
-
-}
-//ECMA spec II.23.1.16
-#[derive(Debug)]
-struct Signature {
-    flags: u8,
-    args: Box<[Type]>,
-    ret: Type,
-}
-impl Signature {
-    pub fn empty() -> Self {
-        Self {
-            flags: 0,
-            args: [].into(),
-            ret: Type::Void,
-        }
-    }
-    pub fn decode(mut signature: &[u8], asm: &EncodedAssembly) -> Self {
-        let flags = signature[0];
-        signature = &signature[1..];
-        let argc: u32 = decode_blob_compressed_value(&mut signature);
-        let mut args = Vec::with_capacity(argc as usize);
-        for _ in 0..argc {
-            args.push(decode_type(&mut signature, asm).unwrap());
-        }
-        let ret = decode_type(&mut signature, asm).unwrap();
-        //println!("signature:{signature:?} flags:{flags:b} argc:{argc}");
-        Self {
-            args: args.into(),
-            ret,
-            flags,
-        }
-    }
-}
-struct DecodedTypeDef {
-    flags: u32,
-    name: Box<str>,
-    namespace: Box<str>,
-    derived_from: Option<DotnetTypeRef>,
-    fields: std::ops::Range<FieldIndex>,
-    methods: std::ops::Range<MethodIndex>,
-}
-struct DotnetTypeRef {}
-impl DotnetTypeRef {
-    fn new(tdor: TypeDefOrRef, asm: &EncodedAssembly) -> Option<Self> {
-        match tdor {
-            TypeDefOrRef::TypeDef(def_idx) => {
-                if def_idx.0 == 0 {
-                    return None;
-                }
-                //asm.type_refs()
-                todo!("Can't get name of type {def_idx:?}");
-            }
-            TypeDefOrRef::TypeRef(index)=>{
-                let index = (index.0) as usize;
-                Some(DotnetTypeRef{})
-            }
-            _ => todo!("Can't get name of type {tdor:?}"),
-        }
-    }
-}
 #[derive(Debug, Clone)]
-pub struct TypeRef {
+pub(crate) struct TypeRef {
     scope: ResolutionScope,
     name: StringIndex,
     namespace: StringIndex,
 }
-#[derive(Debug, Clone)]
-pub struct DecodedTypeRef {
-    scope: ResolutionScope,
-    name: Box<str>,
-    namespace: Box<str>,
-}
-
-impl DecodedTypeRef {
-    pub fn new(scope: ResolutionScope, name: Box<str>, namespace: Box<str>) -> Self { Self { scope, name, namespace } }
-}
 impl TypeRef {
     pub fn new(scope: ResolutionScope, name: StringIndex, namespace: StringIndex) -> Self {
         Self {
@@ -230,3 +42,1801 @@ impl TypeRef {
         self.scope
     }
 }
+#[derive(Copy, Clone, Debug)]
+pub struct ModuleIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct InterfaceImplIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct DeclSecurityIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct EventIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct PropertyIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct ModuleRefIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct AssemblyIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct FileIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct ExportedTypeIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct ManifestResourceIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct GenericParamIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct GenericParamConstraintIndex(pub u32);
+#[derive(Copy, Clone, Debug)]
+pub struct MethodSpecIndex(pub u32);
+impl EventIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x14).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x14);
+    }
+}
+impl PropertyIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x17).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x17);
+    }
+}
+impl ModuleRefIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x1A).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x1A);
+    }
+}
+impl GenericParamIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x2A).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x2A);
+    }
+}
+/// A row of the FieldPtr table, which only appears in uncompressed(`#-`) metadata.
+#[cfg(test)]
+type DecodeRow<T> = fn(&mut &[u8], HeapSizes, &[u32], BitVec64) -> Result<T, DecodeErrorKind>;
+#[cfg(test)]
+type EncodeRow<T> = fn(&T, &mut Vec<u8>, HeapSizes, &[u32], BitVec64);
+/// Decodes `row`, checks that all of it was read and that encoding the result gives back the same bytes.
+/// `rows` holds the row count of every present table, in table order; they set the width of table and coded indices.
+#[cfg(test)]
+fn round_trip<T>(
+    row: &[u8],
+    sizes: HeapSizes,
+    rows: &[(u8, u32)],
+    decode: DecodeRow<T>,
+    encode: EncodeRow<T>,
+) -> T {
+    let tables = rows
+        .iter()
+        .fold(0_u64, |tables, (table, _)| tables | 1 << table)
+        .into();
+    let tables_rows: Vec<u32> = rows.iter().map(|(_, count)| *count).collect();
+    let mut slice = row;
+    let decoded = decode(&mut slice, sizes, &tables_rows, tables).unwrap();
+    assert!(slice.is_empty(), "{} bytes left", slice.len());
+    let mut encoded = Vec::new();
+    encode(&decoded, &mut encoded, sizes, &tables_rows, tables);
+    assert_eq!(encoded, row);
+    decoded
+}
+#[derive(Clone, Debug)]
+pub(crate) struct FieldPtr {
+    field: FieldIndex,
+}
+impl FieldPtr {
+    pub(crate) fn field(&self) -> FieldIndex {
+        self.field
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let field = FieldIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { field })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.field.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn field_ptr_row() {
+    let row = round_trip(
+        &[3, 0],
+        HeapSizes::from(0),
+        &[(0x03, 1), (0x04, 4)],
+        FieldPtr::decode,
+        FieldPtr::encode,
+    );
+    assert_eq!(row.field.0, 3);
+    let row = round_trip(
+        &[3, 0, 0, 0],
+        HeapSizes::from(0),
+        &[(0x03, 1), (0x04, 1 << 16)],
+        FieldPtr::decode,
+        FieldPtr::encode,
+    );
+    assert_eq!(row.field.0, 3);
+}
+/// A row of the MethodPtr table, which only appears in uncompressed(`#-`) metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct MethodPtr {
+    method: MethodIndex,
+}
+impl MethodPtr {
+    pub(crate) fn method(&self) -> MethodIndex {
+        self.method
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let method = MethodIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { method })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.method.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn method_ptr_row() {
+    let row = round_trip(
+        &[2, 0],
+        HeapSizes::from(0),
+        &[(0x05, 1), (0x06, 2)],
+        MethodPtr::decode,
+        MethodPtr::encode,
+    );
+    assert_eq!(row.method.0, 2);
+}
+/// A row of the ParamPtr table, which only appears in uncompressed(`#-`) metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct ParamPtr {
+    param: ParamIndex,
+}
+impl ParamPtr {
+    pub(crate) fn param(&self) -> ParamIndex {
+        self.param
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let param = ParamIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { param })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.param.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn param_ptr_row() {
+    let row = round_trip(
+        &[5, 0],
+        HeapSizes::from(0),
+        &[(0x07, 1), (0x08, 5)],
+        ParamPtr::decode,
+        ParamPtr::encode,
+    );
+    assert_eq!(row.param.0, 5);
+}
+/// A row of the InterfaceImpl table(II.22.23).
+#[derive(Clone, Debug)]
+pub(crate) struct InterfaceImpl {
+    class: TypeDefIndex,
+    interface: TypeDefOrRef,
+}
+impl InterfaceImpl {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let class = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        let interface = TypeDefOrRef::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { class, interface })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.class.encode(out, tables_rows, tables);
+        self.interface.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn interface_impl_row() {
+    let row = round_trip(
+        &[2, 0, 13, 0],
+        HeapSizes::from(0),
+        &[(0x01, 3), (0x02, 2), (0x09, 1)],
+        InterfaceImpl::decode,
+        InterfaceImpl::encode,
+    );
+    assert_eq!(row.class.0, 2);
+    assert!(matches!(row.interface, TypeDefOrRef::TypeRef(index) if index.0 == 3));
+}
+/// A row of the Constant table(II.22.9).
+#[derive(Clone, Debug)]
+pub(crate) struct Constant {
+    element_type: u8,
+    padding: u8,
+    parent: HasConstant,
+    value: BlobIndex,
+}
+impl Constant {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let element_type = take_u8(table_slice)?;
+        let padding = take_u8(table_slice)?;
+        let parent = HasConstant::decode(table_slice, tables_rows, tables)?;
+        let value = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            element_type,
+            padding,
+            parent,
+            value,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.push(self.element_type);
+        out.push(self.padding);
+        self.parent.encode(out, tables_rows, tables);
+        sizes.write_blob_index(out, self.value);
+    }
+}
+#[test]
+fn constant_row() {
+    // The padding byte sits between the element type and the parent, and is kept even when it is not zero.
+    let row = round_trip(
+        &[0x08, 0x01, 9, 0, 0x10, 0, 0, 0],
+        HeapSizes::from(0b111),
+        &[(0x08, 2), (0x0B, 1)],
+        Constant::decode,
+        Constant::encode,
+    );
+    assert_eq!(row.element_type, 0x08);
+    assert_eq!(row.padding, 0x01);
+    assert!(matches!(row.parent, HasConstant::Param(index) if index.0 == 2));
+    assert_eq!(row.value.0, 0x10);
+}
+/// A row of the CustomAttribute table(II.22.10).
+#[derive(Clone, Debug)]
+pub(crate) struct CustomAttribute {
+    parent: HasCustomAttribute,
+    constructor: CustomAttributeType,
+    value: BlobIndex,
+}
+impl CustomAttribute {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let parent = HasCustomAttribute::decode(table_slice, tables_rows, tables)?;
+        let constructor = CustomAttributeType::decode(table_slice, tables_rows, tables)?;
+        let value = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            parent,
+            constructor,
+            value,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.parent.encode(out, tables_rows, tables);
+        self.constructor.encode(out, tables_rows, tables);
+        sizes.write_blob_index(out, self.value);
+    }
+}
+#[test]
+fn custom_attribute_row() {
+    let row = round_trip(
+        &[67, 0, 35, 0, 7, 0],
+        HeapSizes::from(0),
+        &[(0x02, 2), (0x0A, 4), (0x0C, 1)],
+        CustomAttribute::decode,
+        CustomAttribute::encode,
+    );
+    assert!(matches!(row.parent, HasCustomAttribute::TypeDef(index) if index.0 == 2));
+    assert!(matches!(row.constructor, CustomAttributeType::MemberRef(index) if index.0 == 4));
+    assert_eq!(row.value.0, 7);
+    // HasCustomAttribute has 5 tag bits, so 2048 methods already make it wide.
+    let row = round_trip(
+        &[67, 0, 0, 0, 35, 0, 7, 0],
+        HeapSizes::from(0),
+        &[(0x02, 2), (0x06, 1 << 11), (0x0A, 4), (0x0C, 1)],
+        CustomAttribute::decode,
+        CustomAttribute::encode,
+    );
+    assert!(matches!(row.parent, HasCustomAttribute::TypeDef(index) if index.0 == 2));
+}
+/// A row of the FieldMarshal table(II.22.17).
+#[derive(Clone, Debug)]
+pub(crate) struct FieldMarshal {
+    parent: HasFieldMarshal,
+    native_type: BlobIndex,
+}
+impl FieldMarshal {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let parent = HasFieldMarshal::decode(table_slice, tables_rows, tables)?;
+        let native_type = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            parent,
+            native_type,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.parent.encode(out, tables_rows, tables);
+        sizes.write_blob_index(out, self.native_type);
+    }
+}
+#[test]
+fn field_marshal_row() {
+    let row = round_trip(
+        &[3, 0, 5, 0],
+        HeapSizes::from(0),
+        &[(0x08, 1), (0x0D, 1)],
+        FieldMarshal::decode,
+        FieldMarshal::encode,
+    );
+    assert!(matches!(row.parent, HasFieldMarshal::Param(index) if index.0 == 1));
+    assert_eq!(row.native_type.0, 5);
+}
+/// A row of the DeclSecurity table(II.22.11).
+#[derive(Clone, Debug)]
+pub(crate) struct DeclSecurity {
+    action: u16,
+    parent: HasDeclSecurity,
+    permission_set: BlobIndex,
+}
+impl DeclSecurity {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let action = take_u16(table_slice)?;
+        let parent = HasDeclSecurity::decode(table_slice, tables_rows, tables)?;
+        let permission_set = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            action,
+            parent,
+            permission_set,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.action.to_le_bytes());
+        self.parent.encode(out, tables_rows, tables);
+        sizes.write_blob_index(out, self.permission_set);
+    }
+}
+#[test]
+fn decl_security_row() {
+    let row = round_trip(
+        &[2, 0, 6, 0, 9, 0],
+        HeapSizes::from(0),
+        &[(0x0E, 1), (0x20, 1)],
+        DeclSecurity::decode,
+        DeclSecurity::encode,
+    );
+    assert_eq!(row.action, 2);
+    assert!(matches!(row.parent, HasDeclSecurity::Assembly(index) if index.0 == 1));
+    assert_eq!(row.permission_set.0, 9);
+}
+/// A row of the ClassLayout table(II.22.8).
+#[derive(Clone, Debug)]
+pub(crate) struct ClassLayout {
+    packing_size: u16,
+    class_size: u32,
+    parent: TypeDefIndex,
+}
+impl ClassLayout {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let packing_size = take_u16(table_slice)?;
+        let class_size = take_u32(table_slice)?;
+        let parent = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            packing_size,
+            class_size,
+            parent,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.packing_size.to_le_bytes());
+        out.extend(self.class_size.to_le_bytes());
+        self.parent.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn class_layout_row() {
+    // PackingSize is a u16 and comes before the u32 ClassSize.
+    let row = round_trip(
+        &[8, 0, 0x10, 0, 0, 0, 3, 0],
+        HeapSizes::from(0),
+        &[(0x02, 3), (0x0F, 1)],
+        ClassLayout::decode,
+        ClassLayout::encode,
+    );
+    assert_eq!(row.packing_size, 8);
+    assert_eq!(row.class_size, 0x10);
+    assert_eq!(row.parent.0, 3);
+}
+/// A row of the FieldLayout table(II.22.16).
+#[derive(Clone, Debug)]
+pub(crate) struct FieldLayout {
+    offset: u32,
+    field: FieldIndex,
+}
+impl FieldLayout {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let offset = take_u32(table_slice)?;
+        let field = FieldIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { offset, field })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.offset.to_le_bytes());
+        self.field.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn field_layout_row() {
+    let row = round_trip(
+        &[0x20, 0, 0, 0, 2, 0],
+        HeapSizes::from(0),
+        &[(0x04, 2), (0x10, 1)],
+        FieldLayout::decode,
+        FieldLayout::encode,
+    );
+    assert_eq!(row.offset, 0x20);
+    assert_eq!(row.field.0, 2);
+}
+/// A row of the EventMap table(II.22.12).
+#[derive(Clone, Debug)]
+pub(crate) struct EventMap {
+    parent: TypeDefIndex,
+    event_list: EventIndex,
+}
+impl EventMap {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let parent = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        let event_list = EventIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { parent, event_list })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.parent.encode(out, tables_rows, tables);
+        self.event_list.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn event_map_row() {
+    let row = round_trip(
+        &[2, 0, 1, 0],
+        HeapSizes::from(0),
+        &[(0x02, 2), (0x12, 1), (0x14, 1)],
+        EventMap::decode,
+        EventMap::encode,
+    );
+    assert_eq!(row.parent.0, 2);
+    assert_eq!(row.event_list.0, 1);
+}
+/// A row of the EventPtr table, which only appears in uncompressed(`#-`) metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct EventPtr {
+    event: EventIndex,
+}
+impl EventPtr {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let event = EventIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { event })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.event.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn event_ptr_row() {
+    let row = round_trip(
+        &[4, 0],
+        HeapSizes::from(0),
+        &[(0x13, 1), (0x14, 4)],
+        EventPtr::decode,
+        EventPtr::encode,
+    );
+    assert_eq!(row.event.0, 4);
+}
+/// A row of the Event table(II.22.13).
+#[derive(Clone, Debug)]
+pub(crate) struct Event {
+    flags: u16,
+    name: StringIndex,
+    event_type: TypeDefOrRef,
+}
+impl Event {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let flags = take_u16(table_slice)?;
+        let name = sizes.read_string_index(table_slice)?;
+        let event_type = TypeDefOrRef::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            flags,
+            name,
+            event_type,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.flags.to_le_bytes());
+        sizes.write_string_index(out, self.name);
+        self.event_type.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn event_row() {
+    let row = round_trip(
+        &[0, 2, 0x11, 0, 9, 0],
+        HeapSizes::from(0),
+        &[(0x01, 2), (0x14, 1)],
+        Event::decode,
+        Event::encode,
+    );
+    assert_eq!(row.flags, 0x0200);
+    assert_eq!(row.name.0, 0x11);
+    assert!(matches!(row.event_type, TypeDefOrRef::TypeRef(index) if index.0 == 2));
+}
+/// A row of the PropertyMap table(II.22.35).
+#[derive(Clone, Debug)]
+pub(crate) struct PropertyMap {
+    parent: TypeDefIndex,
+    property_list: PropertyIndex,
+}
+impl PropertyMap {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let parent = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        let property_list = PropertyIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            parent,
+            property_list,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.parent.encode(out, tables_rows, tables);
+        self.property_list.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn property_map_row() {
+    let row = round_trip(
+        &[2, 0, 3, 0],
+        HeapSizes::from(0),
+        &[(0x02, 2), (0x15, 1), (0x17, 3)],
+        PropertyMap::decode,
+        PropertyMap::encode,
+    );
+    assert_eq!(row.parent.0, 2);
+    assert_eq!(row.property_list.0, 3);
+}
+/// A row of the PropertyPtr table, which only appears in uncompressed(`#-`) metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct PropertyPtr {
+    property: PropertyIndex,
+}
+impl PropertyPtr {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let property = PropertyIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { property })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.property.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn property_ptr_row() {
+    let row = round_trip(
+        &[1, 0],
+        HeapSizes::from(0),
+        &[(0x16, 1), (0x17, 1)],
+        PropertyPtr::decode,
+        PropertyPtr::encode,
+    );
+    assert_eq!(row.property.0, 1);
+}
+/// A row of the Property table(II.22.34).
+#[derive(Clone, Debug)]
+pub(crate) struct Property {
+    flags: u16,
+    name: StringIndex,
+    signature: BlobIndex,
+}
+impl Property {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let flags = take_u16(table_slice)?;
+        let name = sizes.read_string_index(table_slice)?;
+        let signature = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            flags,
+            name,
+            signature,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.flags.to_le_bytes());
+        sizes.write_string_index(out, self.name);
+        sizes.write_blob_index(out, self.signature);
+    }
+}
+#[test]
+fn property_row() {
+    let row = round_trip(
+        &[0, 0x10, 0x21, 0, 0, 0, 0x30, 0, 0, 0],
+        HeapSizes::from(0b111),
+        &[(0x17, 1)],
+        Property::decode,
+        Property::encode,
+    );
+    assert_eq!(row.flags, 0x1000);
+    assert_eq!(row.name.0, 0x21);
+    assert_eq!(row.signature.0, 0x30);
+}
+/// A row of the MethodSemantics table(II.22.28).
+#[derive(Clone, Debug)]
+pub(crate) struct MethodSemantics {
+    semantics: u16,
+    method: MethodIndex,
+    association: HasSemantics,
+}
+impl MethodSemantics {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let semantics = take_u16(table_slice)?;
+        let method = MethodIndex::decode(table_slice, tables_rows, tables)?;
+        let association = HasSemantics::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            semantics,
+            method,
+            association,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.semantics.to_le_bytes());
+        self.method.encode(out, tables_rows, tables);
+        self.association.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn method_semantics_row() {
+    let row = round_trip(
+        &[8, 0, 3, 0, 5, 0],
+        HeapSizes::from(0),
+        &[(0x06, 3), (0x17, 2), (0x18, 1)],
+        MethodSemantics::decode,
+        MethodSemantics::encode,
+    );
+    assert_eq!(row.semantics, 8);
+    assert_eq!(row.method.0, 3);
+    assert!(matches!(row.association, HasSemantics::Property(index) if index.0 == 2));
+}
+/// A row of the MethodImpl table(II.22.27).
+#[derive(Clone, Debug)]
+pub(crate) struct MethodImpl {
+    class: TypeDefIndex,
+    body: MethodDefOrRef,
+    declaration: MethodDefOrRef,
+}
+impl MethodImpl {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let class = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        let body = MethodDefOrRef::decode(table_slice, tables_rows, tables)?;
+        let declaration = MethodDefOrRef::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            class,
+            body,
+            declaration,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.class.encode(out, tables_rows, tables);
+        self.body.encode(out, tables_rows, tables);
+        self.declaration.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn method_impl_row() {
+    let row = round_trip(
+        &[2, 0, 8, 0, 11, 0],
+        HeapSizes::from(0),
+        &[(0x02, 2), (0x06, 4), (0x0A, 5), (0x19, 1)],
+        MethodImpl::decode,
+        MethodImpl::encode,
+    );
+    assert_eq!(row.class.0, 2);
+    assert!(matches!(row.body, MethodDefOrRef::MethodDef(index) if index.0 == 4));
+    assert!(matches!(row.declaration, MethodDefOrRef::MemberRef(index) if index.0 == 5));
+}
+/// A row of the ModuleRef table(II.22.31).
+#[derive(Clone, Debug)]
+pub(crate) struct ModuleRef {
+    name: StringIndex,
+}
+impl ModuleRef {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let name = sizes.read_string_index(table_slice)?;
+        Ok(Self { name })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        sizes.write_string_index(out, self.name);
+    }
+}
+#[test]
+fn module_ref_row() {
+    let row = round_trip(
+        &[0x40, 0],
+        HeapSizes::from(0),
+        &[(0x1A, 1)],
+        ModuleRef::decode,
+        ModuleRef::encode,
+    );
+    assert_eq!(row.name.0, 0x40);
+}
+/// A row of the TypeSpec table(II.22.39).
+#[derive(Clone, Debug)]
+pub(crate) struct TypeSpec {
+    signature: BlobIndex,
+}
+impl TypeSpec {
+    pub(crate) fn new(signature: BlobIndex) -> Self {
+        Self { signature }
+    }
+    pub(crate) fn signature(&self) -> BlobIndex {
+        self.signature
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let signature = sizes.read_blob_index(table_slice)?;
+        Ok(Self { signature })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        sizes.write_blob_index(out, self.signature);
+    }
+}
+#[test]
+fn type_spec_row() {
+    let row = round_trip(
+        &[0x12, 0],
+        HeapSizes::from(0),
+        &[(0x1B, 1)],
+        TypeSpec::decode,
+        TypeSpec::encode,
+    );
+    assert_eq!(row.signature.0, 0x12);
+}
+/// A row of the ImplMap table(II.22.22).
+#[derive(Clone, Debug)]
+pub(crate) struct ImplMap {
+    mapping_flags: u16,
+    member_forwarded: MemberForwarded,
+    import_name: StringIndex,
+    import_scope: ModuleRefIndex,
+}
+impl ImplMap {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let mapping_flags = take_u16(table_slice)?;
+        let member_forwarded = MemberForwarded::decode(table_slice, tables_rows, tables)?;
+        let import_name = sizes.read_string_index(table_slice)?;
+        let import_scope = ModuleRefIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            mapping_flags,
+            member_forwarded,
+            import_name,
+            import_scope,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.mapping_flags.to_le_bytes());
+        self.member_forwarded.encode(out, tables_rows, tables);
+        sizes.write_string_index(out, self.import_name);
+        self.import_scope.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn impl_map_row() {
+    let row = round_trip(
+        &[0, 1, 7, 0, 0x22, 0, 1, 0],
+        HeapSizes::from(0),
+        &[(0x06, 3), (0x1A, 1), (0x1C, 1)],
+        ImplMap::decode,
+        ImplMap::encode,
+    );
+    assert_eq!(row.mapping_flags, 0x0100);
+    assert!(matches!(row.member_forwarded, MemberForwarded::MethodDef(index) if index.0 == 3));
+    assert_eq!(row.import_name.0, 0x22);
+    assert_eq!(row.import_scope.0, 1);
+    // A ModuleRef table with 65536 rows widens ImportScope, not MemberForwarded.
+    round_trip(
+        &[0, 1, 7, 0, 0x22, 0, 1, 0, 0, 0],
+        HeapSizes::from(0),
+        &[(0x06, 3), (0x1A, 1 << 16), (0x1C, 1)],
+        ImplMap::decode,
+        ImplMap::encode,
+    );
+}
+/// A row of the FieldRVA table(II.22.18).
+#[derive(Clone, Debug)]
+pub(crate) struct FieldRVA {
+    rva: u32,
+    field: FieldIndex,
+}
+impl FieldRVA {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let rva = take_u32(table_slice)?;
+        let field = FieldIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { rva, field })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.rva.to_le_bytes());
+        self.field.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn field_rva_row() {
+    let row = round_trip(
+        &[0x50, 0x20, 0, 0, 4, 0],
+        HeapSizes::from(0),
+        &[(0x04, 4), (0x1D, 1)],
+        FieldRVA::decode,
+        FieldRVA::encode,
+    );
+    assert_eq!(row.rva, 0x2050);
+    assert_eq!(row.field.0, 4);
+}
+/// A row of the EncLog table, used by edit-and-continue.
+#[derive(Clone, Debug)]
+pub(crate) struct EncLog {
+    token: u32,
+    func_code: u32,
+}
+impl EncLog {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let token = take_u32(table_slice)?;
+        let func_code = take_u32(table_slice)?;
+        Ok(Self { token, func_code })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.token.to_le_bytes());
+        out.extend(self.func_code.to_le_bytes());
+    }
+}
+#[test]
+fn enc_log_row() {
+    let row = round_trip(
+        &[1, 0, 0, 6, 1, 0, 0, 0],
+        HeapSizes::from(0),
+        &[(0x1E, 1)],
+        EncLog::decode,
+        EncLog::encode,
+    );
+    assert_eq!(row.token, 0x0600_0001);
+    assert_eq!(row.func_code, 1);
+}
+/// A row of the EncMap table, used by edit-and-continue.
+#[derive(Clone, Debug)]
+pub(crate) struct EncMap {
+    token: u32,
+}
+impl EncMap {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let token = take_u32(table_slice)?;
+        Ok(Self { token })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.token.to_le_bytes());
+    }
+}
+#[test]
+fn enc_map_row() {
+    let row = round_trip(
+        &[3, 0, 0, 2],
+        HeapSizes::from(0),
+        &[(0x1F, 1)],
+        EncMap::decode,
+        EncMap::encode,
+    );
+    assert_eq!(row.token, 0x0200_0003);
+}
+/// A row of the AssemblyProcessor table(II.22.4).
+#[derive(Clone, Debug)]
+pub(crate) struct AssemblyProcessor {
+    processor: u32,
+}
+impl AssemblyProcessor {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let processor = take_u32(table_slice)?;
+        Ok(Self { processor })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.processor.to_le_bytes());
+    }
+}
+#[test]
+fn assembly_processor_row() {
+    let row = round_trip(
+        &[0x4C, 1, 0, 0],
+        HeapSizes::from(0),
+        &[(0x21, 1)],
+        AssemblyProcessor::decode,
+        AssemblyProcessor::encode,
+    );
+    assert_eq!(row.processor, 0x14C);
+}
+/// A row of the AssemblyOS table(II.22.3).
+#[derive(Clone, Debug)]
+pub(crate) struct AssemblyOS {
+    platform_id: u32,
+    major: u32,
+    minor: u32,
+}
+impl AssemblyOS {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let platform_id = take_u32(table_slice)?;
+        let major = take_u32(table_slice)?;
+        let minor = take_u32(table_slice)?;
+        Ok(Self {
+            platform_id,
+            major,
+            minor,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.platform_id.to_le_bytes());
+        out.extend(self.major.to_le_bytes());
+        out.extend(self.minor.to_le_bytes());
+    }
+}
+#[test]
+fn assembly_os_row() {
+    let row = round_trip(
+        &[2, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0],
+        HeapSizes::from(0),
+        &[(0x22, 1)],
+        AssemblyOS::decode,
+        AssemblyOS::encode,
+    );
+    assert_eq!((row.platform_id, row.major, row.minor), (2, 5, 1));
+}
+/// A row of the AssemblyRefProcessor table(II.22.7).
+#[derive(Clone, Debug)]
+pub(crate) struct AssemblyRefProcessor {
+    processor: u32,
+    assembly_ref: AssemblyRefIndex,
+}
+impl AssemblyRefProcessor {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let processor = take_u32(table_slice)?;
+        let assembly_ref = AssemblyRefIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            processor,
+            assembly_ref,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.processor.to_le_bytes());
+        self.assembly_ref.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn assembly_ref_processor_row() {
+    let row = round_trip(
+        &[0x64, 0x86, 0, 0, 1, 0],
+        HeapSizes::from(0),
+        &[(0x23, 1), (0x24, 1)],
+        AssemblyRefProcessor::decode,
+        AssemblyRefProcessor::encode,
+    );
+    assert_eq!(row.processor, 0x8664);
+    assert_eq!(row.assembly_ref.0, 1);
+}
+/// A row of the AssemblyRefOS table(II.22.6).
+#[derive(Clone, Debug)]
+pub(crate) struct AssemblyRefOS {
+    platform_id: u32,
+    major: u32,
+    minor: u32,
+    assembly_ref: AssemblyRefIndex,
+}
+impl AssemblyRefOS {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let platform_id = take_u32(table_slice)?;
+        let major = take_u32(table_slice)?;
+        let minor = take_u32(table_slice)?;
+        let assembly_ref = AssemblyRefIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            platform_id,
+            major,
+            minor,
+            assembly_ref,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.platform_id.to_le_bytes());
+        out.extend(self.major.to_le_bytes());
+        out.extend(self.minor.to_le_bytes());
+        self.assembly_ref.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn assembly_ref_os_row() {
+    let row = round_trip(
+        &[2, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 2, 0],
+        HeapSizes::from(0),
+        &[(0x23, 2), (0x25, 1)],
+        AssemblyRefOS::decode,
+        AssemblyRefOS::encode,
+    );
+    assert_eq!((row.platform_id, row.major, row.minor), (2, 6, 0));
+    assert_eq!(row.assembly_ref.0, 2);
+}
+/// A row of the File table(II.22.19).
+#[derive(Clone, Debug)]
+pub(crate) struct File {
+    flags: u32,
+    name: StringIndex,
+    hash_value: BlobIndex,
+}
+impl File {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let flags = take_u32(table_slice)?;
+        let name = sizes.read_string_index(table_slice)?;
+        let hash_value = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            flags,
+            name,
+            hash_value,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        _tables_rows: &[u32],
+        _tables: BitVec64,
+    ) {
+        out.extend(self.flags.to_le_bytes());
+        sizes.write_string_index(out, self.name);
+        sizes.write_blob_index(out, self.hash_value);
+    }
+}
+#[test]
+fn file_row() {
+    let row = round_trip(
+        &[1, 0, 0, 0, 0x33, 0, 0x44, 0],
+        HeapSizes::from(0),
+        &[(0x26, 1)],
+        File::decode,
+        File::encode,
+    );
+    assert_eq!(row.flags, 1);
+    assert_eq!(row.name.0, 0x33);
+    assert_eq!(row.hash_value.0, 0x44);
+}
+/// A row of the ExportedType table(II.22.14).
+#[derive(Clone, Debug)]
+pub(crate) struct ExportedType {
+    flags: u32,
+    type_def_id: u32,
+    name: StringIndex,
+    namespace: StringIndex,
+    implementation: Implementation,
+}
+impl ExportedType {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let flags = take_u32(table_slice)?;
+        let type_def_id = take_u32(table_slice)?;
+        let name = sizes.read_string_index(table_slice)?;
+        let namespace = sizes.read_string_index(table_slice)?;
+        let implementation = Implementation::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            flags,
+            type_def_id,
+            name,
+            namespace,
+            implementation,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.flags.to_le_bytes());
+        out.extend(self.type_def_id.to_le_bytes());
+        sizes.write_string_index(out, self.name);
+        sizes.write_string_index(out, self.namespace);
+        self.implementation.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn exported_type_row() {
+    // TypeDefId is a plain u32 hint, not a TypeDef index, so it stays four bytes wide with a small TypeDef table.
+    let row = round_trip(
+        &[1, 0, 0, 0, 5, 0, 0, 2, 0x50, 0, 0x60, 0, 9, 0],
+        HeapSizes::from(0),
+        &[(0x02, 1), (0x23, 2), (0x27, 1)],
+        ExportedType::decode,
+        ExportedType::encode,
+    );
+    assert_eq!(row.flags, 1);
+    assert_eq!(row.type_def_id, 0x0200_0005);
+    assert_eq!(row.name.0, 0x50);
+    assert_eq!(row.namespace.0, 0x60);
+    assert!(matches!(row.implementation, Implementation::AssemblyRef(index) if index.0 == 2));
+}
+/// `ManifestResourceAttributes.VisibilityMask`
+pub(crate) const RESOURCE_VISIBILITY_MASK: u32 = 0x7;
+/// `ManifestResourceAttributes.Public`
+pub(crate) const RESOURCE_PUBLIC: u32 = 0x1;
+/// A row of the ManifestResource table(II.22.24).
+#[derive(Clone, Debug)]
+pub(crate) struct ManifestResource {
+    offset: u32,
+    flags: u32,
+    name: StringIndex,
+    implementation: Implementation,
+}
+impl ManifestResource {
+    pub(crate) fn new(
+        offset: u32,
+        flags: u32,
+        name: StringIndex,
+        implementation: Implementation,
+    ) -> Self {
+        Self {
+            offset,
+            flags,
+            name,
+            implementation,
+        }
+    }
+    /// Offset of the resource within the CLI Resources directory, or within the file it is stored in.
+    pub(crate) fn offset(&self) -> u32 {
+        self.offset
+    }
+    pub(crate) fn flags(&self) -> u32 {
+        self.flags
+    }
+    pub(crate) fn name(&self) -> StringIndex {
+        self.name
+    }
+    /// Where the resource is stored, or `None` if it is embedded in this file.
+    pub(crate) fn implementation(&self) -> Option<Implementation> {
+        let (_, index) = self.implementation.row();
+        (index != 0).then_some(self.implementation)
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let offset = take_u32(table_slice)?;
+        let flags = take_u32(table_slice)?;
+        let name = sizes.read_string_index(table_slice)?;
+        let implementation = Implementation::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            offset,
+            flags,
+            name,
+            implementation,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.offset.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        sizes.write_string_index(out, self.name);
+        self.implementation.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn manifest_resource_row() {
+    let row = round_trip(
+        &[0x10, 0, 0, 0, 1, 0, 0, 0, 0x70, 0, 5, 0],
+        HeapSizes::from(0),
+        &[(0x23, 1), (0x28, 1)],
+        ManifestResource::decode,
+        ManifestResource::encode,
+    );
+    assert_eq!(row.offset, 0x10);
+    assert_eq!(row.flags, 1);
+    assert_eq!(row.name.0, 0x70);
+    assert!(matches!(row.implementation, Implementation::AssemblyRef(index) if index.0 == 1));
+}
+/// A row of the NestedClass table(II.22.32).
+#[derive(Clone, Debug)]
+pub(crate) struct NestedClass {
+    nested_class: TypeDefIndex,
+    enclosing_class: TypeDefIndex,
+}
+impl NestedClass {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let nested_class = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        let enclosing_class = TypeDefIndex::decode(table_slice, tables_rows, tables)?;
+        Ok(Self {
+            nested_class,
+            enclosing_class,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.nested_class.encode(out, tables_rows, tables);
+        self.enclosing_class.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn nested_class_row() {
+    let row = round_trip(
+        &[3, 0, 2, 0],
+        HeapSizes::from(0),
+        &[(0x02, 3), (0x29, 1)],
+        NestedClass::decode,
+        NestedClass::encode,
+    );
+    assert_eq!(row.nested_class.0, 3);
+    assert_eq!(row.enclosing_class.0, 2);
+}
+/// Flags of a generic parameter(`GenericParamAttributes`, II.23.1.7).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GenericParamFlags(u16);
+impl GenericParamFlags {
+    /// The parameter is covariant(`+T`, or `out T` in C#).
+    pub const COVARIANT: Self = Self(0x1);
+    /// The parameter is contravariant(`-T`, or `in T` in C#).
+    pub const CONTRAVARIANT: Self = Self(0x2);
+    /// The argument must be a reference type(`class`).
+    pub const REFERENCE_TYPE: Self = Self(0x4);
+    /// The argument must be a value type other than `Nullable<T>`(`valuetype`).
+    pub const NOT_NULLABLE_VALUE_TYPE: Self = Self(0x8);
+    /// The argument must have a public parameterless constructor(`.ctor`).
+    pub const DEFAULT_CONSTRUCTOR: Self = Self(0x10);
+    const VARIANCE_MASK: u16 = 0x3;
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+    /// Checks if all flags set in `other` are also set in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn variance(self) -> Variance {
+        match self.0 & Self::VARIANCE_MASK {
+            0x1 => Variance::Covariant,
+            0x2 => Variance::Contravariant,
+            _ => Variance::Invariant,
+        }
+    }
+}
+impl std::ops::BitOr for GenericParamFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+/// How a generic parameter of an interface or delegate relates instantiations with derived arguments.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Variance {
+    Invariant,
+    /// `I<Derived>` can be used as `I<Base>`.
+    Covariant,
+    /// `I<Base>` can be used as `I<Derived>`.
+    Contravariant,
+}
+/// A row of the GenericParam table(II.22.20).
+#[derive(Clone, Debug)]
+pub(crate) struct GenericParam {
+    number: u16,
+    flags: u16,
+    owner: TypeOrMethodDef,
+    name: StringIndex,
+}
+impl GenericParam {
+    pub(crate) fn new(number: u16, flags: u16, owner: TypeOrMethodDef, name: StringIndex) -> Self {
+        Self {
+            number,
+            flags,
+            owner,
+            name,
+        }
+    }
+    /// Position of the parameter in the parameter list of its owner, starting at 0.
+    pub(crate) fn number(&self) -> u16 {
+        self.number
+    }
+    pub(crate) fn flags(&self) -> u16 {
+        self.flags
+    }
+    pub(crate) fn owner(&self) -> TypeOrMethodDef {
+        self.owner
+    }
+    pub(crate) fn name(&self) -> StringIndex {
+        self.name
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let number = take_u16(table_slice)?;
+        let flags = take_u16(table_slice)?;
+        let owner = TypeOrMethodDef::decode(table_slice, tables_rows, tables)?;
+        let name = sizes.read_string_index(table_slice)?;
+        Ok(Self {
+            number,
+            flags,
+            owner,
+            name,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        out.extend(self.number.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        self.owner.encode(out, tables_rows, tables);
+        sizes.write_string_index(out, self.name);
+    }
+}
+#[test]
+fn generic_param_row() {
+    let row = round_trip(
+        &[1, 0, 4, 0, 5, 0, 0x80, 0],
+        HeapSizes::from(0),
+        &[(0x06, 2), (0x2A, 2)],
+        GenericParam::decode,
+        GenericParam::encode,
+    );
+    assert_eq!(row.number, 1);
+    assert_eq!(row.flags, 4);
+    assert!(matches!(row.owner, TypeOrMethodDef::MethodDef(index) if index.0 == 2));
+    assert_eq!(row.name.0, 0x80);
+}
+/// A row of the MethodSpec table(II.22.29).
+#[derive(Clone, Debug)]
+pub(crate) struct MethodSpec {
+    method: MethodDefOrRef,
+    instantiation: BlobIndex,
+}
+impl MethodSpec {
+    pub(crate) fn new(method: MethodDefOrRef, instantiation: BlobIndex) -> Self {
+        Self {
+            method,
+            instantiation,
+        }
+    }
+    /// The generic method being instantiated.
+    pub(crate) fn method(&self) -> MethodDefOrRef {
+        self.method
+    }
+    pub(crate) fn instantiation(&self) -> BlobIndex {
+        self.instantiation
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let method = MethodDefOrRef::decode(table_slice, tables_rows, tables)?;
+        let instantiation = sizes.read_blob_index(table_slice)?;
+        Ok(Self {
+            method,
+            instantiation,
+        })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.method.encode(out, tables_rows, tables);
+        sizes.write_blob_index(out, self.instantiation);
+    }
+}
+#[test]
+fn method_spec_row() {
+    let row = round_trip(
+        &[7, 0, 0x90, 0],
+        HeapSizes::from(0),
+        &[(0x0A, 3), (0x2B, 1)],
+        MethodSpec::decode,
+        MethodSpec::encode,
+    );
+    assert!(matches!(row.method, MethodDefOrRef::MemberRef(index) if index.0 == 3));
+    assert_eq!(row.instantiation.0, 0x90);
+}
+/// A row of the GenericParamConstraint table(II.22.21).
+#[derive(Clone, Debug)]
+pub(crate) struct GenericParamConstraint {
+    owner: GenericParamIndex,
+    constraint: TypeDefOrRef,
+}
+impl GenericParamConstraint {
+    pub(crate) fn new(owner: GenericParamIndex, constraint: TypeDefOrRef) -> Self {
+        Self { owner, constraint }
+    }
+    pub(crate) fn owner(&self) -> GenericParamIndex {
+        self.owner
+    }
+    /// A type the arguments of the owning parameter must derive from or implement.
+    pub(crate) fn constraint(&self) -> TypeDefOrRef {
+        self.constraint
+    }
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        let owner = GenericParamIndex::decode(table_slice, tables_rows, tables)?;
+        let constraint = TypeDefOrRef::decode(table_slice, tables_rows, tables)?;
+        Ok(Self { owner, constraint })
+    }
+    pub(crate) fn encode(
+        &self,
+        out: &mut Vec<u8>,
+        _sizes: HeapSizes,
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) {
+        self.owner.encode(out, tables_rows, tables);
+        self.constraint.encode(out, tables_rows, tables);
+    }
+}
+#[test]
+fn generic_param_constraint_row() {
+    let row = round_trip(
+        &[2, 0, 6, 0],
+        HeapSizes::from(0),
+        &[(0x1B, 1), (0x2A, 2), (0x2C, 1)],
+        GenericParamConstraint::decode,
+        GenericParamConstraint::encode,
+    );
+    assert_eq!(row.owner.0, 2);
+    assert!(matches!(row.constraint, TypeDefOrRef::TypeSpec(index) if index.0 == 1));
+}
@@ -1,53 +1,97 @@
 use crate::{
-    assembly::{decode_blob_compressed_value, EncodedAssembly},
+    assembly::DecodeErrorKind,
+    compressed::{decode_signed, decode_unsigned, encode_signed, encode_unsigned},
+    signature::{decode_types_at, encode_types, Signature},
     type_def::TypeDefOrRef,
 };
+/// How deep types may be nested in each other, such as `int32**` being nested 2 levels deep. Deeper types are
+/// rejected rather than decoded recursively, as a crafted signature could otherwise overflow the stack.
+const MAX_TYPE_DEPTH: u32 = 64;
 // II.23.1.16
-pub fn decode_type(signature: &mut &[u8], asm: &EncodedAssembly) -> Option<Type> {
-    let tpe = decode_blob_compressed_value(signature);
+pub fn decode_type(signature: &mut &[u8]) -> Result<Type, DecodeErrorKind> {
+    decode_type_at(signature, 0)
+}
+/// Decodes a type nested `depth` levels deep in other types.
+pub(crate) fn decode_type_at(signature: &mut &[u8], depth: u32) -> Result<Type, DecodeErrorKind> {
+    if depth > MAX_TYPE_DEPTH {
+        return Err(DecodeErrorKind::NestedTooDeeply);
+    }
+    let nested = |signature: &mut &[u8]| decode_type_at(signature, depth + 1);
+    let tpe = decode_unsigned(signature)?;
     match tpe {
-        0x0 => None,
-        0x1 => Some(Type::Void),
-        0x2 => Some(Type::Bool),
-        0x3 => Some(Type::Char),
-        0x4 => Some(Type::I8),
-        0x5 => Some(Type::U8),
-        0x6 => Some(Type::I16),
-        0x7 => Some(Type::U16),
-        0x8 => Some(Type::I32),
-        0x9 => Some(Type::U32),
-        0xa => Some(Type::I64),
-        0xb => Some(Type::U64),
-        0xc => Some(Type::F32),
-        0xd => Some(Type::F64),
-        0xe => Some(Type::String),
-        0xf => Some(Type::Ptr(decode_type(signature, asm).unwrap().into())),
-        0x10 => Some(Type::Ref(decode_type(signature, asm).unwrap().into())),
-        0x11 => {
-            let (rows, tables) = asm.tables_rows();
-            Some(Type::ValueType(TypeDefOrRef::decode(
-                signature, rows, tables,
-            )))
-        }
-        0x12 => {
-            let (rows, tables) = asm.tables_rows();
-            Some(Type::ValueType(TypeDefOrRef::decode(
-                signature, rows, tables,
-            )))
-        }
-        0x13 => Some(Type::Generic(decode_blob_compressed_value(signature))),
+        0x1 => Ok(Type::Void),
+        0x2 => Ok(Type::Bool),
+        0x3 => Ok(Type::Char),
+        0x4 => Ok(Type::I8),
+        0x5 => Ok(Type::U8),
+        0x6 => Ok(Type::I16),
+        0x7 => Ok(Type::U16),
+        0x8 => Ok(Type::I32),
+        0x9 => Ok(Type::U32),
+        0xa => Ok(Type::I64),
+        0xb => Ok(Type::U64),
+        0xc => Ok(Type::F32),
+        0xd => Ok(Type::F64),
+        0xe => Ok(Type::String),
+        0x16 => Ok(Type::TypedByRef),
+        0xf => Ok(Type::Ptr(nested(signature)?.into())),
+        0x10 => Ok(Type::Ref(nested(signature)?.into())),
+        0x11 => Ok(Type::ValueType(TypeDefOrRef::decode_compressed(signature)?)),
+        0x12 => Ok(Type::ClassType(TypeDefOrRef::decode_compressed(signature)?)),
+        0x13 => Ok(Type::Generic(decode_unsigned(signature)?)),
         0x14 => {
-            let element = decode_type(signature, asm).unwrap().into();
-            let rank = decode_blob_compressed_value(signature);
-            let bound_count = decode_blob_compressed_value(signature);
-            assert_eq!(bound_count, 0, "Array bounds not supported!");
-            Some(Type::Array(element, rank))
+            let element = nested(signature)?.into();
+            let rank = decode_unsigned(signature)?;
+            let size_count = decode_unsigned(signature)?;
+            let sizes = (0..size_count)
+                .map(|_| decode_unsigned(signature))
+                .collect::<Result<_, _>>()?;
+            let bound_count = decode_unsigned(signature)?;
+            let lower_bounds = (0..bound_count)
+                .map(|_| decode_signed(signature))
+                .collect::<Result<_, _>>()?;
+            Ok(Type::Array {
+                element,
+                rank,
+                sizes,
+                lower_bounds,
+            })
+        }
+        0x15 => {
+            let value_type = match decode_unsigned(signature)? {
+                0x11 => true,
+                0x12 => false,
+                kind => {
+                    return Err(DecodeErrorKind::InvalidValue {
+                        field: "GENERICINST kind",
+                        value: kind as u64,
+                    })
+                }
+            };
+            Ok(Type::GenericInst {
+                value_type,
+                definition: TypeDefOrRef::decode_compressed(signature)?,
+                args: decode_types_at(signature, depth + 1)?,
+            })
         }
-        0x1d => {
-            let element = decode_type(signature, asm).unwrap().into();
-            Some(Type::Array(element, 1))
+        0x18 => Ok(Type::ISize),
+        0x19 => Ok(Type::USize),
+        0x1b => Ok(Type::FnPtr(
+            Signature::decode_at(signature, depth + 1)?.into(),
+        )),
+        0x1c => Ok(Type::Object),
+        0x1d => Ok(Type::SZArray(nested(signature)?.into())),
+        0x1e => Ok(Type::MethodGeneric(decode_unsigned(signature)?)),
+        0x1f | 0x20 => {
+            let modifier = TypeDefOrRef::decode_compressed(signature)?;
+            Ok(Type::Modified {
+                required: tpe == 0x1f,
+                modifier,
+                tpe: nested(signature)?.into(),
+            })
         }
-        _ => todo!("Unknown type {tpe:x}"),
+        0x45 => Ok(Type::Pinned(nested(signature)?.into())),
+        _ => Err(DecodeErrorKind::UnknownElementType(tpe)),
     }
 }
 #[derive(Debug)]
@@ -66,10 +110,229 @@ pub enum Type {
     F32,
     F64,
     String,
+    /// A native sized signed integer(`native int`).
+    ISize,
+    /// A native sized unsigned integer(`native unsigned int`).
+    USize,
+    Object,
     Ptr(Box<Self>),
+    /// A pointer to a function with the given signature.
+    FnPtr(Box<Signature>),
+    /// A managed pointer(`BYREF`), such as a `ref` parameter.
     Ref(Box<Self>),
+    /// `System.TypedReference`.
+    TypedByRef,
+    /// A type with a custom modifier(`CMOD_REQD` or `CMOD_OPT`), such as `modreq(IsVolatile)`.
+    Modified {
+        /// Whether callers must understand the modifier(`modreq`), rather than being free to ignore it(`modopt`).
+        required: bool,
+        modifier: TypeDefOrRef,
+        tpe: Box<Self>,
+    },
+    /// A local which the garbage collector may not move.
+    Pinned(Box<Self>),
     ValueType(TypeDefOrRef),
     ClassType(TypeDefOrRef),
+    /// The n-th generic parameter of the enclosing type(`!n`).
     Generic(u32),
-    Array(Box<Type>, u32),
+    /// The n-th generic parameter of the enclosing method(`!!n`).
+    MethodGeneric(u32),
+    /// A generic type instantiated with `args`, such as `List<int32>`.
+    GenericInst {
+        value_type: bool,
+        definition: TypeDefOrRef,
+        args: Box<[Type]>,
+    },
+    /// A single-dimensional, zero-based array(`T[]`).
+    SZArray(Box<Self>),
+    /// A general array. `sizes` and `lower_bounds` may specify fewer dimensions than `rank`.
+    Array {
+        element: Box<Self>,
+        rank: u32,
+        sizes: Box<[u32]>,
+        lower_bounds: Box<[i32]>,
+    },
+}
+impl Type {
+    /// Decodes the signature of a TypeSpec(II.23.2.14), which is a single type.
+    pub fn decode_type_spec(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        decode_type(signature)
+    }
+    /// Appends this type to `out`, in the format read by [`decode_type`]. Panics if a generic index, array size or
+    /// bound is too large for a compressed integer.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Void => out.push(0x01),
+            Self::Bool => out.push(0x02),
+            Self::Char => out.push(0x03),
+            Self::I8 => out.push(0x04),
+            Self::U8 => out.push(0x05),
+            Self::I16 => out.push(0x06),
+            Self::U16 => out.push(0x07),
+            Self::I32 => out.push(0x08),
+            Self::U32 => out.push(0x09),
+            Self::I64 => out.push(0x0a),
+            Self::U64 => out.push(0x0b),
+            Self::F32 => out.push(0x0c),
+            Self::F64 => out.push(0x0d),
+            Self::String => out.push(0x0e),
+            Self::TypedByRef => out.push(0x16),
+            Self::ISize => out.push(0x18),
+            Self::USize => out.push(0x19),
+            Self::Object => out.push(0x1c),
+            Self::Ptr(tpe) => {
+                out.push(0x0f);
+                tpe.encode(out);
+            }
+            Self::Ref(tpe) => {
+                out.push(0x10);
+                tpe.encode(out);
+            }
+            Self::ValueType(tpe) => {
+                out.push(0x11);
+                tpe.encode_compressed(out);
+            }
+            Self::ClassType(tpe) => {
+                out.push(0x12);
+                tpe.encode_compressed(out);
+            }
+            Self::Generic(index) => {
+                out.push(0x13);
+                encode_unsigned(*index, out);
+            }
+            Self::Array {
+                element,
+                rank,
+                sizes,
+                lower_bounds,
+            } => {
+                out.push(0x14);
+                element.encode(out);
+                encode_unsigned(*rank, out);
+                encode_unsigned(sizes.len() as u32, out);
+                for size in sizes.iter() {
+                    encode_unsigned(*size, out);
+                }
+                encode_unsigned(lower_bounds.len() as u32, out);
+                for bound in lower_bounds.iter() {
+                    encode_signed(*bound, out);
+                }
+            }
+            Self::GenericInst {
+                value_type,
+                definition,
+                args,
+            } => {
+                out.push(0x15);
+                out.push(if *value_type { 0x11 } else { 0x12 });
+                definition.encode_compressed(out);
+                encode_types(args, out);
+            }
+            Self::FnPtr(signature) => {
+                out.push(0x1b);
+                signature.encode(out);
+            }
+            Self::SZArray(element) => {
+                out.push(0x1d);
+                element.encode(out);
+            }
+            Self::MethodGeneric(index) => {
+                out.push(0x1e);
+                encode_unsigned(*index, out);
+            }
+            Self::Modified {
+                required,
+                modifier,
+                tpe,
+            } => {
+                out.push(if *required { 0x1f } else { 0x20 });
+                modifier.encode_compressed(out);
+                tpe.encode(out);
+            }
+            Self::Pinned(tpe) => {
+                out.push(0x45);
+                tpe.encode(out);
+            }
+        }
+    }
+}
+#[test]
+fn element_types() {
+    use crate::TypeRefIndex;
+    let decode = |blob: &[u8]| {
+        let mut slice = blob;
+        let tpe = decode_type(&mut slice).unwrap();
+        assert!(slice.is_empty(), "{blob:x?}");
+        tpe
+    };
+    assert!(matches!(decode(&[0x18]), Type::ISize));
+    assert!(matches!(decode(&[0x19]), Type::USize));
+    assert!(matches!(decode(&[0x1C]), Type::Object));
+    assert!(matches!(
+        decode(&[0x11, 0x05]),
+        Type::ValueType(TypeDefOrRef::TypeRef(TypeRefIndex(1)))
+    ));
+    assert!(matches!(
+        decode(&[0x12, 0x05]),
+        Type::ClassType(TypeDefOrRef::TypeRef(TypeRefIndex(1)))
+    ));
+    assert!(matches!(decode(&[0x1E, 0x02]), Type::MethodGeneric(2)));
+    // class List`1<!!0>
+    let Type::GenericInst {
+        value_type: false,
+        definition: TypeDefOrRef::TypeRef(TypeRefIndex(1)),
+        args,
+    } = decode(&[0x15, 0x12, 0x05, 0x01, 0x1E, 0x00])
+    else {
+        panic!();
+    };
+    assert!(matches!(*args, [Type::MethodGeneric(0)]));
+    // method int32 *(string)
+    let Type::FnPtr(signature) = decode(&[0x1B, 0x00, 0x01, 0x08, 0x0E]) else {
+        panic!();
+    };
+    assert!(matches!(signature.ret(), Type::I32));
+    assert!(matches!(signature.args(), [Type::String]));
+    assert!(matches!(decode(&[0x1D, 0x08]), Type::SZArray(_)));
+    // int32[-2...1, 0...]
+    let Type::Array {
+        element,
+        rank: 3,
+        sizes,
+        lower_bounds,
+    } = decode(&[0x14, 0x08, 0x03, 0x01, 0x04, 0x02, 0x7D, 0x00])
+    else {
+        panic!();
+    };
+    assert!(matches!(*element, Type::I32));
+    assert_eq!(*sizes, [4]);
+    assert_eq!(*lower_bounds, [-2, 0]);
+    assert!(matches!(
+        decode_type(&mut &[0x15, 0x08][..]),
+        Err(DecodeErrorKind::InvalidValue { .. })
+    ));
+    // Pointers, arrays and instantiations may be nested up to MAX_TYPE_DEPTH levels, but not deeper.
+    let mut pointers = vec![0x0F; MAX_TYPE_DEPTH as usize];
+    pointers.push(0x08);
+    assert!(matches!(decode(&pointers), Type::Ptr(_)));
+    pointers.insert(0, 0x0F);
+    assert_eq!(
+        decode_type(&mut &pointers[..]).unwrap_err(),
+        DecodeErrorKind::NestedTooDeeply
+    );
+    for wrapper in [
+        &[0x10][..],
+        &[0x1D],
+        &[0x1F, 0x05],
+        &[0x45],
+        &[0x1B, 0x00, 0x00],
+        &[0x15, 0x12, 0x05, 0x01],
+    ] {
+        let nested = wrapper.repeat(MAX_TYPE_DEPTH as usize + 1);
+        assert_eq!(
+            decode_type(&mut &nested[..]).unwrap_err(),
+            DecodeErrorKind::NestedTooDeeply,
+            "{wrapper:x?}"
+        );
+    }
 }
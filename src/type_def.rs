@@ -1,15 +1,29 @@
 use crate::{
-    assembly::{table_rows, u16_from_slice_at, u32_from_slice_at, StringIndex},
+    assembly::{read_table_index, write_table_index, DecodeErrorKind, StringIndex},
     bitvec::BitVec64,
+    coded_index::{CodedIndex, CodedIndexKind},
+    compressed::{decode_unsigned, encode_unsigned},
     field::FieldIndex,
     method::MethodIndex,
 };
 #[derive(Copy, Clone, Debug)]
 pub struct TypeDefIndex(pub u32);
+impl TypeDefIndex {
+    pub(crate) fn decode(
+        table_slice: &mut &[u8],
+        tables_rows: &[u32],
+        tables: BitVec64,
+    ) -> Result<Self, DecodeErrorKind> {
+        read_table_index(table_slice, tables_rows, tables, 0x02).map(Self)
+    }
+    pub(crate) fn encode(self, out: &mut Vec<u8>, tables_rows: &[u32], tables: BitVec64) {
+        write_table_index(out, self.0, tables_rows, tables, 0x02);
+    }
+}
 #[derive(Copy, Clone, Debug)]
 pub struct TypeRefIndex(pub u32);
 #[derive(Copy, Clone, Debug)]
-pub struct TypeSpecIndex(u32);
+pub struct TypeSpecIndex(pub u32);
 #[derive(Copy, Clone, Debug)]
 
 pub(crate) struct TypeDef {
@@ -39,60 +53,59 @@ impl TypeDef {
     pub(crate) fn field_index(&self) -> FieldIndex {
         self.field_index
     }
-    pub(crate) fn from_vecs(
-        flags: &[u32],
-        type_names: &[StringIndex],
-        namespaces: &[StringIndex],
-        derived_from: &[TypeDefOrRef],
-        fields: &[FieldIndex],
-        methods: &[MethodIndex],
-    ) -> Vec<Self> {
-        let mut res = Vec::with_capacity(flags.len());
-        for index in 0..flags.len() {
-            let flags = flags[index];
-            let name = type_names[index];
-            let namespace = namespaces[index];
-            let derived_from = derived_from[index];
-            let field_index = fields[index];
-            let method_index = methods[index];
-            res.push(Self {
-                flags,
-                name,
-                namespace,
-                derived_from,
-                field_index,
-                method_index,
-            })
+    pub(crate) fn new(
+        flags: u32,
+        name: StringIndex,
+        namespace: StringIndex,
+        derived_from: TypeDefOrRef,
+        field_index: FieldIndex,
+        method_index: MethodIndex,
+    ) -> Self {
+        Self {
+            flags,
+            name,
+            namespace,
+            derived_from,
+            field_index,
+            method_index,
         }
-        res
     }
 }
 #[derive(Copy, Clone, Debug)]
-pub(crate) enum TypeDefOrRef {
+pub enum TypeDefOrRef {
     TypeDef(TypeDefIndex),
     TypeRef(TypeRefIndex),
     TypeSpec(TypeSpecIndex),
 }
 impl TypeDefOrRef {
-    pub(crate) fn decode(table_slice: &mut &[u8], tables_rows: &[u32], tables: BitVec64) -> Self {
-        let typedef_num = table_rows(tables_rows, tables, 0x2).unwrap_or_default();
-        let max = typedef_num;
-        let encoded = if max > (1 << 14) {
-            let encoded = u32_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[4..];
-            encoded
-        } else {
-            let encoded = u16_from_slice_at(table_slice, 0);
-            *table_slice = &table_slice[2..];
-            encoded as u32
-        };
-        let tag = encoded & 0b11;
-        let index = tag & (!0b11);
-        match tag {
-            0x00 => TypeDefOrRef::TypeDef(TypeDefIndex(index)),
-            0x01 => TypeDefOrRef::TypeRef(TypeRefIndex(index)),
-            0x02 => TypeDefOrRef::TypeSpec(TypeSpecIndex(index)),
-            _ => panic!("Invalid TypeDefOrRef tag:{tag}"),
+    /// Decodes a TypeDefOrRefOrSpecEncoded value of a signature blob, as described in II.23.2.8.
+    pub(crate) fn decode_compressed(signature: &mut &[u8]) -> Result<Self, DecodeErrorKind> {
+        let (table, index) = Self::KIND.split(decode_unsigned(signature)?)?;
+        Ok(Self::from_row(table, index))
+    }
+    /// Appends this to `out` as a TypeDefOrRefOrSpecEncoded value.
+    pub(crate) fn encode_compressed(self, out: &mut Vec<u8>) {
+        encode_unsigned(Self::KIND.join(self.row()), out);
+    }
+}
+impl CodedIndex for TypeDefOrRef {
+    const KIND: CodedIndexKind = CodedIndexKind {
+        name: "TypeDefOrRef",
+        tag_bits: 2,
+        tables: &[Some(0x02), Some(0x01), Some(0x1B)],
+    };
+    fn from_row(table: u8, index: u32) -> Self {
+        match table {
+            0x02 => Self::TypeDef(TypeDefIndex(index)),
+            0x01 => Self::TypeRef(TypeRefIndex(index)),
+            _ => Self::TypeSpec(TypeSpecIndex(index)),
+        }
+    }
+    fn row(self) -> (u8, u32) {
+        match self {
+            Self::TypeDef(TypeDefIndex(index)) => (0x02, index),
+            Self::TypeRef(TypeRefIndex(index)) => (0x01, index),
+            Self::TypeSpec(TypeSpecIndex(index)) => (0x1B, index),
         }
     }
 }
@@ -0,0 +1,673 @@
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, Write},
+};
+
+use crate::{
+    assembly::{
+        AssemblyReadError, AssemblyRef, AssemblyRefIndex, BlobIndex, CILHeader, DecodeErrorKind,
+        DecodeLocation, EncodedAssembly, UserStringIndex,
+    },
+    coded_index::{CodedIndex, Implementation, MethodDefOrRef, TypeOrMethodDef},
+    field::{Field, FieldIndex},
+    method::{
+        decode_method, MemberRef, MemberRefParent, Method, MethodDef, MethodIndex,
+        StandAloneSigIndex,
+    },
+    param::{Param, ParamIndex},
+    pe_file::{HeaderPolicy, PEHeader, PESection},
+    r#type::Type,
+    resolution_scope::ResolutionScope,
+    signature::{
+        FieldSignature, MemberRefSignature, MethodSpecSignature, Signature, StandAloneSignature,
+    },
+    table::{
+        GenericParam, GenericParamFlags, GenericParamIndex, ManifestResource,
+        ManifestResourceIndex, MethodSpec, MethodSpecIndex, TypeRef, RESOURCE_PUBLIC,
+        RESOURCE_VISIBILITY_MASK,
+    },
+    type_def::{TypeDef, TypeDefIndex, TypeDefOrRef, TypeRefIndex, TypeSpecIndex},
+    win32_resources::Win32Resource,
+};
+/// Decodes the blob at `index` with `decode`, reporting errors at their offset within the blob.
+fn decode_blob<T>(
+    asm: &EncodedAssembly,
+    index: BlobIndex,
+    decode: impl FnOnce(&mut &[u8]) -> Result<T, DecodeErrorKind>,
+) -> Result<T, AssemblyReadError> {
+    let blob = asm.blob_at(index)?;
+    let mut signature = blob;
+    decode(&mut signature).map_err(|kind| AssemblyReadError::Decode {
+        location: DecodeLocation::Blob(index.0),
+        offset: blob.len() - signature.len(),
+        kind,
+    })
+}
+/// Returns the row with the 1-based metadata `index`, or `None` if it is out of range.
+fn row<T>(rows: &[T], index: u32) -> Option<&T> {
+    rows.get((index as usize).checked_sub(1)?)
+}
+/// Rows of the member list which starts at `start`, and ends where the list of the owner's successor starts, at
+/// `next_start`. In uncompressed(`#-`) metadata, lists may index into a Ptr table, which holds the actual rows.
+fn member_list<'a, P>(
+    start: u32,
+    next_start: Option<u32>,
+    members: usize,
+    ptrs: &'a [P],
+    target: impl Fn(&P) -> u32 + 'a,
+) -> impl Iterator<Item = u32> + 'a {
+    let len = if ptrs.is_empty() { members } else { ptrs.len() };
+    let end = next_start.unwrap_or(len as u32 + 1);
+    (start..end).filter_map(move |index| {
+        if ptrs.is_empty() {
+            Some(index)
+        } else {
+            row(ptrs, index).map(&target)
+        }
+    })
+}
+/// Generic parameters owned by `owner`, in the order they are declared in. The GenericParam table is sorted by the
+/// coded owner column, so they are a single run of rows.
+fn generic_params_of(
+    asm: &EncodedAssembly,
+    owner: TypeOrMethodDef,
+) -> impl Iterator<Item = GenericParamDefinition<'_>> {
+    let key = |owner: TypeOrMethodDef| TypeOrMethodDef::KIND.join(owner.row());
+    let params = asm.generic_params();
+    let start = params.partition_point(|param| key(param.owner()) < key(owner));
+    let len = params[start..].partition_point(|param| key(param.owner()) == key(owner));
+    (start as u32 + 1..=(start + len) as u32)
+        .filter_map(move |index| GenericParamDefinition::new(asm, GenericParamIndex(index)))
+}
+/// A .NET assembly read from a PE file.
+pub struct Assembly {
+    encoded: EncodedAssembly,
+}
+impl Assembly {
+    pub fn from_file(file: &mut (impl Read + Seek)) -> Result<Self, AssemblyReadError> {
+        Self::from_file_with_policy(file, HeaderPolicy::Lenient)
+    }
+    /// Reads an assembly, checking its PE headers according to `policy`.
+    pub fn from_file_with_policy(
+        file: &mut (impl Read + Seek),
+        policy: HeaderPolicy,
+    ) -> Result<Self, AssemblyReadError> {
+        Ok(Self {
+            encoded: EncodedAssembly::from_file(file, policy)?,
+        })
+    }
+    /// Writes the assembly back as a PE file, reproducing the file it was read from byte for byte.
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        self.encoded.write_to(out)
+    }
+    /// The PE headers of the image the assembly was read from.
+    pub fn pe_header(&self) -> &PEHeader {
+        self.encoded.pe_file().pe_header()
+    }
+    /// The CLI header of the image, with its runtime flags and entry point.
+    pub fn cli_header(&self) -> &CILHeader {
+        self.encoded.cli_header()
+    }
+    /// Version string of the metadata root, naming the runtime the assembly was built for, such as `v4.0.30319`.
+    pub fn metadata_version(&self) -> &str {
+        self.encoded.cli_header().raw_metadata().version()
+    }
+    /// Raw contents of the `#US` heap, which holds the string literals used by `ldstr`.
+    pub fn user_string_heap(&self) -> Result<&[u8], AssemblyReadError> {
+        self.encoded.user_string_stream()
+    }
+    /// The string literal loaded by `ldstr` with the given index.
+    pub fn user_string(&self, index: UserStringIndex) -> Result<String, AssemblyReadError> {
+        self.encoded.user_string_at(index)
+    }
+    /// All string literals of the assembly, with their indices.
+    pub fn user_strings(
+        &self,
+    ) -> impl Iterator<Item = Result<(UserStringIndex, String), AssemblyReadError>> + '_ {
+        self.encoded.user_strings()
+    }
+    /// The Win32 resources of the image the assembly was read from.
+    pub fn win32_resources(&self) -> Result<Vec<Win32Resource>, AssemblyReadError> {
+        self.encoded.pe_file().win32_resources()
+    }
+    /// The sections of the image the assembly was read from.
+    pub fn pe_sections(&self) -> &[PESection] {
+        self.encoded.pe_file().sections()
+    }
+    /// Name of the assembly, or `None` if this file is a module without an assembly manifest.
+    pub fn name(&self) -> Result<Option<&str>, AssemblyReadError> {
+        self.encoded
+            .assembly_def()
+            .map(|(name, _)| self.encoded.str_at(name))
+            .transpose()
+    }
+    pub fn version(&self) -> Option<Version> {
+        let (_, version) = self.encoded.assembly_def()?;
+        Some(version.into())
+    }
+    pub fn module_name(&self) -> Result<Option<&str>, AssemblyReadError> {
+        self.encoded
+            .module_name()
+            .map(|name| self.encoded.str_at(name))
+            .transpose()
+    }
+    pub fn module_mvid(&self) -> Option<u128> {
+        self.encoded.module_mvid()
+    }
+    pub fn types(&self) -> impl Iterator<Item = TypeDefinition<'_>> {
+        (1..=self.encoded.type_defs().len() as u32)
+            .filter_map(|index| self.type_def(TypeDefIndex(index)))
+    }
+    pub fn type_def(&self, index: TypeDefIndex) -> Option<TypeDefinition<'_>> {
+        let row = row(self.encoded.type_defs(), index.0)?;
+        Some(TypeDefinition {
+            asm: &self.encoded,
+            index,
+            row,
+        })
+    }
+    pub fn methods(&self) -> impl Iterator<Item = MethodDefinition<'_>> {
+        (1..=self.encoded.methods().len() as u32)
+            .filter_map(|index| self.method(MethodIndex(index)))
+    }
+    pub fn method(&self, index: MethodIndex) -> Option<MethodDefinition<'_>> {
+        MethodDefinition::new(&self.encoded, index)
+    }
+    pub fn fields(&self) -> impl Iterator<Item = FieldDefinition<'_>> {
+        (1..=self.encoded.fields().len() as u32).filter_map(|index| self.field(FieldIndex(index)))
+    }
+    pub fn field(&self, index: FieldIndex) -> Option<FieldDefinition<'_>> {
+        FieldDefinition::new(&self.encoded, index)
+    }
+    pub fn params(&self) -> impl Iterator<Item = ParamDefinition<'_>> {
+        (1..=self.encoded.params().len() as u32).filter_map(|index| self.param(ParamIndex(index)))
+    }
+    pub fn param(&self, index: ParamIndex) -> Option<ParamDefinition<'_>> {
+        ParamDefinition::new(&self.encoded, index)
+    }
+    pub fn type_refs(&self) -> impl Iterator<Item = TypeReference<'_>> {
+        (1..=self.encoded.type_refs().len() as u32)
+            .filter_map(|index| self.type_ref(TypeRefIndex(index)))
+    }
+    pub fn type_ref(&self, index: TypeRefIndex) -> Option<TypeReference<'_>> {
+        let row = row(self.encoded.type_refs(), index.0)?;
+        Some(TypeReference {
+            asm: &self.encoded,
+            index,
+            row,
+        })
+    }
+    pub fn member_refs(&self) -> impl Iterator<Item = MemberReference<'_>> {
+        self.encoded
+            .member_refs()
+            .iter()
+            .map(|row| MemberReference {
+                asm: &self.encoded,
+                row,
+            })
+    }
+    pub fn assembly_refs(&self) -> impl Iterator<Item = AssemblyReference<'_>> {
+        (1..=self.encoded.assembly_refs().len() as u32)
+            .filter_map(|index| self.assembly_ref(AssemblyRefIndex(index)))
+    }
+    pub fn assembly_ref(&self, index: AssemblyRefIndex) -> Option<AssemblyReference<'_>> {
+        let row = row(self.encoded.assembly_refs(), index.0)?;
+        Some(AssemblyReference {
+            asm: &self.encoded,
+            index,
+            row,
+        })
+    }
+    /// The signature of a method's locals or of a `calli` call site, or `None` if `index` is out of range.
+    pub fn stand_alone_signature(
+        &self,
+        index: StandAloneSigIndex,
+    ) -> Result<Option<StandAloneSignature>, AssemblyReadError> {
+        row(self.encoded.stand_alone_sigs(), index.0)
+            .map(|blob| decode_blob(&self.encoded, *blob, StandAloneSignature::decode))
+            .transpose()
+    }
+    /// Decodes the type described by a row of the TypeSpec table, or returns `None` if `index` is out of range.
+    pub fn type_spec(&self, index: TypeSpecIndex) -> Result<Option<Type>, AssemblyReadError> {
+        row(self.encoded.type_specs(), index.0)
+            .map(|spec| decode_blob(&self.encoded, spec.signature(), Type::decode_type_spec))
+            .transpose()
+    }
+    /// Types described by the TypeSpec table, such as instantiations of generic types.
+    pub fn type_specs(&self) -> impl Iterator<Item = Result<Type, AssemblyReadError>> + '_ {
+        self.encoded
+            .type_specs()
+            .iter()
+            .map(|spec| decode_blob(&self.encoded, spec.signature(), Type::decode_type_spec))
+    }
+    /// Generic parameters of all types and methods of the assembly.
+    pub fn generic_params(&self) -> impl Iterator<Item = GenericParamDefinition<'_>> {
+        (1..=self.encoded.generic_params().len() as u32)
+            .filter_map(|index| self.generic_param(GenericParamIndex(index)))
+    }
+    pub fn generic_param(&self, index: GenericParamIndex) -> Option<GenericParamDefinition<'_>> {
+        GenericParamDefinition::new(&self.encoded, index)
+    }
+    /// Instantiations of generic methods, used by calls to them.
+    pub fn method_specs(&self) -> impl Iterator<Item = MethodSpecification<'_>> {
+        (1..=self.encoded.method_specs().len() as u32)
+            .filter_map(|index| self.method_spec(MethodSpecIndex(index)))
+    }
+    pub fn method_spec(&self, index: MethodSpecIndex) -> Option<MethodSpecification<'_>> {
+        let row = row(self.encoded.method_specs(), index.0)?;
+        Some(MethodSpecification {
+            asm: &self.encoded,
+            index,
+            row,
+        })
+    }
+    /// Managed resources of the assembly, both embedded in this file and linked from other ones.
+    pub fn manifest_resources(&self) -> impl Iterator<Item = ManifestResourceDefinition<'_>> {
+        (1..=self.encoded.manifest_resources().len() as u32)
+            .filter_map(|index| self.manifest_resource(ManifestResourceIndex(index)))
+    }
+    pub fn manifest_resource(
+        &self,
+        index: ManifestResourceIndex,
+    ) -> Option<ManifestResourceDefinition<'_>> {
+        let row = row(self.encoded.manifest_resources(), index.0)?;
+        Some(ManifestResourceDefinition {
+            asm: &self.encoded,
+            index,
+            row,
+        })
+    }
+    /// Finds a managed resource by its name.
+    pub fn manifest_resource_by_name(
+        &self,
+        name: &str,
+    ) -> Result<Option<ManifestResourceDefinition<'_>>, AssemblyReadError> {
+        for resource in self.manifest_resources() {
+            if resource.name()? == name {
+                return Ok(Some(resource));
+            }
+        }
+        Ok(None)
+    }
+}
+/// Four part version number of an assembly, in the `major.minor.build.revision` form.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub build: u16,
+    pub revision: u16,
+}
+impl From<(u16, u16, u16, u16)> for Version {
+    fn from((major, minor, build, revision): (u16, u16, u16, u16)) -> Self {
+        Self {
+            major,
+            minor,
+            build,
+            revision,
+        }
+    }
+}
+impl From<Version> for (u16, u16, u16, u16) {
+    fn from(version: Version) -> Self {
+        (
+            version.major,
+            version.minor,
+            version.build,
+            version.revision,
+        )
+    }
+}
+/// A type defined in an assembly(a row of the TypeDef table).
+#[derive(Clone, Copy)]
+pub struct TypeDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: TypeDefIndex,
+    row: &'a TypeDef,
+}
+impl<'a> TypeDefinition<'a> {
+    pub fn index(&self) -> TypeDefIndex {
+        self.index
+    }
+    pub fn flags(&self) -> u32 {
+        self.row.flags()
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn namespace(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.namespace())
+    }
+    pub fn extends(&self) -> TypeDefOrRef {
+        self.row.derived_from()
+    }
+    /// Methods owned by this type. A type owns a run of methods, which ends where the run of the next type starts.
+    pub fn methods(&self) -> impl Iterator<Item = MethodDefinition<'a>> {
+        let asm = self.asm;
+        let next = row(asm.type_defs(), self.index.0 + 1).map(|next| next.method_index().0);
+        member_list(
+            self.row.method_index().0,
+            next,
+            asm.methods().len(),
+            asm.method_ptrs(),
+            |ptr| ptr.method().0,
+        )
+        .filter_map(move |index| MethodDefinition::new(asm, MethodIndex(index)))
+    }
+    /// Fields owned by this type. A type owns a run of fields, which ends where the run of the next type starts.
+    pub fn fields(&self) -> impl Iterator<Item = FieldDefinition<'a>> {
+        let asm = self.asm;
+        let next = row(asm.type_defs(), self.index.0 + 1).map(|next| next.field_index().0);
+        member_list(
+            self.row.field_index().0,
+            next,
+            asm.fields().len(),
+            asm.field_ptrs(),
+            |ptr| ptr.field().0,
+        )
+        .filter_map(move |index| FieldDefinition::new(asm, FieldIndex(index)))
+    }
+    /// Generic parameters of this type, empty if it is not generic.
+    pub fn generic_params(&self) -> impl Iterator<Item = GenericParamDefinition<'a>> {
+        generic_params_of(self.asm, TypeOrMethodDef::TypeDef(self.index))
+    }
+}
+/// A method defined in an assembly(a row of the MethodDef table).
+#[derive(Clone, Copy)]
+pub struct MethodDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: MethodIndex,
+    row: &'a MethodDef,
+}
+impl<'a> MethodDefinition<'a> {
+    fn new(asm: &'a EncodedAssembly, index: MethodIndex) -> Option<Self> {
+        let row = row(asm.methods(), index.0)?;
+        Some(Self { asm, index, row })
+    }
+    pub fn index(&self) -> MethodIndex {
+        self.index
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn flags(&self) -> u16 {
+        self.row.flags()
+    }
+    pub fn impl_flags(&self) -> u16 {
+        self.row.impl_flags()
+    }
+    pub fn signature(&self) -> Result<Signature, AssemblyReadError> {
+        decode_blob(self.asm, self.row.signature(), Signature::decode)
+    }
+    /// The CIL body of this method, or `None` if the method has no body(e.g. it is abstract or implemented by the runtime).
+    pub fn body(&self) -> Result<Option<Method>, AssemblyReadError> {
+        let rva = self.row.rva();
+        if rva.0 == 0 {
+            return Ok(None);
+        }
+        decode_method(self.asm.pe_file(), rva).map(Some)
+    }
+    /// Parameters of this method. A method owns a run of params, which ends where the run of the next method starts.
+    pub fn params(&self) -> impl Iterator<Item = ParamDefinition<'a>> {
+        let asm = self.asm;
+        let next = row(asm.methods(), self.index.0 + 1).map(|next| next.param_start().0);
+        member_list(
+            self.row.param_start().0,
+            next,
+            asm.params().len(),
+            asm.param_ptrs(),
+            |ptr| ptr.param().0,
+        )
+        .filter_map(move |index| ParamDefinition::new(asm, ParamIndex(index)))
+    }
+    /// Generic parameters of this method, empty if it is not generic.
+    pub fn generic_params(&self) -> impl Iterator<Item = GenericParamDefinition<'a>> {
+        generic_params_of(self.asm, TypeOrMethodDef::MethodDef(self.index))
+    }
+}
+/// A field defined in an assembly(a row of the Field table).
+#[derive(Clone, Copy)]
+pub struct FieldDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: FieldIndex,
+    row: &'a Field,
+}
+impl<'a> FieldDefinition<'a> {
+    fn new(asm: &'a EncodedAssembly, index: FieldIndex) -> Option<Self> {
+        let row = row(asm.fields(), index.0)?;
+        Some(Self { asm, index, row })
+    }
+    pub fn index(&self) -> FieldIndex {
+        self.index
+    }
+    pub fn flags(&self) -> u16 {
+        self.row.flags()
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    /// Raw bytes of the field signature blob.
+    pub fn signature_blob(&self) -> Result<&'a [u8], AssemblyReadError> {
+        self.asm.blob_at(self.row.signature())
+    }
+    pub fn signature(&self) -> Result<FieldSignature, AssemblyReadError> {
+        decode_blob(self.asm, self.row.signature(), FieldSignature::decode)
+    }
+}
+/// A parameter of a method(a row of the Param table).
+#[derive(Clone, Copy)]
+pub struct ParamDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: ParamIndex,
+    row: &'a Param,
+}
+impl<'a> ParamDefinition<'a> {
+    fn new(asm: &'a EncodedAssembly, index: ParamIndex) -> Option<Self> {
+        let row = row(asm.params(), index.0)?;
+        Some(Self { asm, index, row })
+    }
+    pub fn index(&self) -> ParamIndex {
+        self.index
+    }
+    pub fn flags(&self) -> u16 {
+        self.row.flags()
+    }
+    /// Position of the parameter. 0 refers to the return value, 1 to the first argument.
+    pub fn sequence(&self) -> u16 {
+        self.row.sequence()
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+}
+/// A reference to a type defined in another module or assembly(a row of the TypeRef table).
+#[derive(Clone, Copy)]
+pub struct TypeReference<'a> {
+    asm: &'a EncodedAssembly,
+    index: TypeRefIndex,
+    row: &'a TypeRef,
+}
+impl<'a> TypeReference<'a> {
+    pub fn index(&self) -> TypeRefIndex {
+        self.index
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn namespace(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.namespace())
+    }
+    pub fn scope(&self) -> ResolutionScope {
+        self.row.scope()
+    }
+}
+/// A reference to a field or method of another type(a row of the MemberRef table).
+#[derive(Clone, Copy)]
+pub struct MemberReference<'a> {
+    asm: &'a EncodedAssembly,
+    row: &'a MemberRef,
+}
+impl<'a> MemberReference<'a> {
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn parent(&self) -> MemberRefParent {
+        self.row.class()
+    }
+    /// Raw bytes of the member signature blob.
+    pub fn signature_blob(&self) -> Result<&'a [u8], AssemblyReadError> {
+        self.asm.blob_at(self.row.signature())
+    }
+    pub fn signature(&self) -> Result<MemberRefSignature, AssemblyReadError> {
+        decode_blob(self.asm, self.row.signature(), MemberRefSignature::decode)
+    }
+}
+/// A reference to another assembly(a row of the AssemblyRef table).
+#[derive(Clone, Copy)]
+pub struct AssemblyReference<'a> {
+    asm: &'a EncodedAssembly,
+    index: AssemblyRefIndex,
+    row: &'a AssemblyRef,
+}
+impl<'a> AssemblyReference<'a> {
+    pub fn index(&self) -> AssemblyRefIndex {
+        self.index
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn culture(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.culture())
+    }
+    pub fn version(&self) -> Version {
+        self.row.version().into()
+    }
+    pub fn flags(&self) -> u32 {
+        self.row.flags()
+    }
+    /// The full public key or its 8 byte token, depending on the flags. Empty if the referenced assembly is not strongly named.
+    pub fn public_key_or_token(&self) -> Result<&'a [u8], AssemblyReadError> {
+        self.asm.blob_at(self.row.public_key_or_token())
+    }
+    pub fn hash_value(&self) -> Result<&'a [u8], AssemblyReadError> {
+        self.asm.blob_at(self.row.hash_value())
+    }
+}
+/// A managed resource of an assembly(a row of the ManifestResource table).
+#[derive(Clone, Copy)]
+pub struct ManifestResourceDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: ManifestResourceIndex,
+    row: &'a ManifestResource,
+}
+impl<'a> ManifestResourceDefinition<'a> {
+    pub fn index(&self) -> ManifestResourceIndex {
+        self.index
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    pub fn flags(&self) -> u32 {
+        self.row.flags()
+    }
+    pub fn is_public(&self) -> bool {
+        self.row.flags() & RESOURCE_VISIBILITY_MASK == RESOURCE_PUBLIC
+    }
+    /// The file or assembly the resource is stored in, or `None` if it is embedded in this file.
+    pub fn implementation(&self) -> Option<Implementation> {
+        self.row.implementation()
+    }
+    /// Offset of the resource within the file it is stored in.
+    pub fn offset(&self) -> u32 {
+        self.row.offset()
+    }
+    /// Contents of the resource, or `None` if it is stored in another file or assembly.
+    pub fn data(&self) -> Result<Option<Cow<'a, [u8]>>, AssemblyReadError> {
+        if self.implementation().is_some() {
+            return Ok(None);
+        }
+        self.asm.managed_resource_at(self.row.offset()).map(Some)
+    }
+}
+/// A generic parameter of a type or method(a row of the GenericParam table).
+#[derive(Clone, Copy)]
+pub struct GenericParamDefinition<'a> {
+    asm: &'a EncodedAssembly,
+    index: GenericParamIndex,
+    row: &'a GenericParam,
+}
+impl<'a> GenericParamDefinition<'a> {
+    fn new(asm: &'a EncodedAssembly, index: GenericParamIndex) -> Option<Self> {
+        let row = row(asm.generic_params(), index.0)?;
+        Some(Self { asm, index, row })
+    }
+    pub fn index(&self) -> GenericParamIndex {
+        self.index
+    }
+    /// Position of this parameter among the generic parameters of its owner, starting at 0.
+    pub fn number(&self) -> u16 {
+        self.row.number()
+    }
+    pub fn flags(&self) -> GenericParamFlags {
+        GenericParamFlags::from_bits(self.row.flags())
+    }
+    /// The type or method this parameter belongs to.
+    pub fn owner(&self) -> TypeOrMethodDef {
+        self.row.owner()
+    }
+    pub fn name(&self) -> Result<&'a str, AssemblyReadError> {
+        self.asm.str_at(self.row.name())
+    }
+    /// Types the arguments of this parameter must derive from or implement.
+    pub fn constraints(&self) -> impl Iterator<Item = TypeDefOrRef> + 'a {
+        // The GenericParamConstraint table is sorted by owner.
+        let constraints = self.asm.generic_param_constraints();
+        let start = constraints.partition_point(|constraint| constraint.owner().0 < self.index.0);
+        let len =
+            constraints[start..].partition_point(|constraint| constraint.owner().0 == self.index.0);
+        constraints[start..start + len]
+            .iter()
+            .map(|constraint| constraint.constraint())
+    }
+}
+/// An instantiation of a generic method(a row of the MethodSpec table).
+#[derive(Clone, Copy)]
+pub struct MethodSpecification<'a> {
+    asm: &'a EncodedAssembly,
+    index: MethodSpecIndex,
+    row: &'a MethodSpec,
+}
+impl<'a> MethodSpecification<'a> {
+    pub fn index(&self) -> MethodSpecIndex {
+        self.index
+    }
+    /// The generic method being instantiated.
+    pub fn method(&self) -> MethodDefOrRef {
+        self.row.method()
+    }
+    pub fn instantiation_blob(&self) -> Result<&'a [u8], AssemblyReadError> {
+        self.asm.blob_at(self.row.instantiation())
+    }
+    /// The generic arguments the method is instantiated with.
+    pub fn instantiation(&self) -> Result<MethodSpecSignature, AssemblyReadError> {
+        decode_blob(
+            self.asm,
+            self.row.instantiation(),
+            MethodSpecSignature::decode,
+        )
+    }
+}
+#[test]
+fn member_lists() {
+    // Without a Ptr table, lists are runs of the member table itself.
+    let direct: Vec<_> = member_list(2, Some(4), 5, &[] as &[u32], |ptr| *ptr).collect();
+    assert_eq!(direct, [2, 3]);
+    let last: Vec<_> = member_list(4, None, 5, &[] as &[u32], |ptr| *ptr).collect();
+    assert_eq!(last, [4, 5]);
+    // With one, they are runs of the Ptr table, which may order the members differently.
+    let ptrs = [3, 1, 2];
+    let first: Vec<_> = member_list(1, Some(3), 3, &ptrs, |ptr| *ptr).collect();
+    assert_eq!(first, [3, 1]);
+    let last: Vec<_> = member_list(3, None, 3, &ptrs, |ptr| *ptr).collect();
+    assert_eq!(last, [2]);
+}
@@ -0,0 +1,540 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::{
+    assembly::{take_u16, take_u32, AssemblyReadError, DecodeErrorKind, DecodeLocation},
+    pe_file::{align_up, RVA},
+    view::Version,
+};
+/// Identifies a resource type, or a resource of a given type, either by number or by name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceId {
+    Id(u32),
+    Name(Box<str>),
+}
+impl ResourceId {
+    pub const ICON: Self = Self::Id(3);
+    pub const GROUP_ICON: Self = Self::Id(14);
+    pub const VERSION: Self = Self::Id(16);
+    pub const MANIFEST: Self = Self::Id(24);
+    /// Named entries come before numbered ones, and each are in ascending order.
+    fn sort_key(&self) -> (u8, u32, Vec<u16>) {
+        match self {
+            Self::Name(name) => (0, 0, name.encode_utf16().collect()),
+            Self::Id(id) => (1, *id, Vec::new()),
+        }
+    }
+}
+/// A Win32 resource, one leaf of the type/name/language tree of the resource directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Win32Resource {
+    pub kind: ResourceId,
+    pub name: ResourceId,
+    pub language: u32,
+    /// Code page of the data, usually 0.
+    pub code_page: u32,
+    pub data: Vec<u8>,
+}
+/// Size of a resource directory table with `entries` entries.
+fn directory_size(entries: usize) -> u32 {
+    16 + 8 * entries as u32
+}
+/// Decodes the resource directory, `directory` being the bytes starting at its RVA. `data_at` returns the data
+/// of a resource, which may lie anywhere in the image.
+pub(crate) fn decode_resources<'a>(
+    directory: &[u8],
+    data_at: impl Fn(RVA, u64) -> Option<Cow<'a, [u8]>>,
+) -> Result<Vec<Win32Resource>, AssemblyReadError> {
+    let err = |offset, kind| AssemblyReadError::Decode {
+        location: DecodeLocation::Win32Resources,
+        offset,
+        kind,
+    };
+    let at = |offset: u32| {
+        directory
+            .get(offset as usize..)
+            .ok_or(err(offset as usize, DecodeErrorKind::UnexpectedEnd))
+    };
+    // Returns the id and the target of every entry of the table at `offset`, and whether they are subdirectories.
+    let entries = |offset: u32| {
+        let mut table = at(offset)?;
+        let read_err = |table: &[u8], kind| err(directory.len() - table.len(), kind);
+        // Characteristics, TimeDateStamp, MajorVersion and MinorVersion
+        table = table
+            .get(12..)
+            .ok_or(err(offset as usize, DecodeErrorKind::UnexpectedEnd))?;
+        let named = take_u16(&mut table).map_err(|kind| read_err(table, kind))?;
+        let numbered = take_u16(&mut table).map_err(|kind| read_err(table, kind))?;
+        let mut entries = Vec::with_capacity(named as usize + numbered as usize);
+        for _ in 0..(named as usize + numbered as usize) {
+            let id = take_u32(&mut table).map_err(|kind| read_err(table, kind))?;
+            let target = take_u32(&mut table).map_err(|kind| read_err(table, kind))?;
+            let id = if id & 0x8000_0000 != 0 {
+                let name_offset = id & 0x7FFF_FFFF;
+                let mut name = at(name_offset)?;
+                let length = take_u16(&mut name).map_err(|kind| read_err(name, kind))?;
+                let units = name
+                    .get(..length as usize * 2)
+                    .ok_or(err(name_offset as usize, DecodeErrorKind::UnexpectedEnd))?;
+                ResourceId::Name(utf16(units).map_err(|kind| err(name_offset as usize + 2, kind))?)
+            } else {
+                ResourceId::Id(id)
+            };
+            entries.push((id, target & 0x7FFF_FFFF, target & 0x8000_0000 != 0));
+        }
+        Ok::<_, AssemblyReadError>(entries)
+    };
+    let subdirectory = |(id, target, is_directory): (ResourceId, u32, bool)| {
+        if is_directory {
+            Ok((id, target))
+        } else {
+            Err(err(
+                target as usize,
+                DecodeErrorKind::InvalidValue {
+                    field: "resource directory entry",
+                    value: target as u64,
+                },
+            ))
+        }
+    };
+    let mut resources = Vec::new();
+    for entry in entries(0)? {
+        let (kind, names) = subdirectory(entry)?;
+        for entry in entries(names)? {
+            let (name, languages) = subdirectory(entry)?;
+            for (language, target, is_directory) in entries(languages)? {
+                let language = match language {
+                    ResourceId::Id(language) if !is_directory => language,
+                    _ => {
+                        return Err(err(
+                            languages as usize,
+                            DecodeErrorKind::InvalidValue {
+                                field: "resource language entry",
+                                value: target as u64,
+                            },
+                        ))
+                    }
+                };
+                let mut data_entry = at(target)?;
+                let read_err = |kind| err(target as usize, kind);
+                let rva = RVA(take_u32(&mut data_entry).map_err(read_err)? as u64);
+                let size = take_u32(&mut data_entry).map_err(read_err)? as u64;
+                let code_page = take_u32(&mut data_entry).map_err(read_err)?;
+                let data = data_at(rva, size).ok_or(AssemblyReadError::InvalidRVA {
+                    location: DecodeLocation::Win32Resources,
+                    rva,
+                    size,
+                })?;
+                resources.push(Win32Resource {
+                    kind: kind.clone(),
+                    name: name.clone(),
+                    language,
+                    code_page,
+                    data: data.into_owned(),
+                });
+            }
+        }
+    }
+    Ok(resources)
+}
+fn utf16(units: &[u8]) -> Result<Box<str>, DecodeErrorKind> {
+    let units: Vec<u16> = units
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    String::from_utf16(&units)
+        .map(Into::into)
+        .map_err(|_| DecodeErrorKind::InvalidUtf16)
+}
+/// Encodes `resources` as a resource directory placed at `rva`. The directory tables come first, followed by the
+/// data entries, the names and the data of the resources.
+pub(crate) fn encode_resources(resources: &[Win32Resource], rva: u32) -> Vec<u8> {
+    let mut sorted: Vec<_> = resources.iter().collect();
+    sorted.sort_by_cached_key(|resource| {
+        (
+            resource.kind.sort_key(),
+            resource.name.sort_key(),
+            resource.language,
+        )
+    });
+    let types: Vec<Vec<&[&Win32Resource]>> = sorted
+        .chunk_by(|a, b| a.kind == b.kind)
+        .map(|of_type| of_type.chunk_by(|a, b| a.name == b.name).collect())
+        .collect();
+    let type_tables_start = directory_size(types.len());
+    let name_tables_start = type_tables_start
+        + types
+            .iter()
+            .map(|names| directory_size(names.len()))
+            .sum::<u32>();
+    let data_entries_start = name_tables_start
+        + types
+            .iter()
+            .flatten()
+            .map(|languages| directory_size(languages.len()))
+            .sum::<u32>();
+    let names_start = data_entries_start + 16 * sorted.len() as u32;
+    let mut names = Vec::new();
+    let mut name_offsets = HashMap::new();
+    for id in sorted
+        .iter()
+        .flat_map(|resource| [&resource.kind, &resource.name])
+    {
+        if let ResourceId::Name(name) = id {
+            name_offsets.entry(name).or_insert_with(|| {
+                let offset = names_start + names.len() as u32;
+                let units: Vec<u16> = name.encode_utf16().collect();
+                names.extend((units.len() as u16).to_le_bytes());
+                names.extend(units.into_iter().flat_map(u16::to_le_bytes));
+                offset
+            });
+        }
+    }
+    let id_field = |id: &ResourceId| match id {
+        ResourceId::Id(id) => *id,
+        ResourceId::Name(name) => 0x8000_0000 | name_offsets[name],
+    };
+    let mut out = Vec::new();
+    let table = |out: &mut Vec<u8>, entries: &mut dyn Iterator<Item = (&ResourceId, u32)>| {
+        let entries: Vec<_> = entries.collect();
+        let named = entries
+            .iter()
+            .filter(|(id, _)| matches!(id, ResourceId::Name(_)))
+            .count();
+        out.extend([0; 12]);
+        out.extend((named as u16).to_le_bytes());
+        out.extend(((entries.len() - named) as u16).to_le_bytes());
+        for (id, target) in entries {
+            out.extend(id_field(id).to_le_bytes());
+            out.extend(target.to_le_bytes());
+        }
+    };
+    let mut next_table = type_tables_start;
+    table(
+        &mut out,
+        &mut types.iter().map(|names| {
+            let offset = next_table;
+            next_table += directory_size(names.len());
+            (&names[0][0].kind, 0x8000_0000 | offset)
+        }),
+    );
+    for names in &types {
+        table(
+            &mut out,
+            &mut names.iter().map(|languages| {
+                let offset = next_table;
+                next_table += directory_size(languages.len());
+                (&languages[0].name, 0x8000_0000 | offset)
+            }),
+        );
+    }
+    let mut next_entry = data_entries_start;
+    let language_ids: Vec<_> = sorted
+        .iter()
+        .map(|resource| ResourceId::Id(resource.language))
+        .collect();
+    let mut language_ids = language_ids.iter();
+    for languages in types.iter().flatten() {
+        table(
+            &mut out,
+            &mut language_ids.by_ref().take(languages.len()).map(|language| {
+                let offset = next_entry;
+                next_entry += 16;
+                (language, offset)
+            }),
+        );
+    }
+    let mut data_offset = align_up(names_start + names.len() as u32, 8);
+    for resource in &sorted {
+        out.extend((rva + data_offset).to_le_bytes());
+        out.extend((resource.data.len() as u32).to_le_bytes());
+        out.extend(resource.code_page.to_le_bytes());
+        out.extend([0; 4]);
+        data_offset = align_up(data_offset + resource.data.len() as u32, 8);
+    }
+    out.extend(names);
+    for resource in &sorted {
+        out.resize(align_up(out.len() as u32, 8) as usize, 0);
+        out.extend(&resource.data);
+    }
+    out
+}
+/// The contents of a `VS_VERSIONINFO` resource, which Windows shows as the details of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub file_version: Version,
+    pub product_version: Version,
+    pub is_dll: bool,
+    /// Entries of the language neutral string table, such as `FileDescription` or `ProductName`.
+    pub strings: Vec<(Box<str>, Box<str>)>,
+}
+/// `dwSignature` of `VS_FIXEDFILEINFO`.
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xFEEF_04BD;
+/// Language neutral, Unicode.
+const TRANSLATION: [u16; 2] = [0x0000, 0x04B0];
+impl VersionInfo {
+    /// Version info with the same file and product version, which are also added to the string table.
+    pub fn new(version: Version, is_dll: bool) -> Self {
+        let version_string = format!(
+            "{}.{}.{}.{}",
+            version.major, version.minor, version.build, version.revision
+        );
+        Self {
+            file_version: version,
+            product_version: version,
+            is_dll,
+            strings: vec![
+                ("FileVersion".into(), version_string.clone().into()),
+                ("ProductVersion".into(), version_string.into()),
+            ],
+        }
+    }
+    /// A version resource holding this info, as resource 1 of the neutral language.
+    pub fn to_resource(&self) -> Win32Resource {
+        Win32Resource {
+            kind: ResourceId::VERSION,
+            name: ResourceId::Id(1),
+            language: 0,
+            code_page: 0,
+            data: self.encode(),
+        }
+    }
+    pub fn encode(&self) -> Vec<u8> {
+        let split = |version: Version| {
+            let (major, minor, build, revision) = version.into();
+            [
+                (major as u32) << 16 | minor as u32,
+                (build as u32) << 16 | revision as u32,
+            ]
+        };
+        let [file_ms, file_ls] = split(self.file_version);
+        let [product_ms, product_ls] = split(self.product_version);
+        // VFT_DLL or VFT_APP
+        let file_type = if self.is_dll { 2 } else { 1 };
+        let fixed_info: Vec<u8> = [
+            FIXED_FILE_INFO_SIGNATURE,
+            0x0001_0000,
+            file_ms,
+            file_ls,
+            product_ms,
+            product_ls,
+            // FileFlagsMask and FileFlags
+            0x3F,
+            0,
+            // VOS__WINDOWS32
+            0x4,
+            file_type,
+            // FileSubtype, and the file date
+            0,
+            0,
+            0,
+        ]
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect();
+        let mut out = Vec::new();
+        version_block(
+            &mut out,
+            "VS_VERSION_INFO",
+            Value::Binary(&fixed_info),
+            |out| {
+                version_block(out, "StringFileInfo", Value::Binary(&[]), |out| {
+                    let table = format!("{:04x}{:04x}", TRANSLATION[0], TRANSLATION[1]);
+                    version_block(out, &table, Value::Binary(&[]), |out| {
+                        for (key, value) in &self.strings {
+                            version_block(out, key, Value::Text(value), |_| ());
+                        }
+                    });
+                });
+                version_block(out, "VarFileInfo", Value::Binary(&[]), |out| {
+                    let translation: Vec<u8> =
+                        TRANSLATION.into_iter().flat_map(u16::to_le_bytes).collect();
+                    version_block(out, "Translation", Value::Binary(&translation), |_| ());
+                });
+            },
+        );
+        out
+    }
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeErrorKind> {
+        let mut data = data;
+        let root = VersionBlock::take(&mut data)?;
+        if &*root.key != "VS_VERSION_INFO" {
+            return Err(DecodeErrorKind::InvalidValue {
+                field: "VS_VERSIONINFO key",
+                value: 0,
+            });
+        }
+        let mut fixed_info = root.value;
+        let mut fields = [0; 13];
+        for field in &mut fields {
+            *field = take_u32(&mut fixed_info)?;
+        }
+        let [signature, _, file_ms, file_ls, product_ms, product_ls, _, _, _, file_type, ..] =
+            fields;
+        if signature != FIXED_FILE_INFO_SIGNATURE {
+            return Err(DecodeErrorKind::InvalidValue {
+                field: "VS_FIXEDFILEINFO signature",
+                value: signature as u64,
+            });
+        }
+        let version = |ms: u32, ls: u32| {
+            Version::from(((ms >> 16) as u16, ms as u16, (ls >> 16) as u16, ls as u16))
+        };
+        let mut strings = Vec::new();
+        let mut children = root.children;
+        while !children.is_empty() {
+            let child = VersionBlock::take(&mut children)?;
+            if &*child.key != "StringFileInfo" {
+                continue;
+            }
+            // Only the first string table is kept.
+            let mut tables = child.children;
+            if tables.is_empty() {
+                continue;
+            }
+            let mut entries = VersionBlock::take(&mut tables)?.children;
+            while !entries.is_empty() {
+                let entry = VersionBlock::take(&mut entries)?;
+                let value = utf16(entry.value)?;
+                strings.push((entry.key, value.trim_end_matches('\0').into()));
+            }
+        }
+        Ok(Self {
+            file_version: version(file_ms, file_ls),
+            product_version: version(product_ms, product_ls),
+            is_dll: file_type == 2,
+            strings,
+        })
+    }
+}
+enum Value<'a> {
+    Binary(&'a [u8]),
+    Text(&'a str),
+}
+/// Appends a block of a version resource, whose children are written by `children`. Every block, and the
+/// value and children inside it, start at a 4 byte boundary.
+fn version_block(out: &mut Vec<u8>, key: &str, value: Value, children: impl FnOnce(&mut Vec<u8>)) {
+    out.resize(align_up(out.len() as u32, 4) as usize, 0);
+    let start = out.len();
+    let (value_type, value_length, value): (u16, u16, Vec<u8>) = match value {
+        Value::Binary(bytes) => (0, bytes.len() as u16, bytes.to_vec()),
+        Value::Text(text) => {
+            let units: Vec<u16> = text.encode_utf16().chain([0]).collect();
+            let bytes = units.iter().flat_map(|unit| unit.to_le_bytes()).collect();
+            (1, units.len() as u16, bytes)
+        }
+    };
+    // wLength is filled in once the children are written.
+    out.extend([0; 2]);
+    out.extend(value_length.to_le_bytes());
+    out.extend(value_type.to_le_bytes());
+    out.extend(key.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+    if !value.is_empty() {
+        out.resize(align_up(out.len() as u32, 4) as usize, 0);
+        out.extend(value);
+    }
+    children(out);
+    let length = (out.len() - start) as u16;
+    out[start..start + 2].copy_from_slice(&length.to_le_bytes());
+}
+/// A block of a version resource.
+struct VersionBlock<'a> {
+    key: Box<str>,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+impl<'a> VersionBlock<'a> {
+    /// Reads the block at the start of `data`, and advances it to the next 4 byte aligned block.
+    fn take(data: &mut &'a [u8]) -> Result<Self, DecodeErrorKind> {
+        let mut header = *data;
+        let length = take_u16(&mut header)? as usize;
+        let value_length = take_u16(&mut header)? as usize;
+        let value_type = take_u16(&mut header)?;
+        // The length covers at least the header, which also keeps a zero length from never advancing `data`.
+        if length < 6 {
+            return Err(DecodeErrorKind::UnexpectedEnd);
+        }
+        let block = data.get(..length).ok_or(DecodeErrorKind::UnexpectedEnd)?;
+        *data = data
+            .get(align_up(length as u32, 4) as usize..)
+            .unwrap_or(&[]);
+        let key_length = block[6..]
+            .chunks_exact(2)
+            .position(|unit| unit == [0, 0])
+            .ok_or(DecodeErrorKind::UnexpectedEnd)?;
+        let key = utf16(&block[6..6 + key_length * 2])?;
+        let value_start = align_up(6 + key_length as u32 * 2 + 2, 4) as usize;
+        let value_size = if value_type == 1 {
+            value_length * 2
+        } else {
+            value_length
+        };
+        let value = block
+            .get(value_start..value_start + value_size)
+            .ok_or(DecodeErrorKind::UnexpectedEnd)?;
+        let children_start = align_up((value_start + value_size) as u32, 4) as usize;
+        Ok(Self {
+            key,
+            value,
+            children: block.get(children_start..).unwrap_or(&[]),
+        })
+    }
+}
+#[test]
+fn version_info_round_trip() {
+    let mut info = VersionInfo::new((1, 2, 3, 4).into(), true);
+    info.strings.push(("ProductName".into(), "Calc".into()));
+    let encoded = info.encode();
+    assert_eq!(&encoded[6..12], [b'V', 0, b'S', 0, b'_', 0]);
+    assert_eq!(
+        u16::from_le_bytes([encoded[0], encoded[1]]) as usize,
+        encoded.len()
+    );
+    assert_eq!(VersionInfo::decode(&encoded).unwrap(), info);
+}
+#[test]
+fn truncated_version_block() {
+    for length in [0_u16, 2, 5] {
+        let mut block = length.to_le_bytes().to_vec();
+        block.extend([0; 6]);
+        assert_eq!(
+            VersionInfo::decode(&block),
+            Err(DecodeErrorKind::UnexpectedEnd),
+            "wLength {length}"
+        );
+    }
+}
+#[test]
+fn resource_tree_round_trip() {
+    let resource = |kind, name, language, data: &[u8]| Win32Resource {
+        kind,
+        name,
+        language,
+        code_page: 0,
+        data: data.to_vec(),
+    };
+    let resources = [
+        resource(ResourceId::MANIFEST, ResourceId::Id(1), 0, b"<assembly/>"),
+        resource(ResourceId::VERSION, ResourceId::Id(1), 0x409, &[1, 2, 3]),
+        resource(ResourceId::VERSION, ResourceId::Id(1), 0, &[4]),
+        resource(
+            ResourceId::Name("CUSTOM".into()),
+            ResourceId::Name("Data".into()),
+            0,
+            &[5, 6],
+        ),
+    ];
+    let rva = 0x4000;
+    let encoded = encode_resources(&resources, rva);
+    let decoded = decode_resources(&encoded, |at, size| {
+        let start = (at.0 - rva as u64) as usize;
+        encoded.get(start..start + size as usize).map(Cow::Borrowed)
+    })
+    .unwrap();
+    // Named types come first, then the numbered ones in ascending order, and languages in ascending order too.
+    let expected = [&resources[3], &resources[2], &resources[1], &resources[0]];
+    assert_eq!(decoded.iter().collect::<Vec<_>>(), expected);
+    // A data entry where a directory is expected.
+    let mut broken = encoded.clone();
+    broken[20..24].copy_from_slice(&0x30_u32.to_le_bytes());
+    assert!(decode_resources(&broken, |_, _| None).is_err());
+}
@@ -0,0 +1,1163 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    assembly::{
+        encode_user_string, get_blob, AssemblyRef, AssemblyRefIndex, BlobIndex, CILHeader,
+        CLIFlags, EncodeError, EntryPoint, GUIDIndex, HeapSizes, MetadataStream, RawMetadata,
+        StringIndex, Table, UserStringIndex, CIL_HEADER_SIZE,
+    },
+    cil::{CILOp, CallSite, CallStackEffect, MethodToken},
+    coded_index::{CodedIndex, Implementation, MethodDefOrRef, TypeOrMethodDef},
+    compressed::encode_unsigned,
+    field::{Field, FieldIndex},
+    method::{
+        ExceptionClause, MemberRef, MemberRefIndex, MemberRefParent, MethodBody, MethodDef,
+        MethodIndex, StandAloneSigIndex,
+    },
+    param::{Param, ParamIndex},
+    pe_file::{
+        align_up, base_relocations, DataDirectory, ImportStub, Machine, PEFile, PEFormat,
+        SectionType, BASE_RELOCATION_DIRECTORY, CLI_HEADER_DIRECTORY, IAT_DIRECTORY, IAT_SIZE,
+        IMPORT_TABLE_DIRECTORY, RESOURCE_DIRECTORY, RVA, SECTION_ALIGNMENT,
+    },
+    r#type::Type,
+    resolution_scope::ResolutionScope,
+    signature::{
+        FieldSignature, MemberRefSignature, MethodSpecSignature, Signature, StandAloneSignature,
+    },
+    table::{
+        FileIndex, GenericParam, GenericParamConstraint, GenericParamFlags, GenericParamIndex,
+        ManifestResource, ManifestResourceIndex, MethodSpec, MethodSpecIndex, TypeRef, TypeSpec,
+    },
+    type_def::{TypeDef, TypeDefIndex, TypeDefOrRef, TypeRefIndex, TypeSpecIndex},
+    view::Version,
+    win32_resources::{encode_resources, Win32Resource},
+};
+/// Version of the runtime the written metadata targets.
+const RUNTIME_VERSION: &str = "v4.0.30319";
+/// SHA1, the only hash algorithm in use.
+const HASH_ALGORITHM_SHA1: u32 = 0x8004;
+/// Builds an assembly in memory, and writes it out as a PE file.
+///
+/// Rows are added in metadata order: fields and methods belong to the most recently added type,
+/// and params belong to the most recently added method.
+pub struct AssemblyBuilder {
+    name: StringIndex,
+    module_name: StringIndex,
+    version: Version,
+    is_dll: bool,
+    machine: Machine,
+    mvid: u128,
+    strings: Vec<u8>,
+    string_indices: HashMap<Box<str>, StringIndex>,
+    blobs: Vec<u8>,
+    blob_indices: HashMap<Box<[u8]>, BlobIndex>,
+    user_strings: Vec<u8>,
+    user_string_indices: HashMap<Box<str>, UserStringIndex>,
+    type_refs: Vec<TypeRef>,
+    type_defs: Vec<TypeDef>,
+    fields: Vec<Field>,
+    methods: Vec<MethodDef>,
+    bodies: Vec<Option<MethodBody>>,
+    params: Vec<Param>,
+    member_refs: Vec<MemberRef>,
+    stand_alone_sigs: Vec<BlobIndex>,
+    type_specs: Vec<TypeSpec>,
+    type_spec_indices: HashMap<BlobIndex, TypeSpecIndex>,
+    generic_params: Vec<GenericParam>,
+    method_specs: Vec<MethodSpec>,
+    generic_param_constraints: Vec<GenericParamConstraint>,
+    assembly_refs: Vec<AssemblyRef>,
+    entrypoint: Option<MethodIndex>,
+    win32_resources: Vec<Win32Resource>,
+    /// Name, flags and data of each embedded managed resource.
+    manifest_resources: Vec<(StringIndex, u32, Box<[u8]>)>,
+}
+impl AssemblyBuilder {
+    /// Creates an assembly containing only the `<Module>` type. The module is named after the assembly.
+    pub fn new(name: &str, version: Version, is_dll: bool) -> Self {
+        let mut builder = Self {
+            name: StringIndex(0),
+            module_name: StringIndex(0),
+            version,
+            is_dll,
+            machine: Machine::I386,
+            mvid: 0,
+            strings: vec![0],
+            string_indices: HashMap::new(),
+            blobs: vec![0],
+            blob_indices: HashMap::new(),
+            user_strings: vec![0],
+            user_string_indices: HashMap::new(),
+            type_refs: Vec::new(),
+            type_defs: Vec::new(),
+            fields: Vec::new(),
+            methods: Vec::new(),
+            bodies: Vec::new(),
+            params: Vec::new(),
+            member_refs: Vec::new(),
+            stand_alone_sigs: Vec::new(),
+            type_specs: Vec::new(),
+            type_spec_indices: HashMap::new(),
+            generic_params: Vec::new(),
+            method_specs: Vec::new(),
+            generic_param_constraints: Vec::new(),
+            assembly_refs: Vec::new(),
+            entrypoint: None,
+            win32_resources: Vec::new(),
+            manifest_resources: Vec::new(),
+        };
+        builder.name = builder.string(name);
+        let extension = if is_dll { "dll" } else { "exe" };
+        builder.module_name = builder.string(&format!("{name}.{extension}"));
+        builder.add_type_def(0, "", "<Module>", None);
+        builder
+    }
+    /// Sets the GUID identifying this module. Defaults to 0.
+    pub fn set_mvid(&mut self, mvid: u128) {
+        self.mvid = mvid;
+    }
+    /// Sets the processor the image targets. Defaults to [`Machine::I386`], whose images run on any
+    /// platform, since they are IL only. Other machines get a PE32+ image.
+    pub fn set_machine(&mut self, machine: Machine) {
+        self.machine = machine;
+    }
+    /// Sets the method called when the assembly is executed.
+    pub fn set_entrypoint(&mut self, method: MethodIndex) {
+        self.entrypoint = Some(method);
+    }
+    /// Adds a Win32 resource, such as [`VersionInfo::to_resource`], placed in a `.rsrc` section.
+    ///
+    /// [`VersionInfo::to_resource`]: crate::VersionInfo::to_resource
+    pub fn add_win32_resource(&mut self, resource: Win32Resource) {
+        self.win32_resources.push(resource);
+    }
+    /// Embeds a managed resource, such as a compiled `.resources` file. A resource with the same name as an
+    /// already added one replaces it, keeping its index.
+    pub fn add_manifest_resource(
+        &mut self,
+        flags: u32,
+        name: &str,
+        data: &[u8],
+    ) -> ManifestResourceIndex {
+        let name = self.string(name);
+        let resource = (name, flags, data.into());
+        let existing = self
+            .manifest_resources
+            .iter()
+            .position(|(existing, _, _)| *existing == name);
+        let index = match existing {
+            Some(index) => {
+                self.manifest_resources[index] = resource;
+                index
+            }
+            None => {
+                self.manifest_resources.push(resource);
+                self.manifest_resources.len() - 1
+            }
+        };
+        ManifestResourceIndex(index as u32 + 1)
+    }
+    fn string(&mut self, string: &str) -> StringIndex {
+        if string.is_empty() {
+            return StringIndex(0);
+        }
+        if let Some(index) = self.string_indices.get(string) {
+            return *index;
+        }
+        let index = StringIndex(self.strings.len() as u32);
+        self.strings.extend(string.as_bytes());
+        self.strings.push(0);
+        self.string_indices.insert(string.into(), index);
+        index
+    }
+    fn blob(&mut self, blob: &[u8]) -> BlobIndex {
+        if blob.is_empty() {
+            return BlobIndex(0);
+        }
+        if let Some(index) = self.blob_indices.get(blob) {
+            return *index;
+        }
+        let index = BlobIndex(self.blobs.len() as u32);
+        encode_unsigned(blob.len() as u32, &mut self.blobs);
+        self.blobs.extend(blob);
+        self.blob_indices.insert(blob.into(), index);
+        index
+    }
+    /// Adds a string literal, to be loaded with [`CILOp::LDStr`]. Adding the same string again returns the same index.
+    pub fn add_user_string(&mut self, value: &str) -> UserStringIndex {
+        if let Some(index) = self.user_string_indices.get(value) {
+            return *index;
+        }
+        let index = UserStringIndex(self.user_strings.len() as u32);
+        encode_user_string(value, &mut self.user_strings);
+        self.user_string_indices.insert(value.into(), index);
+        index
+    }
+    pub fn add_assembly_ref(
+        &mut self,
+        name: &str,
+        version: Version,
+        public_key_token: &[u8],
+    ) -> AssemblyRefIndex {
+        let public_key_token = self.blob(public_key_token);
+        let name = self.string(name);
+        self.assembly_refs.push(AssemblyRef::new(
+            version.into(),
+            0,
+            public_key_token,
+            name,
+            StringIndex(0),
+            BlobIndex(0),
+        ));
+        AssemblyRefIndex(self.assembly_refs.len() as u32)
+    }
+    pub fn add_type_ref(
+        &mut self,
+        scope: ResolutionScope,
+        namespace: &str,
+        name: &str,
+    ) -> TypeRefIndex {
+        let name = self.string(name);
+        let namespace = self.string(namespace);
+        self.type_refs.push(TypeRef::new(scope, name, namespace));
+        TypeRefIndex(self.type_refs.len() as u32)
+    }
+    /// Adds a type, which owns all fields and methods added after it. `extends` is `None` for interfaces and
+    /// `System.Object`.
+    pub fn add_type_def(
+        &mut self,
+        flags: u32,
+        namespace: &str,
+        name: &str,
+        extends: Option<TypeDefOrRef>,
+    ) -> TypeDefIndex {
+        let name = self.string(name);
+        let namespace = self.string(namespace);
+        self.type_defs.push(TypeDef::new(
+            flags,
+            name,
+            namespace,
+            extends.unwrap_or(TypeDefOrRef::TypeDef(TypeDefIndex(0))),
+            FieldIndex(self.fields.len() as u32 + 1),
+            MethodIndex(self.methods.len() as u32 + 1),
+        ));
+        TypeDefIndex(self.type_defs.len() as u32)
+    }
+    /// Adds a field to the last added type.
+    pub fn add_field(&mut self, flags: u16, name: &str, signature: &FieldSignature) -> FieldIndex {
+        let name = self.string(name);
+        let mut blob = Vec::new();
+        signature.encode(&mut blob);
+        let signature = self.blob(&blob);
+        self.fields.push(Field::new(flags, name, signature));
+        FieldIndex(self.fields.len() as u32)
+    }
+    /// Adds a method to the last added type. Abstract and extern methods have no body.
+    pub fn add_method(
+        &mut self,
+        flags: u16,
+        impl_flags: u16,
+        name: &str,
+        signature: &Signature,
+        body: Option<MethodBody>,
+    ) -> MethodIndex {
+        let name = self.string(name);
+        let mut blob = Vec::new();
+        signature.encode(&mut blob);
+        let signature = self.blob(&blob);
+        self.methods.push(MethodDef::new(
+            0,
+            impl_flags,
+            flags,
+            name,
+            signature,
+            ParamIndex(self.params.len() as u32 + 1),
+        ));
+        self.bodies.push(body);
+        MethodIndex(self.methods.len() as u32)
+    }
+    /// Adds a parameter to the last added method. Sequence 0 refers to the return value.
+    pub fn add_param(&mut self, flags: u16, sequence: u16, name: &str) -> ParamIndex {
+        let name = self.string(name);
+        self.params.push(Param::new(flags, sequence, name));
+        ParamIndex(self.params.len() as u32)
+    }
+    pub fn add_member_ref(
+        &mut self,
+        parent: MemberRefParent,
+        name: &str,
+        signature: &MemberRefSignature,
+    ) -> MemberRefIndex {
+        let name = self.string(name);
+        let mut blob = Vec::new();
+        signature.encode(&mut blob);
+        let signature = self.blob(&blob);
+        self.member_refs
+            .push(MemberRef::new(parent, name, signature));
+        MemberRefIndex(self.member_refs.len() as u32)
+    }
+    /// Adds a standalone signature, such as the signature of the locals of a method.
+    pub fn add_stand_alone_sig(&mut self, signature: &StandAloneSignature) -> StandAloneSigIndex {
+        let mut blob = Vec::new();
+        signature.encode(&mut blob);
+        let signature = self.blob(&blob);
+        self.stand_alone_sigs.push(signature);
+        StandAloneSigIndex(self.stand_alone_sigs.len() as u32)
+    }
+    /// Adds a type which has no TypeDef or TypeRef of its own, such as an instantiation of a generic type. Adding the
+    /// same type again returns the same index.
+    pub fn add_type_spec(&mut self, tpe: &Type) -> TypeSpecIndex {
+        let mut signature = Vec::new();
+        tpe.encode(&mut signature);
+        let signature = self.blob(&signature);
+        if let Some(index) = self.type_spec_indices.get(&signature) {
+            return *index;
+        }
+        self.type_specs.push(TypeSpec::new(signature));
+        let index = TypeSpecIndex(self.type_specs.len() as u32);
+        self.type_spec_indices.insert(signature, index);
+        index
+    }
+    /// Adds the `number`-th generic parameter of `owner`. Parameters may be added in any order, as they get sorted by
+    /// their owner when written, so the returned index is only meaningful to this builder.
+    pub fn add_generic_param(
+        &mut self,
+        owner: TypeOrMethodDef,
+        number: u16,
+        flags: GenericParamFlags,
+        name: &str,
+    ) -> GenericParamIndex {
+        let name = self.string(name);
+        self.generic_params
+            .push(GenericParam::new(number, flags.bits(), owner, name));
+        GenericParamIndex(self.generic_params.len() as u32)
+    }
+    /// Requires the arguments of `param` to derive from or implement `constraint`.
+    pub fn add_generic_param_constraint(
+        &mut self,
+        param: GenericParamIndex,
+        constraint: TypeDefOrRef,
+    ) {
+        self.generic_param_constraints
+            .push(GenericParamConstraint::new(param, constraint));
+    }
+    /// Adds an instantiation of the generic `method`, which can then be called with [`MethodToken::MethodSpec`].
+    pub fn add_method_spec(
+        &mut self,
+        method: MethodDefOrRef,
+        instantiation: &MethodSpecSignature,
+    ) -> MethodSpecIndex {
+        let mut signature = Vec::new();
+        instantiation.encode(&mut signature);
+        let signature = self.blob(&signature);
+        self.method_specs.push(MethodSpec::new(method, signature));
+        MethodSpecIndex(self.method_specs.len() as u32)
+    }
+    /// Assembles a method body out of `ops`, looking up the signatures of called methods in this
+    /// builder. Fails if `ops` refer to a method or signature which was not added to it, or on the
+    /// label errors described by [`MethodBody::from_ops`].
+    pub fn assemble(
+        &self,
+        ops: &[CILOp],
+        exception_clauses: &[ExceptionClause],
+    ) -> Result<MethodBody, EncodeError> {
+        let method_signature = |method| match method {
+            MethodDefOrRef::MethodDef(index) => row(&self.methods, index.0)
+                .map(MethodDef::signature)
+                .ok_or(EncodeError::UnknownMethod(MethodToken::MethodDef(index))),
+            MethodDefOrRef::MemberRef(index) => row(&self.member_refs, index.0)
+                .map(MemberRef::signature)
+                .ok_or(EncodeError::UnknownMethod(MethodToken::MemberRef(index))),
+        };
+        MethodBody::from_ops(ops, exception_clauses, |site| {
+            let signature = match site {
+                CallSite::Method(MethodToken::MethodDef(index)) => {
+                    method_signature(MethodDefOrRef::MethodDef(index))?
+                }
+                CallSite::Method(MethodToken::MemberRef(index)) => {
+                    method_signature(MethodDefOrRef::MemberRef(index))?
+                }
+                // The instantiation doesn't change the number of arguments, so the generic signature is enough.
+                CallSite::Method(MethodToken::MethodSpec(index)) => {
+                    let spec = row(&self.method_specs, index.0)
+                        .ok_or(EncodeError::UnknownMethod(MethodToken::MethodSpec(index)))?;
+                    method_signature(spec.method())?
+                }
+                CallSite::Signature(index) => *row(&self.stand_alone_sigs, index.0)
+                    .ok_or(EncodeError::UnknownSignature(index))?,
+            };
+            let signature = get_blob(&self.blobs, signature)
+                .and_then(|mut signature| Signature::decode(&mut signature))
+                .map_err(|kind| EncodeError::InvalidSignature(site, kind))?;
+            Ok(CallStackEffect::from_signature(&signature))
+        })
+    }
+    /// Collects all non-empty tables, with the RVAs of method bodies and the offsets of managed resources filled in.
+    fn tables(
+        &self,
+        method_rvas: &[u32],
+        resource_offsets: &[u32],
+    ) -> Result<Vec<Table>, EncodeError> {
+        let (major, minor, build_number, revision_number) = self.version.into();
+        let methods: Vec<_> = self
+            .methods
+            .iter()
+            .zip(method_rvas)
+            .map(|(method, rva)| {
+                MethodDef::new(
+                    *rva,
+                    method.impl_flags(),
+                    method.flags(),
+                    method.name(),
+                    method.signature(),
+                    method.param_start(),
+                )
+            })
+            .collect();
+        let manifest_resources: Vec<_> = self
+            .manifest_resources
+            .iter()
+            .zip(resource_offsets)
+            .map(|((name, flags, _), offset)| {
+                ManifestResource::new(*offset, *flags, *name, Implementation::File(FileIndex(0)))
+            })
+            .collect();
+        // GenericParam is sorted by owner and number, so parameters are renumbered, and their constraints with them.
+        let mut param_order: Vec<usize> = (0..self.generic_params.len()).collect();
+        param_order.sort_by_key(|&index| {
+            let param = &self.generic_params[index];
+            (
+                TypeOrMethodDef::KIND.join(param.owner().row()),
+                param.number(),
+            )
+        });
+        let mut param_indices = vec![0; param_order.len()];
+        for (new_index, &index) in param_order.iter().enumerate() {
+            param_indices[index] = new_index as u32 + 1;
+        }
+        let generic_params: Vec<_> = param_order
+            .iter()
+            .map(|&index| self.generic_params[index].clone())
+            .collect();
+        let mut constraints: Vec<_> = self
+            .generic_param_constraints
+            .iter()
+            .map(|constraint| {
+                let owner = row(&param_indices, constraint.owner().0)
+                    .ok_or(EncodeError::UnknownGenericParam(constraint.owner()))?;
+                Ok(GenericParamConstraint::new(
+                    GenericParamIndex(*owner),
+                    constraint.constraint(),
+                ))
+            })
+            .collect::<Result<_, EncodeError>>()?;
+        constraints.sort_by_key(|constraint| constraint.owner().0);
+        let tables = [
+            Some(Table::Module {
+                generation: 0,
+                name: self.module_name,
+                mvid: GUIDIndex(1),
+                enc_id: GUIDIndex(0),
+                enc_base_id: GUIDIndex(0),
+            }),
+            (!self.type_refs.is_empty())
+                .then(|| Table::TypeRefTable(self.type_refs.clone().into())),
+            Some(Table::TypeDefTable(self.type_defs.clone().into())),
+            (!self.fields.is_empty()).then(|| Table::Fields(self.fields.clone().into())),
+            (!methods.is_empty()).then(|| Table::MethodDefTable(methods.into())),
+            (!self.params.is_empty()).then(|| Table::Param(self.params.clone())),
+            (!self.member_refs.is_empty())
+                .then(|| Table::MemberRef(self.member_refs.clone().into())),
+            (!self.stand_alone_sigs.is_empty())
+                .then(|| Table::StandAloneSig(self.stand_alone_sigs.clone().into())),
+            (!self.type_specs.is_empty()).then(|| Table::TypeSpec(self.type_specs.clone().into())),
+            Some(Table::Assembly {
+                hash_alg_id: HASH_ALGORITHM_SHA1,
+                major,
+                minor,
+                build_number,
+                revision_number,
+                flags: 0,
+                public_key: BlobIndex(0),
+                name: self.name,
+                culture: StringIndex(0),
+            }),
+            (!self.assembly_refs.is_empty())
+                .then(|| Table::AssemblyRefs(self.assembly_refs.clone().into())),
+            (!manifest_resources.is_empty())
+                .then(|| Table::ManifestResource(manifest_resources.into())),
+            (!generic_params.is_empty()).then(|| Table::GenericParam(generic_params.into())),
+            (!self.method_specs.is_empty())
+                .then(|| Table::MethodSpec(self.method_specs.clone().into())),
+            (!constraints.is_empty()).then(|| Table::GenericParamConstraint(constraints.into())),
+        ];
+        Ok(tables.into_iter().flatten().collect())
+    }
+    /// Writes the assembly as a PE file with a `.text` section, holding the CLI header, method bodies, managed
+    /// resources and metadata, followed by a `.rsrc` section if there are Win32 resources. PE32 images also get a
+    /// native entry point stub, and a `.reloc` section for it. PE32+ images are loaded by the runtime directly, so
+    /// they have neither. Fails if the entry point or a generic parameter constraint refers to something which was
+    /// not added to this builder, or if writing fails.
+    pub fn write_to(&self, out: &mut impl Write) -> Result<(), EncodeError> {
+        if let Some(method) = self.entrypoint {
+            row(&self.methods, method.0)
+                .ok_or(EncodeError::UnknownMethod(MethodToken::MethodDef(method)))?;
+        }
+        let needs_stub = self.machine.format() == PEFormat::PE32;
+        let text_rva = SECTION_ALIGNMENT;
+        let rva_of = |text: &Vec<u8>| text_rva + text.len() as u32;
+        let mut text = vec![0; if needs_stub { IAT_SIZE as usize } else { 0 }];
+        let cli_header = DataDirectory {
+            rva: rva_of(&text),
+            size: CIL_HEADER_SIZE as u32,
+        };
+        text.resize(text.len() + CIL_HEADER_SIZE, 0);
+        let mut method_rvas = Vec::with_capacity(self.bodies.len());
+        for body in &self.bodies {
+            let Some(body) = body else {
+                method_rvas.push(0);
+                continue;
+            };
+            text.resize(align_up(text.len() as u32, 4) as usize, 0);
+            method_rvas.push(rva_of(&text));
+            body.encode(&mut text);
+        }
+        let mut resources = DataDirectory::default();
+        let mut resource_offsets = Vec::with_capacity(self.manifest_resources.len());
+        if !self.manifest_resources.is_empty() {
+            text.resize(align_up(text.len() as u32, 8) as usize, 0);
+            let start = text.len();
+            for (_, _, data) in &self.manifest_resources {
+                resource_offsets.push((text.len() - start) as u32);
+                text.extend((data.len() as u32).to_le_bytes());
+                text.extend(data.iter());
+                text.resize(align_up(text.len() as u32, 8) as usize, 0);
+            }
+            resources = DataDirectory {
+                rva: text_rva + start as u32,
+                size: (text.len() - start) as u32,
+            };
+        }
+        text.resize(align_up(text.len() as u32, 4) as usize, 0);
+        let heap_sizes = HeapSizes::new(
+            self.strings.len(),
+            std::mem::size_of::<u128>(),
+            self.blobs.len(),
+        );
+        let streams = vec![
+            MetadataStream::tables(self.tables(&method_rvas, &resource_offsets)?, heap_sizes),
+            MetadataStream::strings(self.strings.clone()),
+            MetadataStream::user_strings(self.user_strings.clone()),
+            MetadataStream::guids(vec![self.mvid]),
+            MetadataStream::blobs(self.blobs.clone()),
+        ];
+        let entrypoint = self.entrypoint.map_or(EntryPoint::None, EntryPoint::Method);
+        let header = CILHeader::new(
+            CLIFlags::IL_ONLY,
+            entrypoint,
+            resources,
+            RawMetadata::new(RUNTIME_VERSION, streams),
+        );
+        let metadata = header.raw_metadata().encode();
+        let metadata_directory = DataDirectory {
+            rva: rva_of(&text),
+            size: metadata.len() as u32,
+        };
+        text.extend(metadata);
+        let header_start = (cli_header.rva - text_rva) as usize;
+        text[header_start..header_start + CIL_HEADER_SIZE]
+            .copy_from_slice(&header.encode(metadata_directory));
+        let stub = needs_stub.then(|| ImportStub::write(&mut text, text_rva, self.is_dll));
+        let mut data_directories = [DataDirectory::default(); 16];
+        data_directories[CLI_HEADER_DIRECTORY] = cli_header;
+        let mut next_rva = align_up(rva_of(&text), SECTION_ALIGNMENT);
+        let mut sections = vec![(SectionType::Text, text)];
+        if !self.win32_resources.is_empty() {
+            let resources = encode_resources(&self.win32_resources, next_rva);
+            data_directories[RESOURCE_DIRECTORY] = DataDirectory {
+                rva: next_rva,
+                size: resources.len() as u32,
+            };
+            next_rva = align_up(next_rva + resources.len() as u32, SECTION_ALIGNMENT);
+            sections.push((SectionType::Rsrc, resources));
+        }
+        let entrypoint = match stub {
+            Some(stub) => {
+                let relocations = base_relocations(stub.fixup);
+                data_directories[IMPORT_TABLE_DIRECTORY] = stub.import_table;
+                data_directories[BASE_RELOCATION_DIRECTORY] = DataDirectory {
+                    rva: next_rva,
+                    size: relocations.len() as u32,
+                };
+                data_directories[IAT_DIRECTORY] = DataDirectory {
+                    rva: text_rva,
+                    size: IAT_SIZE,
+                };
+                sections.push((SectionType::Reloc, relocations));
+                stub.entrypoint
+            }
+            None => RVA(0),
+        };
+        let image = PEFile::new(
+            self.machine,
+            sections,
+            entrypoint,
+            data_directories,
+            self.is_dll,
+        );
+        Ok(image.write_to(out)?)
+    }
+}
+/// The row of `table` at the 1-based `index`, if there is one.
+fn row<T>(table: &[T], index: u32) -> Option<&T> {
+    table.get((index as usize).checked_sub(1)?)
+}
+#[cfg(test)]
+use crate::signature::{CallingConvention, LocalsSignature};
+#[cfg(test)]
+fn build_add_i32(is_dll: bool) -> AssemblyBuilder {
+    let mut builder = AssemblyBuilder::new("add_i32", (1, 2, 3, 4).into(), is_dll);
+    let runtime = builder.add_assembly_ref(
+        "System.Runtime",
+        (8, 0, 0, 0).into(),
+        &[0xb0, 0x3f, 0x5f, 0x7f, 0x11, 0xd5, 0x0a, 0x3a],
+    );
+    let object = builder.add_type_ref(ResolutionScope::AssemblyRef(runtime), "System", "Object");
+    builder.add_type_def(0x100181, "", "Calc", Some(TypeDefOrRef::TypeRef(object)));
+    builder.add_field(0x16, "counter", &FieldSignature::new(Type::I32));
+    // ldarg.0 ldarg.1 add ret
+    let body = MethodBody::new([0x02, 0x03, 0x58, 0x2a], 2);
+    builder.add_method(
+        0x96,
+        0,
+        "add_i32",
+        &Signature::new(
+            CallingConvention::Default,
+            false,
+            Type::I32,
+            [Type::I32, Type::I32],
+        ),
+        Some(body),
+    );
+    builder.add_param(0, 1, "a");
+    builder.add_param(0, 2, "b");
+    builder
+}
+#[test]
+fn write_add_i32() {
+    use crate::{Assembly, CILOp};
+    let mut out = Vec::new();
+    build_add_i32(true).write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    assert_eq!(asm.name().unwrap(), Some("add_i32"));
+    assert_eq!(asm.version(), Some((1, 2, 3, 4).into()));
+    assert_eq!(asm.module_name().unwrap(), Some("add_i32.dll"));
+    assert_eq!(asm.metadata_version(), RUNTIME_VERSION);
+    assert_eq!(asm.user_string_heap().unwrap(), [0; 4]);
+    let types: Vec<_> = asm.types().collect();
+    assert_eq!(types.len(), 2);
+    assert_eq!(types[0].name().unwrap(), "<Module>");
+    assert_eq!(types[1].name().unwrap(), "Calc");
+    assert_eq!(types[0].methods().count(), 0);
+    let field = types[1].fields().next().unwrap();
+    assert_eq!(field.name().unwrap(), "counter");
+    assert_eq!(field.signature_blob().unwrap(), &[0x06, 0x08]);
+    assert!(matches!(
+        field.signature().unwrap().field_type(),
+        crate::Type::I32
+    ));
+    let method = types[1].methods().next().unwrap();
+    assert_eq!(method.name().unwrap(), "add_i32");
+    assert_eq!(method.signature().unwrap().args().len(), 2);
+    let params: Vec<_> = method.params().map(|param| param.name().unwrap()).collect();
+    assert_eq!(params, ["a", "b"]);
+    let body = method.body().unwrap().unwrap();
+    assert!(matches!(
+        body.ops(),
+        [CILOp::LDArg0, CILOp::LDArg1, CILOp::Add, CILOp::Ret]
+    ));
+    let runtime = asm.assembly_refs().next().unwrap();
+    assert_eq!(runtime.name().unwrap(), "System.Runtime");
+    assert_eq!(runtime.version(), (8, 0, 0, 0).into());
+    let object = asm.type_refs().next().unwrap();
+    assert_eq!(object.name().unwrap(), "Object");
+    assert_eq!(object.namespace().unwrap(), "System");
+    assert!(matches!(
+        object.scope(),
+        ResolutionScope::AssemblyRef(AssemblyRefIndex(1))
+    ));
+    assert!(matches!(
+        types[1].extends(),
+        TypeDefOrRef::TypeRef(TypeRefIndex(1))
+    ));
+}
+#[test]
+fn write_exe() {
+    let mut builder = build_add_i32(false);
+    builder.add_type_def(0x100101, "", "Program", None);
+    // A body too long for a tiny header: 64 nops followed by ret.
+    let mut code = vec![0; 64];
+    code.push(0x2a);
+    let main = builder.add_method(
+        0x96,
+        0,
+        "Main",
+        &Signature::new(CallingConvention::Default, false, Type::Void, []),
+        Some(MethodBody::new(code, 1)),
+    );
+    builder.set_entrypoint(main);
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    assert_eq!(&out[..2], b"MZ");
+    let asm = crate::Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    assert_eq!(asm.module_name().unwrap(), Some("add_i32.exe"));
+    let header = asm.cli_header();
+    assert_eq!(header.runtime_version(), (2, 5));
+    assert!(matches!(
+        header.entrypoint(),
+        EntryPoint::Method(MethodIndex(2))
+    ));
+    let main = asm.types().nth(2).unwrap().methods().next().unwrap();
+    assert_eq!(main.name().unwrap(), "Main");
+    assert_eq!(main.params().count(), 0);
+}
+#[test]
+fn round_trip() {
+    let mut written = Vec::new();
+    build_add_i32(false).write_to(&mut written).unwrap();
+    let asm = crate::Assembly::from_file(&mut std::io::Cursor::new(&written)).unwrap();
+    let mut rewritten = Vec::new();
+    asm.write_to(&mut rewritten).unwrap();
+    assert_eq!(written, rewritten);
+}
+#[test]
+fn round_trip_keeps_padding() {
+    let mut written = Vec::new();
+    build_add_i32(true).write_to(&mut written).unwrap();
+    // Garbage in the padding at the end of the last section, and data appended after it.
+    let last = written.len() - 1;
+    written[last] = 0xAA;
+    written.extend(b"trailing data");
+    let asm = crate::Assembly::from_file(&mut std::io::Cursor::new(&written)).unwrap();
+    let mut rewritten = Vec::new();
+    asm.write_to(&mut rewritten).unwrap();
+    assert_eq!(written, rewritten);
+}
+#[test]
+fn write_fat_body() {
+    use crate::{Assembly, CILOp};
+    let mut builder = build_add_i32(true);
+    // LOCAL_SIG with a single int32 local.
+    let locals = builder.add_stand_alone_sig(&StandAloneSignature::Locals(LocalsSignature::new([
+        Type::I32,
+    ])));
+    let body = MethodBody::new([0x02, 0x25, 0x5a, 0x2a], 2).with_locals(locals, true);
+    builder.add_method(
+        0x96,
+        0,
+        "square",
+        &Signature::new(CallingConvention::Default, false, Type::I32, [Type::I32]),
+        Some(body),
+    );
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    let square = asm.types().nth(1).unwrap().methods().nth(1).unwrap();
+    let body = square.body().unwrap().unwrap();
+    assert!(matches!(
+        body.ops(),
+        [CILOp::LDArg0, CILOp::Dup, CILOp::Mul, CILOp::Ret]
+    ));
+    assert_eq!(body.max_stack(), 2);
+    assert!(matches!(body.local_var_sig(), Some(StandAloneSigIndex(1))));
+    assert!(body.init_locals());
+    let Some(StandAloneSignature::Locals(locals)) =
+        asm.stand_alone_signature(StandAloneSigIndex(1)).unwrap()
+    else {
+        panic!("Expected the locals of square");
+    };
+    assert!(matches!(locals.locals(), [crate::Type::I32]));
+    assert!(body.exception_clauses().is_empty());
+}
+#[test]
+fn assemble_call() {
+    use crate::Assembly;
+    let mut builder = build_add_i32(true);
+    let add_i32 = MethodIndex(1);
+    let ops = [
+        CILOp::LDArg(0),
+        CILOp::Dup,
+        CILOp::Call(MethodToken::MethodDef(add_i32)),
+        CILOp::Ret,
+    ];
+    let body = builder.assemble(&ops, &[]).unwrap();
+    assert_eq!(
+        body.code(),
+        [0x02, 0x25, 0x28, 0x01, 0x00, 0x00, 0x06, 0x2A]
+    );
+    assert_eq!(body.max_stack(), 2);
+    builder.add_method(
+        0x96,
+        0,
+        "twice",
+        &Signature::new(CallingConvention::Default, false, Type::I32, [Type::I32]),
+        Some(body),
+    );
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    let twice = asm.types().nth(1).unwrap().methods().nth(1).unwrap();
+    let body = twice.body().unwrap().unwrap();
+    assert!(matches!(
+        body.ops(),
+        [
+            CILOp::LDArg0,
+            CILOp::Dup,
+            CILOp::Call(MethodToken::MethodDef(MethodIndex(1))),
+            CILOp::Ret
+        ]
+    ));
+}
+#[test]
+fn assemble_try_catch() {
+    use crate::{Assembly, ExceptionClauseKind, Label};
+    let mut builder = build_add_i32(true);
+    let object = TypeDefOrRef::TypeRef(TypeRefIndex(1));
+    let ops = [
+        CILOp::Label(Label(0)),
+        CILOp::LDArg0,
+        CILOp::Pop,
+        CILOp::Leave(Label(2)),
+        CILOp::Label(Label(1)),
+        CILOp::Pop,
+        CILOp::Leave(Label(2)),
+        CILOp::Label(Label(2)),
+        CILOp::Ret,
+    ];
+    let clause = ExceptionClause {
+        kind: ExceptionClauseKind::Catch(object),
+        try_start: Label(0),
+        try_end: Label(1),
+        handler_start: Label(1),
+        handler_end: Label(2),
+    };
+    let body = builder.assemble(&ops, &[clause]).unwrap();
+    assert_eq!(
+        body.code(),
+        [0x02, 0x26, 0xDE, 0x03, 0x26, 0xDE, 0x00, 0x2A]
+    );
+    assert_eq!(body.max_stack(), 1);
+    builder.add_method(
+        0x96,
+        0,
+        "swallow",
+        &Signature::new(CallingConvention::Default, false, Type::Void, [Type::I32]),
+        Some(body),
+    );
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    let swallow = asm.types().nth(1).unwrap().methods().nth(1).unwrap();
+    let body = swallow.body().unwrap().unwrap();
+    assert!(matches!(body.ops(), [CILOp::Label(Label(0)), ..]));
+    assert_eq!(body.ops().len(), ops.len());
+    let [clause] = body.exception_clauses() else {
+        panic!("Unexpected clauses {:?}", body.exception_clauses());
+    };
+    assert!(matches!(
+        clause.kind,
+        ExceptionClauseKind::Catch(TypeDefOrRef::TypeRef(TypeRefIndex(1)))
+    ));
+    assert_eq!(
+        [
+            clause.try_start,
+            clause.try_end,
+            clause.handler_start,
+            clause.handler_end
+        ],
+        [Label(0), Label(1), Label(1), Label(2)]
+    );
+}
+#[test]
+fn write_pe32_plus() {
+    use crate::Assembly;
+    let mut builder = build_add_i32(false);
+    builder.set_machine(Machine::AMD64);
+    builder.set_entrypoint(MethodIndex(1));
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(&out)).unwrap();
+    let header = asm.pe_header();
+    assert_eq!(header.format(), PEFormat::PE32Plus);
+    assert_eq!(header.machine(), 0x8664);
+    assert_eq!(header.data_base(), None);
+    assert_eq!(header.nt_header().image_base(), 0x1_4000_0000);
+    let add_i32 = asm.types().nth(1).unwrap().methods().next().unwrap();
+    assert_eq!(add_i32.name().unwrap(), "add_i32");
+    let mut rewritten = Vec::new();
+    asm.write_to(&mut rewritten).unwrap();
+    assert_eq!(out, rewritten);
+}
+#[test]
+fn write_version_info() {
+    use crate::{Assembly, ResourceId, VersionInfo};
+    let mut builder = build_add_i32(true);
+    let mut info = VersionInfo::new((1, 2, 3, 4).into(), true);
+    info.strings.push(("ProductName".into(), "Calc".into()));
+    builder.add_win32_resource(info.to_resource());
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(&out)).unwrap();
+    let names: Vec<_> = asm
+        .pe_sections()
+        .iter()
+        .map(|section| section.name())
+        .collect();
+    assert_eq!(names, [&b".text"[..], b".rsrc", b".reloc"]);
+    // Each section starts at its own raw data offset, so the relocation block size is right where it belongs.
+    assert_eq!(asm.pe_sections()[2].data()[4..8], 12_u32.to_le_bytes());
+    let [resource] = &asm.win32_resources().unwrap()[..] else {
+        panic!("Expected a single resource");
+    };
+    assert_eq!(resource.kind, ResourceId::VERSION);
+    assert_eq!(VersionInfo::decode(&resource.data).unwrap(), info);
+    // The assembly still loads, with the relocations after the resources.
+    let add_i32 = asm.types().nth(1).unwrap().methods().next().unwrap();
+    assert_eq!(add_i32.name().unwrap(), "add_i32");
+}
+#[test]
+fn write_manifest_resources() {
+    use crate::Assembly;
+    let mut builder = build_add_i32(true);
+    builder.add_manifest_resource(0x1, "Calc.Strings.resources", b"old");
+    builder.add_manifest_resource(0x2, "data.bin", &[1, 2, 3, 4, 5]);
+    let replaced = builder.add_manifest_resource(0x1, "Calc.Strings.resources", b"strings");
+    assert_eq!(replaced.0, 1);
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(&out)).unwrap();
+    let resources: Vec<_> = asm
+        .manifest_resources()
+        .map(|resource| {
+            let data = resource.data().unwrap().unwrap().into_owned();
+            (resource.name().unwrap(), resource.is_public(), data)
+        })
+        .collect();
+    assert_eq!(
+        resources,
+        [
+            ("Calc.Strings.resources", true, b"strings".to_vec()),
+            ("data.bin", false, vec![1, 2, 3, 4, 5]),
+        ]
+    );
+    let data = asm.manifest_resource_by_name("data.bin").unwrap().unwrap();
+    assert!(data.implementation().is_none());
+    assert_eq!(data.offset(), 16);
+    assert!(asm.manifest_resource_by_name("missing").unwrap().is_none());
+    let mut rewritten = Vec::new();
+    asm.write_to(&mut rewritten).unwrap();
+    assert_eq!(out, rewritten);
+}
+#[test]
+fn write_user_strings() {
+    use crate::Assembly;
+    let mut builder = build_add_i32(true);
+    let hello = builder.add_user_string("Hello");
+    let world = builder.add_user_string("w\u{00f6}rld");
+    assert_eq!(builder.add_user_string("Hello").0, hello.0);
+    let ops = [
+        CILOp::LDStr(hello),
+        CILOp::Pop,
+        CILOp::LDStr(world),
+        CILOp::Ret,
+    ];
+    let body = builder.assemble(&ops, &[]).unwrap();
+    builder.add_method(
+        0x96,
+        0,
+        "greet",
+        &Signature::new(CallingConvention::Default, false, Type::String, []),
+        Some(body),
+    );
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    let greet = asm.types().nth(1).unwrap().methods().nth(1).unwrap();
+    let body = greet.body().unwrap().unwrap();
+    let [CILOp::LDStr(first), CILOp::Pop, CILOp::LDStr(second), CILOp::Ret] = body.ops() else {
+        panic!("Unexpected ops {:?}", body.ops());
+    };
+    assert_eq!(asm.user_string(*first).unwrap(), "Hello");
+    assert_eq!(asm.user_string(*second).unwrap(), "w\u{00f6}rld");
+    let strings: Vec<_> = asm.user_strings().map(|string| string.unwrap().1).collect();
+    assert_eq!(strings, ["Hello", "w\u{00f6}rld"]);
+}
+#[test]
+fn dedup_blobs() {
+    let mut builder = build_add_i32(true);
+    builder.add_field(0x06, "other", &FieldSignature::new(Type::I32));
+    let [first, second] = &builder.fields[..] else {
+        panic!("Unexpected fields");
+    };
+    assert_eq!(first.signature(), second.signature());
+    let blob_len = builder.blobs.len();
+    builder.add_stand_alone_sig(&StandAloneSignature::Locals(LocalsSignature::new([
+        Type::I32,
+    ])));
+    builder.add_stand_alone_sig(&StandAloneSignature::Locals(LocalsSignature::new([
+        Type::I32,
+    ])));
+    assert_eq!(builder.stand_alone_sigs[0], builder.stand_alone_sigs[1]);
+    assert_eq!(builder.blobs.len(), blob_len + 4);
+}
+#[test]
+fn write_generics() {
+    use crate::{Assembly, Variance};
+    let mut builder = build_add_i32(true);
+    let object = TypeDefOrRef::TypeRef(TypeRefIndex(1));
+    let pair = builder.add_type_def(0x100001, "", "Pair`2", Some(object));
+    // static !!0 Id<M>(!!0)
+    let id = builder.add_method(
+        0x16,
+        0,
+        "Id",
+        &Signature::new(
+            CallingConvention::Default,
+            false,
+            Type::MethodGeneric(0),
+            [Type::MethodGeneric(0)],
+        )
+        .with_generic_params(1),
+        None,
+    );
+    // Added out of order, to check that they get sorted by owner and number.
+    builder.add_generic_param(
+        TypeOrMethodDef::MethodDef(id),
+        0,
+        GenericParamFlags::default(),
+        "M",
+    );
+    let u = builder.add_generic_param(
+        TypeOrMethodDef::TypeDef(pair),
+        1,
+        GenericParamFlags::REFERENCE_TYPE,
+        "U",
+    );
+    builder.add_generic_param(
+        TypeOrMethodDef::TypeDef(pair),
+        0,
+        GenericParamFlags::COVARIANT,
+        "T",
+    );
+    builder.add_generic_param_constraint(u, object);
+    let instance = Type::GenericInst {
+        value_type: false,
+        definition: TypeDefOrRef::TypeDef(pair),
+        args: [Type::I32, Type::String].into(),
+    };
+    let spec = builder.add_type_spec(&instance);
+    assert_eq!(builder.add_type_spec(&instance).0, spec.0);
+    let id_i32 = builder.add_method_spec(
+        MethodDefOrRef::MethodDef(id),
+        &MethodSpecSignature::new([Type::I32]),
+    );
+    let ops = [
+        CILOp::LDCI4(5),
+        CILOp::Call(MethodToken::MethodSpec(id_i32)),
+        CILOp::Ret,
+    ];
+    let body = builder.assemble(&ops, &[]).unwrap();
+    assert_eq!(body.max_stack(), 1);
+    builder.add_method(
+        0x16,
+        0,
+        "UseId",
+        &Signature::new(CallingConvention::Default, false, Type::I32, []),
+        Some(body),
+    );
+    let mut out = Vec::new();
+    builder.write_to(&mut out).unwrap();
+    let asm = Assembly::from_file(&mut std::io::Cursor::new(out)).unwrap();
+    let names: Vec<_> = asm
+        .generic_params()
+        .map(|param| param.name().unwrap())
+        .collect();
+    assert_eq!(names, ["M", "T", "U"]);
+    let pair = asm.type_def(pair).unwrap();
+    let [t, u] = &pair.generic_params().collect::<Vec<_>>()[..] else {
+        panic!("Pair`2 must have 2 generic params");
+    };
+    assert_eq!((t.name().unwrap(), t.number()), ("T", 0));
+    assert_eq!(t.flags().variance(), Variance::Covariant);
+    assert_eq!(t.constraints().count(), 0);
+    assert!(u.flags().contains(GenericParamFlags::REFERENCE_TYPE));
+    assert!(matches!(
+        u.constraints().collect::<Vec<_>>()[..],
+        [TypeDefOrRef::TypeRef(TypeRefIndex(1))]
+    ));
+    let id = asm.method(id).unwrap();
+    assert_eq!(id.generic_params().next().unwrap().name().unwrap(), "M");
+    assert_eq!(id.signature().unwrap().generic_param_count(), 1);
+    let Type::GenericInst {
+        value_type: false,
+        definition: TypeDefOrRef::TypeDef(TypeDefIndex(3)),
+        args,
+    } = asm.type_spec(spec).unwrap().unwrap()
+    else {
+        panic!("The TypeSpec must be a generic instantiation");
+    };
+    assert!(matches!(*args, [Type::I32, Type::String]));
+    let spec = asm.method_spec(id_i32).unwrap();
+    assert!(matches!(
+        spec.method(),
+        MethodDefOrRef::MethodDef(MethodIndex(2))
+    ));
+    assert!(matches!(spec.instantiation().unwrap().args(), [Type::I32]));
+    let use_id = pair.methods().nth(1).unwrap().body().unwrap().unwrap();
+    assert!(matches!(
+        use_id.ops()[1],
+        CILOp::Call(MethodToken::MethodSpec(MethodSpecIndex(1)))
+    ));
+}
+#[test]
+fn unknown_references() {
+    let mut builder = build_add_i32(true);
+    let assemble = |op| builder.assemble(&[op, CILOp::Ret], &[]);
+    assert!(matches!(
+        assemble(CILOp::Call(MethodToken::MethodDef(MethodIndex(5)))),
+        Err(EncodeError::UnknownMethod(MethodToken::MethodDef(
+            MethodIndex(5)
+        )))
+    ));
+    assert!(matches!(
+        assemble(CILOp::NewObj(MethodToken::MemberRef(MemberRefIndex(0)))),
+        Err(EncodeError::UnknownMethod(MethodToken::MemberRef(
+            MemberRefIndex(0)
+        )))
+    ));
+    assert!(matches!(
+        assemble(CILOp::Call(MethodToken::MethodSpec(MethodSpecIndex(1)))),
+        Err(EncodeError::UnknownMethod(MethodToken::MethodSpec(
+            MethodSpecIndex(1)
+        )))
+    ));
+    assert!(matches!(
+        assemble(CILOp::CallI(StandAloneSigIndex(1))),
+        Err(EncodeError::UnknownSignature(StandAloneSigIndex(1)))
+    ));
+    builder
+        .add_generic_param_constraint(GenericParamIndex(1), TypeDefOrRef::TypeRef(TypeRefIndex(1)));
+    assert!(matches!(
+        builder.write_to(&mut Vec::new()),
+        Err(EncodeError::UnknownGenericParam(GenericParamIndex(1)))
+    ));
+    let mut builder = build_add_i32(true);
+    builder.set_entrypoint(MethodIndex(9));
+    assert!(matches!(
+        builder.write_to(&mut Vec::new()),
+        Err(EncodeError::UnknownMethod(MethodToken::MethodDef(
+            MethodIndex(9)
+        )))
+    ));
+}